@@ -0,0 +1,68 @@
+use std::convert::Infallible;
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use frontend::{User, UserId};
+
+/// Name of whoever is making the request, taken from the user the session belongs to.
+/// Requests only reach the handlers with a session, so it is `None` only in tests.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Actor(pub Option<String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let name = parts
+            .extensions
+            .get::<User>()
+            .map(|user| user.name.trim().to_string())
+            .filter(|name| !name.is_empty());
+        Ok(Actor(name))
+    }
+}
+
+/// Id of the signed in user, for records that link to whoever made them. `None` only in tests.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ActorId(pub Option<UserId>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ActorId {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ActorId(parts.extensions.get::<User>().map(|user| user.id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use super::*;
+
+    async fn actor(request: Request<()>) -> Actor {
+        let (mut parts, _) = request.into_parts();
+        Actor::from_request_parts(&mut parts, &()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn name_should_be_read_from_the_signed_in_user() {
+        let mut request = Request::new(());
+        request.extensions_mut().insert(User {
+            name: " Alice ".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(Actor(Some("Alice".to_string())), actor(request).await);
+    }
+
+    #[tokio::test]
+    async fn missing_user_should_be_none() {
+        assert_eq!(Actor(None), actor(Request::new(())).await);
+        let (mut parts, _) = Request::new(()).into_parts();
+        assert_eq!(
+            ActorId(None),
+            ActorId::from_request_parts(&mut parts, &()).await.unwrap()
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use axum::{
+    extract::{self, Query, State},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use edgedb_tokio::{Client, Transaction};
+use frontend::{
+    AuditAction, AuditEntity, AuditEvent, AuditQueryParams, FieldChange, SortDirection,
+};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    actor::Actor,
+    auth::SessionClient,
+    errors::ApiError,
+    organizations::CurrentOrganization,
+    query::{id_list, DatetimeField, ListConfig, ListQuery, Op, Param},
+    AppState,
+};
+
+const AUDIT_SHAPE: &str = "id, entity, entity_id, action, changes, changed_by, created";
+
+pub fn audit_routes() -> Router<AppState> {
+    Router::new().route("/audit", get(audit_events))
+}
+
+/// Fields recorded in the audit log for each kind of record, links are recorded by name
+fn snapshot_query(entity: AuditEntity) -> &'static str {
+    match entity {
+        AuditEntity::Customer => {
+            r#"
+            select Customer {
+                name,
+                email,
+                status,
+                deleted,
+                owner := .owner.name,
+                tags := (select .tags order by .name).name,
+                custom
+            } filter .id = <uuid>$0;"#
+        }
+        AuditEntity::Opportunity => {
+            r#"
+            select Opportunity {
+                name,
+                pipeline := .stage.pipeline.name,
+                status := .stage.name,
+                amount := <str>.amount,
+                currency,
+                close_date,
+                probability,
+                owner := .owner.name,
+                tags := (select .tags order by .name).name,
+                custom
+            } filter .id = <uuid>$0;"#
+        }
+        AuditEntity::Activity => {
+            r#"
+            select Activity {
+                kind,
+                body,
+                opportunity := .opportunity.name
+            } filter .id = <uuid>$0;"#
+        }
+        AuditEntity::Contact => {
+            r#"
+            select Contact {
+                name,
+                email,
+                phone,
+                job_title,
+                is_primary
+            } filter .id = <uuid>$0;"#
+        }
+        AuditEntity::Task => {
+            r#"
+            select Task {
+                title,
+                due,
+                assignee := .assignee.name,
+                completed,
+                priority,
+                opportunity := .opportunity.name
+            } filter .id = <uuid>$0;"#
+        }
+    }
+}
+
+/// Current values of the record, null once it no longer exists
+async fn snapshot(
+    tx: &mut Transaction,
+    entity: AuditEntity,
+    id: Uuid,
+) -> Result<Value, edgedb_tokio::Error> {
+    let json = tx.query_single_json(snapshot_query(entity), &(id,)).await?;
+    Ok(json
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default())
+}
+
+/// Fields whose value differs between the two snapshots, fields missing from a snapshot count as null
+fn diff(before: &Value, after: &Value) -> Vec<FieldChange> {
+    let empty = Map::new();
+    let before = before.as_object().unwrap_or(&empty);
+    let after = after.as_object().unwrap_or(&empty);
+    let mut fields: Vec<&String> = before.keys().collect();
+    fields.extend(after.keys().filter(|field| !before.contains_key(*field)));
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let old = before.get(field).cloned().unwrap_or_default();
+            let new = after.get(field).cloned().unwrap_or_default();
+            (old != new).then(|| FieldChange {
+                field: field.clone(),
+                before: old,
+                after: new,
+            })
+        })
+        .collect()
+}
+
+/// Records a change to a record in the audit log as part of the transaction making the change.
+/// Start the trail before changing the record and finish it afterwards.
+pub struct AuditTrail {
+    entity: AuditEntity,
+    entity_id: Uuid,
+    customer_id: Uuid,
+    /// Organisation of the customer when the trail started, it may be gone by the end
+    organization: Option<Uuid>,
+    changed_by: Option<String>,
+    before: Value,
+    notes: Vec<FieldChange>,
+}
+
+impl AuditTrail {
+    /// Trail for a record that has just been created
+    pub fn new(entity: AuditEntity, entity_id: Uuid, customer_id: Uuid, actor: &Actor) -> Self {
+        AuditTrail {
+            entity,
+            entity_id,
+            customer_id,
+            organization: None,
+            changed_by: actor.0.clone(),
+            before: Value::Null,
+            notes: vec![],
+        }
+    }
+
+    /// Records a change that is not one of the snapshot fields, e.g. what a merge brought in
+    pub fn note(mut self, field: &str, before: Value, after: Value) -> Self {
+        self.notes.push(FieldChange {
+            field: field.to_string(),
+            before,
+            after,
+        });
+        self
+    }
+
+    /// Trail starting from the current values of the record
+    pub async fn start(
+        tx: &mut Transaction,
+        entity: AuditEntity,
+        entity_id: Uuid,
+        customer_id: Uuid,
+        actor: &Actor,
+    ) -> Result<Self, edgedb_tokio::Error> {
+        let before = snapshot(tx, entity, entity_id).await?;
+        let organization = tx
+            .query_single(
+                "select (select Customer filter .id = <uuid>$0).organization.id;",
+                &(customer_id,),
+            )
+            .await?;
+        Ok(AuditTrail {
+            before,
+            organization,
+            ..AuditTrail::new(entity, entity_id, customer_id, actor)
+        })
+    }
+
+    /// Writes the event, an update that changed nothing is not recorded
+    pub async fn finish(
+        self,
+        tx: &mut Transaction,
+        action: AuditAction,
+    ) -> Result<(), edgedb_tokio::Error> {
+        let after = snapshot(tx, self.entity, self.entity_id).await?;
+        let mut changes = diff(&self.before, &after);
+        changes.extend(self.notes);
+        if changes.is_empty() && action == AuditAction::Updated {
+            return Ok(());
+        }
+        let changes = serde_json::to_string(&changes).unwrap_or_else(|_| "[]".to_string());
+        let _: Uuid = tx
+            .query_required_single(
+                r#"
+                select (insert AuditEvent {
+                    entity := <str>$0,
+                    entity_id := <uuid>$1,
+                    customer_id := <uuid>$2,
+                    action := <AuditAction><str>$3,
+                    changes := to_json(<str>$4),
+                    changed_by := <optional str>$5,
+                    organization := (select Organization filter .id = <optional uuid>$6)
+                        ?? (select Customer filter .id = <uuid>$2).organization
+                        ?? assert_exists((select Organization order by .created limit 1)),
+                }).id;"#,
+                &(
+                    self.entity.to_string(),
+                    self.entity_id,
+                    self.customer_id,
+                    action.to_string(),
+                    changes,
+                    self.changed_by,
+                    self.organization,
+                ),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+/// The customer and the customers that were merged into it, following merges of merges.
+/// A merge leaves the duplicate's events alone, the surviving customer's `merged` note names the duplicate.
+async fn merged_customers(db: &Client, id: Uuid) -> Result<Vec<Uuid>, edgedb_tokio::Error> {
+    let mut customers = vec![id];
+    let mut merged = vec![id];
+    while !merged.is_empty() {
+        merged = db
+            .query(
+                r#"
+                with changes := json_array_unpack((
+                    select AuditEvent filter .entity = <str>$1 and .action = AuditAction.Merged
+                        and .entity_id in <uuid><str>json_array_unpack(to_json(<str>$0))
+                ).changes)
+                for change in changes union (
+                    select <uuid><str>json_get(change, 'after', 'id')
+                    filter <str>json_get(change, 'field') = 'merged'
+                );"#,
+                &(id_list(&merged), AuditEntity::Customer.to_string()),
+            )
+            .await?;
+        merged.retain(|id| !customers.contains(id));
+        customers.extend(merged.iter().copied());
+    }
+    Ok(customers)
+}
+
+/// Audit log of a record, newest first.
+/// The log of a customer includes the changes to its opportunities, activities, contacts and tasks
+/// and the log of the customers merged into it.
+async fn audit_events(
+    SessionClient(db): SessionClient,
+    State(config): State<ListConfig>,
+    CurrentOrganization(organization): CurrentOrganization,
+    Query(params): extract::Query<AuditQueryParams>,
+) -> Result<Response, ApiError> {
+    params.validate()?;
+    let query = ListQuery::new("AuditEvent", AUDIT_SHAPE, config)
+        .filter_organization(".organization.id", organization);
+    let query = match params.entity {
+        AuditEntity::Customer => query.filter_in(
+            "<str>.customer_id",
+            merged_customers(&db, params.id)
+                .await?
+                .iter()
+                .map(Uuid::to_string)
+                .collect(),
+        ),
+        entity => query
+            .filter(".entity", Op::Eq, Param::Str(entity.to_string()))
+            .filter(".entity_id", Op::Eq, Param::Uuid(params.id)),
+    };
+    let (query, args) = query
+        .order_by(&DatetimeField(".created"), SortDirection::Desc)
+        .limit(params.limit)
+        .build();
+    let result: Vec<AuditEvent> = db.query(query.as_str(), &args).await?;
+    Ok((Json(result)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    async fn get_db() -> Client {
+        edgedb_tokio::create_client()
+            .await
+            .expect("Failed to connect to the DB")
+    }
+
+    async fn add_event(db: &Client, customer: Uuid, action: AuditAction, changes: Value) {
+        let _: Uuid = db
+            .query_required_single(
+                r#"
+                select (insert AuditEvent {
+                    entity := 'customer',
+                    entity_id := <uuid>$0,
+                    customer_id := <uuid>$0,
+                    action := <AuditAction><str>$1,
+                    changes := to_json(<str>$2),
+                }).id;"#,
+                &(customer, action.to_string(), changes.to_string()),
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn customer_log_should_include_the_customers_merged_into_it() {
+        let db = get_db().await;
+        let (customer, duplicate, earlier) = (
+            Uuid::from_u128(rand::random()),
+            Uuid::from_u128(rand::random()),
+            Uuid::from_u128(rand::random()),
+        );
+        let merged = |id: Uuid| json!([{"field": "merged", "before": null, "after": {"id": id}}]);
+        add_event(&db, earlier, AuditAction::Created, json!([])).await;
+        add_event(&db, duplicate, AuditAction::Merged, merged(earlier)).await;
+        add_event(&db, customer, AuditAction::Merged, merged(duplicate)).await;
+
+        let response = audit_events(
+            SessionClient(db.clone()),
+            State(ListConfig::default()),
+            CurrentOrganization::default(),
+            Query(AuditQueryParams {
+                entity: AuditEntity::Customer,
+                id: customer,
+                limit: 10,
+            }),
+        )
+        .await
+        .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let events: Vec<AuditEvent> = serde_json::from_slice(&body).unwrap();
+        let unrelated = merged_customers(&db, Uuid::from_u128(rand::random()))
+            .await
+            .unwrap();
+
+        assert_eq!(3, events.len());
+        assert_eq!(1, unrelated.len());
+    }
+
+    #[test]
+    fn diff_should_only_list_changed_fields() {
+        let before = json!({"name": "Acme", "email": "a@acme.com", "deleted": null});
+        let after = json!({"name": "Acme Ltd", "email": "a@acme.com", "deleted": null});
+        assert_eq!(
+            vec![FieldChange {
+                field: "name".to_string(),
+                before: json!("Acme"),
+                after: json!("Acme Ltd"),
+            }],
+            diff(&before, &after)
+        );
+    }
+
+    #[test]
+    fn diff_against_nothing_should_list_every_field_with_a_value() {
+        let created = json!({"name": "Acme", "deleted": null});
+        assert_eq!(
+            vec![FieldChange {
+                field: "name".to_string(),
+                before: Value::Null,
+                after: json!("Acme"),
+            }],
+            diff(&Value::Null, &created)
+        );
+        assert_eq!(
+            vec!["name"],
+            diff(&created, &Value::Null)
+                .iter()
+                .map(|change| change.field.as_str())
+                .collect::<Vec<&str>>()
+        );
+    }
+}
@@ -0,0 +1,624 @@
+use std::sync::OnceLock;
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    async_trait,
+    extract::{self, FromRequestParts, Path, State},
+    handler::Handler,
+    http::{
+        header::{COOKIE, SET_COOKIE},
+        request::Parts,
+        HeaderMap, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use edgedb_protocol::value::{EnumValue, Value};
+use edgedb_tokio::Client;
+use frontend::{
+    Credentials, NewUser, Organization, OrganizationId, Permission, Role, User, UserId,
+};
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use validator::Validate;
+
+use crate::{
+    actor::Actor, errors::ApiError, organizations::CurrentOrganization, permissions::allow,
+    transaction::transaction, AppState,
+};
+
+/// Cookie holding the session token, only the hash of the token is stored
+pub const SESSION_COOKIE: &str = "basiccrm_session";
+/// How long a session lasts after signing in
+const SESSION_HOURS: i64 = 7 * 24;
+
+pub const USER_SHAPE: &str = "id, email, name, role, organization: { id, name }, operator";
+
+/// Signing in and out, reachable without a session
+pub fn auth_routes() -> Router<AppState> {
+    Router::new()
+        .route("/auth/login", post(login))
+        .route("/auth/logout", post(logout))
+        .route("/auth/setup", post(setup))
+}
+
+/// The signed in user and the other accounts, behind the session check
+pub fn user_routes() -> Router<AppState> {
+    Router::new()
+        .route("/auth/me", get(me))
+        .route(
+            "/users",
+            get(users).post(create_user.layer(allow(Permission::ManageUsers))),
+        )
+        .route(
+            "/user/:id/role",
+            put(update_role.layer(allow(Permission::ManageUsers))),
+        )
+}
+
+/// User with the password hash, only read to check a password
+#[derive(Debug, Deserialize)]
+struct StoredUser {
+    id: UserId,
+    email: String,
+    name: String,
+    role: Role,
+    organization: Organization,
+    operator: bool,
+    password_hash: String,
+}
+
+pub fn hash_password(password: &str) -> Result<String, ApiError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|error| {
+            tracing::error!("Password could not be hashed: {}", error);
+            ApiError::BadRequest("The password could not be saved".to_string())
+        })
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+/// Hash checked when the email is unknown so a wrong email takes as long as a wrong password
+fn dummy_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| hash_password("not a real password").unwrap_or_default())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Random token handed to the browser in the session cookie
+fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+/// What is stored for a token, so the sessions table can not be used to sign in
+fn token_hash(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+/// Session token sent in the `Cookie` header
+pub fn session_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, token)| token.to_string())
+        .filter(|token| !token.is_empty())
+}
+
+/// Cookie the browser keeps for the session, it can not be read by scripts or sent to other sites
+fn session_cookie(token: &str, max_age: i64) -> String {
+    format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; Secure; SameSite=Strict",
+        SESSION_COOKIE, token, max_age
+    )
+}
+
+/// Starts a session for the user, returning the token for the cookie
+async fn start_session(db: &Client, user: UserId) -> Result<String, ApiError> {
+    let token = new_token();
+    db.query_required_single::<i64, _>(
+        "select count((delete Session filter .expires < datetime_current()));",
+        &(),
+    )
+    .await?;
+    db.query_required_single::<UserId, _>(
+        r#"
+        select (insert Session {
+            user := (select User filter .id = <uuid>$0),
+            token_hash := <str>$1,
+            expires := datetime_current() + <cal::relative_duration><str>$2,
+        }).user.id;"#,
+        &(user, token_hash(&token), format!("{} hours", SESSION_HOURS)),
+    )
+    .await?;
+    Ok(token)
+}
+
+/// User whose session the token belongs to, when the session has not expired
+pub async fn session_user(db: &Client, token: &str) -> Result<Option<User>, ApiError> {
+    let user: Option<User> = db
+        .query_single(
+            r#"
+            select <json>(
+                select Session filter .token_hash = <str>$0 and .expires > datetime_current()
+            ).user { id, email, name, role, organization: { id, name }, operator } limit 1;"#,
+            &(token_hash(token),),
+        )
+        .await?;
+    Ok(user)
+}
+
+/// Client for the signed in user's queries. Its globals hold their organisation and role, so the
+/// access policies hide the records of other organisations and deny the writes the role does not allow.
+#[derive(Clone)]
+pub struct SessionClient(pub Client);
+
+impl SessionClient {
+    pub fn new(db: &Client, user: &User) -> Self {
+        SessionClient(db.with_globals_fn(|globals| {
+            globals.set("current_organization", user.organization.id);
+            globals.set(
+                "current_role",
+                Value::Enum(EnumValue::from(user.role.to_string().as_str())),
+            );
+        }))
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for SessionClient {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<SessionClient>()
+            .cloned()
+            .ok_or_else(|| ApiError::Unauthorized("Please sign in".to_string()))
+    }
+}
+
+/// Rejects api calls without a valid session, the signed in user and a client scoped to them are
+/// passed on to the handlers
+pub async fn require_session<B>(
+    State(db): State<Client>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, ApiError> {
+    let unauthorized = || ApiError::Unauthorized("Please sign in".to_string());
+    let token = session_token(request.headers()).ok_or_else(unauthorized)?;
+    let user = session_user(&db, &token).await?.ok_or_else(unauthorized)?;
+    request
+        .extensions_mut()
+        .insert(SessionClient::new(&db, &user));
+    request.extensions_mut().insert(user);
+    Ok(next.run(request).await)
+}
+
+fn signed_in(user: User, token: &str) -> Response {
+    (
+        [(SET_COOKIE, session_cookie(token, SESSION_HOURS * 60 * 60))],
+        Json(user),
+    )
+        .into_response()
+}
+
+/// Checks the email and password and starts a session
+async fn login(
+    State(db): State<Client>,
+    Json(body): extract::Json<Credentials>,
+) -> Result<Response, ApiError> {
+    body.validate()?;
+    let json = db
+        .query_single_json(
+            r#"
+            select User { id, email, name, role, organization: { id, name }, operator, password_hash }
+            filter .email = str_lower(str_trim(<str>$0)) limit 1;"#,
+            &(body.email.clone(),),
+        )
+        .await?;
+    let stored = json.and_then(|json| serde_json::from_str::<StoredUser>(&json).ok());
+    let hash = match &stored {
+        Some(user) => user.password_hash.clone(),
+        None => dummy_hash().to_string(),
+    };
+    let valid = verify_password(&body.password, &hash);
+    let user = stored.filter(|_| valid).ok_or_else(|| {
+        ApiError::Unauthorized("The email or password is not correct".to_string())
+    })?;
+    let token = start_session(&db, user.id).await?;
+    Ok(signed_in(
+        User {
+            id: user.id,
+            email: user.email,
+            name: user.name,
+            role: user.role,
+            organization: user.organization,
+            operator: user.operator,
+        },
+        &token,
+    ))
+}
+
+/// Ends the session of the cookie, if there is one, and clears the cookie
+async fn logout(State(db): State<Client>, headers: HeaderMap) -> Result<Response, ApiError> {
+    if let Some(token) = session_token(&headers) {
+        db.query::<UserId, _>(
+            "select (delete Session filter .token_hash = <str>$0).user.id;",
+            &(token_hash(&token),),
+        )
+        .await?;
+    }
+    Ok((StatusCode::OK, [(SET_COOKIE, session_cookie("", 0))]).into_response())
+}
+
+/// Adds the user to the organisation, or to the first organisation when there is none
+async fn insert_user(
+    db: &Client,
+    body: &NewUser,
+    actor: &Actor,
+    organization: Option<OrganizationId>,
+) -> Result<User, ApiError> {
+    let hash = hash_password(&body.password)?;
+    Ok(db
+        .query_required_single(
+            r#"
+            select <json>(insert User {
+                email := str_lower(str_trim(<str>$0)),
+                name := str_trim(<str>$1),
+                password_hash := <str>$2,
+                role := <Role><str>$4,
+                organization := (select Organization filter .id = <optional uuid>$5)
+                    ?? assert_exists((select Organization order by .created limit 1)),
+                created_by := <optional str>$3,
+                modified_by := <optional str>$3,
+            }) { id, email, name, role, organization: { id, name }, operator };"#,
+            &(
+                body.email.clone(),
+                body.name.clone(),
+                hash,
+                actor.0.clone(),
+                body.role.to_string(),
+                organization,
+            ),
+        )
+        .await?)
+}
+
+/// Creates the first account as an admin of the first organisation and an operator of the deployment
+/// and signs it in, after that admins add the other users
+async fn setup(
+    State(db): State<Client>,
+    Json(body): extract::Json<NewUser>,
+) -> Result<Response, ApiError> {
+    body.validate()?;
+    let hash = hash_password(&body.password)?;
+    let created: Option<User> = transaction(db.clone(), move |mut tx| {
+        let body = body.clone();
+        let hash = hash.clone();
+        async move {
+            let users: i64 = tx.query_required_single("select count(User);", &()).await?;
+            if users > 0 {
+                return Ok(None);
+            }
+            let user: User = tx
+                .query_required_single(
+                    r#"
+                    select <json>(insert User {
+                        email := str_lower(str_trim(<str>$0)),
+                        name := str_trim(<str>$1),
+                        password_hash := <str>$2,
+                        role := Role.Admin,
+                        operator := true,
+                    }) { id, email, name, role, organization: { id, name }, operator };"#,
+                    &(body.email, body.name, hash),
+                )
+                .await?;
+            Ok(Some(user))
+        }
+    })
+    .await?;
+    let user = created.ok_or_else(|| {
+        ApiError::Forbidden("The app is already set up, please sign in".to_string())
+    })?;
+    let token = start_session(&db, user.id).await?;
+    Ok(signed_in(user, &token))
+}
+
+/// The signed in user
+async fn me(extract::Extension(user): extract::Extension<User>) -> Response {
+    Json(user).into_response()
+}
+
+/// Users of the signed in user's organisation
+async fn users(
+    SessionClient(db): SessionClient,
+    CurrentOrganization(organization): CurrentOrganization,
+) -> Result<Response, ApiError> {
+    let query = format!(
+        r#"
+        select <json>User {{ {} }}
+        filter not exists <optional uuid>$0 or .organization.id ?= <optional uuid>$0
+        order by .name;"#,
+        USER_SHAPE
+    );
+    let result: Vec<User> = db.query(query.as_str(), &(organization,)).await?;
+    Ok((Json(result)).into_response())
+}
+
+/// Adds an account for a colleague in the same organisation, they sign in with the password given here
+async fn create_user(
+    SessionClient(db): SessionClient,
+    actor: Actor,
+    CurrentOrganization(organization): CurrentOrganization,
+    Json(body): extract::Json<NewUser>,
+) -> Result<Response, ApiError> {
+    body.validate()?;
+    let user = insert_user(&db, &body, &actor, organization).await?;
+    Ok((StatusCode::CREATED, Json(user)).into_response())
+}
+
+/// Gives the user another role, as long as their organisation still has an admin afterwards
+async fn update_role(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<UserId>,
+    actor: Actor,
+    CurrentOrganization(organization): CurrentOrganization,
+    Json(role): extract::Json<Role>,
+) -> Result<Response, ApiError> {
+    let query = format!(
+        r#"
+        with
+            user := (
+                select User filter .id = <uuid>$0
+                    and (not exists <optional uuid>$3 or .organization.id ?= <optional uuid>$3)
+            ),
+            other_admins := (
+                select detached User
+                filter .role = Role.Admin and .id != <uuid>$0 and .organization = user.organization
+            )
+        select <json>(
+            update user filter <Role><str>$1 = Role.Admin or exists other_admins
+            set {{
+                role := <Role><str>$1,
+                modified := datetime_current(),
+                modified_by := <optional str>$2,
+            }}
+        ) {{ {} }};"#,
+        USER_SHAPE
+    );
+    let user: Option<User> = db
+        .query_single(
+            query.as_str(),
+            &(id, role.to_string(), actor.0.clone(), organization),
+        )
+        .await?;
+    match user {
+        Some(user) => Ok(Json(user).into_response()),
+        None => {
+            let exists: bool = db
+                .query_required_single(
+                    r#"
+                    select exists (
+                        select User filter .id = <uuid>$0
+                            and (not exists <optional uuid>$1 or .organization.id ?= <optional uuid>$1)
+                    );"#,
+                    &(id, organization),
+                )
+                .await?;
+            match exists {
+                true => Err(ApiError::Unprocessable(
+                    "There has to be at least one admin".to_string(),
+                )),
+                false => Err(ApiError::NotFound),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+    use rand::distributions::{Alphanumeric, DistString};
+    use uuid::Uuid;
+
+    use super::*;
+
+    async fn get_db() -> Client {
+        edgedb_tokio::create_client()
+            .await
+            .expect("Failed to connect to the DB")
+    }
+
+    fn cookie_headers(cookie: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_str(cookie).unwrap());
+        headers
+    }
+
+    #[test]
+    fn password_should_only_verify_against_its_own_hash() {
+        let hash = hash_password("correct horse battery").unwrap();
+        assert!(hash.starts_with("$argon2"));
+        assert!(verify_password("correct horse battery", &hash));
+        assert!(!verify_password("correct horse battery!", &hash));
+        assert!(!verify_password("anything", "not a hash"));
+    }
+
+    #[test]
+    fn tokens_should_be_random_and_stored_hashed() {
+        let token = new_token();
+        assert_eq!(64, token.len());
+        assert_ne!(token, new_token());
+        assert_eq!(64, token_hash(&token).len());
+        assert_ne!(token, token_hash(&token));
+        assert_eq!(token_hash(&token), token_hash(&token));
+    }
+
+    #[test]
+    fn session_token_should_be_read_from_the_cookie_header() {
+        let headers = cookie_headers(&format!("theme=dark; {}=abc123; other=1", SESSION_COOKIE));
+        assert_eq!(Some("abc123".to_string()), session_token(&headers));
+        assert_eq!(None, session_token(&cookie_headers("theme=dark")));
+        assert_eq!(
+            None,
+            session_token(&cookie_headers(&format!("{}=", SESSION_COOKIE)))
+        );
+        assert_eq!(None, session_token(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn session_cookie_should_be_http_only_and_secure() {
+        let cookie = session_cookie("abc123", 60);
+        assert!(cookie.starts_with(&format!("{}=abc123;", SESSION_COOKIE)));
+        assert!(cookie.contains("HttpOnly"));
+        assert!(cookie.contains("Secure"));
+        assert!(cookie.contains("SameSite=Strict"));
+        assert!(session_cookie("", 0).contains("Max-Age=0"));
+    }
+
+    #[tokio::test]
+    async fn login_should_start_a_session_until_logout() {
+        let db = get_db().await;
+        let email = format!(
+            "{}@test.email.com",
+            Alphanumeric
+                .sample_string(&mut rand::thread_rng(), 16)
+                .to_lowercase()
+        );
+        let user = insert_user(
+            &db,
+            &NewUser {
+                email: email.clone(),
+                name: "Test User".to_string(),
+                password: "a long enough password".to_string(),
+                role: Role::SalesRep,
+            },
+            &Actor::default(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let wrong = login(
+            State(db.clone()),
+            Json(Credentials {
+                email: email.clone(),
+                password: "not the password".to_string(),
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::UNAUTHORIZED, wrong.status());
+
+        let response = login(
+            State(db.clone()),
+            Json(Credentials {
+                email: email.to_uppercase(),
+                password: "a long enough password".to_string(),
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::OK, response.status());
+        let cookie = response
+            .headers()
+            .get(SET_COOKIE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+        let headers = cookie_headers(&cookie);
+        let token = session_token(&headers).unwrap();
+        assert_eq!(Some(user.clone()), session_user(&db, &token).await.unwrap());
+
+        let _ = logout(State(db.clone()), headers).await;
+        assert_eq!(None, session_user(&db, &token).await.unwrap());
+        db.query::<UserId, _>(
+            "select (delete User filter .id = <uuid>$0).id;",
+            &(user.id,),
+        )
+        .await
+        .unwrap();
+    }
+
+    async fn add_user(db: &Client, role: Role) -> User {
+        let email = format!(
+            "{}@test.email.com",
+            Alphanumeric
+                .sample_string(&mut rand::thread_rng(), 16)
+                .to_lowercase()
+        );
+        let body = NewUser {
+            email,
+            name: "Test User".to_string(),
+            password: "a long enough password".to_string(),
+            role,
+        };
+        insert_user(db, &body, &Actor::default(), None)
+            .await
+            .unwrap()
+    }
+
+    async fn insert_as(db: &Client, user: &User, query: &str) -> Result<Vec<Uuid>, ApiError> {
+        let SessionClient(db) = SessionClient::new(db, user);
+        Ok(db.query::<Uuid, _>(query, &()).await?)
+    }
+
+    #[tokio::test]
+    async fn session_client_should_deny_writes_the_role_does_not_allow() {
+        let db = get_db().await;
+        let read_only = add_user(&db, Role::ReadOnly).await;
+        let sales_rep = add_user(&db, Role::SalesRep).await;
+        let admin = add_user(&db, Role::Admin).await;
+        let customer = "select (insert Customer { name := 'Policy', email := <str>uuid_generate_v4() ++ '@test.email.com' }).id;";
+        let tag = "select (insert Tag { name := 'Policy ' ++ <str>uuid_generate_v4(), colour := '#123456' }).id;";
+
+        let read_only_customer = insert_as(&db, &read_only, customer).await;
+        let sales_rep_tag = insert_as(&db, &sales_rep, tag).await;
+        let admin_tag = insert_as(&db, &admin, tag).await;
+        db.query::<Uuid, _>(
+            "select (delete Tag filter .id ?= <optional uuid>$0).id;",
+            &(admin_tag.as_ref().ok().and_then(|ids| ids.first().copied()),),
+        )
+        .await
+        .unwrap();
+        db.query::<UserId, _>(
+            "select (delete User filter .id in {<uuid>$0, <uuid>$1, <uuid>$2}).id;",
+            &(read_only.id, sales_rep.id, admin.id),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(read_only_customer, Err(ApiError::Forbidden(_))));
+        assert!(matches!(sales_rep_tag, Err(ApiError::Forbidden(_))));
+        assert_eq!(1, admin_tag.unwrap().len());
+    }
+}
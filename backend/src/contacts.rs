@@ -0,0 +1,513 @@
+use axum::{
+    extract::{self, Path, State},
+    handler::Handler,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, put},
+    Json, Router,
+};
+use edgedb_tokio::Transaction;
+use frontend::{
+    AuditAction, AuditEntity, Contact, ContactId, CustomerId, Permission, SortDirection,
+};
+use validator::Validate;
+
+use crate::{
+    actor::Actor,
+    audit::AuditTrail,
+    auth::SessionClient,
+    errors::ApiError,
+    permissions::allow,
+    query::{empty_as_none, DatetimeField, ListConfig, ListQuery, Op, Param},
+    transaction::transaction,
+    AppState,
+};
+
+pub fn contact_routes() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/customer/:id/contacts",
+            get(contacts).post(create_contact.layer(allow(Permission::Edit))),
+        )
+        .route(
+            "/customer/:id/contact/:cid",
+            put(update_contact.layer(allow(Permission::Edit)))
+                .delete(delete_contact.layer(allow(Permission::Edit))),
+        )
+}
+
+async fn contacts(
+    SessionClient(db): SessionClient,
+    State(config): State<ListConfig>,
+    Path(id): extract::Path<CustomerId>,
+) -> Result<Response, ApiError> {
+    let (query, args) = ListQuery::new(
+        "Contact",
+        "id, name, email, phone, job_title, is_primary, created",
+        config,
+    )
+    .filter(".customer.id", Op::Eq, Param::Uuid(id))
+    .order_by(&DatetimeField(".created"), SortDirection::Asc)
+    .build();
+    let result: Vec<Contact> = db.query(query.as_str(), &args).await?;
+    Ok((Json(result)).into_response())
+}
+
+/// Trails for the customer's primary contacts other than `keep`, started before a new primary
+/// contact demotes them
+async fn demoted_trails(
+    tx: &mut Transaction,
+    id: CustomerId,
+    keep: Option<ContactId>,
+    actor: &Actor,
+) -> Result<Vec<AuditTrail>, edgedb_tokio::Error> {
+    let primary: Vec<ContactId> = tx
+        .query(
+            r#"
+            select (
+                select Contact filter .customer.id = <uuid>$0 and .is_primary
+                    and .id ?!= <optional uuid>$1
+            ).id;"#,
+            &(id, keep),
+        )
+        .await?;
+    let mut trails = vec![];
+    for cid in primary {
+        trails.push(AuditTrail::start(tx, AuditEntity::Contact, cid, id, actor).await?);
+    }
+    Ok(trails)
+}
+
+/// Adds a contact to the customer, a new primary contact replaces the current one
+async fn create_contact(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<CustomerId>,
+    actor: Actor,
+    Json(body): extract::Json<Contact>,
+) -> Result<Response, ApiError> {
+    body.validate()?;
+    let result: Option<Contact> = transaction(db, move |mut tx| {
+        let body = body.clone();
+        let actor = actor.clone();
+        async move {
+            let demoted = match body.is_primary {
+                true => demoted_trails(&mut tx, id, None, &actor).await?,
+                false => vec![],
+            };
+            let contact: Option<Contact> = tx
+                .query_single(
+                    r#"
+                    with
+                        customer := (select Customer filter .id = <uuid>$0 and not exists .deleted),
+                        demoted := (
+                            update customer.contacts filter <bool>$5 and .is_primary
+                            set { is_primary := false, modified := datetime_current(), modified_by := <optional str>$6 }
+                        ),
+                        contact := (for c in customer union (
+                            insert Contact {
+                                name := <str>$1,
+                                email := <str>$2,
+                                phone := <optional str>$3,
+                                job_title := <optional str>$4,
+                                is_primary := <bool>$5,
+                                created_by := <optional str>$6,
+                                modified_by := <optional str>$6,
+                            }
+                        )),
+                        linked := (update customer set { contacts += contact })
+                    select <json>contact {
+                        id,
+                        name,
+                        email,
+                        phone,
+                        job_title,
+                        is_primary,
+                        created
+                    };"#,
+                    &(
+                        id,
+                        body.name,
+                        body.email,
+                        empty_as_none(body.phone),
+                        empty_as_none(body.job_title),
+                        body.is_primary,
+                        actor.0.clone(),
+                    ),
+                )
+                .await?;
+            if let Some(contact) = &contact {
+                for trail in demoted {
+                    trail.finish(&mut tx, AuditAction::Updated).await?;
+                }
+                AuditTrail::new(AuditEntity::Contact, contact.id, id, &actor)
+                    .finish(&mut tx, AuditAction::Created)
+                    .await?;
+            }
+            Ok(contact)
+        }
+    })
+    .await?;
+    let contact = result.ok_or(ApiError::NotFound)?;
+    Ok((StatusCode::CREATED, Json(contact)).into_response())
+}
+
+async fn update_contact(
+    SessionClient(db): SessionClient,
+    Path((id, cid)): extract::Path<(CustomerId, ContactId)>,
+    actor: Actor,
+    Json(body): extract::Json<Contact>,
+) -> Result<Response, ApiError> {
+    if body.id.ne(&cid) {
+        return Err(ApiError::BadRequest(
+            "The contact id does not match the url".to_string(),
+        ));
+    }
+    body.validate()?;
+    let result: Option<Contact> = transaction(db, move |mut tx| {
+        let body = body.clone();
+        let actor = actor.clone();
+        async move {
+            let demoted = match body.is_primary {
+                true => demoted_trails(&mut tx, id, Some(cid), &actor).await?,
+                false => vec![],
+            };
+            let trail = AuditTrail::start(&mut tx, AuditEntity::Contact, cid, id, &actor).await?;
+            let contact: Option<Contact> = tx
+                .query_single(
+                    r#"
+                    with
+                        customer := (select Customer filter .id = <uuid>$0 and not exists .deleted),
+                        demoted := (
+                            update customer.contacts filter <bool>$6 and .is_primary and .id != <uuid>$1
+                            set { is_primary := false, modified := datetime_current(), modified_by := <optional str>$7 }
+                        ),
+                        contact := (
+                            update customer.contacts filter .id = <uuid>$1
+                            set {
+                                name := <str>$2,
+                                email := <str>$3,
+                                phone := <optional str>$4,
+                                job_title := <optional str>$5,
+                                is_primary := <bool>$6,
+                                modified := datetime_current(),
+                                modified_by := <optional str>$7,
+                            }
+                        )
+                    select <json>contact {
+                        id,
+                        name,
+                        email,
+                        phone,
+                        job_title,
+                        is_primary,
+                        created
+                    };"#,
+                    &(
+                        id,
+                        body.id,
+                        body.name,
+                        body.email,
+                        empty_as_none(body.phone),
+                        empty_as_none(body.job_title),
+                        body.is_primary,
+                        actor.0.clone(),
+                    ),
+                )
+                .await?;
+            // the other contacts are demoted even when this one is not found
+            for trail in demoted {
+                trail.finish(&mut tx, AuditAction::Updated).await?;
+            }
+            if contact.is_some() {
+                trail.finish(&mut tx, AuditAction::Updated).await?;
+            }
+            Ok(contact)
+        }
+    })
+    .await?;
+    let contact = result.ok_or(ApiError::NotFound)?;
+    Ok((Json(contact)).into_response())
+}
+
+async fn delete_contact(
+    SessionClient(db): SessionClient,
+    Path((id, cid)): extract::Path<(CustomerId, ContactId)>,
+    actor: Actor,
+) -> Result<Response, ApiError> {
+    let result: Option<ContactId> = transaction(db, move |mut tx| {
+        let actor = actor.clone();
+        async move {
+            let trail = AuditTrail::start(&mut tx, AuditEntity::Contact, cid, id, &actor).await?;
+            let deleted: Option<ContactId> = tx
+                .query_single(
+                    r#"
+                    select (
+                        delete Contact filter .customer.id = <uuid>$0 and .id = <uuid>$1
+                    ).id;"#,
+                    &(id, cid),
+                )
+                .await?;
+            if deleted.is_some() {
+                trail.finish(&mut tx, AuditAction::Deleted).await?;
+            }
+            Ok(deleted)
+        }
+    })
+    .await?;
+    result.ok_or(ApiError::NotFound)?;
+    Ok((StatusCode::OK).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use edgedb_tokio::Client;
+    use frontend::{Customer, ErrorResponse};
+    use rand::distributions::{Alphanumeric, DistString};
+    use serde::de::DeserializeOwned;
+
+    use super::*;
+    const TEST_EMAIL_DOMAIN: &str = "@test.email.com";
+
+    async fn get_db() -> Client {
+        edgedb_tokio::create_client()
+            .await
+            .expect("Failed to connect to the DB")
+    }
+
+    async fn add_customer(db: &Client) -> Customer {
+        db.query_required_single(
+            r#"
+            select <json>(
+                insert Customer {
+                    name := <str>$0,
+                    email := <str>$1,
+                })
+                {
+                    id,
+                    name,
+                    email,
+                    status,
+                    created
+                };"#,
+            &(
+                "Contact test".to_string(),
+                format!(
+                    "{}{}",
+                    Alphanumeric.sample_string(&mut rand::thread_rng(), 16),
+                    TEST_EMAIL_DOMAIN
+                ),
+            ),
+        )
+        .await
+        .expect("Failed to add customer")
+    }
+
+    async fn remove_customer(db: &Client, id: CustomerId) {
+        let _: Vec<edgedb_protocol::value::Value> = db
+            .query("delete Customer filter .id = <uuid>$0", &(id,))
+            .await
+            .expect("Failed to remove customer");
+    }
+
+    async fn into_type<T: DeserializeOwned>(response: Response) -> T {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice::<T>(&body).unwrap()
+    }
+
+    fn contact(name: &str, is_primary: bool) -> Contact {
+        Contact {
+            name: name.to_string(),
+            email: format!("{}{}", name.to_lowercase(), TEST_EMAIL_DOMAIN),
+            phone: Some("+64 21 123 4567".to_string()),
+            job_title: Some("Buyer".to_string()),
+            is_primary,
+            ..Default::default()
+        }
+    }
+
+    async fn list(db: &Client, id: CustomerId) -> Vec<Contact> {
+        into_type(
+            contacts(
+                SessionClient(db.clone()),
+                State(ListConfig::default()),
+                Path(id),
+            )
+            .await
+            .into_response(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn new_primary_contact_should_replace_the_current_one() {
+        let db = get_db().await;
+        let customer = add_customer(&db).await;
+
+        let response = create_contact(
+            SessionClient(db.clone()),
+            Path(customer.id),
+            Actor::default(),
+            Json(contact("Alice", true)),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::CREATED, response.status());
+        let alice: Contact = into_type(response).await;
+        let bob: Contact = into_type(
+            create_contact(
+                SessionClient(db.clone()),
+                Path(customer.id),
+                Actor::default(),
+                Json(contact("Bob", true)),
+            )
+            .await
+            .into_response(),
+        )
+        .await;
+
+        let contacts = list(&db, customer.id).await;
+        remove_customer(&db, customer.id).await;
+        assert_eq!(2, contacts.len());
+        let primary: Vec<&Contact> = contacts.iter().filter(|c| c.is_primary).collect();
+        assert_eq!(1, primary.len());
+        assert_eq!(bob.id, primary[0].id);
+        assert_ne!(alice.id, primary[0].id);
+    }
+
+    #[tokio::test]
+    async fn demoting_a_primary_contact_should_be_recorded_in_the_customers_log() {
+        let db = get_db().await;
+        let customer = add_customer(&db).await;
+        for name in ["Alice", "Bob"] {
+            let _ = create_contact(
+                SessionClient(db.clone()),
+                Path(customer.id),
+                Actor::default(),
+                Json(contact(name, true)),
+            )
+            .await
+            .into_response();
+        }
+
+        let actions: Vec<String> = db
+            .query(
+                r#"
+                select <str>(
+                    select AuditEvent filter .entity = 'contact' and .customer_id = <uuid>$0
+                    order by .created
+                ).action;"#,
+                &(customer.id,),
+            )
+            .await
+            .unwrap();
+        remove_customer(&db, customer.id).await;
+        assert_eq!(vec!["Created", "Updated", "Created"], actions);
+    }
+
+    #[tokio::test]
+    async fn update_contact_should_persist_details() {
+        let db = get_db().await;
+        let customer = add_customer(&db).await;
+        let added: Contact = into_type(
+            create_contact(
+                SessionClient(db.clone()),
+                Path(customer.id),
+                Actor::default(),
+                Json(contact("Carol", false)),
+            )
+            .await
+            .into_response(),
+        )
+        .await;
+
+        let response = update_contact(
+            SessionClient(db.clone()),
+            Path((customer.id, added.id)),
+            Actor::default(),
+            Json(Contact {
+                job_title: Some("Director".to_string()),
+                phone: None,
+                ..added.clone()
+            }),
+        )
+        .await
+        .into_response();
+
+        let contacts = list(&db, customer.id).await;
+        remove_customer(&db, customer.id).await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(Some("Director".to_string()), contacts[0].job_title);
+        assert_eq!(None, contacts[0].phone);
+    }
+
+    #[tokio::test]
+    async fn invalid_contact_should_be_bad_request() {
+        let db = get_db().await;
+        let response = create_contact(
+            SessionClient(db.clone()),
+            Path(CustomerId::from_u128(rand::random())),
+            Actor::default(),
+            Json(Contact {
+                phone: Some("call me".to_string()),
+                ..contact("Dave", false)
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let error: ErrorResponse = into_type(response).await;
+        assert!(error.fields.contains_key("phone"));
+    }
+
+    #[tokio::test]
+    async fn contact_for_missing_customer_should_be_not_found() {
+        let db = get_db().await;
+        let response = create_contact(
+            SessionClient(db.clone()),
+            Path(CustomerId::from_u128(rand::random())),
+            Actor::default(),
+            Json(contact("Erin", false)),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+    }
+
+    #[tokio::test]
+    async fn delete_contact_should_remove_only_that_contact() {
+        let db = get_db().await;
+        let customer = add_customer(&db).await;
+        let added: Contact = into_type(
+            create_contact(
+                SessionClient(db.clone()),
+                Path(customer.id),
+                Actor::default(),
+                Json(contact("Frank", false)),
+            )
+            .await
+            .into_response(),
+        )
+        .await;
+
+        let deleted = delete_contact(
+            SessionClient(db.clone()),
+            Path((customer.id, added.id)),
+            Actor::default(),
+        )
+        .await
+        .into_response();
+        let missing = delete_contact(
+            SessionClient(db.clone()),
+            Path((customer.id, added.id)),
+            Actor::default(),
+        )
+        .await
+        .into_response();
+
+        let contacts = list(&db, customer.id).await;
+        remove_customer(&db, customer.id).await;
+        assert_eq!(StatusCode::OK, deleted.status());
+        assert_eq!(StatusCode::NOT_FOUND, missing.status());
+        assert!(contacts.is_empty());
+    }
+}
@@ -0,0 +1,534 @@
+use axum::{
+    extract::{self, Path, Query, State},
+    handler::Handler,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, put},
+    Json, Router,
+};
+use edgedb_protocol::value::Value;
+use edgedb_tokio::Client;
+use frontend::{
+    check_custom_values, CustomField, CustomFieldEntity, CustomFieldId, CustomFieldKind,
+    CustomFieldsQueryParams, CustomFilter, CustomValues, OrganizationId, Permission, SortDirection,
+};
+use validator::Validate;
+
+use crate::{
+    actor::Actor,
+    auth::SessionClient,
+    errors::ApiError,
+    organizations::CurrentOrganization,
+    permissions::allow,
+    query::{DatetimeField, ListConfig, ListQuery, Op, Param},
+    AppState,
+};
+
+const CUSTOM_FIELD_SHAPE: &str =
+    "id, entity, key, label, kind, is_required, options := .options ?? <array<str>>[], min, max";
+
+pub fn custom_field_routes() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/custom-fields",
+            get(list_custom_fields)
+                .post(create_custom_field.layer(allow(Permission::ManageSettings))),
+        )
+        .route(
+            "/custom-field/:id",
+            put(update_custom_field.layer(allow(Permission::ManageSettings)))
+                .delete(delete_custom_field.layer(allow(Permission::ManageSettings))),
+        )
+}
+
+/// Fields of the organisation for the kind of record in the order they were added
+pub async fn custom_fields(
+    db: &Client,
+    organization: Option<OrganizationId>,
+    entity: CustomFieldEntity,
+) -> Result<Vec<CustomField>, ApiError> {
+    let (query, args) = ListQuery::new("CustomField", CUSTOM_FIELD_SHAPE, ListConfig::default())
+        .filter("<str>.entity", Op::Eq, Param::Str(entity.to_string()))
+        .filter_organization(".organization.id", organization)
+        .order_by(&DatetimeField(".created"), SortDirection::Asc)
+        .build();
+    Ok(db.query(query.as_str(), &args).await?)
+}
+
+/// The values to store for a record, or the messages for the values that do not suit their field
+pub async fn checked_custom_values(
+    db: &Client,
+    organization: Option<OrganizationId>,
+    entity: CustomFieldEntity,
+    values: &CustomValues,
+) -> Result<CustomValues, ApiError> {
+    let fields = custom_fields(db, organization, entity).await?;
+    check_custom_values(&fields, values).map_err(ApiError::InvalidFields)
+}
+
+/// Values as the json text bound to a `to_json(<str>$n)` argument
+pub fn custom_json(values: &CustomValues) -> String {
+    serde_json::to_string(values).unwrap_or_else(|_| "{}".to_string())
+}
+
+/// The fields the filters refer to, only read when there are filters
+pub async fn filter_fields(
+    db: &Client,
+    organization: Option<OrganizationId>,
+    entity: CustomFieldEntity,
+    filters: &[CustomFilter],
+) -> Result<Vec<CustomField>, ApiError> {
+    match filters.is_empty() {
+        true => Ok(vec![]),
+        false => custom_fields(db, organization, entity).await,
+    }
+}
+
+/// Type the stored json values of a kind of field are compared as
+fn cast(kind: CustomFieldKind) -> &'static str {
+    match kind {
+        CustomFieldKind::Text | CustomFieldKind::Select => "str",
+        CustomFieldKind::Number => "float64",
+        CustomFieldKind::Date => "cal::local_date",
+        CustomFieldKind::Boolean => "bool",
+    }
+}
+
+/// Keeps rows whose `.custom` values equal every filter, the filter values are checked like stored values
+pub fn filter_custom(
+    mut query: ListQuery,
+    fields: &[CustomField],
+    filters: &[CustomFilter],
+) -> Result<ListQuery, ApiError> {
+    for filter in filters {
+        let field = fields
+            .iter()
+            .find(|f| f.key == filter.key)
+            .ok_or_else(|| ApiError::BadRequest(format!("{} is not a custom field", filter.key)))?;
+        let value = field
+            .check(Some(&serde_json::Value::String(filter.value.clone())))
+            .ok()
+            .flatten()
+            .ok_or_else(|| {
+                ApiError::BadRequest(format!(
+                    "{} is not a valid value for {}",
+                    filter.value, field.label
+                ))
+            })?;
+        let value = match value {
+            serde_json::Value::String(text) => text,
+            other => other.to_string(),
+        };
+        query = query.filter_json(".custom", filter.key.clone(), cast(field.kind), value);
+    }
+    Ok(query)
+}
+
+fn options_json(field: &CustomField) -> String {
+    let options: Vec<&str> = match field.kind {
+        CustomFieldKind::Select => field.options.iter().map(|o| o.trim()).collect(),
+        _ => vec![],
+    };
+    serde_json::to_string(&options).unwrap_or_else(|_| "[]".to_string())
+}
+
+async fn find_custom_field(
+    db: &Client,
+    organization: Option<OrganizationId>,
+    id: CustomFieldId,
+) -> Result<CustomField, ApiError> {
+    let (query, args) = ListQuery::new("CustomField", CUSTOM_FIELD_SHAPE, ListConfig::default())
+        .filter(".id", Op::Eq, Param::Uuid(id))
+        .filter_organization(".organization.id", organization)
+        .build();
+    let result: Option<CustomField> = db.query(query.as_str(), &args).await?.pop();
+    result.ok_or(ApiError::NotFound)
+}
+
+/// Every custom field of the organisation, or those of one kind of record
+async fn list_custom_fields(
+    SessionClient(db): SessionClient,
+    State(config): State<ListConfig>,
+    CurrentOrganization(organization): CurrentOrganization,
+    Query(params): extract::Query<CustomFieldsQueryParams>,
+) -> Result<Response, ApiError> {
+    let mut query = ListQuery::new("CustomField", CUSTOM_FIELD_SHAPE, config)
+        .filter_organization(".organization.id", organization);
+    if let Some(entity) = params.entity {
+        query = query.filter("<str>.entity", Op::Eq, Param::Str(entity.to_string()));
+    }
+    let (query, args) = query
+        .order_by(&DatetimeField(".created"), SortDirection::Asc)
+        .build();
+    let result: Vec<CustomField> = db.query(query.as_str(), &args).await?;
+    Ok((Json(result)).into_response())
+}
+
+async fn create_custom_field(
+    SessionClient(db): SessionClient,
+    actor: Actor,
+    CurrentOrganization(organization): CurrentOrganization,
+    Json(body): extract::Json<CustomField>,
+) -> Result<Response, ApiError> {
+    body.validate()?;
+    let id: CustomFieldId = db
+        .query_required_single(
+            r#"
+            select (insert CustomField {
+                entity := <CustomFieldEntity><str>$0,
+                key := <str>$1,
+                label := str_trim(<str>$2),
+                kind := <CustomFieldKind><str>$3,
+                is_required := <bool>$4,
+                options := <array<str>>to_json(<str>$5),
+                min := <optional float64>$6,
+                max := <optional float64>$7,
+                created_by := <optional str>$8,
+                modified_by := <optional str>$8,
+                organization := (select Organization filter .id = <optional uuid>$9)
+                    ?? assert_exists((select Organization order by .created limit 1)),
+            }).id;"#,
+            &(
+                body.entity.to_string(),
+                body.key.clone(),
+                body.label.clone(),
+                body.kind.to_string(),
+                body.is_required,
+                options_json(&body),
+                body.min,
+                body.max,
+                actor.0,
+                organization,
+            ),
+        )
+        .await?;
+    let field = find_custom_field(&db, organization, id).await?;
+    Ok((StatusCode::CREATED, Json(field)).into_response())
+}
+
+/// Changes the label, rules and options of the field.
+/// The record kind, key and type stay as they were so stored values keep their meaning.
+async fn update_custom_field(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<CustomFieldId>,
+    actor: Actor,
+    CurrentOrganization(organization): CurrentOrganization,
+    Json(body): extract::Json<CustomField>,
+) -> Result<Response, ApiError> {
+    if body.id.ne(&id) {
+        return Err(ApiError::BadRequest(
+            "The custom field id does not match the url".to_string(),
+        ));
+    }
+    let current = find_custom_field(&db, organization, id).await?;
+    let body = CustomField {
+        entity: current.entity,
+        key: current.key,
+        kind: current.kind,
+        ..body
+    };
+    body.validate()?;
+    let _: Option<CustomFieldId> = db
+        .query_single(
+            r#"
+            select (update CustomField filter .id = <uuid>$0
+                and (not exists <optional uuid>$7 or .organization.id ?= <optional uuid>$7)
+            set {
+                label := str_trim(<str>$1),
+                is_required := <bool>$2,
+                options := <array<str>>to_json(<str>$3),
+                min := <optional float64>$4,
+                max := <optional float64>$5,
+                modified := datetime_current(),
+                modified_by := <optional str>$6,
+            }).id;"#,
+            &(
+                id,
+                body.label.clone(),
+                body.is_required,
+                options_json(&body),
+                body.min,
+                body.max,
+                actor.0,
+                organization,
+            ),
+        )
+        .await?;
+    let field = find_custom_field(&db, organization, id).await?;
+    Ok((Json(field)).into_response())
+}
+
+/// Deletes the field, values already stored under its key are dropped the next time the record is saved
+async fn delete_custom_field(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<CustomFieldId>,
+    CurrentOrganization(organization): CurrentOrganization,
+) -> Result<Response, ApiError> {
+    let result: Option<Value> = db
+        .query_single(
+            r#"
+            delete CustomField filter .id = <uuid>$0
+                and (not exists <optional uuid>$1 or .organization.id ?= <optional uuid>$1)"#,
+            &(id, organization),
+        )
+        .await?;
+    result.ok_or(ApiError::NotFound)?;
+    Ok((StatusCode::OK).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use frontend::ErrorResponse;
+    use rand::distributions::{Alphanumeric, DistString};
+    use serde::de::DeserializeOwned;
+    use serde_json::json;
+
+    use super::*;
+
+    async fn get_db() -> Client {
+        edgedb_tokio::create_client()
+            .await
+            .expect("Failed to connect to the DB")
+    }
+
+    async fn into_type<T: DeserializeOwned>(response: Response) -> T {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice::<T>(&body).unwrap()
+    }
+
+    fn field(key: &str, kind: CustomFieldKind) -> CustomField {
+        CustomField {
+            key: key.to_string(),
+            label: key.to_string(),
+            kind,
+            ..Default::default()
+        }
+    }
+
+    fn values(value: serde_json::Value) -> CustomValues {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn values_should_be_converted_to_the_field_type() {
+        let fields = vec![
+            field("industry", CustomFieldKind::Text),
+            field("licences", CustomFieldKind::Number),
+            field("renewal", CustomFieldKind::Date),
+            field("partner", CustomFieldKind::Boolean),
+            CustomField {
+                options: vec!["Small".to_string(), "Large".to_string()],
+                ..field("band", CustomFieldKind::Select)
+            },
+        ];
+        let checked = check_custom_values(
+            &fields,
+            &values(json!({
+                "industry": " Retail ",
+                "licences": "25",
+                "renewal": "2024-03-01",
+                "partner": "true",
+                "band": "Large",
+                "removed": "dropped"
+            })),
+        );
+        assert_eq!(
+            Ok(values(json!({
+                "industry": "Retail",
+                "licences": 25.0,
+                "renewal": "2024-03-01",
+                "partner": true,
+                "band": "Large"
+            }))),
+            checked
+        );
+    }
+
+    #[test]
+    fn invalid_values_should_be_reported_by_key() {
+        let fields = vec![
+            CustomField {
+                is_required: true,
+                ..field("industry", CustomFieldKind::Text)
+            },
+            CustomField {
+                min: Some(1.0),
+                max: Some(100.0),
+                ..field("licences", CustomFieldKind::Number)
+            },
+            CustomField {
+                options: vec!["Small".to_string()],
+                ..field("band", CustomFieldKind::Select)
+            },
+            field("renewal", CustomFieldKind::Date),
+            field("notes", CustomFieldKind::Text),
+        ];
+        let errors = check_custom_values(
+            &fields,
+            &values(json!({
+                "industry": "  ",
+                "licences": 500,
+                "band": "Huge",
+                "renewal": "01/03/2024"
+            })),
+        )
+        .unwrap_err();
+        assert_eq!(4, errors.len());
+        assert_eq!(
+            Some(&vec!["Please enter a value".to_string()]),
+            errors.get("custom.industry")
+        );
+        assert_eq!(
+            Some(&vec!["Must be at most 100".to_string()]),
+            errors.get("custom.licences")
+        );
+        assert!(errors.contains_key("custom.band"));
+        assert!(errors.contains_key("custom.renewal"));
+    }
+
+    #[test]
+    fn select_field_without_options_should_be_invalid() {
+        let select = field("band", CustomFieldKind::Select);
+        assert!(select.validate().is_err());
+        let range = CustomField {
+            min: Some(10.0),
+            max: Some(1.0),
+            ..field("licences", CustomFieldKind::Number)
+        };
+        assert!(range.validate().is_err());
+        assert!(field("Not A Key", CustomFieldKind::Text)
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn filters_should_cast_values_by_field_type() {
+        let fields = vec![
+            field("licences", CustomFieldKind::Number),
+            field("partner", CustomFieldKind::Boolean),
+        ];
+        let filters: Vec<CustomFilter> = vec![
+            "licences:25".parse().unwrap(),
+            "partner:true".parse().unwrap(),
+        ];
+        let (query, _) = filter_custom(
+            ListQuery::new("Customer", "id", ListConfig::default()),
+            &fields,
+            &filters,
+        )
+        .unwrap()
+        .build();
+        assert!(query.contains(
+            "<float64>json_get(.custom, <str>$p0) = <float64><str>$p1 and <bool>json_get(.custom, <str>$p2) = <bool><str>$p3"
+        ));
+
+        let unknown = filter_custom(
+            ListQuery::new("Customer", "id", ListConfig::default()),
+            &fields,
+            &["industry:Retail".parse().unwrap()],
+        );
+        assert!(matches!(unknown, Err(ApiError::BadRequest(_))));
+        let invalid = filter_custom(
+            ListQuery::new("Customer", "id", ListConfig::default()),
+            &fields,
+            &["licences:many".parse().unwrap()],
+        );
+        assert!(matches!(invalid, Err(ApiError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn custom_field_should_be_created_and_keep_its_key_on_update() {
+        let db = get_db().await;
+        let key = format!(
+            "test_{}",
+            Alphanumeric
+                .sample_string(&mut rand::thread_rng(), 12)
+                .to_lowercase()
+        );
+        let response = create_custom_field(
+            SessionClient(db.clone()),
+            Actor::default(),
+            CurrentOrganization::default(),
+            Json(CustomField {
+                entity: CustomFieldEntity::Opportunity,
+                options: vec!["Small".to_string(), " Large ".to_string()],
+                ..field(&key, CustomFieldKind::Select)
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::CREATED, response.status());
+        let created: CustomField = into_type(response).await;
+
+        let response = update_custom_field(
+            SessionClient(db.clone()),
+            Path(created.id),
+            Actor::default(),
+            CurrentOrganization::default(),
+            Json(CustomField {
+                key: "renamed".to_string(),
+                label: "Deal size".to_string(),
+                is_required: true,
+                ..created.clone()
+            }),
+        )
+        .await
+        .into_response();
+        let updated: CustomField = into_type(response).await;
+        let listed = custom_fields(&db, None, CustomFieldEntity::Opportunity)
+            .await
+            .unwrap();
+        let _ = delete_custom_field(
+            SessionClient(db.clone()),
+            Path(created.id),
+            CurrentOrganization::default(),
+        )
+        .await;
+
+        assert_eq!(vec!["Small", "Large"], created.options);
+        assert_eq!(key, updated.key);
+        assert_eq!("Deal size", updated.label);
+        assert!(updated.is_required);
+        assert!(listed.contains(&updated));
+    }
+
+    #[tokio::test]
+    async fn duplicate_key_should_conflict() {
+        let db = get_db().await;
+        let key = format!(
+            "test_{}",
+            Alphanumeric
+                .sample_string(&mut rand::thread_rng(), 12)
+                .to_lowercase()
+        );
+        let created: CustomField = into_type(
+            create_custom_field(
+                SessionClient(db.clone()),
+                Actor::default(),
+                CurrentOrganization::default(),
+                Json(field(&key, CustomFieldKind::Text)),
+            )
+            .await
+            .into_response(),
+        )
+        .await;
+        let response = create_custom_field(
+            SessionClient(db.clone()),
+            Actor::default(),
+            CurrentOrganization::default(),
+            Json(field(&key, CustomFieldKind::Number)),
+        )
+        .await
+        .into_response();
+        let _ = delete_custom_field(
+            SessionClient(db.clone()),
+            Path(created.id),
+            CurrentOrganization::default(),
+        )
+        .await;
+
+        assert_eq!(StatusCode::CONFLICT, response.status());
+        let error: ErrorResponse = into_type(response).await;
+        assert!(error.fields.contains_key("key"));
+    }
+}
@@ -1,52 +1,398 @@
+use std::time::Duration;
+
 use axum::{
     extract::{self, Path, Query, State},
-    http::StatusCode,
+    handler::Handler,
+    http::{header::ETAG, StatusCode},
     response::{IntoResponse, Response},
-    routing::{get, put},
+    routing::{delete, get, post, put},
     Json, Router,
 };
-use edgedb_protocol::value::Value;
-use edgedb_tokio::Client;
-use frontend::{Customer, CustomerId, CustomersQueryParams, Opportunity, OpportunityId};
+use chrono::{DateTime, NaiveDate, Utc};
+use edgedb_protocol::{model::Datetime, value::Value};
+use edgedb_tokio::Transaction;
+use frontend::{
+    Activity, ActivityId, AssignRequest, AuditAction, AuditEntity, CustomField, CustomFieldEntity,
+    Customer, CustomerDeletion, CustomerId, CustomerSortField, CustomerStatus,
+    CustomersQueryParams, DeletedCustomer, OpportunitiesQueryParams, Opportunity, OpportunityId,
+    OpportunitySortField, OrganizationId, Page, Permission, SortDirection, StageHistory, StageTime,
+    StatusChange, UserId,
+};
+use serde::Deserialize;
+use uuid::Uuid;
 use validator::Validate;
 
-pub fn customer_routes() -> Router<Client> {
+use crate::{
+    actor::{Actor, ActorId},
+    audit::AuditTrail,
+    auth::SessionClient,
+    custom_fields::{checked_custom_values, custom_json, filter_custom, filter_fields},
+    errors::{from_json, ApiError},
+    organizations::CurrentOrganization,
+    pagination::{Cursor, CursorDirection},
+    permissions::{allow, CurrentRole},
+    pipelines::find_stage,
+    query::{id_list, unique, DatetimeField, ListConfig, ListQuery, Op, Param, SortField},
+    transaction::transaction,
+    version::{etag, missing_or_stale, IfMatch},
+    AppState,
+};
+
+const CUSTOMER_SHAPE: &str = "id, name, email, status, created, version := <str>.modified, tags: { id, name, colour } order by .name, owner: { id, name }, custom";
+const CUSTOMER_EXISTS: &str =
+    "select exists (select Customer filter .id = <uuid>$0 and not exists .deleted);";
+const OPPORTUNITY_EXISTS: &str =
+    "select exists (select Opportunity filter .customer.id = <uuid>$0 and .id = <uuid>$1);";
+const OPPORTUNITY_SHAPE: &str = "id, name, pipeline_id := .stage.pipeline.id, status := .stage.name, outcome := <str>.stage.outcome, amount := <str>.amount, currency, close_date, probability, created, version := <str>.modified, tags: { id, name, colour } order by .name, owner: { id, name }, custom";
+
+pub fn customer_routes() -> Router<AppState> {
     Router::new()
-        .route("/customers", get(customers))
-        .route("/customer/:id", get(customer).put(update_customer))
+        .route(
+            "/customers",
+            get(customers).post(create_customer.layer(allow(Permission::Edit))),
+        )
+        .route(
+            "/customers/assign",
+            post(assign_owner.layer(allow(Permission::AssignOwners))),
+        )
+        .route(
+            "/customers/trash",
+            get(trash).delete(purge_trash.layer(allow(Permission::DeleteCustomers))),
+        )
+        .route(
+            "/customers/trash/:id",
+            delete(purge_customer.layer(allow(Permission::DeleteCustomers))),
+        )
+        .route(
+            "/customer/:id",
+            get(customer)
+                // changing the status to NonActive is checked in the handler
+                .put(update_customer.layer(allow(Permission::Edit)))
+                .delete(delete_customer.layer(allow(Permission::DeleteCustomers))),
+        )
+        .route("/customer/:id/deletion", get(deletion_preview))
+        .route(
+            "/customer/:id/restore",
+            post(restore_customer.layer(allow(Permission::DeleteCustomers))),
+        )
         .route(
             "/customer/:id/opportunities",
-            get(opportunities).post(add_opportunity),
+            get(opportunities).post(add_opportunity.layer(allow(Permission::Edit))),
         )
         .route(
             "/customer/:id/opportunity/:oid",
-            put(update_opportunity).delete(delete_opportunity),
+            put(update_opportunity.layer(allow(Permission::Edit)))
+                .delete(delete_opportunity.layer(allow(Permission::DeleteOpportunities))),
+        )
+        .route(
+            "/customer/:id/opportunity/:oid/stage-history",
+            get(stage_history),
+        )
+        .route("/customer/:id/status-changes", get(status_changes))
+        .route(
+            "/customer/:id/activities",
+            get(activities).post(add_activity.layer(allow(Permission::Edit))),
+        )
+        .route(
+            "/customer/:id/activity/:aid",
+            put(update_activity.layer(allow(Permission::Edit)))
+                .delete(delete_activity.layer(allow(Permission::Edit))),
+        )
+}
+
+impl SortField for CustomerSortField {
+    fn path(&self) -> &'static str {
+        match self {
+            CustomerSortField::Name => ".name",
+            CustomerSortField::Email => ".email",
+            CustomerSortField::Status => ".status",
+            CustomerSortField::Created => ".created",
+            // customers without an owner sort as if the owner had an empty name, so the cursor always has a value
+            CustomerSortField::Owner => "(.owner.name ?? '')",
+        }
+    }
+
+    /// Cursor values are cast to the field type so comparisons follow the same order as `order by`
+    fn cast(&self) -> &'static str {
+        match self {
+            CustomerSortField::Name => "str",
+            CustomerSortField::Email => "str",
+            CustomerSortField::Status => "CustomerStatus",
+            CustomerSortField::Created => "datetime",
+            CustomerSortField::Owner => "str",
+        }
+    }
+}
+
+impl SortField for OpportunitySortField {
+    fn path(&self) -> &'static str {
+        match self {
+            OpportunitySortField::Created => ".created",
+            OpportunitySortField::CloseDate => ".close_date",
+        }
+    }
+
+    fn cast(&self) -> &'static str {
+        match self {
+            OpportunitySortField::Created => "datetime",
+            OpportunitySortField::CloseDate => "cal::local_date",
+        }
+    }
+
+    fn optional(&self) -> bool {
+        matches!(self, OpportunitySortField::CloseDate)
+    }
+}
+
+/// Value of the sort field a cursor or seek starts from
+pub fn sort_value(customer: &Customer, field: &CustomerSortField) -> String {
+    match field {
+        CustomerSortField::Name => customer.name.clone(),
+        CustomerSortField::Email => customer.email.clone(),
+        CustomerSortField::Status => customer.status.clone(),
+        CustomerSortField::Created => customer.created.clone(),
+        CustomerSortField::Owner => customer
+            .owner
+            .as_ref()
+            .map(|owner| owner.name.clone())
+            .unwrap_or_default(),
+    }
+}
+
+/// Start of the day in UTC for a `%Y-%m-%d` date
+fn day_start(date: &str) -> Result<Datetime, ApiError> {
+    let invalid = || ApiError::BadRequest(format!("{} is not a valid date", date));
+    let start = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .ok_or_else(invalid)?;
+    Datetime::try_from(DateTime::<Utc>::from_utc(start, Utc)).map_err(|_| invalid())
+}
+
+/// Customers of the organisation outside the trash matching the status, tag, email domain, created date, owner
+/// and custom field filters. `fields` are the customer custom fields the custom filters refer to, `user` is who
+/// "mine" refers to.
+pub fn filtered_customers(
+    shape: &'static str,
+    config: ListConfig,
+    params: &CustomersQueryParams,
+    fields: &[CustomField],
+    user: Option<UserId>,
+    organization: Option<OrganizationId>,
+) -> Result<ListQuery, ApiError> {
+    let mut query = ListQuery::new("Customer", shape, config)
+        .filter_missing(".deleted")
+        .filter_organization(".organization.id", organization)
+        .filter_in(
+            "<str>.status",
+            params.status.iter().map(|s| s.to_string()).collect(),
         )
+        .filter_any_in(
+            "<str>.tags.id",
+            params.tags.iter().map(|t| t.to_string()).collect(),
+        );
+    if let Some(domain) = params
+        .email_domain
+        .as_ref()
+        .map(|d| d.trim_start_matches('@').to_lowercase())
+        .filter(|d| !d.is_empty())
+    {
+        query = query.filter(
+            "str_lower(str_split(.email, '@')[-1])",
+            Op::Eq,
+            Param::Str(domain),
+        );
+    }
+    if let Some(from) = &params.created_from {
+        query = query.filter(".created", Op::Gte, Param::Datetime(day_start(from)?));
+    }
+    if let Some(to) = &params.created_to {
+        let end = day_start(to)? + Duration::from_secs(24 * 60 * 60);
+        query = query.filter(".created", Op::Lt, Param::Datetime(end));
+    }
+    if params.mine {
+        // without a signed in user nothing is theirs
+        query = query.filter(".owner.id", Op::Eq, Param::Uuid(user.unwrap_or_default()));
+    }
+    filter_custom(query, fields, &params.custom)
 }
 
 async fn customers(
-    State(db): State<Client>,
+    SessionClient(db): SessionClient,
+    State(config): State<ListConfig>,
+    ActorId(user): ActorId,
+    CurrentOrganization(organization): CurrentOrganization,
     Query(pagination): extract::Query<CustomersQueryParams>,
-) -> Response {
-    let query = format!(
-        r#"select <json>Customer {{
-            id,
-            name,
-            email,
-            status,
-            created
-        }} order by Customer.{} {} offset {} limit {}"#,
-        &pagination.sort, &pagination.direction, &pagination.offset, &pagination.limit
-    );
+) -> Result<Response, ApiError> {
+    pagination.validate()?;
+    let cursor = match &pagination.cursor {
+        Some(cursor) => Some(
+            Cursor::decode(cursor)
+                .ok_or_else(|| ApiError::BadRequest("The cursor is not valid".to_string()))?,
+        ),
+        None => None,
+    };
+    // pages before the cursor are read in reverse order and flipped back afterwards
+    let backwards = matches!(&cursor, Some(c) if c.direction == CursorDirection::Before);
+    let direction = match backwards {
+        true => pagination.direction.reverse(),
+        false => pagination.direction,
+    };
     tracing::trace!("{:?}", pagination);
-    let result: Vec<Customer> = db
-        .query(query.as_str(), &())
-        .await
-        .expect("Failed to query");
-    (Json(result)).into_response()
+    let fields = filter_fields(
+        &db,
+        organization,
+        CustomFieldEntity::Customer,
+        &pagination.custom,
+    )
+    .await?;
+    let mut query = filtered_customers(
+        CUSTOMER_SHAPE,
+        config,
+        &pagination,
+        &fields,
+        user,
+        organization,
+    )?
+    .order_by(&pagination.sort, direction)
+    .limit(pagination.limit);
+    if let Some(cursor) = &cursor {
+        query = query.seek(cursor.value.clone(), cursor.id);
+    }
+    let limit = query.page_size();
+    let (query, args) = query.build_page();
+    let (total, mut items): (i64, Vec<Customer>) =
+        db.query_required_single(query.as_str(), &args).await?;
+    // the query fetches one extra row to tell whether there is another page in the direction we are reading
+    let has_more = items.len() > limit;
+    items.truncate(limit);
+    if backwards {
+        items.reverse();
+    }
+    let (has_next, has_prev) = match backwards {
+        true => (true, has_more),
+        false => (has_more, cursor.is_some()),
+    };
+    let next_cursor = items
+        .last()
+        .filter(|_| has_next)
+        .map(|c| Cursor::after(sort_value(c, &pagination.sort), c.id).encode());
+    let prev_cursor = items
+        .first()
+        .filter(|_| has_prev)
+        .map(|c| Cursor::before(sort_value(c, &pagination.sort), c.id).encode());
+    Ok((Json(Page {
+        items,
+        total,
+        next_cursor,
+        prev_cursor,
+    }))
+    .into_response())
+}
+
+/// Sales rep of the organisation who went longest without a new lead, or never had one. They are marked as
+/// having had one now so the next lead goes to someone else.
+async fn next_lead_owner(
+    tx: &mut Transaction,
+    organization: Option<OrganizationId>,
+) -> Result<Option<UserId>, edgedb_tokio::Error> {
+    tx.query_single(
+        r#"
+        with rep := (
+            select User
+            filter .role = Role.SalesRep
+                and (not exists <optional uuid>$0 or .organization.id ?= <optional uuid>$0)
+            order by .last_lead_assigned empty first then .created
+            limit 1
+        )
+        select (update rep set { last_lead_assigned := datetime_current() }).id;"#,
+        &(organization,),
+    )
+    .await
+}
+
+/// Inserts the customer into the organisation, or the first organisation when there is none, and records its
+/// creation in the audit log. Custom values must already be checked.
+/// New leads are handed round the organisation's sales reps in turn, other customers belong to `creator`.
+pub async fn insert_customer(
+    tx: &mut Transaction,
+    body: &Customer,
+    actor: &Actor,
+    creator: Option<UserId>,
+    organization: Option<OrganizationId>,
+) -> Result<Customer, edgedb_tokio::Error> {
+    let owner = match body.status == CustomerStatus::Lead.to_string() {
+        true => next_lead_owner(tx, organization).await?.or(creator),
+        false => creator,
+    };
+    let customer: Customer = tx
+        .query_required_single(
+            r#"
+                select <json>(
+                    insert Customer {
+                        name := <str>$0,
+                        email := <str>$1,
+                        status := <str>$2,
+                        created_by := <optional str>$3,
+                        modified_by := <optional str>$3,
+                        custom := to_json(<str>$4),
+                        owner := (select User filter .id = <optional uuid>$5),
+                        organization := (select Organization filter .id = <optional uuid>$6)
+                            ?? assert_exists((select Organization order by .created limit 1)),
+                    })
+                    {
+                        id,
+                        name,
+                        email,
+                        status,
+                        created,
+                        version := <str>.modified,
+                        owner: { id, name },
+                        custom
+                    };"#,
+            &(
+                body.name.clone(),
+                body.email.clone(),
+                body.status.clone(),
+                actor.0.clone(),
+                custom_json(&body.custom),
+                owner,
+                organization,
+            ),
+        )
+        .await?;
+    AuditTrail::new(AuditEntity::Customer, customer.id, customer.id, actor)
+        .finish(tx, AuditAction::Created)
+        .await?;
+    Ok(customer)
+}
+
+async fn create_customer(
+    SessionClient(db): SessionClient,
+    actor: Actor,
+    ActorId(creator): ActorId,
+    CurrentOrganization(organization): CurrentOrganization,
+    Json(body): extract::Json<Customer>,
+) -> Result<Response, ApiError> {
+    body.validate()?;
+    let body = Customer {
+        custom: checked_custom_values(&db, organization, CustomFieldEntity::Customer, &body.custom)
+            .await?,
+        ..body
+    };
+    let result: Customer = transaction(db, move |mut tx| {
+        let body = body.clone();
+        let actor = actor.clone();
+        async move { insert_customer(&mut tx, &body, &actor, creator, organization).await }
+    })
+    .await?;
+    Ok((StatusCode::CREATED, Json(result)).into_response())
 }
 
-async fn customer(State(db): State<Client>, Path(id): extract::Path<CustomerId>) -> Response {
+async fn customer(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<CustomerId>,
+) -> Result<Response, ApiError> {
     let result: Customer = db
         .query_required_single(
             r#"
@@ -55,139 +401,877 @@ async fn customer(State(db): State<Client>, Path(id): extract::Path<CustomerId>)
                 name,
                 email,
                 status,
-                created
-            } filter Customer.id = <uuid>$0 limit 1"#,
+                created,
+                version := <str>.modified,
+                tags: { id, name, colour } order by .name,
+                owner: { id, name },
+                custom
+            } filter Customer.id = <uuid>$0 and not exists Customer.deleted limit 1"#,
             &(id,),
         )
-        .await
-        .expect("Failed to query");
-    (Json(result)).into_response()
+        .await?;
+    Ok(([(ETAG, etag(&result.version))], Json(result)).into_response())
 }
 
+/// Updates the customer, when `If-Match` is sent the customer must still have that version
 async fn update_customer(
-    State(db): State<Client>,
+    SessionClient(db): SessionClient,
     Path(id): extract::Path<CustomerId>,
+    actor: Actor,
+    role: CurrentRole,
+    CurrentOrganization(organization): CurrentOrganization,
+    IfMatch(version): IfMatch,
     Json(body): extract::Json<Customer>,
-) -> Response {
+) -> Result<Response, ApiError> {
     if body.id.ne(&id) {
-        return (StatusCode::BAD_REQUEST).into_response();
+        return Err(ApiError::BadRequest(
+            "The customer id does not match the url".to_string(),
+        ));
+    }
+    body.validate()?;
+    if body.status == CustomerStatus::NonActive.to_string() {
+        let status: Option<String> = db
+            .query_single(
+                "select <str>(select Customer filter .id = <uuid>$0).status;",
+                &(id,),
+            )
+            .await?;
+        if status != Some(body.status.clone()) {
+            role.require(Permission::DeactivateCustomers)?;
+        }
     }
-    match body.validate() {
-        Err(_) => (StatusCode::BAD_REQUEST).into_response(),
-        Ok(_) => {
-            let _: Value = db
-                .query_required_single(
+    let custom = custom_json(
+        &checked_custom_values(&db, organization, CustomFieldEntity::Customer, &body.custom)
+            .await?,
+    );
+    let result: Customer = transaction(db, move |mut tx| {
+        let body = body.clone();
+        let actor = actor.clone();
+        let version = version.clone();
+        let custom = custom.clone();
+        async move {
+            let trail =
+                AuditTrail::start(&mut tx, AuditEntity::Customer, id, id, &actor).await?;
+            let customer: Option<Customer> = tx
+                .query_single(
                     r#"
                     select <json>(
-                        update Customer filter Customer.id = <uuid>$0
+                        update Customer filter Customer.id = <uuid>$0 and not exists Customer.deleted
+                            and <str>.modified = (<optional str>$5 ?? <str>.modified)
                         set{
-                            status := <str>$1,
+                            name := <str>$1,
+                            email := <str>$2,
+                            status := <str>$3,
+                            custom := to_json(<str>$6),
+                            modified := datetime_current(),
+                            modified_by := <optional str>$4,
                         }) 
                         {
                             id,
                             name,
                             email,
                             status,
-                            created
+                            created,
+                            version := <str>.modified
                         };"#,
-                    &(body.id, body.status),
+                    &(
+                        body.id,
+                        body.name,
+                        body.email,
+                        body.status,
+                        actor.0.clone(),
+                        version,
+                        custom,
+                    ),
                 )
-                .await
-                .expect("Failed to update");
-            (StatusCode::OK).into_response()
+                .await?;
+            let Some(customer) = customer else {
+                return Ok(Err(missing_or_stale(&mut tx, CUSTOMER_EXISTS, &(id,)).await?));
+            };
+            trail.finish(&mut tx, AuditAction::Updated).await?;
+            Ok(Ok(customer))
         }
-    }
+    })
+    .await??;
+    Ok((StatusCode::OK, [(ETAG, etag(&result.version))]).into_response())
+}
+
+/// Reports what will be removed along with the customer
+async fn deletion_preview(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<CustomerId>,
+) -> Result<Response, ApiError> {
+    let result: Option<CustomerDeletion> = db
+        .query_single(
+            r#"
+            select <json>Customer {
+                id,
+                opportunity_count := count(Customer.opportunities)
+            } filter Customer.id = <uuid>$0 and not exists Customer.deleted limit 1"#,
+            &(id,),
+        )
+        .await?;
+    let deletion = result.ok_or(ApiError::NotFound)?;
+    Ok((Json(deletion)).into_response())
+}
+
+/// Soft deletes the customer by moving it to the trash, opportunities are kept until it is purged
+async fn delete_customer(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<CustomerId>,
+    actor: Actor,
+    IfMatch(version): IfMatch,
+) -> Result<Response, ApiError> {
+    let deletion: CustomerDeletion = transaction(db, move |mut tx| {
+        let actor = actor.clone();
+        let version = version.clone();
+        async move {
+            let trail =
+                AuditTrail::start(&mut tx, AuditEntity::Customer, id, id, &actor).await?;
+            let deletion: Option<CustomerDeletion> = tx
+                .query_single(
+                    r#"
+                    select <json>(
+                        update Customer filter Customer.id = <uuid>$0 and not exists Customer.deleted
+                            and <str>.modified = (<optional str>$2 ?? <str>.modified)
+                        set {
+                            deleted := datetime_current(),
+                            modified := datetime_current(),
+                            modified_by := <optional str>$1,
+                        })
+                        {
+                            id,
+                            opportunity_count := count(Customer.opportunities)
+                        };"#,
+                    &(id, actor.0.clone(), version),
+                )
+                .await?;
+            let Some(deletion) = deletion else {
+                return Ok(Err(missing_or_stale(&mut tx, CUSTOMER_EXISTS, &(id,)).await?));
+            };
+            trail.finish(&mut tx, AuditAction::Deleted).await?;
+            Ok(Ok(deletion))
+        }
+    })
+    .await??;
+    Ok((Json(deletion)).into_response())
+}
+
+async fn restore_customer(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<CustomerId>,
+    actor: Actor,
+) -> Result<Response, ApiError> {
+    let result: Option<Customer> = transaction(db, move |mut tx| {
+        let actor = actor.clone();
+        async move {
+            let trail = AuditTrail::start(&mut tx, AuditEntity::Customer, id, id, &actor).await?;
+            let customer: Option<Customer> = tx
+                .query_single(
+                    r#"
+                    select <json>(
+                        update Customer filter Customer.id = <uuid>$0 and exists Customer.deleted
+                        set {
+                            deleted := {},
+                            modified := datetime_current(),
+                            modified_by := <optional str>$1,
+                        })
+                        {
+                            id,
+                            name,
+                            email,
+                            status,
+                            created,
+                            version := <str>.modified,
+                            tags: { id, name, colour } order by .name,
+                            custom
+                        };"#,
+                    &(id, actor.0.clone()),
+                )
+                .await?;
+            if customer.is_some() {
+                trail.finish(&mut tx, AuditAction::Restored).await?;
+            }
+            Ok(customer)
+        }
+    })
+    .await?;
+    let customer = result.ok_or(ApiError::NotFound)?;
+    Ok((Json(customer)).into_response())
+}
+
+async fn trash(
+    SessionClient(db): SessionClient,
+    State(config): State<ListConfig>,
+    CurrentOrganization(organization): CurrentOrganization,
+) -> Result<Response, ApiError> {
+    let (query, args) = ListQuery::new(
+        "Customer",
+        "id, name, email, deleted, opportunity_count := count(.opportunities)",
+        config,
+    )
+    .filter_exists(".deleted")
+    .filter_organization(".organization.id", organization)
+    .order_by(&DatetimeField(".deleted"), SortDirection::Desc)
+    .build();
+    let result: Vec<DeletedCustomer> = db.query(query.as_str(), &args).await?;
+    Ok((Json(result)).into_response())
+}
+
+/// Permanently deletes a trashed customer along with its opportunities
+async fn purge_customer(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<CustomerId>,
+    actor: Actor,
+) -> Result<Response, ApiError> {
+    let result: Option<CustomerDeletion> = transaction(db, move |mut tx| {
+        let actor = actor.clone();
+        async move {
+            let trail = AuditTrail::start(&mut tx, AuditEntity::Customer, id, id, &actor).await?;
+            let deletion: Option<CustomerDeletion> = tx
+                .query_single(
+                    r#"
+                    select <json>(
+                        delete Customer filter Customer.id = <uuid>$0 and exists Customer.deleted
+                    ) {
+                        id,
+                        opportunity_count := count(Customer.opportunities)
+                    };"#,
+                    &(id,),
+                )
+                .await?;
+            if deletion.is_some() {
+                trail.finish(&mut tx, AuditAction::Purged).await?;
+            }
+            Ok(deletion)
+        }
+    })
+    .await?;
+    let deletion = result.ok_or(ApiError::NotFound)?;
+    Ok((Json(deletion)).into_response())
+}
+
+/// Permanently deletes everything in the organisation's trash
+async fn purge_trash(
+    SessionClient(db): SessionClient,
+    actor: Actor,
+    CurrentOrganization(organization): CurrentOrganization,
+) -> Result<Response, ApiError> {
+    let result: Vec<CustomerDeletion> = transaction(db, move |mut tx| {
+        let actor = actor.clone();
+        async move {
+            let ids: Vec<CustomerId> = tx
+                .query(
+                    r#"
+                    select (
+                        select Customer filter exists .deleted
+                            and (not exists <optional uuid>$0 or .organization.id ?= <optional uuid>$0)
+                    ).id;"#,
+                    &(organization,),
+                )
+                .await?;
+            let mut trails = vec![];
+            for id in ids.iter() {
+                trails.push(
+                    AuditTrail::start(&mut tx, AuditEntity::Customer, *id, *id, &actor).await?,
+                );
+            }
+            let deletions: Vec<CustomerDeletion> = tx
+                .query(
+                    r#"
+                        select <json>(
+                            delete Customer filter .id in <uuid><str>json_array_unpack(to_json(<str>$0))
+                        ) {
+                            id,
+                            opportunity_count := count(Customer.opportunities)
+                        };"#,
+                    &(id_list(&ids),),
+                )
+                .await?;
+            for trail in trails {
+                trail.finish(&mut tx, AuditAction::Purged).await?;
+            }
+            Ok(deletions)
+        }
+    })
+    .await?;
+    Ok((Json(result)).into_response())
 }
 
+/// Adds an opportunity to the customer, it belongs to the customer's owner or to whoever adds it when the
+/// customer has none
 async fn add_opportunity(
-    State(db): State<Client>,
+    SessionClient(db): SessionClient,
     Path(id): extract::Path<CustomerId>,
+    actor: Actor,
+    ActorId(creator): ActorId,
+    CurrentOrganization(organization): CurrentOrganization,
     Json(body): extract::Json<Opportunity>,
-) -> Response {
-    match body.validate() {
-        Ok(_) => {
-            let _: Value = db
-                .query_required_single(
+) -> Result<Response, ApiError> {
+    body.validate()?;
+    let stage = find_stage(&db, organization, body.pipeline_id, &body.status).await?;
+    let custom = custom_json(
+        &checked_custom_values(
+            &db,
+            organization,
+            CustomFieldEntity::Opportunity,
+            &body.custom,
+        )
+        .await?,
+    );
+    let result: Option<OpportunityId> = transaction(db, move |mut tx| {
+        let body = body.clone();
+        let actor = actor.clone();
+        let custom = custom.clone();
+        async move {
+            let oid: Option<OpportunityId> = tx
+                .query_single(
                     r#"
-                    update Customer filter Customer.id = <uuid>$0
-                    set {
-                        opportunities += (insert Opportunity { name := <str>$1, status := <str>$2})
-                    };"#,
-                    &(id, body.name, body.status),
+                    with
+                        customer := (select Customer filter .id = <uuid>$0 and not exists .deleted),
+                        opportunity := (for c in customer union (
+                            insert Opportunity {
+                                name := <str>$1,
+                                stage := (select Stage filter .id = <uuid>$2),
+                                amount := <decimal><optional str>$3,
+                                currency := <str>$4,
+                                close_date := <cal::local_date><optional str>$5,
+                                probability := <int16>$6,
+                                custom := to_json(<str>$8),
+                                owner := c.owner ?? (select User filter .id = <optional uuid>$9),
+                                organization := c.organization,
+                                created_by := <optional str>$7,
+                                modified_by := <optional str>$7,
+                                status_changes := (insert StatusChange {
+                                    stage := (select Stage filter .id = <uuid>$2),
+                                    changed_by := <optional str>$7,
+                                    created_by := <optional str>$7,
+                                    modified_by := <optional str>$7,
+                                })
+                            }
+                        )),
+                        linked := (update customer set { opportunities += opportunity })
+                    select opportunity.id;"#,
+                    &(
+                        id,
+                        body.name,
+                        stage,
+                        body.amount,
+                        body.currency,
+                        body.close_date,
+                        body.probability,
+                        actor.0.clone(),
+                        custom,
+                        creator,
+                    ),
                 )
-                .await
-                .expect("Failed to add");
-            (StatusCode::OK).into_response()
+                .await?;
+            if let Some(oid) = oid {
+                AuditTrail::new(AuditEntity::Opportunity, oid, id, &actor)
+                    .finish(&mut tx, AuditAction::Created)
+                    .await?;
+            }
+            Ok(oid)
         }
-        Err(_) => (StatusCode::BAD_REQUEST).into_response(),
-    }
+    })
+    .await?;
+    result.ok_or(ApiError::NotFound)?;
+    Ok((StatusCode::OK).into_response())
 }
 
+/// Updates the opportunity, moving it to another stage records the change along with the previous stage.
+/// When `If-Match` is sent the opportunity must still have that version.
 async fn update_opportunity(
-    State(db): State<Client>,
+    SessionClient(db): SessionClient,
     Path((id, oid)): extract::Path<(CustomerId, OpportunityId)>,
+    actor: Actor,
+    CurrentOrganization(organization): CurrentOrganization,
+    IfMatch(version): IfMatch,
     Json(body): extract::Json<Opportunity>,
-) -> Response {
+) -> Result<Response, ApiError> {
     if body.id.ne(&oid) {
-        return (StatusCode::BAD_REQUEST).into_response();
+        return Err(ApiError::BadRequest(
+            "The opportunity id does not match the url".to_string(),
+        ));
     }
-    match body.validate() {
-        Ok(_) => {
-            let _: Value = db
-                .query_required_single(
+    body.validate()?;
+    let stage = find_stage(&db, organization, body.pipeline_id, &body.status).await?;
+    let custom = custom_json(
+        &checked_custom_values(
+            &db,
+            organization,
+            CustomFieldEntity::Opportunity,
+            &body.custom,
+        )
+        .await?,
+    );
+    transaction(db, move |mut tx| {
+        let body = body.clone();
+        let actor = actor.clone();
+        let version = version.clone();
+        let custom = custom.clone();
+        async move {
+            let trail =
+                AuditTrail::start(&mut tx, AuditEntity::Opportunity, oid, id, &actor).await?;
+            let updated: Option<Value> = tx
+                .query_single(
                     r#"
                     update Opportunity filter Opportunity.customer.id = <uuid>$0 and Opportunity.id = <uuid>$1
+                        and <str>.modified = (<optional str>$9 ?? <str>.modified)
                     set {
                         name := <str>$2,
-                        status := <str>$3
+                        stage := (select Stage filter .id = <uuid>$3),
+                        amount := <decimal><optional str>$4,
+                        currency := <str>$5,
+                        close_date := <cal::local_date><optional str>$6,
+                        probability := <int16>$7,
+                        custom := to_json(<str>$10),
+                        modified := datetime_current(),
+                        modified_by := <optional str>$8,
+                        status_changes += (
+                            for new_stage in (select Stage filter .id = <uuid>$3 and .id != Opportunity.stage.id)
+                            union (insert StatusChange {
+                                stage := new_stage,
+                                previous_stage := Opportunity.stage,
+                                changed_by := <optional str>$8,
+                                created_by := <optional str>$8,
+                                modified_by := <optional str>$8,
+                            })
+                        )
                     };"#,
-                    &(id, body.id, body.name, body.status),
+                    &(
+                        id,
+                        body.id,
+                        body.name,
+                        stage,
+                        body.amount,
+                        body.currency,
+                        body.close_date,
+                        body.probability,
+                        actor.0.clone(),
+                        version,
+                        custom,
+                    ),
                 )
-                .await
-                .expect("Failed to update");
-            (StatusCode::OK).into_response()
+                .await?;
+            if updated.is_none() {
+                return Ok(Err(
+                    missing_or_stale(&mut tx, OPPORTUNITY_EXISTS, &(id, oid)).await?
+                ));
+            }
+            trail.finish(&mut tx, AuditAction::Updated).await?;
+            Ok(Ok(()))
         }
-        Err(_) => (StatusCode::BAD_REQUEST).into_response(),
-    }
+    })
+    .await??;
+    Ok((StatusCode::OK).into_response())
+}
+
+/// Opportunities of the customer, newest first unless another order is requested
+async fn opportunities(
+    SessionClient(db): SessionClient,
+    State(config): State<ListConfig>,
+    Path(id): extract::Path<CustomerId>,
+    Query(params): extract::Query<OpportunitiesQueryParams>,
+) -> Result<Response, ApiError> {
+    let (query, args) = ListQuery::new("Opportunity", OPPORTUNITY_SHAPE, config)
+        .filter(".customer.id", Op::Eq, Param::Uuid(id))
+        .order_by(&params.sort, params.direction)
+        .build();
+    let result: Vec<Opportunity> = db.query(query.as_str(), &args).await?;
+    Ok((Json(result)).into_response())
+}
+
+async fn delete_opportunity(
+    SessionClient(db): SessionClient,
+    Path((id, oid)): extract::Path<(CustomerId, OpportunityId)>,
+    actor: Actor,
+    IfMatch(version): IfMatch,
+) -> Result<Response, ApiError> {
+    transaction(db, move |mut tx| {
+        let actor = actor.clone();
+        let version = version.clone();
+        async move {
+            let trail =
+                AuditTrail::start(&mut tx, AuditEntity::Opportunity, oid, id, &actor).await?;
+            let deleted: Option<Value> = tx
+                .query_single(
+                    r#"
+                    delete Opportunity filter Opportunity.customer.id = <uuid>$0 and Opportunity.id = <uuid>$1
+                        and <str>.modified = (<optional str>$2 ?? <str>.modified)"#,
+                    &(id, oid, version),
+                )
+                .await?;
+            if deleted.is_none() {
+                return Ok(Err(
+                    missing_or_stale(&mut tx, OPPORTUNITY_EXISTS, &(id, oid)).await?
+                ));
+            }
+            trail.finish(&mut tx, AuditAction::Deleted).await?;
+            Ok(Ok(()))
+        }
+    })
+    .await??;
+    Ok((StatusCode::OK).into_response())
+}
+
+/// Customer or opportunity changing hands, along with the customer its audit events belong to
+#[derive(Debug, Deserialize)]
+struct Assigned {
+    id: Uuid,
+    customer_id: Uuid,
 }
 
-async fn opportunities(State(db): State<Client>, Path(id): extract::Path<CustomerId>) -> Response {
-    let result: Vec<Opportunity> = db
-        .query(
+/// Changes the owner of one record, records that already belong to the owner are left alone so their
+/// version does not change
+fn assign_query(object: &str) -> String {
+    format!(
+        r#"
+        with owner := (select User filter .id = <optional uuid>$1)
+        select (
+            update {object} filter .id = <uuid>$0 and not (.owner.id ?= owner.id)
+            set {{
+                owner := owner,
+                modified := datetime_current(),
+                modified_by := <optional str>$2,
+            }}
+        ).id;"#
+    )
+}
+
+/// Gives every record in the request to the owner, recording each change in the audit log.
+/// Returns the error to report when the owner or a record can not be found in the organisation, before
+/// anything is changed.
+async fn assign(
+    tx: &mut Transaction,
+    body: &AssignRequest,
+    actor: &Actor,
+    organization: Option<OrganizationId>,
+) -> Result<Result<(), ApiError>, edgedb_tokio::Error> {
+    let owner_exists: bool = tx
+        .query_required_single(
             r#"
-            select <json>Opportunity {
+            select not exists <optional uuid>$0 or exists (
+                select User filter .id = <optional uuid>$0
+                    and (not exists <optional uuid>$1 or .organization.id ?= <optional uuid>$1)
+            );"#,
+            &(body.owner, organization),
+        )
+        .await?;
+    let customers = unique(&body.customers);
+    let customer_count: i64 = tx
+        .query_required_single(
+            r#"
+            select count(
+                Customer filter .id in <uuid><str>json_array_unpack(to_json(<str>$0)) and not exists .deleted
+                    and (not exists <optional uuid>$1 or .organization.id ?= <optional uuid>$1)
+            );"#,
+            &(id_list(&customers), organization),
+        )
+        .await?;
+    let json = tx
+        .query_json(
+            r#"
+            select Opportunity {
                 id,
-                name,
-                status,
-                created
-            } filter Opportunity.customer.id = <uuid>$0
-            order by Opportunity.created desc"#,
-            &(id,),
+                customer_id := assert_single(.customer.id)
+            } filter .id in <uuid><str>json_array_unpack(to_json(<str>$0))
+                and exists .customer and not exists .customer.deleted
+                and (not exists <optional uuid>$1 or .organization.id ?= <optional uuid>$1);"#,
+            &(id_list(&unique(&body.opportunities)), organization),
         )
-        .await
-        .expect("Failed to query");
-    (Json(result)).into_response()
+        .await?;
+    let opportunities: Vec<Assigned> = from_json(&json)?;
+    if !owner_exists
+        || customer_count as usize != customers.len()
+        || opportunities.len() != unique(&body.opportunities).len()
+    {
+        return Ok(Err(ApiError::NotFound));
+    }
+
+    let customer_query = assign_query("Customer");
+    let opportunity_query = assign_query("Opportunity");
+    let records = customers
+        .into_iter()
+        .map(|id| {
+            let customer = Assigned {
+                id,
+                customer_id: id,
+            };
+            (AuditEntity::Customer, &customer_query, customer)
+        })
+        .chain(
+            opportunities
+                .into_iter()
+                .map(|opportunity| (AuditEntity::Opportunity, &opportunity_query, opportunity)),
+        );
+    for (entity, query, record) in records {
+        let trail = AuditTrail::start(tx, entity, record.id, record.customer_id, actor).await?;
+        let _: Vec<Uuid> = tx
+            .query(query.as_str(), &(record.id, body.owner, actor.0.clone()))
+            .await?;
+        trail.finish(tx, AuditAction::Updated).await?;
+    }
+    Ok(Ok(()))
 }
 
-async fn delete_opportunity(
-    State(db): State<Client>,
+/// Gives a selection of customers and opportunities to another user in one transaction
+async fn assign_owner(
+    SessionClient(db): SessionClient,
+    actor: Actor,
+    CurrentOrganization(organization): CurrentOrganization,
+    Json(body): extract::Json<AssignRequest>,
+) -> Result<Response, ApiError> {
+    body.validate()?;
+    transaction(db, move |mut tx| {
+        let body = body.clone();
+        let actor = actor.clone();
+        async move { assign(&mut tx, &body, &actor, organization).await }
+    })
+    .await??;
+    Ok((StatusCode::OK).into_response())
+}
+
+/// Seconds spent in each stage in the order the stages were first reached.
+/// Time in the current stage runs up to `now` unless the stage is won or lost.
+fn time_in_stage(changes: &[StatusChange], now: DateTime<Utc>) -> Vec<StageTime> {
+    let started = |change: &StatusChange| {
+        DateTime::parse_from_rfc3339(&change.created)
+            .map(|date| date.with_timezone(&Utc))
+            .ok()
+    };
+    let mut times: Vec<StageTime> = vec![];
+    for (index, change) in changes.iter().enumerate() {
+        let end = match changes.get(index + 1) {
+            Some(next) => started(next),
+            None if matches!(change.outcome.as_deref(), Some("Won") | Some("Lost")) => {
+                started(change)
+            }
+            None => Some(now),
+        };
+        let seconds = match (started(change), end) {
+            (Some(start), Some(end)) => (end - start).num_seconds().max(0),
+            _ => 0,
+        };
+        match times.iter_mut().find(|time| time.status == change.status) {
+            Some(time) => time.seconds += seconds,
+            None => times.push(StageTime {
+                status: change.status.clone(),
+                seconds,
+            }),
+        }
+    }
+    times
+}
+
+/// Every stage the opportunity has been in, oldest first, with the time spent in each
+async fn stage_history(
+    SessionClient(db): SessionClient,
     Path((id, oid)): extract::Path<(CustomerId, OpportunityId)>,
-) -> Response {
-    let _: Value = db
-        .query_required_single(
+) -> Result<Response, ApiError> {
+    let result: Option<StageHistory> = db
+        .query_single(
             r#"
-            delete Opportunity filter Opportunity.customer.id = <uuid>$0 and Opportunity.id = <uuid>$1"#,
-            &(id,oid),
+            select <json>Opportunity {
+                opportunity_id := .id,
+                changes := (
+                    select .status_changes {
+                        id,
+                        opportunity_id := .opportunity.id,
+                        opportunity_name := .opportunity.name,
+                        status := .stage.name,
+                        previous_status := .previous_stage.name,
+                        outcome := <str>.stage.outcome,
+                        changed_by,
+                        created
+                    } order by .created
+                )
+            } filter .customer.id = <uuid>$0 and .id = <uuid>$1;"#,
+            &(id, oid),
         )
-        .await
-        .expect("Failed to delete");
-    (StatusCode::OK).into_response()
+        .await?;
+    let mut history = result.ok_or(ApiError::NotFound)?;
+    history.time_in_stage = time_in_stage(&history.changes, Utc::now());
+    Ok((Json(history)).into_response())
+}
+
+/// Status changes of all the customer's opportunities, newest first
+async fn status_changes(
+    SessionClient(db): SessionClient,
+    State(config): State<ListConfig>,
+    Path(id): extract::Path<CustomerId>,
+) -> Result<Response, ApiError> {
+    let (query, args) = ListQuery::new(
+        "StatusChange",
+        "id, opportunity_id := .opportunity.id, opportunity_name := .opportunity.name, status := .stage.name, previous_status := .previous_stage.name, outcome := <str>.stage.outcome, changed_by, created",
+        config,
+    )
+    .filter(".opportunity.customer.id", Op::Eq, Param::Uuid(id))
+    .order_by(&DatetimeField(".created"), SortDirection::Desc)
+    .build();
+    let result: Vec<StatusChange> = db.query(query.as_str(), &args).await?;
+    Ok((Json(result)).into_response())
+}
+
+async fn activities(
+    SessionClient(db): SessionClient,
+    State(config): State<ListConfig>,
+    Path(id): extract::Path<CustomerId>,
+) -> Result<Response, ApiError> {
+    let (query, args) = ListQuery::new(
+        "Activity",
+        "id, kind, body, opportunity_id := .opportunity.id, opportunity_name := .opportunity.name, created",
+        config,
+    )
+    .filter(".customer.id", Op::Eq, Param::Uuid(id))
+    .order_by(&DatetimeField(".created"), SortDirection::Desc)
+    .build();
+    let result: Vec<Activity> = db.query(query.as_str(), &args).await?;
+    Ok((Json(result)).into_response())
+}
+
+/// Records an activity, the opportunity is only linked when it belongs to the customer
+async fn add_activity(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<CustomerId>,
+    actor: Actor,
+    Json(body): extract::Json<Activity>,
+) -> Result<Response, ApiError> {
+    body.validate()?;
+    let result: Option<Activity> = transaction(db, move |mut tx| {
+        let body = body.clone();
+        let actor = actor.clone();
+        async move {
+            let activity: Option<Activity> = tx
+                .query_single(
+                    r#"
+                    with
+                        customer := (select Customer filter .id = <uuid>$0 and not exists .deleted),
+                        activity := (for c in customer union (
+                            insert Activity {
+                                kind := <str>$1,
+                                body := <str>$2,
+                                opportunity := (select c.opportunities filter .id = <optional uuid>$3),
+                                created_by := <optional str>$4,
+                                modified_by := <optional str>$4,
+                            }
+                        )),
+                        linked := (update customer set { activities += activity })
+                    select <json>activity {
+                        id,
+                        kind,
+                        body,
+                        opportunity_id := .opportunity.id,
+                        opportunity_name := .opportunity.name,
+                        created
+                    };"#,
+                    &(id, body.kind, body.body, body.opportunity_id, actor.0.clone()),
+                )
+                .await?;
+            if let Some(activity) = &activity {
+                AuditTrail::new(AuditEntity::Activity, activity.id, id, &actor)
+                    .finish(&mut tx, AuditAction::Created)
+                    .await?;
+            }
+            Ok(activity)
+        }
+    })
+    .await?;
+    let activity = result.ok_or(ApiError::NotFound)?;
+    Ok((StatusCode::CREATED, Json(activity)).into_response())
+}
+
+async fn update_activity(
+    SessionClient(db): SessionClient,
+    Path((id, aid)): extract::Path<(CustomerId, ActivityId)>,
+    actor: Actor,
+    Json(body): extract::Json<Activity>,
+) -> Result<Response, ApiError> {
+    if body.id.ne(&aid) {
+        return Err(ApiError::BadRequest(
+            "The activity id does not match the url".to_string(),
+        ));
+    }
+    body.validate()?;
+    let result: Option<Activity> = transaction(db, move |mut tx| {
+        let body = body.clone();
+        let actor = actor.clone();
+        async move {
+            let trail =
+                AuditTrail::start(&mut tx, AuditEntity::Activity, aid, id, &actor).await?;
+            let activity: Option<Activity> = tx
+                .query_single(
+                    r#"
+                    with customer := (select Customer filter .id = <uuid>$0 and not exists .deleted)
+                    select <json>(
+                        update customer.activities filter .id = <uuid>$1
+                        set {
+                            kind := <str>$2,
+                            body := <str>$3,
+                            opportunity := (select customer.opportunities filter .id = <optional uuid>$4),
+                            modified := datetime_current(),
+                            modified_by := <optional str>$5,
+                        }
+                    ) {
+                        id,
+                        kind,
+                        body,
+                        opportunity_id := .opportunity.id,
+                        opportunity_name := .opportunity.name,
+                        created
+                    };"#,
+                    &(
+                        id,
+                        body.id,
+                        body.kind,
+                        body.body,
+                        body.opportunity_id,
+                        actor.0.clone(),
+                    ),
+                )
+                .await?;
+            if activity.is_some() {
+                trail.finish(&mut tx, AuditAction::Updated).await?;
+            }
+            Ok(activity)
+        }
+    })
+    .await?;
+    let activity = result.ok_or(ApiError::NotFound)?;
+    Ok((Json(activity)).into_response())
+}
+
+async fn delete_activity(
+    SessionClient(db): SessionClient,
+    Path((id, aid)): extract::Path<(CustomerId, ActivityId)>,
+    actor: Actor,
+) -> Result<Response, ApiError> {
+    let result: Option<Value> = transaction(db, move |mut tx| {
+        let actor = actor.clone();
+        async move {
+            let trail = AuditTrail::start(&mut tx, AuditEntity::Activity, aid, id, &actor).await?;
+            let deleted: Option<Value> = tx
+                .query_single(
+                    r#"
+                        delete Activity filter .customer.id = <uuid>$0 and .id = <uuid>$1"#,
+                    &(id, aid),
+                )
+                .await?;
+            if deleted.is_some() {
+                trail.finish(&mut tx, AuditAction::Deleted).await?;
+            }
+            Ok(deleted)
+        }
+    })
+    .await?;
+    result.ok_or(ApiError::NotFound)?;
+    Ok((StatusCode::OK).into_response())
 }
 
 #[cfg(test)]
 mod tests {
-    use edgedb_tokio::Error;
-    use frontend::{CustomerSortField, SortDirection};
+    use edgedb_tokio::{Client, Error};
+    use frontend::{AuditEvent, ErrorResponse, FieldChange, Role};
     use rand::distributions::{Alphanumeric, DistString};
     use serde::de::DeserializeOwned;
 
@@ -242,91 +1326,1017 @@ mod tests {
     async fn customers_should_return_result() {
         let db = get_db().await;
         let result = customers(
-            State(db),
+            SessionClient(db),
+            State(ListConfig::default()),
+            ActorId::default(),
+            CurrentOrganization::default(),
             Query(CustomersQueryParams {
                 sort: CustomerSortField::Created,
                 direction: SortDirection::Desc,
-                offset: 0,
                 limit: 2,
+                ..Default::default()
             }),
         )
-        .await;
+        .await
+        .into_response();
         assert_eq!(result.status(), StatusCode::OK);
     }
 
     #[tokio::test]
-    async fn add_and_remove_valid_customer_should_succeed() {
+    async fn customers_should_filter_by_status_and_domain() {
         let db = get_db().await;
         let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
-        let customer = add_customer(
+        let email_domain = format!("{}.filter.test.com", random_string.to_lowercase());
+        let added_customer = add_customer(
             &db,
             Customer {
                 name: format!("Test {}", random_string),
-                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
-                status: "Active".to_string(),
+                email: format!("{}@{}", random_string, email_domain),
+                status: "Lead".to_string(),
                 ..Default::default()
             },
         )
         .await
         .expect("Failed to add");
-
-        let remove = remove_customer(&db, customer.id).await;
-        assert_eq!(true, remove.is_ok());
-    }
-
-    #[tokio::test]
-    async fn update_valid_customer_should_succeed() {
-        let db = get_db().await;
-        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
-        let added_customer = add_customer(
-            &db,
-            Customer {
-                name: format!("Test {}", random_string),
-                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
-                status: "Active".to_string(),
+        let leads = customers(
+            SessionClient(db.clone()),
+            State(ListConfig::default()),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Query(CustomersQueryParams {
+                status: vec![CustomerStatus::Lead, CustomerStatus::NonActive],
+                email_domain: Some(email_domain.clone()),
+                created_from: Some("2000-01-01".to_string()),
                 ..Default::default()
-            },
+            }),
         )
         .await
-        .expect("Failed to add");
-        let update_result = update_customer(
-            State(db.clone()),
-            Path(added_customer.id),
-            Json(Customer {
-                id: added_customer.id,
-                status: "Lead".to_string(),
-                ..added_customer
+        .into_response();
+        let active = customers(
+            SessionClient(db.clone()),
+            State(ListConfig::default()),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Query(CustomersQueryParams {
+                status: vec![CustomerStatus::Active],
+                email_domain: Some(email_domain.clone()),
+                ..Default::default()
             }),
         )
-        .await;
-        let updated_customer = customer(State(db.clone()), Path(added_customer.id)).await;
+        .await
+        .into_response();
         let _ = remove_customer(&db, added_customer.id).await;
-        let updated_customer = into_type::<Customer>(updated_customer).await;
+        let leads = into_type::<Page<Customer>>(leads).await;
+        let active = into_type::<Page<Customer>>(active).await;
 
-        assert_eq!(StatusCode::OK, update_result.status());
-        assert_eq!("Lead".to_string(), updated_customer.status);
+        assert_eq!(1, leads.total);
+        assert_eq!(added_customer.id, leads.items.first().unwrap().id);
+        assert_eq!(0, active.items.len());
     }
 
     #[tokio::test]
-    async fn add_invalid_customer_should_fail() {
+    async fn customers_should_page_with_cursors() {
         let db = get_db().await;
         let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
-        let customer = add_customer(
-            &db,
-            Customer {
-                name: format!("Test {}", random_string),
+        let email_domain = format!("{}.paging.test.com", random_string.to_lowercase());
+        let mut added_ids = vec![];
+        for index in 0..3 {
+            let added_customer = add_customer(
+                &db,
+                Customer {
+                    name: format!("Test {} {}", index, random_string),
+                    email: format!("{}{}@{}", index, random_string, email_domain),
+                    status: "Active".to_string(),
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("Failed to add");
+            added_ids.push(added_customer.id);
+        }
+        let query = CustomersQueryParams {
+            sort: CustomerSortField::Name,
+            direction: SortDirection::Asc,
+            limit: 2,
+            email_domain: Some(email_domain.clone()),
+            ..Default::default()
+        };
+        let first = customers(
+            SessionClient(db.clone()),
+            State(ListConfig::default()),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Query(query.clone()),
+        )
+        .await
+        .into_response();
+        let first = into_type::<Page<Customer>>(first).await;
+        let second = customers(
+            SessionClient(db.clone()),
+            State(ListConfig::default()),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Query(CustomersQueryParams {
+                cursor: first.next_cursor.clone(),
+                ..query.clone()
+            }),
+        )
+        .await
+        .into_response();
+        let second = into_type::<Page<Customer>>(second).await;
+        let back = customers(
+            SessionClient(db.clone()),
+            State(ListConfig::default()),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Query(CustomersQueryParams {
+                cursor: second.prev_cursor.clone(),
+                ..query.clone()
+            }),
+        )
+        .await
+        .into_response();
+        let back = into_type::<Page<Customer>>(back).await;
+        for id in added_ids.iter() {
+            let _ = remove_customer(&db, *id).await;
+        }
+
+        assert_eq!(3, first.total);
+        assert_eq!(
+            added_ids[0..2],
+            first.items.iter().map(|c| c.id).collect::<Vec<_>>()
+        );
+        assert_eq!(None, first.prev_cursor);
+        assert_eq!(added_ids[2], second.items.first().unwrap().id);
+        assert_eq!(None, second.next_cursor);
+        assert_eq!(first.items, back.items);
+    }
+
+    #[tokio::test]
+    async fn customers_with_invalid_cursor_should_fail() {
+        let db = get_db().await;
+        let result = customers(
+            SessionClient(db),
+            State(ListConfig::default()),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Query(CustomersQueryParams {
+                cursor: Some("not a cursor".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::BAD_REQUEST, result.status());
+    }
+
+    #[tokio::test]
+    async fn customers_with_invalid_date_should_fail() {
+        let db = get_db().await;
+        let result = customers(
+            SessionClient(db),
+            State(ListConfig::default()),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Query(CustomersQueryParams {
+                created_to: Some("yesterday".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::BAD_REQUEST, result.status());
+    }
+
+    #[tokio::test]
+    async fn add_and_remove_valid_customer_should_succeed() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Active".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("Failed to add");
+
+        let remove = remove_customer(&db, customer.id).await;
+        assert_eq!(true, remove.is_ok());
+    }
+
+    #[tokio::test]
+    async fn create_valid_customer_should_succeed() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let response = create_customer(
+            SessionClient(db.clone()),
+            Actor::default(),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Json(Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Lead".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::CREATED, response.status());
+        let created_customer = into_type::<Customer>(response).await;
+        let _ = remove_customer(&db, created_customer.id).await;
+        assert_eq!(format!("Test {}", random_string), created_customer.name);
+        assert_eq!("Lead".to_string(), created_customer.status);
+    }
+
+    #[tokio::test]
+    async fn create_invalid_customer_should_fail() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let response = create_customer(
+            SessionClient(db),
+            Actor::default(),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Json(Customer {
+                name: format!("Test {}", random_string),
+                email: "not an email".to_string(),
+                status: "Active".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    }
+
+    #[tokio::test]
+    async fn create_duplicate_customer_should_conflict() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let added_customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Active".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("Failed to add");
+        let response = create_customer(
+            SessionClient(db.clone()),
+            Actor::default(),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Json(Customer {
+                id: Default::default(),
+                ..added_customer.clone()
+            }),
+        )
+        .await
+        .into_response();
+        let _ = remove_customer(&db, added_customer.id).await;
+        assert_eq!(StatusCode::CONFLICT, response.status());
+        let error = into_type::<ErrorResponse>(response).await;
+        assert!(error.fields.contains_key("email"));
+    }
+
+    #[tokio::test]
+    async fn missing_customer_should_be_not_found() {
+        let db = get_db().await;
+        let response = customer(
+            SessionClient(db),
+            Path(CustomerId::from_u128(rand::random())),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+    }
+
+    #[tokio::test]
+    async fn update_valid_customer_should_succeed() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let added_customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Active".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("Failed to add");
+        let update_result = update_customer(
+            SessionClient(db.clone()),
+            Path(added_customer.id),
+            Actor::default(),
+            CurrentRole(Some(Role::Admin)),
+            CurrentOrganization::default(),
+            IfMatch::default(),
+            Json(Customer {
+                id: added_customer.id,
+                status: "Lead".to_string(),
+                ..added_customer
+            }),
+        )
+        .await
+        .into_response();
+        let updated_customer = customer(SessionClient(db.clone()), Path(added_customer.id))
+            .await
+            .into_response();
+        let _ = remove_customer(&db, added_customer.id).await;
+        let updated_customer = into_type::<Customer>(updated_customer).await;
+
+        assert_eq!(StatusCode::OK, update_result.status());
+        assert_eq!("Lead".to_string(), updated_customer.status);
+    }
+
+    #[tokio::test]
+    async fn update_customer_details_should_persist() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let added_customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Active".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("Failed to add");
+        let update_result = update_customer(
+            SessionClient(db.clone()),
+            Path(added_customer.id),
+            Actor::default(),
+            CurrentRole(Some(Role::Admin)),
+            CurrentOrganization::default(),
+            IfMatch::default(),
+            Json(Customer {
+                name: format!("Renamed {}", random_string),
+                email: format!("renamed{}{}", random_string, TEST_EMAIL_DOMAIN),
+                ..added_customer.clone()
+            }),
+        )
+        .await
+        .into_response();
+        let updated_customer = customer(SessionClient(db.clone()), Path(added_customer.id))
+            .await
+            .into_response();
+        let _ = remove_customer(&db, added_customer.id).await;
+        let updated_customer = into_type::<Customer>(updated_customer).await;
+
+        assert_eq!(StatusCode::OK, update_result.status());
+        assert_eq!(format!("Renamed {}", random_string), updated_customer.name);
+        assert_eq!(
+            format!("renamed{}{}", random_string, TEST_EMAIL_DOMAIN),
+            updated_customer.email
+        );
+    }
+
+    #[tokio::test]
+    async fn update_invalid_customer_should_fail() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let added_customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Active".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("Failed to add");
+        let update_result = update_customer(
+            SessionClient(db.clone()),
+            Path(added_customer.id),
+            Actor::default(),
+            CurrentRole(Some(Role::Admin)),
+            CurrentOrganization::default(),
+            IfMatch::default(),
+            Json(Customer {
+                email: "not an email".to_string(),
+                ..added_customer.clone()
+            }),
+        )
+        .await
+        .into_response();
+        let _ = remove_customer(&db, added_customer.id).await;
+        assert_eq!(StatusCode::BAD_REQUEST, update_result.status());
+    }
+
+    #[tokio::test]
+    async fn only_managers_should_deactivate_customers() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let added_customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Active".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("Failed to add");
+        let deactivate = |role: Role| {
+            update_customer(
+                SessionClient(db.clone()),
+                Path(added_customer.id),
+                Actor::default(),
+                CurrentRole(Some(role)),
+                CurrentOrganization::default(),
+                IfMatch::default(),
+                Json(Customer {
+                    status: "NonActive".to_string(),
+                    ..added_customer.clone()
+                }),
+            )
+        };
+        let rep_result = deactivate(Role::SalesRep).await.into_response();
+        let manager_result = deactivate(Role::SalesManager).await.into_response();
+        let _ = remove_customer(&db, added_customer.id).await;
+        assert_eq!(StatusCode::FORBIDDEN, rep_result.status());
+        assert_eq!(StatusCode::OK, manager_result.status());
+    }
+
+    async fn add_user(db: &Client, role: &str) -> UserId {
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        db.query_required_single(
+            r#"
+            select (insert User {
+                name := <str>$0,
+                email := <str>$1,
+                password_hash := '',
+                role := <Role><str>$2,
+            }).id;"#,
+            &(
+                format!("User {}", random_string),
+                format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                role.to_string(),
+            ),
+        )
+        .await
+        .expect("Failed to add user")
+    }
+
+    async fn remove_user(db: &Client, id: UserId) {
+        let _: Option<Value> = db
+            .query_single("delete User filter .id = <uuid>$0", &(id,))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn new_leads_should_go_to_a_sales_rep() {
+        let db = get_db().await;
+        let rep = add_user(&db, "SalesRep").await;
+        let manager = add_user(&db, "SalesManager").await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let created = |status: &str, email: String| {
+            create_customer(
+                SessionClient(db.clone()),
+                Actor::default(),
+                ActorId(Some(manager)),
+                CurrentOrganization::default(),
+                Json(Customer {
+                    name: format!("Test {}", random_string),
+                    email,
+                    status: status.to_string(),
+                    ..Default::default()
+                }),
+            )
+        };
+        let lead: Customer = into_type(
+            created(
+                "Lead",
+                format!("lead{}{}", random_string, TEST_EMAIL_DOMAIN),
+            )
+            .await
+            .into_response(),
+        )
+        .await;
+        let active: Customer = into_type(
+            created(
+                "Active",
+                format!("active{}{}", random_string, TEST_EMAIL_DOMAIN),
+            )
+            .await
+            .into_response(),
+        )
+        .await;
+        let lead_owner_role: Option<String> = db
+            .query_single(
+                "select <str>(select User filter .id = <uuid>$0).role",
+                &(lead.owner.clone().unwrap_or_default().id,),
+            )
+            .await
+            .unwrap();
+        let _ = remove_customer(&db, lead.id).await;
+        let _ = remove_customer(&db, active.id).await;
+        remove_user(&db, rep).await;
+        remove_user(&db, manager).await;
+
+        assert_eq!(Some("SalesRep".to_string()), lead_owner_role);
+        assert_eq!(Some(manager), active.owner.map(|owner| owner.id));
+    }
+
+    #[tokio::test]
+    async fn customers_should_be_assigned_and_filtered_by_owner() {
+        let db = get_db().await;
+        let owner = add_user(&db, "SalesRep").await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let added_customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Active".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("Failed to add");
+        let missing_owner = assign_owner(
+            SessionClient(db.clone()),
+            Actor::default(),
+            CurrentOrganization::default(),
+            Json(AssignRequest {
+                owner: Some(UserId::from_u128(rand::random())),
+                customers: vec![added_customer.id],
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        let assigned = assign_owner(
+            SessionClient(db.clone()),
+            Actor::default(),
+            CurrentOrganization::default(),
+            Json(AssignRequest {
+                owner: Some(owner),
+                customers: vec![added_customer.id],
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        let mine = |user: Option<UserId>| {
+            customers(
+                SessionClient(db.clone()),
+                State(ListConfig::default()),
+                ActorId(user),
+                CurrentOrganization::default(),
+                Query(CustomersQueryParams {
+                    mine: true,
+                    sort: CustomerSortField::Owner,
+                    ..Default::default()
+                }),
+            )
+        };
+        let owned: Page<Customer> = into_type(mine(Some(owner)).await.into_response()).await;
+        let not_signed_in: Page<Customer> = into_type(mine(None).await.into_response()).await;
+        let _ = remove_customer(&db, added_customer.id).await;
+        remove_user(&db, owner).await;
+
+        assert_eq!(StatusCode::NOT_FOUND, missing_owner.status());
+        assert_eq!(StatusCode::OK, assigned.status());
+        assert_eq!(
+            vec![added_customer.id],
+            owned.items.iter().map(|c| c.id).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(owner),
+            owned.items[0].owner.as_ref().map(|owner| owner.id)
+        );
+        assert_eq!(0, not_signed_in.total);
+    }
+
+    #[tokio::test]
+    async fn add_invalid_customer_should_fail() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "InvalidStatus".to_string(),
+                ..Default::default()
+            },
+        )
+        .await;
+        println!("{:?}", customer);
+        assert_eq!(true, customer.is_err());
+    }
+
+    #[tokio::test]
+    async fn delete_customer_should_move_to_trash_and_restore() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let added_customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Active".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("Failed to add");
+        let _ = add_opportunity(
+            SessionClient(db.clone()),
+            Path(added_customer.id),
+            Actor::default(),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Json(Opportunity {
+                name: format!("Opportunity {}", random_string),
+                status: "New".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        let preview = deletion_preview(SessionClient(db.clone()), Path(added_customer.id))
+            .await
+            .into_response();
+        let preview = into_type::<CustomerDeletion>(preview).await;
+        let delete_response = delete_customer(
+            SessionClient(db.clone()),
+            Path(added_customer.id),
+            Actor::default(),
+            IfMatch::default(),
+        )
+        .await
+        .into_response();
+        let deleted_lookup = deletion_preview(SessionClient(db.clone()), Path(added_customer.id))
+            .await
+            .into_response();
+        let added_to_trashed = add_opportunity(
+            SessionClient(db.clone()),
+            Path(added_customer.id),
+            Actor::default(),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Json(Opportunity {
+                name: format!("Late {}", random_string),
+                status: "New".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        let trashed = into_type::<Vec<DeletedCustomer>>(
+            trash(
+                SessionClient(db.clone()),
+                State(ListConfig::default()),
+                CurrentOrganization::default(),
+            )
+            .await
+            .into_response(),
+        )
+        .await;
+        let restore_response = restore_customer(
+            SessionClient(db.clone()),
+            Path(added_customer.id),
+            Actor::default(),
+        )
+        .await
+        .into_response();
+        let restored = customer(SessionClient(db.clone()), Path(added_customer.id))
+            .await
+            .into_response();
+        let _ = remove_customer(&db, added_customer.id).await;
+
+        assert_eq!(1, preview.opportunity_count);
+        assert_eq!(StatusCode::OK, delete_response.status());
+        assert_eq!(StatusCode::NOT_FOUND, deleted_lookup.status());
+        assert_eq!(StatusCode::NOT_FOUND, added_to_trashed.status());
+        assert!(trashed.iter().any(|c| c.id == added_customer.id));
+        assert_eq!(StatusCode::OK, restore_response.status());
+        assert_eq!(added_customer.id, into_type::<Customer>(restored).await.id);
+    }
+
+    #[tokio::test]
+    async fn purge_customer_should_only_remove_trashed_customers() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let added_customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Active".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("Failed to add");
+        let active_purge = purge_customer(
+            SessionClient(db.clone()),
+            Path(added_customer.id),
+            Actor::default(),
+        )
+        .await
+        .into_response();
+        let _ = delete_customer(
+            SessionClient(db.clone()),
+            Path(added_customer.id),
+            Actor::default(),
+            IfMatch::default(),
+        )
+        .await
+        .into_response();
+        let trashed_purge = purge_customer(
+            SessionClient(db.clone()),
+            Path(added_customer.id),
+            Actor::default(),
+        )
+        .await
+        .into_response();
+        let remove = remove_customer(&db, added_customer.id).await;
+
+        assert_eq!(StatusCode::NOT_FOUND, active_purge.status());
+        assert_eq!(StatusCode::OK, trashed_purge.status());
+        assert!(remove.is_err());
+    }
+
+    #[tokio::test]
+    async fn add_valid_opportunity_should_succeed() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Active".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("Failed to add");
+        let response = add_opportunity(
+            SessionClient(db.clone()),
+            Path(customer.id),
+            Actor::default(),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Json(Opportunity {
+                name: format!("Opportunity {}", random_string),
+                status: "New".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::OK, response.status());
+        let added_opportunities = opportunities(
+            SessionClient(db.clone()),
+            State(ListConfig::default()),
+            Path(customer.id),
+            Query(OpportunitiesQueryParams::default()),
+        )
+        .await
+        .into_response();
+        let _ = remove_customer(&db, customer.id).await;
+        assert_eq!(StatusCode::OK, added_opportunities.status());
+        let results = into_type::<Vec<Opportunity>>(added_opportunities).await;
+        assert_eq!(1, results.len());
+        assert_eq!(
+            format!("Opportunity {}", random_string),
+            results.first().unwrap().name
+        );
+    }
+
+    #[tokio::test]
+    async fn update_opportunity_should_succeed() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Active".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("Failed to add");
+        let response = add_opportunity(
+            SessionClient(db.clone()),
+            Path(customer.id),
+            Actor::default(),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Json(Opportunity {
+                name: format!("Opportunity {}", random_string),
+                status: "New".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::OK, response.status());
+        let added_opportunities = opportunities(
+            SessionClient(db.clone()),
+            State(ListConfig::default()),
+            Path(customer.id),
+            Query(OpportunitiesQueryParams::default()),
+        )
+        .await
+        .into_response();
+        let results = into_type::<Vec<Opportunity>>(added_opportunities).await;
+        let update_response = update_opportunity(
+            SessionClient(db.clone()),
+            Path((customer.id, results.first().unwrap().id)),
+            Actor::default(),
+            CurrentOrganization::default(),
+            IfMatch::default(),
+            Json(Opportunity {
+                id: results.first().unwrap().id,
+                name: "Updated Name".to_string(),
+                status: "ClosedWon".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+
+        let updated_opportunities = opportunities(
+            SessionClient(db.clone()),
+            State(ListConfig::default()),
+            Path(customer.id),
+            Query(OpportunitiesQueryParams::default()),
+        )
+        .await
+        .into_response();
+
+        let _ = remove_customer(&db, customer.id).await;
+        let update_result = into_type::<Vec<Opportunity>>(updated_opportunities).await;
+        assert_eq!(StatusCode::OK, update_response.status());
+        assert_eq!(1, update_result.len());
+        assert_eq!(
+            "Updated Name".to_string(),
+            update_result.first().unwrap().name
+        );
+        assert_eq!(
+            "ClosedWon".to_string(),
+            update_result.first().unwrap().status
+        );
+    }
+
+    #[tokio::test]
+    async fn add_invalid_opportunity_should_fail() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
                 email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Active".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("Failed to add");
+        let response = add_opportunity(
+            SessionClient(db.clone()),
+            Path(customer.id),
+            Actor::default(),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Json(Opportunity {
+                name: format!("Opportunity {}", random_string),
                 status: "InvalidStatus".to_string(),
                 ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        let _ = remove_customer(&db, customer.id).await;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    }
+
+    #[tokio::test]
+    async fn invalid_opportunity_size_should_fail() {
+        let db = get_db().await;
+        let response = add_opportunity(
+            SessionClient(db.clone()),
+            Path(CustomerId::from_u128(rand::random())),
+            Actor::default(),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Json(Opportunity {
+                name: "Opportunity".to_string(),
+                status: "New".to_string(),
+                amount: Some("12.345".to_string()),
+                currency: "dollars".to_string(),
+                close_date: Some("soon".to_string()),
+                probability: 101,
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let error = into_type::<ErrorResponse>(response).await;
+        for field in ["amount", "currency", "close_date", "probability"] {
+            assert!(
+                error.fields.contains_key(field),
+                "{} should be invalid",
+                field
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn opportunities_should_sort_by_close_date() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Active".to_string(),
+                ..Default::default()
             },
         )
+        .await
+        .expect("Failed to add");
+        for (name, close_date) in [
+            ("Later", Some("2023-09-01")),
+            ("Undated", None),
+            ("Sooner", Some("2023-06-01")),
+        ] {
+            let response = add_opportunity(
+                SessionClient(db.clone()),
+                Path(customer.id),
+                Actor::default(),
+                ActorId::default(),
+                CurrentOrganization::default(),
+                Json(Opportunity {
+                    name: name.to_string(),
+                    status: "New".to_string(),
+                    amount: Some("1499.95".to_string()),
+                    currency: "AUD".to_string(),
+                    close_date: close_date.map(|d| d.to_string()),
+                    probability: 40,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .into_response();
+            assert_eq!(StatusCode::OK, response.status());
+        }
+
+        let sorted = into_type::<Vec<Opportunity>>(
+            opportunities(
+                SessionClient(db.clone()),
+                State(ListConfig::default()),
+                Path(customer.id),
+                Query(OpportunitiesQueryParams {
+                    sort: OpportunitySortField::CloseDate,
+                    direction: SortDirection::Asc,
+                }),
+            )
+            .await
+            .into_response(),
+        )
         .await;
-        println!("{:?}", customer);
-        assert_eq!(true, customer.is_err());
+        let _ = remove_customer(&db, customer.id).await;
+        assert_eq!(
+            vec!["Sooner", "Later", "Undated"],
+            sorted
+                .iter()
+                .map(|o| o.name.as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert_eq!(Some("1499.95".to_string()), sorted[0].amount);
+        assert_eq!("AUD", sorted[0].currency);
+        assert_eq!(Some("2023-06-01".to_string()), sorted[0].close_date);
+        assert_eq!(40, sorted[0].probability);
     }
 
     #[tokio::test]
-    async fn add_valid_opportunity_should_succeed() {
+    async fn opportunity_status_changes_should_be_recorded() {
         let db = get_db().await;
         let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
         let customer = add_customer(
@@ -340,9 +2350,12 @@ mod tests {
         )
         .await
         .expect("Failed to add");
-        let response = add_opportunity(
-            State(db.clone()),
+        let _ = add_opportunity(
+            SessionClient(db.clone()),
             Path(customer.id),
+            Actor::default(),
+            ActorId::default(),
+            CurrentOrganization::default(),
             Json(Opportunity {
                 name: format!("Opportunity {}", random_string),
                 status: "New".to_string(),
@@ -350,20 +2363,54 @@ mod tests {
             }),
         )
         .await;
-        assert_eq!(StatusCode::OK, response.status());
-        let added_opportunities = opportunities(State(db.clone()), Path(customer.id)).await;
+        let added = into_type::<Vec<Opportunity>>(
+            opportunities(
+                SessionClient(db.clone()),
+                State(ListConfig::default()),
+                Path(customer.id),
+                Query(OpportunitiesQueryParams::default()),
+            )
+            .await
+            .into_response(),
+        )
+        .await;
+        let opportunity = added.first().unwrap().clone();
+        for status in ["New", "ClosedWon"] {
+            let _ = update_opportunity(
+                SessionClient(db.clone()),
+                Path((customer.id, opportunity.id)),
+                Actor::default(),
+                CurrentOrganization::default(),
+                IfMatch::default(),
+                Json(Opportunity {
+                    status: status.to_string(),
+                    ..opportunity.clone()
+                }),
+            )
+            .await;
+        }
+
+        let changes = status_changes(
+            SessionClient(db.clone()),
+            State(ListConfig::default()),
+            Path(customer.id),
+        )
+        .await
+        .into_response();
         let _ = remove_customer(&db, customer.id).await;
-        assert_eq!(StatusCode::OK, added_opportunities.status());
-        let results = into_type::<Vec<Opportunity>>(added_opportunities).await;
-        assert_eq!(1, results.len());
+        let changes = into_type::<Vec<StatusChange>>(changes).await;
         assert_eq!(
-            format!("Opportunity {}", random_string),
-            results.first().unwrap().name
+            vec!["ClosedWon", "New"],
+            changes
+                .iter()
+                .map(|c| c.status.as_str())
+                .collect::<Vec<&str>>()
         );
+        assert_eq!(opportunity.id, changes[0].opportunity_id);
     }
 
     #[tokio::test]
-    async fn update_opportunity_should_succeed() {
+    async fn stage_history_should_record_previous_stage_and_who_moved_it() {
         let db = get_db().await;
         let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
         let customer = add_customer(
@@ -377,9 +2424,12 @@ mod tests {
         )
         .await
         .expect("Failed to add");
-        let response = add_opportunity(
-            State(db.clone()),
+        let _ = add_opportunity(
+            SessionClient(db.clone()),
             Path(customer.id),
+            Actor(Some("Alice".to_string())),
+            ActorId::default(),
+            CurrentOrganization::default(),
             Json(Opportunity {
                 name: format!("Opportunity {}", random_string),
                 status: "New".to_string(),
@@ -387,39 +2437,359 @@ mod tests {
             }),
         )
         .await;
+        let opportunity = into_type::<Vec<Opportunity>>(
+            opportunities(
+                SessionClient(db.clone()),
+                State(ListConfig::default()),
+                Path(customer.id),
+                Query(OpportunitiesQueryParams::default()),
+            )
+            .await
+            .into_response(),
+        )
+        .await
+        .first()
+        .unwrap()
+        .clone();
+        for status in ["Proposal", "ClosedWon"] {
+            let _ = update_opportunity(
+                SessionClient(db.clone()),
+                Path((customer.id, opportunity.id)),
+                Actor(Some("Bob".to_string())),
+                CurrentOrganization::default(),
+                IfMatch::default(),
+                Json(Opportunity {
+                    status: status.to_string(),
+                    ..opportunity.clone()
+                }),
+            )
+            .await;
+        }
+
+        let response = stage_history(
+            SessionClient(db.clone()),
+            Path((customer.id, opportunity.id)),
+        )
+        .await
+        .into_response();
+        let missing = stage_history(
+            SessionClient(db.clone()),
+            Path((customer.id, OpportunityId::from_u128(rand::random()))),
+        )
+        .await
+        .into_response();
+        let _ = remove_customer(&db, customer.id).await;
         assert_eq!(StatusCode::OK, response.status());
-        let added_opportunities = opportunities(State(db.clone()), Path(customer.id)).await;
-        let results = into_type::<Vec<Opportunity>>(added_opportunities).await;
-        let update_response = update_opportunity(
-            State(db.clone()),
-            Path((customer.id, results.first().unwrap().id)),
+        assert_eq!(StatusCode::NOT_FOUND, missing.status());
+        let history = into_type::<StageHistory>(response).await;
+        assert_eq!(
+            vec![
+                (None, "New", Some("Alice")),
+                (Some("New"), "Proposal", Some("Bob")),
+                (Some("Proposal"), "ClosedWon", Some("Bob")),
+            ],
+            history
+                .changes
+                .iter()
+                .map(|c| (
+                    c.previous_status.as_deref(),
+                    c.status.as_str(),
+                    c.changed_by.as_deref()
+                ))
+                .collect::<Vec<(Option<&str>, &str, Option<&str>)>>()
+        );
+        assert_eq!(
+            vec!["New", "Proposal", "ClosedWon"],
+            history
+                .time_in_stage
+                .iter()
+                .map(|t| t.status.as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert_eq!(0, history.time_in_stage[2].seconds);
+    }
+
+    #[test]
+    fn time_in_stage_should_add_up_revisits_and_stop_when_closed() {
+        let change = |status: &str, outcome: &str, created: &str| StatusChange {
+            status: status.to_string(),
+            outcome: Some(outcome.to_string()),
+            created: created.to_string(),
+            ..Default::default()
+        };
+        let now = DateTime::parse_from_rfc3339("2023-05-10T00:00:00+00:00")
+            .unwrap()
+            .with_timezone(&Utc);
+        let open = vec![
+            change("New", "Open", "2023-05-01T00:00:00+00:00"),
+            change("Proposal", "Open", "2023-05-02T00:00:00+00:00"),
+            change("New", "Open", "2023-05-04T00:00:00+00:00"),
+        ];
+        let closed = vec![
+            change("New", "Open", "2023-05-01T00:00:00+00:00"),
+            change("ClosedWon", "Won", "2023-05-03T00:00:00+00:00"),
+        ];
+        let day = 24 * 60 * 60;
+
+        assert_eq!(
+            vec![
+                StageTime {
+                    status: "New".to_string(),
+                    seconds: 7 * day
+                },
+                StageTime {
+                    status: "Proposal".to_string(),
+                    seconds: 2 * day
+                },
+            ],
+            time_in_stage(&open, now)
+        );
+        assert_eq!(0, time_in_stage(&closed, now)[1].seconds);
+        assert_eq!(2 * day, time_in_stage(&closed, now)[0].seconds);
+    }
+
+    #[tokio::test]
+    async fn activities_should_be_added_updated_and_deleted() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Active".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("Failed to add");
+        let _ = add_opportunity(
+            SessionClient(db.clone()),
+            Path(customer.id),
+            Actor::default(),
+            ActorId::default(),
+            CurrentOrganization::default(),
             Json(Opportunity {
-                id: results.first().unwrap().id,
-                name: "Updated Name".to_string(),
-                status: "ClosedWon".to_string(),
+                name: format!("Opportunity {}", random_string),
+                status: "New".to_string(),
                 ..Default::default()
             }),
         )
         .await;
+        let opportunity = into_type::<Vec<Opportunity>>(
+            opportunities(
+                SessionClient(db.clone()),
+                State(ListConfig::default()),
+                Path(customer.id),
+                Query(OpportunitiesQueryParams::default()),
+            )
+            .await
+            .into_response(),
+        )
+        .await
+        .first()
+        .unwrap()
+        .clone();
 
-        let updated_opportunities = opportunities(State(db.clone()), Path(customer.id)).await;
+        let response = add_activity(
+            SessionClient(db.clone()),
+            Path(customer.id),
+            Actor::default(),
+            Json(Activity {
+                kind: "Call".to_string(),
+                body: "Discussed renewal".to_string(),
+                opportunity_id: Some(opportunity.id),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::CREATED, response.status());
+        let call = into_type::<Activity>(response).await;
+        let other_customers_opportunity = add_activity(
+            SessionClient(db.clone()),
+            Path(customer.id),
+            Actor::default(),
+            Json(Activity {
+                kind: "Note".to_string(),
+                body: "Not linked".to_string(),
+                opportunity_id: Some(OpportunityId::from_u128(rand::random())),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        let note = into_type::<Activity>(other_customers_opportunity).await;
+        let updated = update_activity(
+            SessionClient(db.clone()),
+            Path((customer.id, call.id)),
+            Actor::default(),
+            Json(Activity {
+                kind: "Meeting".to_string(),
+                ..call.clone()
+            }),
+        )
+        .await
+        .into_response();
+        let deleted = delete_activity(
+            SessionClient(db.clone()),
+            Path((customer.id, note.id)),
+            Actor::default(),
+        )
+        .await
+        .into_response();
+        let deleted_again = delete_activity(
+            SessionClient(db.clone()),
+            Path((customer.id, note.id)),
+            Actor::default(),
+        )
+        .await
+        .into_response();
+        let remaining = activities(
+            SessionClient(db.clone()),
+            State(ListConfig::default()),
+            Path(customer.id),
+        )
+        .await
+        .into_response();
 
         let _ = remove_customer(&db, customer.id).await;
-        let update_result = into_type::<Vec<Opportunity>>(updated_opportunities).await;
-        assert_eq!(StatusCode::OK, update_response.status());
-        assert_eq!(1, update_result.len());
+        assert_eq!(Some(opportunity.name), call.opportunity_name);
+        assert_eq!(None, note.opportunity_id);
+        assert_eq!(StatusCode::OK, updated.status());
+        assert_eq!(StatusCode::OK, deleted.status());
+        assert_eq!(StatusCode::NOT_FOUND, deleted_again.status());
+        let remaining = into_type::<Vec<Activity>>(remaining).await;
+        assert_eq!(1, remaining.len());
+        assert_eq!("Meeting", remaining[0].kind);
+    }
+
+    #[tokio::test]
+    async fn add_invalid_activity_should_fail() {
+        let db = get_db().await;
+        let response = add_activity(
+            SessionClient(db.clone()),
+            Path(CustomerId::from_u128(rand::random())),
+            Actor::default(),
+            Json(Activity {
+                kind: "Fax".to_string(),
+                body: "".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let error = into_type::<ErrorResponse>(response).await;
+        assert!(error.fields.contains_key("kind"));
+        assert!(error.fields.contains_key("body"));
+    }
+
+    async fn audit_log(db: &Client, id: CustomerId) -> Vec<AuditEvent> {
+        db.query(
+            r#"
+            select <json>AuditEvent {
+                id,
+                entity,
+                entity_id,
+                action,
+                changes,
+                changed_by,
+                created
+            } filter .customer_id = <uuid>$0 order by .created;"#,
+            &(id,),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn changes_should_be_recorded_in_the_audit_log() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let created = into_type::<Customer>(
+            create_customer(
+                SessionClient(db.clone()),
+                Actor(Some("Alice".to_string())),
+                ActorId::default(),
+                CurrentOrganization::default(),
+                Json(Customer {
+                    name: format!("Test {}", random_string),
+                    email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                    status: "Active".to_string(),
+                    ..Default::default()
+                }),
+            )
+            .await
+            .into_response(),
+        )
+        .await;
+        let renamed = Customer {
+            name: format!("Renamed {}", random_string),
+            ..created.clone()
+        };
+        for _ in 0..2 {
+            let _ = update_customer(
+                SessionClient(db.clone()),
+                Path(created.id),
+                Actor(Some("Bob".to_string())),
+                CurrentRole(Some(Role::Admin)),
+                CurrentOrganization::default(),
+                IfMatch::default(),
+                Json(renamed.clone()),
+            )
+            .await;
+        }
+        let _ = add_opportunity(
+            SessionClient(db.clone()),
+            Path(created.id),
+            Actor(Some("Alice".to_string())),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Json(Opportunity {
+                name: format!("Opportunity {}", random_string),
+                status: "New".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await;
+        let modified_by: Option<String> = db
+            .query_required_single(
+                "select (select Customer filter .id = <uuid>$0).modified_by;",
+                &(created.id,),
+            )
+            .await
+            .ok();
+        let log = audit_log(&db, created.id).await;
+        let _ = remove_customer(&db, created.id).await;
+
+        assert_eq!(Some("Bob".to_string()), modified_by);
         assert_eq!(
-            "Updated Name".to_string(),
-            update_result.first().unwrap().name
+            vec![
+                ("customer", "Created", Some("Alice")),
+                ("customer", "Updated", Some("Bob")),
+                ("opportunity", "Created", Some("Alice")),
+            ],
+            log.iter()
+                .map(|e| (
+                    e.entity.as_str(),
+                    e.action.as_str(),
+                    e.changed_by.as_deref()
+                ))
+                .collect::<Vec<(&str, &str, Option<&str>)>>()
         );
         assert_eq!(
-            "ClosedWon".to_string(),
-            update_result.first().unwrap().status
+            vec![FieldChange {
+                field: "name".to_string(),
+                before: serde_json::json!(created.name),
+                after: serde_json::json!(renamed.name),
+            }],
+            log[1].changes
         );
     }
 
     #[tokio::test]
-    async fn add_invalid_opportunity_should_fail() {
+    async fn trashing_and_restoring_should_be_recorded_in_the_audit_log() {
         let db = get_db().await;
         let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
         let customer = add_customer(
@@ -433,17 +2803,130 @@ mod tests {
         )
         .await
         .expect("Failed to add");
-        let response = add_opportunity(
-            State(db.clone()),
+        let _ = delete_customer(
+            SessionClient(db.clone()),
             Path(customer.id),
-            Json(Opportunity {
-                name: format!("Opportunity {}", random_string),
-                status: "InvalidStatus".to_string(),
+            Actor::default(),
+            IfMatch::default(),
+        )
+        .await;
+        let _ = restore_customer(
+            SessionClient(db.clone()),
+            Path(customer.id),
+            Actor::default(),
+        )
+        .await;
+        let missing = delete_activity(
+            SessionClient(db.clone()),
+            Path((customer.id, ActivityId::from_u128(rand::random()))),
+            Actor::default(),
+        )
+        .await
+        .into_response();
+        let log = audit_log(&db, customer.id).await;
+        let _ = remove_customer(&db, customer.id).await;
+
+        assert_eq!(StatusCode::NOT_FOUND, missing.status());
+        assert_eq!(
+            vec!["Deleted", "Restored"],
+            log.iter().map(|e| e.action.as_str()).collect::<Vec<&str>>()
+        );
+        assert_eq!(
+            vec!["deleted"],
+            log[0]
+                .changes
+                .iter()
+                .map(|c| c.field.as_str())
+                .collect::<Vec<&str>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_version_should_be_precondition_failed() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let added_customer = add_customer(
+            &db,
+            Customer {
+                name: format!("Test {}", random_string),
+                email: format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+                status: "Active".to_string(),
                 ..Default::default()
+            },
+        )
+        .await
+        .expect("Failed to add");
+        let loaded = customer(SessionClient(db.clone()), Path(added_customer.id))
+            .await
+            .into_response();
+        let loaded_etag = loaded.headers().get(ETAG).cloned();
+        let loaded = into_type::<Customer>(loaded).await;
+        let renamed = Customer {
+            name: format!("Renamed {}", random_string),
+            ..loaded.clone()
+        };
+        let first_save = update_customer(
+            SessionClient(db.clone()),
+            Path(loaded.id),
+            Actor::default(),
+            CurrentRole(Some(Role::Admin)),
+            CurrentOrganization::default(),
+            IfMatch(Some(loaded.version.clone())),
+            Json(renamed.clone()),
+        )
+        .await
+        .into_response();
+        let second_save = update_customer(
+            SessionClient(db.clone()),
+            Path(loaded.id),
+            Actor::default(),
+            CurrentRole(Some(Role::Admin)),
+            CurrentOrganization::default(),
+            IfMatch(Some(loaded.version.clone())),
+            Json(renamed.clone()),
+        )
+        .await
+        .into_response();
+        let stale_delete = delete_customer(
+            SessionClient(db.clone()),
+            Path(loaded.id),
+            Actor::default(),
+            IfMatch(Some(loaded.version.clone())),
+        )
+        .await
+        .into_response();
+        let missing_id = CustomerId::from_u128(rand::random());
+        let missing = update_customer(
+            SessionClient(db.clone()),
+            Path(missing_id),
+            Actor::default(),
+            CurrentRole(Some(Role::Admin)),
+            CurrentOrganization::default(),
+            IfMatch(Some(loaded.version.clone())),
+            Json(Customer {
+                id: missing_id,
+                ..renamed.clone()
             }),
         )
+        .await
+        .into_response();
+        let current = into_type::<Customer>(
+            customer(SessionClient(db.clone()), Path(loaded.id))
+                .await
+                .into_response(),
+        )
         .await;
-        let _ = remove_customer(&db, customer.id).await;
-        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let _ = remove_customer(&db, added_customer.id).await;
+
+        assert_eq!(Some(etag(&loaded.version)), loaded_etag);
+        assert_eq!(StatusCode::OK, first_save.status());
+        assert_eq!(
+            Some(&etag(&current.version)),
+            first_save.headers().get(ETAG)
+        );
+        assert_eq!(StatusCode::PRECONDITION_FAILED, second_save.status());
+        assert_eq!(StatusCode::PRECONDITION_FAILED, stale_delete.status());
+        assert_eq!(StatusCode::NOT_FOUND, missing.status());
+        assert_eq!(renamed.name, current.name);
     }
 }
@@ -0,0 +1,592 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    extract::{self, Path, Query},
+    handler::Handler,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use edgedb_tokio::Transaction;
+use frontend::{
+    AuditAction, AuditEntity, Customer, CustomerId, DuplicateCandidate, DuplicatesQueryParams,
+    MergeRequest, Permission,
+};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    actor::Actor,
+    audit::AuditTrail,
+    auth::SessionClient,
+    errors::{from_json, ApiError},
+    organizations::CurrentOrganization,
+    permissions::allow,
+    query::id_list,
+    transaction::transaction,
+    AppState,
+};
+
+/// Words that only say what kind of company it is, left out when comparing names
+const COMPANY_WORDS: [&str; 14] = [
+    "the",
+    "ltd",
+    "limited",
+    "inc",
+    "incorporated",
+    "llc",
+    "llp",
+    "plc",
+    "co",
+    "company",
+    "corp",
+    "corporation",
+    "pty",
+    "gmbh",
+];
+/// Domains shared by unrelated people, an email on one of them says nothing about the company
+const FREE_EMAIL_DOMAINS: [&str; 10] = [
+    "gmail.com",
+    "googlemail.com",
+    "outlook.com",
+    "hotmail.com",
+    "live.com",
+    "yahoo.com",
+    "icloud.com",
+    "me.com",
+    "aol.com",
+    "protonmail.com",
+];
+const NAME_WEIGHT: f64 = 0.6;
+const DOMAIN_WEIGHT: f64 = 0.25;
+const CONTACT_WEIGHT: f64 = 0.15;
+/// Words, domains or emails shared by more customers than this are too common to pair them up
+const MAX_GROUP_SIZE: usize = 100;
+
+pub fn duplicate_routes() -> Router<AppState> {
+    Router::new()
+        .route("/customers/duplicates", get(duplicates))
+        .route(
+            "/customer/:id/merge",
+            post(merge_customer.layer(allow(Permission::DeleteCustomers))),
+        )
+}
+
+/// Customer along with the lower case emails of it and its contacts
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct CustomerEmails {
+    #[serde(flatten)]
+    customer: Customer,
+    contact_emails: Vec<String>,
+}
+
+/// What duplicates are compared on
+#[derive(Debug, Clone, PartialEq)]
+struct Profile {
+    customer: Customer,
+    words: Vec<String>,
+    domain: Option<String>,
+    emails: HashSet<String>,
+}
+
+/// Words of the name in lower case without punctuation or company words,
+/// so "ACME Ltd." and "Acme Limited" are both `["acme"]`
+fn name_words(name: &str) -> Vec<String> {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !COMPANY_WORDS.contains(word))
+        .map(|word| word.to_string())
+        .collect()
+}
+
+/// Domain of the email unless it is a free email provider
+fn company_domain(email: &str) -> Option<String> {
+    email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim().to_lowercase())
+        .filter(|domain| !domain.is_empty() && !FREE_EMAIL_DOMAINS.contains(&domain.as_str()))
+}
+
+impl From<CustomerEmails> for Profile {
+    fn from(customer: CustomerEmails) -> Self {
+        let mut emails: HashSet<String> = customer.contact_emails.into_iter().collect();
+        emails.insert(customer.customer.email.to_lowercase());
+        Profile {
+            words: name_words(&customer.customer.name),
+            domain: company_domain(&customer.customer.email),
+            emails,
+            customer: customer.customer,
+        }
+    }
+}
+
+/// Score between 0 and 1 and the reasons for it
+fn score(a: &Profile, b: &Profile) -> (f64, Vec<String>) {
+    let mut score = 0.0;
+    let mut reasons = vec![];
+    if !a.words.is_empty() && a.words == b.words {
+        score += NAME_WEIGHT;
+        reasons.push("Same name once case, punctuation and company words are ignored".to_string());
+    } else {
+        let a_words: HashSet<&String> = a.words.iter().collect();
+        let b_words: HashSet<&String> = b.words.iter().collect();
+        let shared = a_words.intersection(&b_words).count();
+        if shared > 0 {
+            let similarity = shared as f64 / a_words.union(&b_words).count() as f64;
+            score += NAME_WEIGHT * similarity;
+            reasons.push(format!("Names share {} of their words", shared));
+        }
+    }
+    if let (Some(a_domain), Some(b_domain)) = (&a.domain, &b.domain) {
+        if a_domain == b_domain {
+            score += DOMAIN_WEIGHT;
+            reasons.push(format!("Both use the {} email domain", a_domain));
+        }
+    }
+    let shared = a.emails.intersection(&b.emails).count();
+    if shared > 0 {
+        score += CONTACT_WEIGHT * shared as f64 / a.emails.len().min(b.emails.len()) as f64;
+        reasons.push(format!("{} email addresses in common", shared));
+    }
+    (score, reasons)
+}
+
+/// Pairs scoring at least `min_score`, best first. Only customers sharing a name word,
+/// a company email domain or an email address are compared, so not every pair is scored.
+/// The customer created first is suggested as the one to keep.
+fn candidates(profiles: &[Profile], min_score: f64, limit: usize) -> Vec<DuplicateCandidate> {
+    let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, profile) in profiles.iter().enumerate() {
+        let keys = profile
+            .words
+            .iter()
+            .map(|word| format!("word:{}", word))
+            .chain(
+                profile
+                    .domain
+                    .iter()
+                    .map(|domain| format!("domain:{}", domain)),
+            )
+            .chain(
+                profile
+                    .emails
+                    .iter()
+                    .map(|email| format!("email:{}", email)),
+            )
+            .collect::<HashSet<String>>();
+        for key in keys {
+            groups.entry(key).or_default().push(index);
+        }
+    }
+    let mut pairs: HashSet<(usize, usize)> = HashSet::new();
+    for members in groups.values().filter(|m| m.len() <= MAX_GROUP_SIZE) {
+        for (position, a) in members.iter().enumerate() {
+            for b in &members[position + 1..] {
+                pairs.insert((*a.min(b), *a.max(b)));
+            }
+        }
+    }
+    let mut found: Vec<DuplicateCandidate> = pairs
+        .into_iter()
+        .filter_map(|(a, b)| {
+            let (score, reasons) = score(&profiles[a], &profiles[b]);
+            let (first, second) = match profiles[a].customer.created <= profiles[b].customer.created
+            {
+                true => (&profiles[a], &profiles[b]),
+                false => (&profiles[b], &profiles[a]),
+            };
+            (score >= min_score).then(|| DuplicateCandidate {
+                customer: first.customer.clone(),
+                duplicate: second.customer.clone(),
+                score,
+                reasons,
+            })
+        })
+        .collect();
+    found.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.customer.name.cmp(&b.customer.name))
+    });
+    found.truncate(limit);
+    found
+}
+
+/// Pairs of customers of the organisation outside the trash that are likely the same company
+async fn duplicates(
+    SessionClient(db): SessionClient,
+    CurrentOrganization(organization): CurrentOrganization,
+    Query(params): extract::Query<DuplicatesQueryParams>,
+) -> Result<Response, ApiError> {
+    params.validate()?;
+    let json = db
+        .query_json(
+            r#"
+            select Customer {
+                id,
+                name,
+                email,
+                status,
+                created,
+                version := <str>.modified,
+                contact_emails := str_lower(.contacts.email)
+            } filter not exists .deleted
+                and (not exists <optional uuid>$0 or .organization.id ?= <optional uuid>$0);"#,
+            &(organization,),
+        )
+        .await?;
+    let customers: Vec<CustomerEmails> = from_json(&json)?;
+    let profiles: Vec<Profile> = customers.into_iter().map(Profile::from).collect();
+    Ok(Json(candidates(&profiles, params.min_score, params.limit)).into_response())
+}
+
+/// Records linked to the duplicate that move to the customer it is merged into
+#[derive(Debug, Default, Deserialize)]
+struct MergedRecords {
+    name: String,
+    email: String,
+    opportunities: Vec<Uuid>,
+    contacts: Vec<Uuid>,
+    activities: Vec<Uuid>,
+    tasks: Vec<Uuid>,
+}
+
+/// Moves everything linked to the duplicate onto the customer and removes the duplicate.
+/// Returns the customer, or the error to report when either of them can not be found.
+async fn merge(
+    tx: &mut Transaction,
+    id: CustomerId,
+    duplicate_id: CustomerId,
+    actor: &Actor,
+) -> Result<Result<Customer, ApiError>, edgedb_tokio::Error> {
+    let exists: bool = tx
+        .query_required_single(
+            "select exists (select Customer filter .id = <uuid>$0 and not exists .deleted);",
+            &(id,),
+        )
+        .await?;
+    // a customer of another organisation is never offered as the duplicate
+    let merged = tx
+        .query_single_json(
+            r#"
+            with customer := (select detached Customer filter .id = <uuid>$1)
+            select Customer {
+                name,
+                email,
+                opportunities := .opportunities.id,
+                contacts := .contacts.id,
+                activities := .activities.id,
+                tasks := .tasks.id
+            } filter .id = <uuid>$0 and not exists .deleted
+                and .organization = customer.organization;"#,
+            &(duplicate_id, id),
+        )
+        .await?;
+    let merged: MergedRecords = match (exists, merged) {
+        (true, Some(json)) => from_json(&json)?,
+        _ => return Ok(Err(ApiError::NotFound)),
+    };
+    let customer_trail = AuditTrail::start(tx, AuditEntity::Customer, id, id, actor).await?;
+    let duplicate_trail =
+        AuditTrail::start(tx, AuditEntity::Customer, duplicate_id, duplicate_id, actor).await?;
+
+    // the links are exclusive so they are taken off the duplicate before they are added to the customer
+    let _: Uuid = tx
+        .query_required_single(
+            r#"
+            select (
+                update Customer filter .id = <uuid>$0
+                set {
+                    opportunities := {},
+                    contacts := {},
+                    activities := {},
+                    tasks := {},
+                }
+            ).id;"#,
+            &(duplicate_id,),
+        )
+        .await?;
+    // the customer keeps its own primary contact when it has one
+    let _: Vec<Uuid> = tx
+        .query(
+            r#"
+            with customer := (select Customer filter .id = <uuid>$0)
+            select (
+                update Contact
+                filter .id in <uuid><str>json_array_unpack(to_json(<str>$1))
+                    and .is_primary
+                    and exists (select customer.contacts filter .is_primary)
+                set {
+                    is_primary := false,
+                    modified := datetime_current(),
+                    modified_by := <optional str>$2,
+                }
+            ).id;"#,
+            &(id, id_list(&merged.contacts), actor.0.clone()),
+        )
+        .await?;
+    let customer: Customer = tx
+        .query_required_single(
+            r#"
+            select <json>(
+                update Customer filter .id = <uuid>$0
+                set {
+                    opportunities += (
+                        select Opportunity filter .id in <uuid><str>json_array_unpack(to_json(<str>$1))
+                    ),
+                    contacts += (
+                        select Contact filter .id in <uuid><str>json_array_unpack(to_json(<str>$2))
+                    ),
+                    activities += (
+                        select Activity filter .id in <uuid><str>json_array_unpack(to_json(<str>$3))
+                    ),
+                    tasks += (
+                        select Task filter .id in <uuid><str>json_array_unpack(to_json(<str>$4))
+                    ),
+                    tags += (select Customer filter .id = <uuid>$6).tags,
+                    modified := datetime_current(),
+                    modified_by := <optional str>$5,
+                }
+            ) {
+                id,
+                name,
+                email,
+                status,
+                created,
+                version := <str>.modified,
+                tags: { id, name, colour } order by .name
+            };"#,
+            &(
+                id,
+                id_list(&merged.opportunities),
+                id_list(&merged.contacts),
+                id_list(&merged.activities),
+                id_list(&merged.tasks),
+                actor.0.clone(),
+                duplicate_id,
+            ),
+        )
+        .await?;
+    let _: Uuid = tx
+        .query_required_single(
+            "select (delete Customer filter .id = <uuid>$0).id;",
+            &(duplicate_id,),
+        )
+        .await?;
+
+    customer_trail
+        .note(
+            "merged",
+            serde_json::Value::Null,
+            json!({
+                "id": duplicate_id,
+                "name": merged.name,
+                "email": merged.email,
+                "opportunities": merged.opportunities.len(),
+                "contacts": merged.contacts.len(),
+                "activities": merged.activities.len(),
+                "tasks": merged.tasks.len(),
+            }),
+        )
+        .finish(tx, AuditAction::Merged)
+        .await?;
+    duplicate_trail
+        .note(
+            "merged_into",
+            serde_json::Value::Null,
+            json!({"id": id, "name": customer.name, "email": customer.email}),
+        )
+        .finish(tx, AuditAction::Merged)
+        .await?;
+    Ok(Ok(customer))
+}
+
+/// Merges the duplicate in the body into the customer in the url in one transaction,
+/// its opportunities, contacts, activities and tasks move over and the duplicate is removed
+async fn merge_customer(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<CustomerId>,
+    actor: Actor,
+    Json(body): extract::Json<MergeRequest>,
+) -> Result<Response, ApiError> {
+    if body.duplicate_id == id {
+        return Err(ApiError::BadRequest(
+            "A customer can not be merged into itself".to_string(),
+        ));
+    }
+    let customer = transaction(db, move |mut tx| {
+        let actor = actor.clone();
+        async move { merge(&mut tx, id, body.duplicate_id, &actor).await }
+    })
+    .await??;
+    Ok(Json(customer).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use edgedb_tokio::Client;
+    use frontend::AuditEvent;
+    use rand::distributions::{Alphanumeric, DistString};
+
+    use super::*;
+    const TEST_EMAIL_DOMAIN: &str = "@test.email.com";
+
+    async fn get_db() -> Client {
+        edgedb_tokio::create_client()
+            .await
+            .expect("Failed to connect to the DB")
+    }
+
+    fn profile(name: &str, email: &str, contacts: Vec<&str>, created: &str) -> Profile {
+        Profile::from(CustomerEmails {
+            customer: Customer {
+                name: name.to_string(),
+                email: email.to_string(),
+                created: created.to_string(),
+                ..Default::default()
+            },
+            contact_emails: contacts.into_iter().map(|c| c.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn names_should_match_without_case_punctuation_or_company_words() {
+        assert_eq!(vec!["acme"], name_words("ACME Ltd."));
+        assert_eq!(name_words("Acme Limited"), name_words("The Acme Company"));
+        assert_eq!(vec!["smith", "sons"], name_words("Smith & Sons Pty"));
+    }
+
+    #[test]
+    fn free_email_domains_should_not_count() {
+        assert_eq!(Some("acme.com".to_string()), company_domain("Jo@ACME.com"));
+        assert_eq!(None, company_domain("jo@gmail.com"));
+        assert_eq!(None, company_domain("not an email"));
+    }
+
+    #[test]
+    fn score_should_add_up_name_domain_and_contacts() {
+        let acme = profile("ACME Ltd", "info@acme.com", vec!["jo@acme.com"], "1");
+        let limited = profile("Acme Limited", "sales@acme.com", vec!["jo@acme.com"], "2");
+        let (total, reasons) = score(&acme, &limited);
+        // one of the two addresses each has is shared
+        assert!((total - (NAME_WEIGHT + DOMAIN_WEIGHT + CONTACT_WEIGHT / 2.0)).abs() < 1e-9);
+        assert_eq!(3, reasons.len());
+
+        let other = profile("Globex", "info@gmail.com", vec![], "3");
+        assert_eq!((0.0, vec![]), score(&acme, &other));
+    }
+
+    #[test]
+    fn candidates_should_suggest_keeping_the_older_customer() {
+        let profiles = vec![
+            profile("Acme Limited", "sales@acme.com", vec![], "2023-05-02"),
+            profile("Globex", "info@globex.com", vec![], "2023-05-01"),
+            profile("ACME Ltd", "info@acme.com", vec![], "2023-05-01"),
+        ];
+        let found = candidates(&profiles, 0.5, 10);
+        assert_eq!(1, found.len());
+        assert_eq!("ACME Ltd", found[0].customer.name);
+        assert_eq!("Acme Limited", found[0].duplicate.name);
+        assert!(candidates(&profiles, 0.9, 10).is_empty());
+    }
+
+    async fn add_customer(db: &Client, name: &str, email: &str) -> Customer {
+        db.query_required_single(
+            r#"
+            select <json>(insert Customer {
+                name := <str>$0,
+                email := <str>$1,
+                status := CustomerStatus.Active,
+            }) { id, name, email, status, created };"#,
+            &(name.to_string(), email.to_string()),
+        )
+        .await
+        .expect("Failed to add")
+    }
+
+    #[tokio::test]
+    async fn merge_should_move_records_and_remove_the_duplicate() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let customer = add_customer(
+            &db,
+            &format!("Merge {} Ltd", random_string),
+            &format!("a{}{}", random_string, TEST_EMAIL_DOMAIN),
+        )
+        .await;
+        let duplicate = add_customer(
+            &db,
+            &format!("Merge {} Limited", random_string),
+            &format!("b{}{}", random_string, TEST_EMAIL_DOMAIN),
+        )
+        .await;
+        let _: Uuid = db
+            .query_required_single(
+                r#"
+                with contact := (insert Contact {
+                    name := 'Jo', email := 'jo@example.com', is_primary := true
+                })
+                select (update Customer filter .id = <uuid>$0 set { contacts += contact }).id;"#,
+                &(duplicate.id,),
+            )
+            .await
+            .unwrap();
+
+        let response = merge_customer(
+            SessionClient(db.clone()),
+            Path(customer.id),
+            Actor(Some("Alice".to_string())),
+            Json(MergeRequest {
+                duplicate_id: duplicate.id,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(axum::http::StatusCode::OK, response.status());
+
+        let (contacts, duplicate_left): (i64, bool) = db
+            .query_required_single(
+                r#"
+                select (
+                    count((select Customer filter .id = <uuid>$0).contacts),
+                    exists (select Customer filter .id = <uuid>$1)
+                );"#,
+                &(customer.id, duplicate.id),
+            )
+            .await
+            .unwrap();
+        assert_eq!((1, false), (contacts, duplicate_left));
+        let events: Vec<AuditEvent> = db
+            .query(
+                r#"
+                select <json>AuditEvent {
+                    id, entity, entity_id, action, changes, changed_by, created
+                } filter .customer_id = <uuid>$0 and .action = AuditAction.Merged;"#,
+                &(customer.id,),
+            )
+            .await
+            .unwrap();
+        assert_eq!(1, events.len());
+        assert_eq!(Some("Alice".to_string()), events[0].changed_by);
+
+        let merged_again = merge_customer(
+            SessionClient(db.clone()),
+            Path(customer.id),
+            Actor::default(),
+            Json(MergeRequest {
+                duplicate_id: duplicate.id,
+            }),
+        )
+        .await;
+        assert!(matches!(merged_again, Err(ApiError::NotFound)));
+        let _: Uuid = db
+            .query_required_single(
+                "select (delete Customer filter .id = <uuid>$0).id;",
+                &(customer.id,),
+            )
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+use std::{collections::HashMap, fmt::Display};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use edgedb_errors::{
+    AccessPolicyError, ConstraintViolationError, ErrorKind, InvalidValueError, NoDataError,
+    NumericOutOfRangeError, ProtocolEncodingError,
+};
+use frontend::ErrorResponse;
+use serde::de::DeserializeOwned;
+use validator::ValidationErrors;
+
+/// Errors returned from the api handlers, each maps to a status code and an `ErrorResponse` body
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    /// There is no session or the credentials are wrong
+    Unauthorized(String),
+    /// Signed in but not allowed to do this
+    Forbidden(String),
+    NotFound,
+    /// A unique value is already taken, holds the field and the message
+    Conflict(String, String),
+    Validation(ValidationErrors),
+    /// Messages for fields only known at runtime, keyed by field e.g. `custom.industry`
+    InvalidFields(HashMap<String, Vec<String>>),
+    /// The database rejected the values e.g. a constraint or enum violation
+    Unprocessable(String),
+    /// The record changed since the version the client sent in `If-Match`
+    PreconditionFailed,
+    Database(edgedb_tokio::Error),
+    /// Any other fault of the server, logged and reported without its details
+    Internal(anyhow::Error),
+}
+
+impl From<edgedb_tokio::Error> for ApiError {
+    fn from(error: edgedb_tokio::Error) -> Self {
+        let message = error.initial_message().unwrap_or_default().to_string();
+        if error.is::<NoDataError>() {
+            ApiError::NotFound
+        } else if error.is::<AccessPolicyError>() {
+            // the role's policies deny the write, the route checks should have caught it first
+            ApiError::Forbidden(message)
+        } else if error.is::<ConstraintViolationError>() && message.contains("exclusivity") {
+            // edgedb formats these as "<property> violates exclusivity constraint"
+            let field = message
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            ApiError::Conflict(field, message)
+        } else if error.is::<ConstraintViolationError>()
+            || error.is::<InvalidValueError>()
+            || error.is::<NumericOutOfRangeError>()
+        {
+            ApiError::Unprocessable(message)
+        } else {
+            ApiError::Database(error)
+        }
+    }
+}
+
+/// Reads the result of a `query_json` call, json that does not match the type fails like any other
+/// database error instead of being read as empty
+pub fn from_json<T: DeserializeOwned>(json: &str) -> Result<T, edgedb_tokio::Error> {
+    serde_json::from_str(json).map_err(ProtocolEncodingError::with_source)
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        ApiError::Validation(errors)
+    }
+}
+
+fn field_messages(errors: &ValidationErrors) -> HashMap<String, Vec<String>> {
+    errors
+        .field_errors()
+        .into_iter()
+        .map(|(field, errors)| {
+            (
+                field.to_string(),
+                errors
+                    .iter()
+                    .map(|e| match &e.message {
+                        Some(message) => message.to_string(),
+                        None => e.code.to_string(),
+                    })
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+impl ApiError {
+    /// Status code and body the error is reported with
+    pub fn into_parts(self) -> (StatusCode, ErrorResponse) {
+        match self {
+            ApiError::BadRequest(message) => (
+                StatusCode::BAD_REQUEST,
+                ErrorResponse {
+                    code: "bad_request".to_string(),
+                    message,
+                    ..Default::default()
+                },
+            ),
+            ApiError::Unauthorized(message) => (
+                StatusCode::UNAUTHORIZED,
+                ErrorResponse {
+                    code: "unauthorized".to_string(),
+                    message,
+                    ..Default::default()
+                },
+            ),
+            ApiError::Forbidden(message) => (
+                StatusCode::FORBIDDEN,
+                ErrorResponse {
+                    code: "forbidden".to_string(),
+                    message,
+                    ..Default::default()
+                },
+            ),
+            ApiError::NotFound => (
+                StatusCode::NOT_FOUND,
+                ErrorResponse {
+                    code: "not_found".to_string(),
+                    message: "The record could not be found".to_string(),
+                    ..Default::default()
+                },
+            ),
+            ApiError::Conflict(field, message) => (
+                StatusCode::CONFLICT,
+                ErrorResponse {
+                    code: "conflict".to_string(),
+                    fields: HashMap::from([(field, vec!["Already in use".to_string()])]),
+                    message,
+                },
+            ),
+            ApiError::Validation(errors) => (
+                StatusCode::BAD_REQUEST,
+                ErrorResponse {
+                    code: "validation".to_string(),
+                    message: "Some fields are invalid".to_string(),
+                    fields: field_messages(&errors),
+                },
+            ),
+            ApiError::InvalidFields(fields) => (
+                StatusCode::BAD_REQUEST,
+                ErrorResponse {
+                    code: "validation".to_string(),
+                    message: "Some fields are invalid".to_string(),
+                    fields,
+                },
+            ),
+            ApiError::Unprocessable(message) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ErrorResponse {
+                    code: "constraint_violation".to_string(),
+                    message,
+                    ..Default::default()
+                },
+            ),
+            ApiError::PreconditionFailed => (
+                StatusCode::PRECONDITION_FAILED,
+                ErrorResponse {
+                    code: "precondition_failed".to_string(),
+                    message: "The record was changed by someone else since it was loaded"
+                        .to_string(),
+                    ..Default::default()
+                },
+            ),
+            ApiError::Database(error) => internal(&error),
+            ApiError::Internal(error) => internal(&error),
+        }
+    }
+}
+
+fn internal(error: &dyn Display) -> (StatusCode, ErrorResponse) {
+    tracing::error!("{:#}", error);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        ErrorResponse {
+            code: "internal".to_string(),
+            message: "Something went wrong".to_string(),
+            ..Default::default()
+        },
+    )
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, body) = self.into_parts();
+        (status, Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use edgedb_errors::ClientConnectionError;
+    use frontend::Customer;
+    use validator::Validate;
+
+    use super::*;
+
+    async fn into_body(response: Response) -> ErrorResponse {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice::<ErrorResponse>(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn missing_row_should_be_not_found() {
+        let response = ApiError::from(NoDataError::build()).into_response();
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert_eq!("not_found", into_body(response).await.code);
+    }
+
+    #[tokio::test]
+    async fn exclusive_violation_should_be_conflict_on_field() {
+        let error = ConstraintViolationError::with_message("email violates exclusivity constraint");
+        let response = ApiError::from(error).into_response();
+        assert_eq!(StatusCode::CONFLICT, response.status());
+        assert!(into_body(response).await.fields.contains_key("email"));
+    }
+
+    #[tokio::test]
+    async fn access_policy_violation_should_be_forbidden() {
+        let error =
+            AccessPolicyError::with_message("access policy violation on insert of default::Tag");
+        let response = ApiError::from(error).into_response();
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+        assert_eq!("forbidden", into_body(response).await.code);
+    }
+
+    #[tokio::test]
+    async fn other_constraint_violation_should_be_unprocessable() {
+        let error = InvalidValueError::with_message("invalid input value for enum");
+        let response = ApiError::from(error).into_response();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+    }
+
+    #[tokio::test]
+    async fn precondition_failed_should_be_412() {
+        let response = ApiError::PreconditionFailed.into_response();
+        assert_eq!(StatusCode::PRECONDITION_FAILED, response.status());
+        assert_eq!("precondition_failed", into_body(response).await.code);
+    }
+
+    #[tokio::test]
+    async fn invalid_fields_should_keep_their_keys() {
+        let fields = HashMap::from([(
+            "custom.industry".to_string(),
+            vec!["Please enter a value".to_string()],
+        )]);
+        let response = ApiError::InvalidFields(fields.clone()).into_response();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let body = into_body(response).await;
+        assert_eq!("validation", body.code);
+        assert_eq!(fields, body.fields);
+    }
+
+    #[tokio::test]
+    async fn missing_session_should_be_401() {
+        let response = ApiError::Unauthorized("Please sign in".to_string()).into_response();
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+        assert_eq!("unauthorized", into_body(response).await.code);
+    }
+
+    #[tokio::test]
+    async fn unexpected_database_error_should_be_internal() {
+        let response = ApiError::from(ClientConnectionError::build()).into_response();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+        assert_eq!("internal", into_body(response).await.code);
+    }
+
+    #[tokio::test]
+    async fn server_fault_should_be_internal() {
+        let response = ApiError::Internal(anyhow::anyhow!("no temp dir")).into_response();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+        assert_eq!("internal", into_body(response).await.code);
+    }
+
+    #[tokio::test]
+    async fn json_that_does_not_match_should_be_internal() {
+        let error = from_json::<Vec<Customer>>(r#"[{"id": 1}]"#).unwrap_err();
+        let response = ApiError::from(error).into_response();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+        assert_eq!(
+            Ok(vec![1, 2]),
+            from_json::<Vec<i32>>("[1, 2]").map_err(|_| ())
+        );
+    }
+
+    #[tokio::test]
+    async fn validation_errors_should_list_field_messages() {
+        let errors = Customer {
+            name: "A".to_string(),
+            email: "not an email".to_string(),
+            status: "Active".to_string(),
+            ..Default::default()
+        }
+        .validate()
+        .unwrap_err();
+        let response = ApiError::from(errors).into_response();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let body = into_body(response).await;
+        assert_eq!(
+            Some(&vec!["Must be longer than 3 characters".to_string()]),
+            body.fields.get("name")
+        );
+        assert!(body.fields.contains_key("email"));
+    }
+}
@@ -0,0 +1,478 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, Write},
+};
+
+use anyhow::Context;
+use axum::{
+    body::{boxed, Body},
+    extract::{self, Query, State},
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use edgedb_tokio::Client;
+use frontend::{
+    CustomFieldEntity, CustomerExport, CustomerSortField, CustomersQueryParams, ExportFormat,
+    ExportParams, Owner,
+};
+use hyper::body::{Bytes, Sender};
+use validator::Validate;
+use zip::{write::FileOptions, ZipWriter};
+
+use crate::{
+    actor::ActorId,
+    auth::SessionClient,
+    custom_fields::filter_fields,
+    customers::{filtered_customers, sort_value},
+    errors::ApiError,
+    organizations::CurrentOrganization,
+    query::{ListConfig, ListQuery},
+    AppState,
+};
+
+const EXPORT_SHAPE: &str = "id, name, email, status, created, version := <str>.modified, owner: { id, name }, custom, opportunities := (select .opportunities { id, name, pipeline_id := .stage.pipeline.id, status := .stage.name, outcome := <str>.stage.outcome, amount := <str>.amount, currency, close_date, probability, created, version := <str>.modified, owner: { id, name }, custom } order by .created)";
+
+/// Columns of the CSV and XLSX exports, a row per opportunity
+const COLUMNS: [&str; 15] = [
+    "customer_id",
+    "customer_name",
+    "customer_email",
+    "customer_status",
+    "customer_created",
+    "opportunity_id",
+    "opportunity_name",
+    "stage",
+    "outcome",
+    "amount",
+    "currency",
+    "close_date",
+    "probability",
+    "opportunity_created",
+    "owner",
+];
+
+pub fn export_routes() -> Router<AppState> {
+    Router::new().route("/customers/export", get(export_customers))
+}
+
+fn owner_name(owner: Option<&Owner>) -> String {
+    owner.map(|owner| owner.name.clone()).unwrap_or_default()
+}
+
+/// Rows of the customer for the CSV and XLSX exports, the owner is the opportunity's when there is one
+fn records(export: &CustomerExport) -> Vec<Vec<String>> {
+    let customer = &export.customer;
+    let customer_cells = vec![
+        customer.id.to_string(),
+        customer.name.clone(),
+        customer.email.clone(),
+        customer.status.clone(),
+        customer.created.clone(),
+    ];
+    if export.opportunities.is_empty() {
+        let mut record = customer_cells;
+        record.resize(COLUMNS.len() - 1, String::new());
+        record.push(owner_name(customer.owner.as_ref()));
+        return vec![record];
+    }
+    export
+        .opportunities
+        .iter()
+        .map(|opportunity| {
+            let mut record = customer_cells.clone();
+            record.extend([
+                opportunity.id.to_string(),
+                opportunity.name.clone(),
+                opportunity.status.clone(),
+                opportunity.outcome.clone().unwrap_or_default(),
+                opportunity.amount.clone().unwrap_or_default(),
+                opportunity.currency.clone(),
+                opportunity.close_date.clone().unwrap_or_default(),
+                opportunity.probability.to_string(),
+                opportunity.created.clone(),
+                owner_name(opportunity.owner.as_ref()),
+            ]);
+            record
+        })
+        .collect()
+}
+
+/// Turns pages of customers into the bytes of one export format
+trait ExportWriter: Send {
+    /// Bytes to send for the page, empty when the format can only be sent once it is complete
+    fn write_page(&mut self, customers: &[CustomerExport]) -> io::Result<Vec<u8>>;
+    /// Whatever is left to send once every page has been written
+    fn finish(self: Box<Self>) -> io::Result<Box<dyn Read + Send>>;
+}
+
+#[derive(Default)]
+struct CsvExport {
+    header_written: bool,
+}
+
+impl ExportWriter for CsvExport {
+    fn write_page(&mut self, customers: &[CustomerExport]) -> io::Result<Vec<u8>> {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        if !self.header_written {
+            writer.write_record(COLUMNS)?;
+            self.header_written = true;
+        }
+        for record in customers.iter().flat_map(records) {
+            writer.write_record(&record)?;
+        }
+        writer.into_inner().map_err(|error| error.into_error())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(io::empty()))
+    }
+}
+
+struct NdjsonExport;
+
+impl ExportWriter for NdjsonExport {
+    fn write_page(&mut self, customers: &[CustomerExport]) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        for customer in customers {
+            serde_json::to_writer(&mut bytes, customer)?;
+            bytes.push(b'\n');
+        }
+        Ok(bytes)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(io::empty()))
+    }
+}
+
+/// Workbook with a single sheet built up in a temporary file, a zip archive can only be sent
+/// once its directory has been written at the end
+struct XlsxExport {
+    zip: ZipWriter<File>,
+}
+
+const XLSX_CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/></Types>"#;
+const XLSX_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#;
+const XLSX_WORKBOOK: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="Customers" sheetId="1" r:id="rId1"/></sheets></workbook>"#;
+const XLSX_WORKBOOK_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/></Relationships>"#;
+
+/// Text escaped for an XML element, characters XML does not allow are dropped
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' ' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Sheet row, amounts and probabilities are written as numbers so they can be summed
+fn xlsx_row(record: &[String]) -> String {
+    let cells: String = record
+        .iter()
+        .zip(COLUMNS)
+        .map(|(value, column)| match column {
+            "amount" | "probability" if value.parse::<f64>().is_ok() => {
+                format!("<c><v>{}</v></c>", value)
+            }
+            _ if value.is_empty() => "<c/>".to_string(),
+            _ => format!(
+                r#"<c t="inlineStr"><is><t xml:space="preserve">{}</t></is></c>"#,
+                xml_escape(value)
+            ),
+        })
+        .collect();
+    format!("<row>{}</row>", cells)
+}
+
+impl XlsxExport {
+    fn new() -> io::Result<Self> {
+        let mut zip = ZipWriter::new(tempfile::tempfile()?);
+        zip.start_file("xl/worksheets/sheet1.xml", FileOptions::default())?;
+        zip.write_all(br#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#)?;
+        zip.write_all(
+            br#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>"#,
+        )?;
+        let header: Vec<String> = COLUMNS.iter().map(|column| column.to_string()).collect();
+        zip.write_all(xlsx_row(&header).as_bytes())?;
+        Ok(XlsxExport { zip })
+    }
+}
+
+impl ExportWriter for XlsxExport {
+    fn write_page(&mut self, customers: &[CustomerExport]) -> io::Result<Vec<u8>> {
+        for record in customers.iter().flat_map(records) {
+            self.zip.write_all(xlsx_row(&record).as_bytes())?;
+        }
+        Ok(vec![])
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<Box<dyn Read + Send>> {
+        self.zip.write_all(b"</sheetData></worksheet>")?;
+        for (name, contents) in [
+            ("[Content_Types].xml", XLSX_CONTENT_TYPES),
+            ("_rels/.rels", XLSX_RELS),
+            ("xl/workbook.xml", XLSX_WORKBOOK),
+            ("xl/_rels/workbook.xml.rels", XLSX_WORKBOOK_RELS),
+        ] {
+            self.zip.start_file(name, FileOptions::default())?;
+            self.zip.write_all(contents.as_bytes())?;
+        }
+        let mut file = self.zip.finish()?;
+        file.rewind()?;
+        Ok(Box::new(file))
+    }
+}
+
+fn export_writer(format: ExportFormat) -> io::Result<Box<dyn ExportWriter>> {
+    Ok(match format {
+        ExportFormat::Csv => Box::<CsvExport>::default(),
+        ExportFormat::Ndjson => Box::new(NdjsonExport),
+        ExportFormat::Xlsx => Box::new(XlsxExport::new()?),
+    })
+}
+
+fn content_type(format: ExportFormat) -> &'static str {
+    match format {
+        ExportFormat::Csv => "text/csv; charset=utf-8",
+        ExportFormat::Ndjson => "application/x-ndjson",
+        ExportFormat::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    }
+}
+
+/// Reads the matching customers a page at a time, seeking past the last customer of the
+/// previous page, and sends each page on as soon as it is written
+async fn send_export(
+    db: Client,
+    filtered: ListQuery,
+    sort: CustomerSortField,
+    mut writer: Box<dyn ExportWriter>,
+    sender: &mut Sender,
+) -> anyhow::Result<()> {
+    let mut after = None;
+    loop {
+        let mut query = filtered.clone();
+        if let Some((value, id)) = after.take() {
+            query = query.seek(value, id);
+        }
+        let page_size = query.page_size();
+        let (query, args) = query.build();
+        let customers: Vec<CustomerExport> = db.query(query.as_str(), &args).await?;
+        let bytes = writer.write_page(&customers)?;
+        if !bytes.is_empty() {
+            sender.send_data(Bytes::from(bytes)).await?;
+        }
+        match customers.last() {
+            Some(last) if customers.len() == page_size => {
+                after = Some((sort_value(&last.customer, &sort), last.customer.id))
+            }
+            _ => break,
+        }
+    }
+    let mut rest = writer.finish()?;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = rest.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        sender
+            .send_data(Bytes::copy_from_slice(&buffer[..read]))
+            .await?;
+    }
+}
+
+/// Downloads every customer matching the list filters along with their opportunities.
+/// The rows are streamed a page at a time so the export is never held in memory as a whole,
+/// if reading fails part way the download is cut short rather than ending cleanly.
+/// Each page is its own query, so the export is not a consistent snapshot: customers changed
+/// while it runs may show their old or new values, or be missed or repeated when the sort field changes.
+async fn export_customers(
+    SessionClient(db): SessionClient,
+    State(config): State<ListConfig>,
+    ActorId(user): ActorId,
+    CurrentOrganization(organization): CurrentOrganization,
+    Query(export): extract::Query<ExportParams>,
+    Query(params): extract::Query<CustomersQueryParams>,
+) -> Result<Response, ApiError> {
+    params.validate()?;
+    // the query is built up front so mistakes in the filters are reported before the download starts
+    let fields = filter_fields(
+        &db,
+        organization,
+        CustomFieldEntity::Customer,
+        &params.custom,
+    )
+    .await?;
+    let filtered = filtered_customers(EXPORT_SHAPE, config, &params, &fields, user, organization)?
+        .order_by(&params.sort, params.direction)
+        .limit(config.max_limit);
+    let writer = export_writer(export.format)
+        .context("The export could not be started")
+        .map_err(ApiError::Internal)?;
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        if let Err(error) = send_export(db, filtered, params.sort, writer, &mut sender).await {
+            tracing::error!("Export failed: {:#}", error);
+            sender.abort();
+        }
+    });
+    Ok((
+        [
+            (CONTENT_TYPE, content_type(export.format).to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"customers.{}\"", export.format),
+            ),
+        ],
+        boxed(body),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use axum::http::StatusCode;
+    use edgedb_protocol::value::Value;
+    use rand::distributions::{Alphanumeric, DistString};
+    use zip::ZipArchive;
+
+    use frontend::{Customer, Opportunity};
+
+    use super::*;
+    const TEST_EMAIL_DOMAIN: &str = "@test.email.com";
+
+    async fn get_db() -> Client {
+        edgedb_tokio::create_client()
+            .await
+            .expect("Failed to connect to the DB")
+    }
+
+    fn export_customer(opportunities: Vec<&str>) -> CustomerExport {
+        CustomerExport {
+            customer: Customer {
+                name: "Acme, \"the\" company".to_string(),
+                email: "sales@acme.com".to_string(),
+                status: "Active".to_string(),
+                ..Default::default()
+            },
+            opportunities: opportunities
+                .into_iter()
+                .map(|name| Opportunity {
+                    name: name.to_string(),
+                    status: "Lead".to_string(),
+                    amount: Some("1200.50".to_string()),
+                    probability: 40,
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn customers_should_have_a_row_per_opportunity() {
+        assert_eq!(
+            2,
+            records(&export_customer(vec!["Renewal", "Upsell"])).len()
+        );
+        let records = records(&export_customer(vec![]));
+        assert_eq!(1, records.len());
+        assert_eq!(COLUMNS.len(), records[0].len());
+        assert_eq!("", records[0][5]);
+    }
+
+    #[test]
+    fn csv_export_should_write_the_header_once() {
+        let mut writer = CsvExport::default();
+        let first = writer
+            .write_page(&[export_customer(vec!["Renewal"])])
+            .unwrap();
+        let second = writer.write_page(&[export_customer(vec![])]).unwrap();
+        let text = String::from_utf8([first, second].concat()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(3, lines.len());
+        assert!(lines[0].starts_with("customer_id,customer_name"));
+        assert!(lines[1].contains("\"Acme, \"\"the\"\" company\""));
+    }
+
+    #[test]
+    fn xlsx_export_should_be_a_workbook_with_escaped_cells() {
+        let mut writer = Box::new(XlsxExport::new().unwrap());
+        assert!(writer
+            .write_page(&[export_customer(vec!["R&D <pilot>"])])
+            .unwrap()
+            .is_empty());
+        let mut bytes = vec![];
+        writer.finish().unwrap().read_to_end(&mut bytes).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let mut sheet = String::new();
+        archive
+            .by_name("xl/worksheets/sheet1.xml")
+            .unwrap()
+            .read_to_string(&mut sheet)
+            .unwrap();
+        assert!(sheet.contains("R&amp;D &lt;pilot&gt;"));
+        assert!(sheet.contains("<c><v>1200.50</v></c>"));
+        assert!(archive.by_name("xl/workbook.xml").is_ok());
+    }
+
+    #[tokio::test]
+    async fn export_should_stream_the_filtered_customers() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let email = format!("{}{}", random_string, TEST_EMAIL_DOMAIN);
+        let _: Value = db
+            .query_required_single(
+                "insert Customer { name := <str>$0, email := <str>$1, status := CustomerStatus.Lead };",
+                &(format!("Export {}", random_string), email.clone()),
+            )
+            .await
+            .expect("Failed to add");
+        let params = CustomersQueryParams {
+            email_domain: Some(TEST_EMAIL_DOMAIN.to_string()),
+            ..Default::default()
+        };
+        let response = export_customers(
+            SessionClient(db.clone()),
+            State(ListConfig { max_limit: 2 }),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            Query(ExportParams {
+                format: ExportFormat::Ndjson,
+            }),
+            Query(params),
+        )
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let exported: Vec<CustomerExport> = String::from_utf8(body.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert!(exported.iter().any(|c| c.customer.email == email));
+        assert!(exported
+            .iter()
+            .all(|c| c.customer.email.ends_with(TEST_EMAIL_DOMAIN)));
+        let _: Value = db
+            .query_required_single("delete Customer filter .email = <str>$0;", &(email,))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,792 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{self, DefaultBodyLimit, Multipart, Query},
+    handler::Handler,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use edgedb_tokio::{Client, Transaction};
+use frontend::{
+    check_custom_values, AuditAction, AuditEntity, CustomField, CustomFieldEntity, CustomValues,
+    Customer, CustomerStatus, ImportParams, ImportReport, ImportRowError, OnDuplicate,
+    OrganizationId, Permission, UserId,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    actor::{Actor, ActorId},
+    audit::AuditTrail,
+    auth::SessionClient,
+    custom_fields::{custom_fields, custom_json},
+    customers::insert_customer,
+    errors::{from_json, ApiError},
+    organizations::CurrentOrganization,
+    permissions::{allow, CurrentRole},
+    transaction::transaction,
+    AppState,
+};
+
+/// Rows saved per transaction
+const BATCH_SIZE: usize = 100;
+/// Largest file accepted, enough for tens of thousands of customers
+const MAX_FILE_BYTES: usize = 10 * 1024 * 1024;
+
+pub fn import_routes() -> Router<AppState> {
+    Router::new().route(
+        "/customers/import",
+        post(import_customers.layer(allow(Permission::Edit)))
+            .layer(DefaultBodyLimit::max(MAX_FILE_BYTES)),
+    )
+}
+
+/// Customer read from a line of the file
+#[derive(Debug, Clone, PartialEq)]
+struct Row {
+    line: usize,
+    customer: Customer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Change {
+    Create,
+    Update,
+}
+
+/// Customer a row updates along with the custom values it has now
+#[derive(Debug, Deserialize)]
+struct StoredCustom {
+    id: Uuid,
+    custom: CustomValues,
+}
+
+/// Customer already using an email that is in the file
+#[derive(Debug, Deserialize)]
+struct ExistingEmail {
+    email: String,
+    in_trash: bool,
+    status: String,
+}
+
+fn row_error(line: usize, error: ApiError) -> ImportRowError {
+    ImportRowError {
+        row: line,
+        error: error.into_parts().1,
+    }
+}
+
+/// Reads the customers from the file through the column mapping, the header must have the
+/// name and email columns. Columns named after the key of a custom field fill its value.
+/// Lines that can not be read are returned as errors.
+fn read_rows(
+    file: &[u8],
+    params: &ImportParams,
+    fields: &[CustomField],
+) -> Result<(Vec<Row>, Vec<ImportRowError>), ApiError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(file);
+    let headers = reader
+        .headers()
+        .map_err(|error| ApiError::BadRequest(format!("The file is not valid CSV: {}", error)))?
+        .clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header.eq_ignore_ascii_case(name.trim()))
+    };
+    let missing = |name: &str| ApiError::BadRequest(format!("The file has no `{}` column", name));
+    let name = column(&params.name_column).ok_or_else(|| missing(&params.name_column))?;
+    let email = column(&params.email_column).ok_or_else(|| missing(&params.email_column))?;
+    let status = column(&params.status_column);
+    let custom: Vec<(&str, usize)> = fields
+        .iter()
+        .filter_map(|field| column(&field.key).map(|index| (field.key.as_str(), index)))
+        .collect();
+
+    let mut rows = vec![];
+    let mut errors = vec![];
+    for record in reader.records() {
+        match record {
+            Ok(record) => {
+                let cell = |index: usize| record.get(index).unwrap_or_default().to_string();
+                rows.push(Row {
+                    line: record
+                        .position()
+                        .map(|p| p.line() as usize)
+                        .unwrap_or_default(),
+                    customer: Customer {
+                        name: cell(name),
+                        email: cell(email),
+                        status: status
+                            .map(cell)
+                            .filter(|status| !status.is_empty())
+                            .unwrap_or_else(|| CustomerStatus::Active.to_string()),
+                        custom: custom
+                            .iter()
+                            .map(|(key, index)| (key.to_string(), cell(*index)))
+                            .filter(|(_, value)| !value.is_empty())
+                            .map(|(key, value)| (key, serde_json::Value::String(value)))
+                            .collect::<CustomValues>(),
+                        ..Default::default()
+                    },
+                });
+            }
+            Err(error) => errors.push(row_error(
+                error
+                    .position()
+                    .map(|p| p.line() as usize)
+                    .unwrap_or_default(),
+                ApiError::BadRequest(format!("The line could not be read: {}", error)),
+            )),
+        }
+    }
+    Ok((rows, errors))
+}
+
+/// Decides what happens to each row, counting skipped rows and collecting errors in the report.
+/// `existing` holds the customers already using an email of the file by that email.
+/// Custom values are checked against `fields`, an update only checks and changes the values the
+/// row has a cell for and keeps the others. Moving a customer to NonActive needs `role` to be
+/// allowed to deactivate customers, as it does when the customer is edited.
+fn plan_rows(
+    rows: Vec<Row>,
+    fields: &[CustomField],
+    existing: &HashMap<String, ExistingEmail>,
+    on_duplicate: OnDuplicate,
+    role: CurrentRole,
+    report: &mut ImportReport,
+) -> Vec<(Change, Row)> {
+    let mut first_lines: HashMap<String, usize> = HashMap::new();
+    let mut planned = vec![];
+    for row in rows {
+        if let Err(errors) = row.customer.validate() {
+            report.errors.push(row_error(row.line, errors.into()));
+            continue;
+        }
+        if let Some(first) = first_lines.get(&row.customer.email) {
+            let message = format!("The email is already on row {}", first);
+            report.errors.push(row_error(
+                row.line,
+                ApiError::Conflict("email".to_string(), message),
+            ));
+            continue;
+        }
+        first_lines.insert(row.customer.email.clone(), row.line);
+        let change = match (existing.get(&row.customer.email), on_duplicate) {
+            (None, _) => Change::Create,
+            (Some(_), OnDuplicate::Skip) => {
+                report.skipped += 1;
+                continue;
+            }
+            (Some(stored), OnDuplicate::Update) if !stored.in_trash => {
+                let deactivating = row.customer.status == CustomerStatus::NonActive.to_string()
+                    && stored.status != row.customer.status;
+                if deactivating {
+                    if let Err(error) = role.require(Permission::DeactivateCustomers) {
+                        report.errors.push(row_error(row.line, error));
+                        continue;
+                    }
+                }
+                Change::Update
+            }
+            (Some(_), OnDuplicate::Update) => {
+                report.errors.push(row_error(
+                    row.line,
+                    ApiError::Conflict(
+                        "email".to_string(),
+                        "The customer with this email is in the trash, restore it to update it"
+                            .to_string(),
+                    ),
+                ));
+                continue;
+            }
+            (Some(_), OnDuplicate::Fail) => {
+                report.errors.push(row_error(
+                    row.line,
+                    ApiError::Conflict(
+                        "email".to_string(),
+                        "A customer with this email already exists".to_string(),
+                    ),
+                ));
+                continue;
+            }
+        };
+        let checked = match change {
+            Change::Create => check_custom_values(fields, &row.customer.custom),
+            Change::Update => {
+                let given: Vec<CustomField> = fields
+                    .iter()
+                    .filter(|field| row.customer.custom.contains_key(&field.key))
+                    .cloned()
+                    .collect();
+                check_custom_values(&given, &row.customer.custom)
+            }
+        };
+        match checked {
+            Ok(custom) => {
+                let customer = Customer {
+                    custom,
+                    ..row.customer
+                };
+                planned.push((change, Row { customer, ..row }))
+            }
+            Err(errors) => report
+                .errors
+                .push(row_error(row.line, ApiError::InvalidFields(errors))),
+        }
+    }
+    report.created = planned
+        .iter()
+        .filter(|(change, _)| *change == Change::Create)
+        .count();
+    report.updated = planned.len() - report.created;
+    planned
+}
+
+/// Emails of the rows that already belong to a customer of the organisation, including customers in the trash
+async fn existing_emails(
+    db: &Client,
+    rows: &[Row],
+    organization: Option<OrganizationId>,
+) -> Result<HashMap<String, ExistingEmail>, ApiError> {
+    let emails: Vec<&str> = rows.iter().map(|row| row.customer.email.as_str()).collect();
+    let emails = serde_json::to_string(&emails).unwrap_or_else(|_| "[]".to_string());
+    let json = db
+        .query_json(
+            r#"
+            select Customer {
+                email,
+                in_trash := exists .deleted,
+                status
+            } filter .email in <str>json_array_unpack(to_json(<str>$0))
+                and (not exists <optional uuid>$1 or .organization.id ?= <optional uuid>$1);"#,
+            &(emails, organization),
+        )
+        .await?;
+    let existing: Vec<ExistingEmail> = from_json(&json)?;
+    Ok(existing
+        .into_iter()
+        .map(|customer| (customer.email.clone(), customer))
+        .collect())
+}
+
+/// Saves a row, an update only touches the customer when the name, status or custom values differ.
+/// The custom values of the row are added to those the customer already has.
+async fn save_row(
+    tx: &mut Transaction,
+    change: Change,
+    customer: &Customer,
+    actor: &Actor,
+    creator: Option<UserId>,
+    organization: Option<OrganizationId>,
+) -> Result<(), edgedb_tokio::Error> {
+    match change {
+        Change::Create => {
+            insert_customer(tx, customer, actor, creator, organization).await?;
+        }
+        Change::Update => {
+            let json = tx
+                .query_required_single_json(
+                    r#"
+                    select Customer { id, custom }
+                    filter .email = <str>$0 and not exists .deleted
+                        and (not exists <optional uuid>$1 or .organization.id ?= <optional uuid>$1)
+                    limit 1;"#,
+                    &(customer.email.clone(), organization),
+                )
+                .await?;
+            let StoredCustom { id, mut custom } = from_json(&json)?;
+            custom.extend(customer.custom.clone());
+            let trail = AuditTrail::start(tx, AuditEntity::Customer, id, id, actor).await?;
+            let _: Option<Uuid> = tx
+                .query_single(
+                    r#"
+                    select (
+                        update Customer filter .id = <uuid>$0
+                            and (
+                                .name != <str>$1
+                                or .status != <CustomerStatus><str>$2
+                                or .custom != to_json(<str>$4)
+                            )
+                        set {
+                            name := <str>$1,
+                            status := <str>$2,
+                            custom := to_json(<str>$4),
+                            modified := datetime_current(),
+                            modified_by := <optional str>$3,
+                        }
+                    ).id;"#,
+                    &(
+                        id,
+                        customer.name.clone(),
+                        customer.status.clone(),
+                        actor.0.clone(),
+                        custom_json(&custom),
+                    ),
+                )
+                .await?;
+            trail.finish(tx, AuditAction::Updated).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks the rows of the file and, unless it is a dry run or a row has an error, saves them
+/// in batches each in its own transaction. When a batch fails the batches before it stay saved
+/// and the report counts only the saved rows.
+async fn import(
+    db: Client,
+    actor: Actor,
+    ActorId(creator): ActorId,
+    CurrentOrganization(organization): CurrentOrganization,
+    role: CurrentRole,
+    params: ImportParams,
+    file: &[u8],
+) -> Result<(StatusCode, ImportReport), ApiError> {
+    let fields = custom_fields(&db, organization, CustomFieldEntity::Customer).await?;
+    let (rows, errors) = read_rows(file, &params, &fields)?;
+    let mut report = ImportReport {
+        dry_run: params.dry_run,
+        rows: rows.len() + errors.len(),
+        errors,
+        ..Default::default()
+    };
+    let existing = existing_emails(&db, &rows, organization).await?;
+    let planned = plan_rows(
+        rows,
+        &fields,
+        &existing,
+        params.on_duplicate,
+        role,
+        &mut report,
+    );
+    report.errors.sort_by_key(|error| error.row);
+    if params.dry_run {
+        return Ok((StatusCode::OK, report));
+    }
+    if !report.errors.is_empty() {
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, report));
+    }
+
+    report.created = 0;
+    report.updated = 0;
+    for batch in planned.chunks(BATCH_SIZE) {
+        let first_line = batch.first().map(|(_, row)| row.line).unwrap_or_default();
+        let last_line = batch.last().map(|(_, row)| row.line).unwrap_or_default();
+        let batch = batch.to_vec();
+        let actor = actor.clone();
+        let saved = transaction(db.clone(), move |mut tx| {
+            let batch = batch.clone();
+            let actor = actor.clone();
+            async move {
+                for (change, row) in &batch {
+                    save_row(
+                        &mut tx,
+                        *change,
+                        &row.customer,
+                        &actor,
+                        creator,
+                        organization,
+                    )
+                    .await?;
+                }
+                Ok(batch)
+            }
+        })
+        .await;
+        match saved {
+            Ok(batch) => {
+                for (change, _) in batch {
+                    match change {
+                        Change::Create => report.created += 1,
+                        Change::Update => report.updated += 1,
+                    }
+                }
+            }
+            Err(error) => {
+                let mut error = row_error(first_line, error);
+                error.error.message = format!(
+                    "Rows {} to {} and those after them were not saved: {}",
+                    first_line, last_line, error.error.message
+                );
+                report.errors.push(error);
+                return Ok((StatusCode::UNPROCESSABLE_ENTITY, report));
+            }
+        }
+    }
+    Ok((StatusCode::OK, report))
+}
+
+/// Imports customers from the CSV file sent in the `file` field of the form
+async fn import_customers(
+    SessionClient(db): SessionClient,
+    actor: Actor,
+    creator: ActorId,
+    organization: CurrentOrganization,
+    role: CurrentRole,
+    Query(params): extract::Query<ImportParams>,
+    mut multipart: Multipart,
+) -> Result<Response, ApiError> {
+    let bad_form = |error: extract::multipart::MultipartError| {
+        ApiError::BadRequest(format!("The form could not be read: {}", error))
+    };
+    while let Some(field) = multipart.next_field().await.map_err(bad_form)? {
+        if field.name() == Some("file") {
+            let file = field.bytes().await.map_err(bad_form)?;
+            let (status, report) =
+                import(db, actor, creator, organization, role, params, &file).await?;
+            return Ok((status, Json(report)).into_response());
+        }
+    }
+    Err(ApiError::BadRequest(
+        "The CSV file must be sent in the `file` field".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use edgedb_protocol::value::Value;
+    use frontend::Role;
+    use rand::distributions::{Alphanumeric, DistString};
+
+    use super::*;
+    const TEST_EMAIL_DOMAIN: &str = "@test.email.com";
+
+    async fn get_db() -> Client {
+        edgedb_tokio::create_client()
+            .await
+            .expect("Failed to connect to the DB")
+    }
+
+    fn stored(email: &str, in_trash: bool, status: CustomerStatus) -> (String, ExistingEmail) {
+        (
+            email.to_string(),
+            ExistingEmail {
+                email: email.to_string(),
+                in_trash,
+                status: status.to_string(),
+            },
+        )
+    }
+
+    fn customer(line: usize, name: &str, email: &str) -> Row {
+        Row {
+            line,
+            customer: Customer {
+                name: name.to_string(),
+                email: email.to_string(),
+                status: "Active".to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn rows_should_be_read_through_the_column_mapping() {
+        let file = "Company,E-mail\nAcme Ltd, sales@acme.com \nGlobex,\"info@globex.com\"\n";
+        let params = ImportParams {
+            name_column: "company".to_string(),
+            email_column: "E-mail".to_string(),
+            ..Default::default()
+        };
+        let (rows, errors) = read_rows(file.as_bytes(), &params, &[]).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(
+            vec![
+                customer(2, "Acme Ltd", "sales@acme.com"),
+                customer(3, "Globex", "info@globex.com")
+            ],
+            rows
+        );
+    }
+
+    #[test]
+    fn missing_column_should_be_bad_request() {
+        let result = read_rows(
+            b"name,mail\nAcme,a@acme.com\n",
+            &ImportParams::default(),
+            &[],
+        );
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+    }
+
+    #[test]
+    fn rows_should_be_planned_by_the_duplicate_choice() {
+        let rows = || {
+            vec![
+                customer(2, "Acme", "new@acme.com"),
+                customer(3, "Acme", "taken@acme.com"),
+                customer(4, "Acme", "new@acme.com"),
+                customer(5, "A", "not an email"),
+            ]
+        };
+        let existing = HashMap::from([stored("taken@acme.com", false, CustomerStatus::Active)]);
+        let role = CurrentRole(Some(Role::SalesRep));
+
+        let mut report = ImportReport::default();
+        let planned = plan_rows(rows(), &[], &existing, OnDuplicate::Skip, role, &mut report);
+        assert_eq!(
+            vec![Change::Create],
+            planned.iter().map(|(c, _)| *c).collect::<Vec<_>>()
+        );
+        assert_eq!((1, 0, 1), (report.created, report.updated, report.skipped));
+        assert_eq!(
+            vec![4, 5],
+            report.errors.iter().map(|e| e.row).collect::<Vec<_>>()
+        );
+        assert_eq!("conflict", report.errors[0].error.code);
+        assert_eq!("validation", report.errors[1].error.code);
+
+        let mut report = ImportReport::default();
+        plan_rows(
+            rows(),
+            &[],
+            &existing,
+            OnDuplicate::Update,
+            role,
+            &mut report,
+        );
+        assert_eq!((1, 1, 0), (report.created, report.updated, report.skipped));
+
+        let mut report = ImportReport::default();
+        plan_rows(rows(), &[], &existing, OnDuplicate::Fail, role, &mut report);
+        assert_eq!(
+            vec![3, 4, 5],
+            report.errors.iter().map(|e| e.row).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn custom_columns_should_be_checked_for_new_customers() {
+        let fields = vec![CustomField {
+            key: "seats".to_string(),
+            label: "Seats".to_string(),
+            kind: frontend::CustomFieldKind::Number,
+            ..Default::default()
+        }];
+        let file = "name,email,seats\nAcme,a@acme.com,12\nGlobex,g@globex.com,many\nInitech,i@initech.com,\n";
+        let (rows, _) = read_rows(file.as_bytes(), &ImportParams::default(), &fields).unwrap();
+
+        let mut report = ImportReport::default();
+        let planned = plan_rows(
+            rows,
+            &fields,
+            &HashMap::new(),
+            OnDuplicate::Fail,
+            CurrentRole(Some(Role::SalesRep)),
+            &mut report,
+        );
+        assert_eq!(
+            vec![Some(&serde_json::json!(12.0)), None],
+            planned
+                .iter()
+                .map(|(_, row)| row.customer.custom.get("seats"))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![3],
+            report.errors.iter().map(|e| e.row).collect::<Vec<_>>()
+        );
+        assert!(report.errors[0].error.fields.contains_key("custom.seats"));
+    }
+
+    #[test]
+    fn updates_should_only_check_the_custom_values_in_the_file() {
+        let fields = vec![
+            CustomField {
+                key: "seats".to_string(),
+                label: "Seats".to_string(),
+                kind: frontend::CustomFieldKind::Number,
+                ..Default::default()
+            },
+            CustomField {
+                key: "industry".to_string(),
+                label: "Industry".to_string(),
+                is_required: true,
+                ..Default::default()
+            },
+        ];
+        let file = "name,email,seats\nAcme,a@acme.com,12\nGlobex,g@globex.com,many\nInitech,i@initech.com,\n";
+        let (rows, _) = read_rows(file.as_bytes(), &ImportParams::default(), &fields).unwrap();
+        let existing = rows
+            .iter()
+            .map(|row| stored(&row.customer.email, false, CustomerStatus::Active))
+            .collect();
+
+        let mut report = ImportReport::default();
+        let planned = plan_rows(
+            rows,
+            &fields,
+            &existing,
+            OnDuplicate::Update,
+            CurrentRole(Some(Role::SalesRep)),
+            &mut report,
+        );
+        assert_eq!(
+            vec![Some(&serde_json::json!(12.0)), None],
+            planned
+                .iter()
+                .map(|(_, row)| row.customer.custom.get("seats"))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![3],
+            report.errors.iter().map(|e| e.row).collect::<Vec<_>>()
+        );
+        assert!(!report.errors[0]
+            .error
+            .fields
+            .contains_key("custom.industry"));
+    }
+
+    #[test]
+    fn only_roles_that_may_deactivate_should_import_non_active_updates() {
+        let file = "name,email,status\nAcme,a@acme.com,NonActive\nGlobex,g@globex.com,NonActive\n";
+        let (rows, _) = read_rows(file.as_bytes(), &ImportParams::default(), &[]).unwrap();
+        let existing = HashMap::from([
+            stored("a@acme.com", false, CustomerStatus::Active),
+            stored("g@globex.com", false, CustomerStatus::NonActive),
+        ]);
+        let plan = |role: Role| {
+            let mut report = ImportReport::default();
+            plan_rows(
+                rows.clone(),
+                &[],
+                &existing,
+                OnDuplicate::Update,
+                CurrentRole(Some(role)),
+                &mut report,
+            );
+            report
+        };
+
+        let rep = plan(Role::SalesRep);
+        let manager = plan(Role::SalesManager);
+
+        assert_eq!(
+            vec![(2, "forbidden".to_string())],
+            rep.errors
+                .iter()
+                .map(|e| (e.row, e.error.code.clone()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, rep.updated);
+        assert!(manager.errors.is_empty());
+        assert_eq!(2, manager.updated);
+    }
+
+    #[tokio::test]
+    async fn import_should_save_nothing_on_a_dry_run_or_with_errors() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let email = format!("{}{}", random_string, TEST_EMAIL_DOMAIN);
+        let file = format!(
+            "name,email\nImport {0},{1}\nImport {0},{1}\n",
+            random_string, email
+        );
+        let count = |db: Client, email: String| async move {
+            db.query_required_single::<i64, _>(
+                "select count(Customer filter .email = <str>$0);",
+                &(email,),
+            )
+            .await
+            .unwrap()
+        };
+
+        let dry_run = ImportParams {
+            dry_run: true,
+            ..Default::default()
+        };
+        let (status, report) = import(
+            db.clone(),
+            Actor::default(),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            CurrentRole(Some(Role::SalesRep)),
+            dry_run,
+            file.as_bytes(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!((2, 1), (report.rows, report.created));
+        assert_eq!(
+            vec![3],
+            report.errors.iter().map(|e| e.row).collect::<Vec<_>>()
+        );
+
+        let (status, _) = import(
+            db.clone(),
+            Actor::default(),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            CurrentRole(Some(Role::SalesRep)),
+            ImportParams::default(),
+            file.as_bytes(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+        assert_eq!(0, count(db.clone(), email.clone()).await);
+    }
+
+    #[tokio::test]
+    async fn import_should_create_then_update_by_email() {
+        let db = get_db().await;
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let email = format!("{}{}", random_string, TEST_EMAIL_DOMAIN);
+        let params = ImportParams {
+            on_duplicate: OnDuplicate::Update,
+            ..Default::default()
+        };
+        let created = format!("name,email\nImport {},{}\n", random_string, email);
+        let (status, report) = import(
+            db.clone(),
+            Actor::default(),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            CurrentRole(Some(Role::SalesManager)),
+            params.clone(),
+            created.as_bytes(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!((1, 0), (report.created, report.updated));
+
+        let updated = format!(
+            "name,email,status\nRenamed {},{},NonActive\n",
+            random_string, email
+        );
+        let (_, report) = import(
+            db.clone(),
+            Actor::default(),
+            ActorId::default(),
+            CurrentOrganization::default(),
+            CurrentRole(Some(Role::SalesManager)),
+            params,
+            updated.as_bytes(),
+        )
+        .await
+        .unwrap();
+        assert_eq!((0, 1), (report.created, report.updated));
+        let customer: Customer = db
+            .query_required_single(
+                "select <json>Customer { id, name, email, status, created } filter .email = <str>$0;",
+                &(email.clone(),),
+            )
+            .await
+            .unwrap();
+        assert_eq!(format!("Renamed {}", random_string), customer.name);
+        assert_eq!("NonActive", customer.status);
+        db.query_required_single::<Value, _>("delete Customer filter .email = <str>$0;", &(email,))
+            .await
+            .unwrap();
+    }
+}
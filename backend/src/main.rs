@@ -1,6 +1,13 @@
-use axum::{routing::get_service, Router};
+use audit::audit_routes;
+use auth::{auth_routes, require_session, user_routes};
+use axum::{extract::FromRef, middleware, routing::get_service, Router};
+use contacts::contact_routes;
+use custom_fields::custom_field_routes;
 use customers::customer_routes;
-use edgedb_tokio::RetryOptions;
+use duplicates::duplicate_routes;
+use edgedb_tokio::{Client, RetryOptions};
+use export::export_routes;
+use import::import_routes;
 use opentelemetry::sdk::trace::{self};
 use opentelemetry::{
     global::{self},
@@ -8,15 +15,57 @@ use opentelemetry::{
     KeyValue,
 };
 use opentelemetry_otlp::WithExportConfig;
+use organizations::{organization_routes, same_organization};
+use pipelines::pipeline_routes;
+use query::ListConfig;
 use std::time::Duration;
 use std::{collections::HashMap, env, net::SocketAddr, path::PathBuf};
+use tags::tag_routes;
+use tasks::task_routes;
 use tokio::signal;
 use tower_http::{catch_panic::CatchPanicLayer, services::ServeFile, trace::TraceLayer};
 use tracing::instrument::WithSubscriber;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::{filter, Layer};
+mod actor;
+mod audit;
+mod auth;
+mod contacts;
+mod custom_fields;
 mod customers;
+mod duplicates;
+mod errors;
+mod export;
+mod import;
+mod organizations;
+mod pagination;
+mod permissions;
+mod pipelines;
+mod query;
+mod tags;
+mod tasks;
+mod transaction;
+mod version;
+
+/// State shared with the handlers, each handler extracts only the parts it needs
+#[derive(Clone)]
+pub struct AppState {
+    pub db: Client,
+    pub list: ListConfig,
+}
+
+impl FromRef<AppState> for Client {
+    fn from_ref(state: &AppState) -> Self {
+        state.db.clone()
+    }
+}
+
+impl FromRef<AppState> for ListConfig {
+    fn from_ref(state: &AppState) -> Self {
+        state.list
+    }
+}
 
 async fn setup_server() -> Router {
     let assets_dir = PathBuf::from("./dist");
@@ -33,9 +82,39 @@ async fn setup_server() -> Router {
             Duration::from_millis(10 * attempt.pow(2) as u64)
         }));
 
+    let state = AppState {
+        db: edge_db,
+        list: ListConfig::from_env(),
+    };
     Router::new()
         .fallback(static_files_service)
-        .nest("/api", customer_routes().with_state(edge_db))
+        .nest(
+            "/api",
+            customer_routes()
+                .merge(contact_routes())
+                .merge(task_routes())
+                .merge(duplicate_routes())
+                // `:id` is a customer in every route above
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    same_organization,
+                ))
+                .merge(pipeline_routes())
+                .merge(audit_routes())
+                .merge(import_routes())
+                .merge(export_routes())
+                .merge(tag_routes())
+                .merge(custom_field_routes())
+                .merge(user_routes())
+                .merge(organization_routes())
+                // every route above needs a session, signing in does not
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    require_session,
+                ))
+                .merge(auth_routes())
+                .with_state(state),
+        )
         .layer(CatchPanicLayer::new())
         .layer(TraceLayer::new_for_http())
 }
@@ -0,0 +1,417 @@
+use std::{collections::HashMap, convert::Infallible};
+
+use axum::{
+    async_trait,
+    extract::{self, FromRequestParts, Path, State},
+    handler::Handler,
+    http::{request::Parts, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use edgedb_tokio::Client;
+use frontend::{NewOrganization, OrganizationId, Permission, User};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    actor::Actor,
+    auth::{hash_password, USER_SHAPE},
+    errors::ApiError,
+    permissions::allow,
+    AppState,
+};
+
+// Users, customers, opportunities, tags, pipelines and custom fields have access policies hiding other
+// organisations, the `SessionClient` the handlers query with has the `current_organization` global set by
+// `require_session`.
+// Records without a policy of their own, such as tasks, are filtered on the signed in user's organisation
+// by the handlers, the same way the policies do: `not exists <optional uuid>$n or .organization.id ?= <optional uuid>$n`.
+
+pub fn organization_routes() -> Router<AppState> {
+    Router::new().route(
+        "/organizations",
+        post(create_organization.layer(allow(Permission::ManageOrganizations))),
+    )
+}
+
+/// Organisation of the signed in user, the records of other organisations are hidden from them.
+/// `None` only in tests, then nothing is hidden and new records go to the first organisation.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CurrentOrganization(pub Option<OrganizationId>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for CurrentOrganization {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(CurrentOrganization(
+            parts
+                .extensions
+                .get::<User>()
+                .map(|user| user.organization.id),
+        ))
+    }
+}
+
+/// Answers requests for a customer of another organisation with a 404, as if it did not exist.
+/// Only for routes where `:id` is a customer, has to sit behind `require_session`.
+pub async fn same_organization<B>(
+    State(db): State<Client>,
+    CurrentOrganization(organization): CurrentOrganization,
+    params: Option<Path<HashMap<String, String>>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, ApiError> {
+    let customer =
+        params.and_then(|Path(params)| params.get("id").and_then(|id| Uuid::parse_str(id).ok()));
+    if let (Some(organization), Some(customer)) = (organization, customer) {
+        let visible: bool = db
+            .query_required_single(
+                "select exists (select Customer filter .id = <uuid>$0 and .organization.id = <uuid>$1);",
+                &(customer, organization),
+            )
+            .await?;
+        if !visible {
+            return Err(ApiError::NotFound);
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+/// Sets up another organisation along with its first admin, who adds the rest of its users, and a
+/// default pipeline with the same stages the first organisation started with.
+/// Uses the client without globals, the policies would not let the admin into another organisation.
+async fn create_organization(
+    State(db): State<Client>,
+    actor: Actor,
+    Json(body): extract::Json<NewOrganization>,
+) -> Result<Response, ApiError> {
+    body.validate()?;
+    let hash = hash_password(&body.password)?;
+    let query = format!(
+        r#"
+        with
+            organization := (insert Organization {{
+                name := str_trim(<str>$0),
+                created_by := <optional str>$4,
+                modified_by := <optional str>$4,
+            }}),
+            pipeline := (insert Pipeline {{
+                name := 'Sales',
+                is_default := true,
+                organization := organization,
+                stages := (
+                    for stage in {{
+                        ('New', StageOutcome.Open, 0),
+                        ('Qualification', StageOutcome.Open, 1),
+                        ('Proposal', StageOutcome.Open, 2),
+                        ('Negotiation', StageOutcome.Open, 3),
+                        ('ClosedWon', StageOutcome.Won, 4),
+                        ('ClosedLost', StageOutcome.Lost, 5),
+                    }} union (
+                        insert Stage {{
+                            name := stage.0,
+                            outcome := stage.1,
+                            position := <int16>stage.2,
+                            created_by := <optional str>$4,
+                            modified_by := <optional str>$4,
+                        }}
+                    )
+                ),
+                created_by := <optional str>$4,
+                modified_by := <optional str>$4,
+            }})
+        select <json>(insert User {{
+            email := str_lower(str_trim(<str>$1)),
+            name := str_trim(<str>$2),
+            password_hash := <str>$3,
+            role := Role.Admin,
+            organization := organization,
+            created_by := <optional str>$4,
+            modified_by := <optional str>$4,
+        }}) {{ {} }};"#,
+        USER_SHAPE
+    );
+    let user: User = db
+        .query_required_single(
+            query.as_str(),
+            &(body.organization, body.email, body.name, hash, actor.0),
+        )
+        .await?;
+    Ok((StatusCode::CREATED, Json(user)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get};
+    use frontend::{ErrorResponse, Organization, Role};
+    use rand::distributions::{Alphanumeric, DistString};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::auth::SessionClient;
+
+    async fn get_db() -> Client {
+        edgedb_tokio::create_client()
+            .await
+            .expect("Failed to connect to the DB")
+    }
+
+    async fn add_organization(db: &Client) -> User {
+        let random_string = Alphanumeric
+            .sample_string(&mut rand::thread_rng(), 16)
+            .to_lowercase();
+        let response = create_organization(
+            State(db.clone()),
+            Actor::default(),
+            Json(NewOrganization {
+                organization: format!("Organization {}", random_string),
+                email: format!("{}@test.email.com", random_string),
+                name: "Test Admin".to_string(),
+                password: "a long enough password".to_string(),
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::CREATED, response.status());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    async fn remove_organization(db: &Client, organization: &Organization) {
+        db.query::<Uuid, _>(
+            r#"
+            with
+                customers := (delete Customer filter .organization.id = <uuid>$0),
+                users := (delete User filter .organization.id = <uuid>$0),
+                pipelines := (delete Pipeline filter .organization.id = <uuid>$0)
+            select (delete Organization filter .id = <uuid>$0).id;"#,
+            &(organization.id,),
+        )
+        .await
+        .unwrap();
+    }
+
+    async fn status(db: &Client, user: &User, customer: Uuid) -> StatusCode {
+        let app: Router = Router::new()
+            .route("/customer/:id", get(|| async { "done" }))
+            .route_layer(middleware::from_fn_with_state(
+                db.clone(),
+                same_organization,
+            ));
+        let mut request = Request::get(format!("/customer/{}", customer))
+            .body(Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(user.clone());
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn organization_should_be_read_from_the_signed_in_user() {
+        let id = Uuid::from_u128(rand::random());
+        let user = User {
+            organization: Organization {
+                id,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut parts = Request::new(()).into_parts().0;
+        parts.extensions.insert(user);
+        assert_eq!(
+            CurrentOrganization(Some(id)),
+            CurrentOrganization::from_request_parts(&mut parts, &())
+                .await
+                .unwrap()
+        );
+        let mut parts = Request::new(()).into_parts().0;
+        assert_eq!(
+            CurrentOrganization(None),
+            CurrentOrganization::from_request_parts(&mut parts, &())
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn customers_of_other_organizations_should_be_not_found() {
+        let db = get_db().await;
+        let first = add_organization(&db).await;
+        let second = add_organization(&db).await;
+        let email = format!(
+            "{}@test.email.com",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 16)
+        );
+        // the same email can be used once in each organisation
+        let mut customers = vec![];
+        for user in [&first, &second] {
+            let id: Uuid = db
+                .query_required_single(
+                    r#"
+                    select (insert Customer {
+                        name := 'Test',
+                        email := <str>$0,
+                        organization := (select Organization filter .id = <uuid>$1),
+                    }).id;"#,
+                    &(email.clone(), user.organization.id),
+                )
+                .await
+                .unwrap();
+            customers.push(id);
+        }
+        let own = status(&db, &first, customers[0]).await;
+        let other = status(&db, &first, customers[1]).await;
+        remove_organization(&db, &first.organization).await;
+        remove_organization(&db, &second.organization).await;
+
+        assert_eq!(Role::Admin, first.role);
+        assert_eq!(StatusCode::OK, own);
+        assert_eq!(StatusCode::NOT_FOUND, other);
+    }
+
+    #[tokio::test]
+    async fn session_client_should_only_see_its_own_organization() {
+        let db = get_db().await;
+        let first = add_organization(&db).await;
+        let second = add_organization(&db).await;
+        let id: Uuid = db
+            .query_required_single(
+                r#"
+                select (insert Customer {
+                    name := 'Test',
+                    email := 'policy@test.email.com',
+                    organization := (select Organization filter .id = <uuid>$0),
+                }).id;"#,
+                &(second.organization.id,),
+            )
+            .await
+            .unwrap();
+        let count = |user: User| {
+            let db = db.clone();
+            async move {
+                let SessionClient(scoped) = SessionClient::new(&db, &user);
+                scoped
+                    .query_required_single::<i64, _>(
+                        "select count(Customer filter .id = <uuid>$0);",
+                        &(id,),
+                    )
+                    .await
+                    .unwrap()
+            }
+        };
+        let seen_by_first = count(first.clone()).await;
+        let seen_by_second = count(second.clone()).await;
+        remove_organization(&db, &first.organization).await;
+        remove_organization(&db, &second.organization).await;
+
+        assert_eq!(0, seen_by_first);
+        assert_eq!(1, seen_by_second);
+    }
+
+    #[tokio::test]
+    async fn session_client_should_only_see_records_of_its_own_customers() {
+        let db = get_db().await;
+        let first = add_organization(&db).await;
+        let second = add_organization(&db).await;
+        let (contact, task): (Uuid, Uuid) = db
+            .query_required_single(
+                r#"
+                with
+                    contact := (insert Contact { name := 'Jo', email := 'jo@test.email.com' }),
+                    task := (insert Task { title := 'Call', due := <cal::local_date>'2023-05-10' }),
+                    customer := (insert Customer {
+                        name := 'Test',
+                        email := 'children@test.email.com',
+                        organization := (select Organization filter .id = <uuid>$0),
+                        contacts := contact,
+                        tasks := task,
+                    })
+                select (contact.id, task.id);"#,
+                &(second.organization.id,),
+            )
+            .await
+            .unwrap();
+        let count = |user: User| {
+            let db = db.clone();
+            async move {
+                let SessionClient(scoped) = SessionClient::new(&db, &user);
+                scoped
+                    .query_required_single::<i64, _>(
+                        r#"
+                        select count(Contact filter .id = <uuid>$0)
+                            + count(Task filter .id = <uuid>$1);"#,
+                        &(contact, task),
+                    )
+                    .await
+                    .unwrap()
+            }
+        };
+        let seen_by_first = count(first.clone()).await;
+        let seen_by_second = count(second.clone()).await;
+        remove_organization(&db, &first.organization).await;
+        remove_organization(&db, &second.organization).await;
+
+        assert_eq!(0, seen_by_first);
+        assert_eq!(2, seen_by_second);
+    }
+
+    #[tokio::test]
+    async fn new_organization_should_get_a_default_pipeline() {
+        let db = get_db().await;
+        let admin = add_organization(&db).await;
+        let SessionClient(scoped) = SessionClient::new(&db, &admin);
+        let stages: Vec<String> = scoped
+            .query(
+                r#"
+                with pipeline := (select Pipeline filter .is_default limit 1)
+                select (select pipeline.stages order by .position).name;"#,
+                &(),
+            )
+            .await
+            .unwrap();
+        remove_organization(&db, &admin.organization).await;
+
+        assert_eq!(
+            vec![
+                "New",
+                "Qualification",
+                "Proposal",
+                "Negotiation",
+                "ClosedWon",
+                "ClosedLost"
+            ],
+            stages
+        );
+    }
+
+    #[tokio::test]
+    async fn organization_names_should_be_unique() {
+        let db = get_db().await;
+        let admin = add_organization(&db).await;
+        let response = create_organization(
+            State(db.clone()),
+            Actor::default(),
+            Json(NewOrganization {
+                organization: admin.organization.name.clone(),
+                email: format!(
+                    "{}@test.email.com",
+                    Alphanumeric
+                        .sample_string(&mut rand::thread_rng(), 16)
+                        .to_lowercase()
+                ),
+                name: "Test Admin".to_string(),
+                password: "a long enough password".to_string(),
+            }),
+        )
+        .await
+        .into_response();
+        remove_organization(&db, &admin.organization).await;
+
+        assert_eq!(StatusCode::CONFLICT, response.status());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let error: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!("conflict", error.code);
+    }
+}
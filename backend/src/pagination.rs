@@ -0,0 +1,80 @@
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CursorDirection {
+    After,
+    Before,
+}
+
+/// Position in a sorted list made up of the sort field value and id of the row at the edge of a page.
+/// The id breaks ties so rows with the same sort value are never skipped or repeated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub direction: CursorDirection,
+    pub value: String,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn after(value: String, id: Uuid) -> Self {
+        Cursor {
+            direction: CursorDirection::After,
+            value,
+            id,
+        }
+    }
+
+    pub fn before(value: String, id: Uuid) -> Self {
+        Cursor {
+            direction: CursorDirection::Before,
+            value,
+            id,
+        }
+    }
+
+    /// Opaque string handed to the client, the value goes last as it may contain the separator
+    pub fn encode(&self) -> String {
+        let direction = match self.direction {
+            CursorDirection::After => "after",
+            CursorDirection::Before => "before",
+        };
+        format!("{}|{}|{}", direction, self.id, self.value)
+    }
+
+    pub fn decode(cursor: &str) -> Option<Cursor> {
+        let mut parts = cursor.splitn(3, '|');
+        let direction = match parts.next()? {
+            "after" => CursorDirection::After,
+            "before" => CursorDirection::Before,
+            _ => return None,
+        };
+        let id = Uuid::parse_str(parts.next()?).ok()?;
+        let value = parts.next()?.to_string();
+        Some(Cursor {
+            direction,
+            value,
+            id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_should_round_trip() {
+        let cursor = Cursor::before("Acme | Co".to_string(), Uuid::from_u128(42));
+        assert_eq!(Some(cursor.clone()), Cursor::decode(&cursor.encode()));
+    }
+
+    #[test]
+    fn invalid_cursor_should_not_decode() {
+        assert_eq!(
+            None,
+            Cursor::decode("sideways|00000000-0000-0000-0000-00000000002a|a")
+        );
+        assert_eq!(None, Cursor::decode("after|not-a-uuid|a"));
+        assert_eq!(None, Cursor::decode("after"));
+    }
+}
@@ -0,0 +1,174 @@
+use axum::{
+    async_trait,
+    body::BoxBody,
+    extract::FromRequestParts,
+    http::{request::Parts, Request},
+    response::{IntoResponse, Response},
+};
+use frontend::{Permission, Role, User};
+use tower_http::validate_request::{ValidateRequest, ValidateRequestHeaderLayer};
+
+use crate::errors::ApiError;
+
+// The roles are checked here for every route. The access policies in the schema back up the broad
+// rules, read-only users can not write, sales reps can not delete or trash and only admins change
+// settings and users, with the role `require_session` puts in the `current_role` global.
+
+/// Layer for a single handler, rejecting the request with a 403 unless the signed in user may
+/// perform `permission`. Has to sit behind `require_session`, which adds the user.
+pub fn allow(permission: Permission) -> ValidateRequestHeaderLayer<Allow> {
+    ValidateRequestHeaderLayer::custom(Allow(permission))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Allow(Permission);
+
+impl<B> ValidateRequest<B> for Allow {
+    type ResponseBody = BoxBody;
+
+    fn validate(&mut self, request: &mut Request<B>) -> Result<(), Response> {
+        let user = request.extensions().get::<User>();
+        check(
+            user.map(|user| user.role),
+            user.map_or(false, |user| user.can(self.0)),
+        )
+        .map_err(|error| error.into_response())
+    }
+}
+
+fn check(role: Option<Role>, allowed: bool) -> Result<(), ApiError> {
+    match role {
+        None => Err(ApiError::Unauthorized("Please sign in".to_string())),
+        Some(_) if allowed => Ok(()),
+        Some(role) => Err(ApiError::Forbidden(format!(
+            "{} users are not allowed to do this",
+            role.label()
+        ))),
+    }
+}
+
+/// Role of the signed in user, for checks that depend on the body of the request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CurrentRole(pub Option<Role>);
+
+impl CurrentRole {
+    pub fn require(self, permission: Permission) -> Result<(), ApiError> {
+        check(self.0, self.0.map_or(false, |role| role.can(permission)))
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for CurrentRole {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(CurrentRole(
+            parts.extensions.get::<User>().map(|user| user.role),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::StatusCode,
+        routing::{post, Router},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn status(role: Option<Role>, permission: Permission) -> StatusCode {
+        status_of(
+            role.map(|role| User {
+                role,
+                ..Default::default()
+            }),
+            permission,
+        )
+        .await
+    }
+
+    async fn status_of(user: Option<User>, permission: Permission) -> StatusCode {
+        let app: Router =
+            Router::new().route("/", post(|| async { "done" }).layer(allow(permission)));
+        let mut request = Request::post("/").body(Body::empty()).unwrap();
+        if let Some(user) = user {
+            request.extensions_mut().insert(user);
+        }
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[test]
+    fn only_managers_should_delete_and_deactivate() {
+        for permission in [
+            Permission::DeactivateCustomers,
+            Permission::DeleteCustomers,
+            Permission::DeleteOpportunities,
+        ] {
+            assert!(Role::Admin.can(permission));
+            assert!(Role::SalesManager.can(permission));
+            assert!(!Role::SalesRep.can(permission));
+            assert!(!Role::ReadOnly.can(permission));
+        }
+    }
+
+    #[test]
+    fn read_only_should_not_edit() {
+        assert!(Role::SalesRep.can(Permission::Edit));
+        assert!(!Role::ReadOnly.can(Permission::Edit));
+        assert!(Role::Admin.can(Permission::ManageUsers));
+        assert!(!Role::SalesManager.can(Permission::ManageSettings));
+    }
+
+    #[tokio::test]
+    async fn routes_should_only_run_for_allowed_roles() {
+        assert_eq!(
+            StatusCode::OK,
+            status(Some(Role::SalesManager), Permission::DeleteOpportunities).await
+        );
+        assert_eq!(
+            StatusCode::FORBIDDEN,
+            status(Some(Role::SalesRep), Permission::DeleteOpportunities).await
+        );
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            status(None, Permission::Edit).await
+        );
+    }
+
+    #[tokio::test]
+    async fn only_operators_should_manage_organizations() {
+        let admin = User {
+            role: Role::Admin,
+            ..Default::default()
+        };
+        let operator = User {
+            role: Role::SalesRep,
+            operator: true,
+            ..Default::default()
+        };
+        assert!(!Role::Admin.can(Permission::ManageOrganizations));
+        assert!(operator.can(Permission::ManageOrganizations));
+        assert!(!operator.can(Permission::ManageUsers));
+        assert_eq!(
+            StatusCode::FORBIDDEN,
+            status_of(Some(admin), Permission::ManageOrganizations).await
+        );
+        assert_eq!(
+            StatusCode::OK,
+            status_of(Some(operator), Permission::ManageOrganizations).await
+        );
+    }
+
+    #[test]
+    fn current_role_should_require_the_permission() {
+        assert!(CurrentRole(Some(Role::Admin))
+            .require(Permission::DeactivateCustomers)
+            .is_ok());
+        assert!(CurrentRole(Some(Role::SalesRep))
+            .require(Permission::DeactivateCustomers)
+            .is_err());
+    }
+}
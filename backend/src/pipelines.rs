@@ -0,0 +1,406 @@
+use axum::{
+    extract::{self, Path, State},
+    handler::Handler,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, put},
+    Json, Router,
+};
+use edgedb_protocol::value::Value;
+use edgedb_tokio::Client;
+use frontend::{OrganizationId, Permission, Pipeline, PipelineId, SortDirection, StageId};
+use validator::{Validate, ValidationError, ValidationErrors};
+
+use crate::{
+    actor::Actor,
+    auth::SessionClient,
+    errors::ApiError,
+    organizations::CurrentOrganization,
+    permissions::allow,
+    query::{DatetimeField, ListConfig, ListQuery},
+    AppState,
+};
+
+const PIPELINE_SHAPE: &str =
+    "id, name, is_default, stages: { id, name, outcome } order by .position, created";
+
+pub fn pipeline_routes() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/pipelines",
+            get(pipelines).post(create_pipeline.layer(allow(Permission::ManageSettings))),
+        )
+        .route(
+            "/pipeline/:id",
+            put(update_pipeline.layer(allow(Permission::ManageSettings)))
+                .delete(delete_pipeline.layer(allow(Permission::ManageSettings))),
+        )
+}
+
+/// Finds the stage called `name` in the organisation's pipeline, or in its default pipeline when
+/// there is no id. An unknown stage is reported as a validation error on `status`.
+pub async fn find_stage(
+    db: &Client,
+    organization: Option<OrganizationId>,
+    pipeline_id: Option<PipelineId>,
+    name: &str,
+) -> Result<StageId, ApiError> {
+    let stage: Option<StageId> = db
+        .query_single(
+            r#"
+            with
+                pipelines := (
+                    select Pipeline
+                    filter not exists <optional uuid>$2 or .organization.id ?= <optional uuid>$2
+                ),
+                pipeline := (
+                    (select pipelines filter .id = <optional uuid>$0)
+                    if exists <optional uuid>$0
+                    else (select pipelines order by .is_default desc then .created limit 1)
+                )
+            select (select pipeline.stages filter .name = <str>$1 limit 1).id;"#,
+            &(pipeline_id, name, organization),
+        )
+        .await?;
+    stage.ok_or_else(|| {
+        let mut errors = ValidationErrors::new();
+        errors.add(
+            "status",
+            ValidationError {
+                message: Some(format!("{} is not a stage of the pipeline", name).into()),
+                ..ValidationError::new("stage")
+            },
+        );
+        ApiError::Validation(errors)
+    })
+}
+
+async fn find_pipeline(
+    db: &Client,
+    organization: Option<OrganizationId>,
+    id: PipelineId,
+) -> Result<Pipeline, ApiError> {
+    let result: Option<Pipeline> = db
+        .query_single(
+            r#"
+            select <json>Pipeline {
+                id,
+                name,
+                is_default,
+                stages: { id, name, outcome } order by .position,
+                created
+            } filter .id = <uuid>$0
+                and (not exists <optional uuid>$1 or .organization.id ?= <optional uuid>$1);"#,
+            &(id, organization),
+        )
+        .await?;
+    result.ok_or(ApiError::NotFound)
+}
+
+/// Every pipeline of the organisation with its stages in order, oldest pipeline first
+async fn pipelines(
+    SessionClient(db): SessionClient,
+    State(config): State<ListConfig>,
+    CurrentOrganization(organization): CurrentOrganization,
+) -> Result<Response, ApiError> {
+    let (query, args) = ListQuery::new("Pipeline", PIPELINE_SHAPE, config)
+        .filter_organization(".organization.id", organization)
+        .order_by(&DatetimeField(".created"), SortDirection::Asc)
+        .build();
+    let result: Vec<Pipeline> = db.query(query.as_str(), &args).await?;
+    Ok((Json(result)).into_response())
+}
+
+/// Adds a pipeline, a new default pipeline replaces the current one
+async fn create_pipeline(
+    SessionClient(db): SessionClient,
+    actor: Actor,
+    CurrentOrganization(organization): CurrentOrganization,
+    Json(body): extract::Json<Pipeline>,
+) -> Result<Response, ApiError> {
+    body.validate()?;
+    let stages = serde_json::to_string(&body.stages)
+        .map_err(|_| ApiError::BadRequest("The stages could not be read".to_string()))?;
+    let id: PipelineId = db
+        .query_required_single(
+            r#"
+            with
+                demoted := (
+                    update Pipeline filter <bool>$1 and .is_default
+                        and (not exists <optional uuid>$4 or .organization.id ?= <optional uuid>$4)
+                    set { is_default := false, modified := datetime_current(), modified_by := <optional str>$3 }
+                ),
+                pipeline := (insert Pipeline {
+                    name := <str>$0,
+                    is_default := <bool>$1,
+                    stages := (
+                        for stage in enumerate(json_array_unpack(to_json(<str>$2))) union (
+                            insert Stage {
+                                name := str_trim(<str>stage.1['name']),
+                                outcome := <StageOutcome><str>stage.1['outcome'],
+                                position := <int16>stage.0,
+                                created_by := <optional str>$3,
+                                modified_by := <optional str>$3,
+                            }
+                        )
+                    ),
+                    created_by := <optional str>$3,
+                    modified_by := <optional str>$3,
+                    organization := (select Organization filter .id = <optional uuid>$4)
+                        ?? assert_exists((select Organization order by .created limit 1)),
+                })
+            select pipeline.id;"#,
+            &(body.name, body.is_default, stages, actor.0, organization),
+        )
+        .await?;
+    let pipeline = find_pipeline(&db, organization, id).await?;
+    Ok((StatusCode::CREATED, Json(pipeline)).into_response())
+}
+
+/// Renames the pipeline and replaces its stages.
+/// Stages are matched by id, so opportunities keep their stage when it is renamed or moved.
+/// Removing a stage that opportunities are in is rejected by the database.
+async fn update_pipeline(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<PipelineId>,
+    actor: Actor,
+    CurrentOrganization(organization): CurrentOrganization,
+    Json(body): extract::Json<Pipeline>,
+) -> Result<Response, ApiError> {
+    if body.id.ne(&id) {
+        return Err(ApiError::BadRequest(
+            "The pipeline id does not match the url".to_string(),
+        ));
+    }
+    body.validate()?;
+    let stages = serde_json::to_string(&body.stages)
+        .map_err(|_| ApiError::BadRequest("The stages could not be read".to_string()))?;
+    let result: Option<PipelineId> = db
+        .query_single(
+            r#"
+            with
+                pipeline := (
+                    select Pipeline filter .id = <uuid>$0
+                        and (not exists <optional uuid>$5 or .organization.id ?= <optional uuid>$5)
+                ),
+                stages := enumerate(json_array_unpack(to_json(<str>$3))),
+                ids := (for stage in stages union <uuid><str>stage.1['id']),
+                kept := (
+                    for stage in stages union (
+                        update pipeline.stages filter .id = <uuid><str>stage.1['id']
+                        set {
+                            name := str_trim(<str>stage.1['name']),
+                            outcome := <StageOutcome><str>stage.1['outcome'],
+                            position := <int16>stage.0,
+                            modified := datetime_current(),
+                            modified_by := <optional str>$4,
+                        }
+                    )
+                ),
+                added := (
+                    for stage in (select stages filter <uuid><str>stages.1['id'] not in pipeline.stages.id) union (
+                        insert Stage {
+                            name := str_trim(<str>stage.1['name']),
+                            outcome := <StageOutcome><str>stage.1['outcome'],
+                            position := <int16>stage.0,
+                            created_by := <optional str>$4,
+                            modified_by := <optional str>$4,
+                        }
+                    )
+                ),
+                removed := (delete pipeline.stages filter .id not in ids),
+                demoted := (
+                    update Pipeline filter <bool>$2 and .is_default and .id != <uuid>$0
+                        and .organization = pipeline.organization
+                    set { is_default := false, modified := datetime_current(), modified_by := <optional str>$4 }
+                ),
+                updated := (
+                    update pipeline
+                    set {
+                        name := <str>$1,
+                        is_default := <bool>$2,
+                        stages := kept union added,
+                        modified := datetime_current(),
+                        modified_by := <optional str>$4,
+                    }
+                )
+            select updated.id;"#,
+            &(id, body.name, body.is_default, stages, actor.0, organization),
+        )
+        .await?;
+    result.ok_or(ApiError::NotFound)?;
+    let pipeline = find_pipeline(&db, organization, id).await?;
+    Ok((Json(pipeline)).into_response())
+}
+
+/// Deletes the pipeline and its stages, rejected by the database while opportunities use it
+async fn delete_pipeline(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<PipelineId>,
+    CurrentOrganization(organization): CurrentOrganization,
+) -> Result<Response, ApiError> {
+    let result: Option<Value> = db
+        .query_single(
+            r#"
+            delete Pipeline filter .id = <uuid>$0
+                and (not exists <optional uuid>$1 or .organization.id ?= <optional uuid>$1)"#,
+            &(id, organization),
+        )
+        .await?;
+    result.ok_or(ApiError::NotFound)?;
+    Ok((StatusCode::OK).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use frontend::{ErrorResponse, Stage};
+    use rand::distributions::{Alphanumeric, DistString};
+    use serde::de::DeserializeOwned;
+
+    use super::*;
+
+    async fn get_db() -> Client {
+        edgedb_tokio::create_client()
+            .await
+            .expect("Failed to connect to the DB")
+    }
+
+    async fn into_type<T: DeserializeOwned>(response: Response) -> T {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice::<T>(&body).unwrap()
+    }
+
+    fn stage(name: &str, outcome: &str) -> Stage {
+        Stage {
+            name: name.to_string(),
+            outcome: outcome.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn pipeline(stages: Vec<Stage>) -> Pipeline {
+        Pipeline {
+            name: format!(
+                "Pipeline {}",
+                Alphanumeric.sample_string(&mut rand::thread_rng(), 16)
+            ),
+            stages,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn stages_should_be_kept_in_order_and_found_by_name() {
+        let db = get_db().await;
+        let response = create_pipeline(
+            SessionClient(db.clone()),
+            Actor::default(),
+            CurrentOrganization::default(),
+            Json(pipeline(vec![
+                stage("Lead", "Open"),
+                stage("Demo", "Open"),
+                stage("Signed", "Won"),
+                stage("Gone", "Lost"),
+            ])),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::CREATED, response.status());
+        let created: Pipeline = into_type(response).await;
+
+        let found = find_stage(&db, None, Some(created.id), "Demo").await;
+        let missing = find_stage(&db, None, Some(created.id), "Qualification").await;
+        let _ = delete_pipeline(
+            SessionClient(db.clone()),
+            Path(created.id),
+            CurrentOrganization::default(),
+        )
+        .await;
+
+        assert_eq!(
+            vec!["Lead", "Demo", "Signed", "Gone"],
+            created
+                .stages
+                .iter()
+                .map(|s| s.name.as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert_eq!(created.stages[1].id, found.unwrap());
+        assert!(matches!(missing, Err(ApiError::Validation(_))));
+    }
+
+    #[tokio::test]
+    async fn update_should_keep_renamed_stages_and_remove_missing_ones() {
+        let db = get_db().await;
+        let created: Pipeline = into_type(
+            create_pipeline(
+                SessionClient(db.clone()),
+                Actor::default(),
+                CurrentOrganization::default(),
+                Json(pipeline(vec![
+                    stage("Lead", "Open"),
+                    stage("Demo", "Open"),
+                    stage("Signed", "Won"),
+                ])),
+            )
+            .await
+            .into_response(),
+        )
+        .await;
+        let renamed = Stage {
+            name: "Trial".to_string(),
+            ..created.stages[1].clone()
+        };
+
+        let response = update_pipeline(
+            SessionClient(db.clone()),
+            Path(created.id),
+            Actor::default(),
+            CurrentOrganization::default(),
+            Json(Pipeline {
+                stages: vec![
+                    renamed.clone(),
+                    stage("Lost", "Lost"),
+                    created.stages[2].clone(),
+                ],
+                ..created.clone()
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::OK, response.status());
+        let updated: Pipeline = into_type(response).await;
+        let _ = delete_pipeline(
+            SessionClient(db.clone()),
+            Path(created.id),
+            CurrentOrganization::default(),
+        )
+        .await;
+
+        assert_eq!(
+            vec!["Trial", "Lost", "Signed"],
+            updated
+                .stages
+                .iter()
+                .map(|s| s.name.as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert_eq!(renamed.id, updated.stages[0].id);
+    }
+
+    #[tokio::test]
+    async fn duplicate_stage_names_should_be_bad_request() {
+        let db = get_db().await;
+        let response = create_pipeline(
+            SessionClient(db.clone()),
+            Actor::default(),
+            CurrentOrganization::default(),
+            Json(pipeline(vec![stage("Lead", "Open"), stage("Lead", "Won")])),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let error: ErrorResponse = into_type(response).await;
+        assert!(error.fields.contains_key("stages"));
+    }
+}
@@ -0,0 +1,534 @@
+use std::{env, fmt};
+
+use edgedb_protocol::{
+    model::{Datetime, LocalDate},
+    value::{SparseObject, Value},
+};
+use frontend::SortDirection;
+use uuid::Uuid;
+
+const DEFAULT_MAX_LIMIT: usize = 100;
+
+/// Settings shared by every list endpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ListConfig {
+    /// Largest number of rows a single request can return
+    pub max_limit: usize,
+}
+
+impl ListConfig {
+    /// Reads `MAX_LIST_LIMIT` falling back to the default when it is missing or not a number
+    pub fn from_env() -> Self {
+        let max_limit = env::var("MAX_LIST_LIMIT")
+            .ok()
+            .and_then(|limit| limit.parse().ok())
+            .filter(|limit| *limit > 0)
+            .unwrap_or(DEFAULT_MAX_LIMIT);
+        ListConfig { max_limit }
+    }
+}
+
+impl Default for ListConfig {
+    fn default() -> Self {
+        ListConfig {
+            max_limit: DEFAULT_MAX_LIMIT,
+        }
+    }
+}
+
+/// A field a list can be ordered by
+pub trait SortField {
+    /// Path of the field relative to the object e.g. `.name`
+    fn path(&self) -> &'static str;
+    /// Type a cursor value is cast to before it is compared with the field
+    fn cast(&self) -> &'static str;
+    /// Whether the field can be empty, rows without a value are ordered last in either direction.
+    /// Optional fields can not be used with `seek`.
+    fn optional(&self) -> bool {
+        false
+    }
+}
+
+/// A `datetime` field, for lists that always use the same order
+pub struct DatetimeField(pub &'static str);
+
+impl SortField for DatetimeField {
+    fn path(&self) -> &'static str {
+        self.0
+    }
+
+    fn cast(&self) -> &'static str {
+        "datetime"
+    }
+}
+
+/// A `cal::local_date` field, for lists that always use the same order
+pub struct DateField(pub &'static str);
+
+impl SortField for DateField {
+    fn path(&self) -> &'static str {
+        self.0
+    }
+
+    fn cast(&self) -> &'static str {
+        "cal::local_date"
+    }
+}
+
+/// A `str` field, for lists that always use the same order
+pub struct TextField(pub &'static str);
+
+impl SortField for TextField {
+    fn path(&self) -> &'static str {
+        self.0
+    }
+
+    fn cast(&self) -> &'static str {
+        "str"
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Lt,
+    Gt,
+    Gte,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            Op::Eq => write!(f, "="),
+            Op::Lt => write!(f, "<"),
+            Op::Gt => write!(f, ">"),
+            Op::Gte => write!(f, ">="),
+        }
+    }
+}
+
+/// Typed value sent as a query argument, it never becomes part of the query text
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    Str(String),
+    Uuid(Uuid),
+    Int(i64),
+    Bool(bool),
+    Datetime(Datetime),
+    Date(LocalDate),
+    StrList(Vec<String>),
+}
+
+impl Param {
+    fn cast(&self) -> &'static str {
+        match &self {
+            Param::Str(_) => "str",
+            Param::Uuid(_) => "uuid",
+            Param::Int(_) => "int64",
+            Param::Bool(_) => "bool",
+            Param::Datetime(_) => "datetime",
+            Param::Date(_) => "cal::local_date",
+            Param::StrList(_) => "array<str>",
+        }
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            Param::Str(value) => Value::Str(value),
+            Param::Uuid(value) => Value::Uuid(value),
+            Param::Int(value) => Value::Int64(value),
+            Param::Bool(value) => Value::Bool(value),
+            Param::Datetime(value) => Value::Datetime(value),
+            Param::Date(value) => Value::LocalDate(value),
+            Param::StrList(values) => Value::Array(values.into_iter().map(Value::Str).collect()),
+        }
+    }
+}
+
+/// Optional text for an `<optional str>$n` argument without surrounding spaces, blank text is no value
+pub fn empty_as_none(value: Option<String>) -> Option<String> {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Ids as the json text bound to a `<uuid><str>json_array_unpack(to_json(<str>$n))` argument,
+/// the client can not send arrays of ids
+pub fn id_list(ids: &[Uuid]) -> String {
+    serde_json::to_string(ids).unwrap_or_else(|_| "[]".to_string())
+}
+
+/// Ids without repeats, in order, so they can be compared to the count of records found
+pub fn unique(ids: &[Uuid]) -> Vec<Uuid> {
+    let mut ids = ids.to_vec();
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
+
+/// Builds a select over an object type from typed filters, ordering and paging.
+/// Only paths and shapes known at compile time are written into the query, every value is bound as a named argument.
+#[derive(Clone)]
+pub struct ListQuery {
+    object: &'static str,
+    shape: &'static str,
+    filters: Vec<String>,
+    seek: Option<String>,
+    /// Path, cursor cast, direction and whether the sort field is optional
+    order: Option<(&'static str, &'static str, SortDirection, bool)>,
+    limit: usize,
+    max_limit: usize,
+    args: Vec<(String, Value)>,
+}
+
+impl ListQuery {
+    /// `shape` is the list of fields inside the braces e.g. `id, name`.
+    /// Rows are limited to the configured maximum unless a smaller limit is set.
+    pub fn new(object: &'static str, shape: &'static str, config: ListConfig) -> Self {
+        ListQuery {
+            object,
+            shape,
+            filters: vec![],
+            seek: None,
+            order: None,
+            limit: config.max_limit,
+            max_limit: config.max_limit,
+            args: vec![],
+        }
+    }
+
+    fn bind(&mut self, param: Param) -> String {
+        let name = format!("p{}", self.args.len());
+        let placeholder = format!("<{}>${}", param.cast(), name);
+        self.args.push((name, param.into_value()));
+        placeholder
+    }
+
+    pub fn filter(mut self, path: &'static str, op: Op, param: Param) -> Self {
+        let placeholder = self.bind(param);
+        let filter = format!("{} {} {}", path, op, placeholder);
+        self.filters.push(filter);
+        self
+    }
+
+    /// Keeps rows where the path is one of the values, an empty list does not filter
+    pub fn filter_in(mut self, path: &'static str, values: Vec<String>) -> Self {
+        if values.is_empty() {
+            return self;
+        }
+        let placeholder = self.bind(Param::StrList(values));
+        self.filters
+            .push(format!("{} in array_unpack({})", path, placeholder));
+        self
+    }
+
+    /// Keeps rows where any value of a multi path is one of the values, an empty list does not filter
+    pub fn filter_any_in(mut self, path: &'static str, values: Vec<String>) -> Self {
+        if values.is_empty() {
+            return self;
+        }
+        let placeholder = self.bind(Param::StrList(values));
+        self.filters
+            .push(format!("any({} in array_unpack({}))", path, placeholder));
+        self
+    }
+
+    /// Keeps rows where the value under `key` of a json path, cast to `cast`, equals the value
+    pub fn filter_json(
+        mut self,
+        path: &'static str,
+        key: String,
+        cast: &'static str,
+        value: String,
+    ) -> Self {
+        let key = self.bind(Param::Str(key));
+        let value = self.bind(Param::Str(value));
+        self.filters
+            .push(format!("<{cast}>json_get({path}, {key}) = <{cast}>{value}"));
+        self
+    }
+
+    /// Keeps rows of the organisation at the path, `None` does not filter
+    pub fn filter_organization(self, path: &'static str, organization: Option<Uuid>) -> Self {
+        match organization {
+            Some(organization) => self.filter(path, Op::Eq, Param::Uuid(organization)),
+            None => self,
+        }
+    }
+
+    pub fn filter_exists(mut self, path: &'static str) -> Self {
+        self.filters.push(format!("exists {}", path));
+        self
+    }
+
+    pub fn filter_missing(mut self, path: &'static str) -> Self {
+        self.filters.push(format!("not exists {}", path));
+        self
+    }
+
+    /// Orders by the field, ties are broken by id so the order is stable between pages
+    pub fn order_by(mut self, field: &impl SortField, direction: SortDirection) -> Self {
+        self.order = Some((field.path(), field.cast(), direction, field.optional()));
+        self
+    }
+
+    /// Skips rows up to and including the one with the given sort value and id.
+    /// Only applies to the rows in the page, not the total.
+    pub fn seek(mut self, value: String, id: Uuid) -> Self {
+        let (path, cast, direction) = match self.order {
+            Some((path, cast, direction, false)) => (path, cast, direction),
+            _ => return self,
+        };
+        let comparison = match direction {
+            SortDirection::Asc => Op::Gt,
+            SortDirection::Desc => Op::Lt,
+        };
+        let value = self.bind(Param::Str(value));
+        let id = self.bind(Param::Uuid(id));
+        self.seek = Some(format!(
+            "({path} {comparison} <{cast}>{value} or ({path} = <{cast}>{value} and .id {comparison} {id}))"
+        ));
+        self
+    }
+
+    /// Sets the number of rows returned, clamped to the configured maximum
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit.clamp(1, self.max_limit);
+        self
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.limit
+    }
+
+    fn filter_clause(filters: &[String]) -> String {
+        match filters.is_empty() {
+            true => String::new(),
+            false => format!(" filter {}", filters.join(" and ")),
+        }
+    }
+
+    fn order_clause(&self) -> String {
+        match self.order {
+            Some((path, _, direction, optional)) => {
+                let empty = if optional { " empty last" } else { "" };
+                format!(
+                    " order by {} {}{} then .id {}",
+                    path, direction, empty, direction
+                )
+            }
+            None => String::new(),
+        }
+    }
+
+    fn into_args(self) -> Value {
+        Value::SparseObject(SparseObject::from_pairs(
+            self.args
+                .into_iter()
+                .map(|(name, value)| (name, Some(value))),
+        ))
+    }
+
+    /// Query selecting the matching rows as json along with its arguments
+    pub fn build(mut self) -> (String, Value) {
+        let limit = self.bind(Param::Int(self.limit as i64));
+        let mut filters = self.filters.clone();
+        filters.extend(self.seek.clone());
+        let query = format!(
+            "select <json>{} {{ {} }}{}{} limit {}",
+            self.object,
+            self.shape,
+            Self::filter_clause(&filters),
+            self.order_clause(),
+            limit
+        );
+        (query, self.into_args())
+    }
+
+    /// Query selecting a tuple of the total number of matching rows and the rows of the page as json.
+    /// One row more than the page size is fetched so the caller can tell if there is another page.
+    pub fn build_page(mut self) -> (String, Value) {
+        let limit = self.bind(Param::Int(self.limit as i64 + 1));
+        let seek = self.seek.iter().cloned().collect::<Vec<String>>();
+        let query = format!(
+            "with filtered := (select {}{}) select (count(filtered), array_agg(<json>(select filtered {{ {} }}{}{} limit {})))",
+            self.object,
+            Self::filter_clause(&self.filters),
+            self.shape,
+            Self::filter_clause(&seek),
+            self.order_clause(),
+            limit
+        );
+        (query, self.into_args())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Name;
+
+    impl SortField for Name {
+        fn path(&self) -> &'static str {
+            ".name"
+        }
+
+        fn cast(&self) -> &'static str {
+            "str"
+        }
+    }
+
+    fn arg_names(args: &Value) -> Vec<String> {
+        match args {
+            Value::SparseObject(args) => args.pairs().map(|(name, _)| name.to_string()).collect(),
+            _ => vec![],
+        }
+    }
+
+    #[test]
+    fn values_should_be_bound_not_written_into_the_query() {
+        let (query, args) = ListQuery::new("Customer", "id, name", ListConfig::default())
+            .filter(".name", Op::Eq, Param::Str("x' or true or '".to_string()))
+            .filter_missing(".deleted")
+            .build();
+        assert!(!query.contains("or true"));
+        assert_eq!(
+            "select <json>Customer { id, name } filter .name = <str>$p0 and not exists .deleted limit <int64>$p1",
+            query
+        );
+        assert_eq!(vec!["p0", "p1"], arg_names(&args));
+    }
+
+    #[test]
+    fn empty_in_filter_should_be_skipped() {
+        let (query, _) = ListQuery::new("Customer", "id", ListConfig::default())
+            .filter_in("<str>.status", vec![])
+            .build();
+        assert!(!query.contains(" filter "));
+    }
+
+    #[test]
+    fn any_in_filter_should_match_any_value_of_the_path() {
+        let (query, args) = ListQuery::new("Customer", "id", ListConfig::default())
+            .filter_any_in("<str>.tags.id", vec!["a".to_string(), "b".to_string()])
+            .filter_any_in("<str>.tags.name", vec![])
+            .build();
+        assert_eq!(
+            "select <json>Customer { id } filter any(<str>.tags.id in array_unpack(<array<str>>$p0)) limit <int64>$p1",
+            query
+        );
+        assert_eq!(vec!["p0", "p1"], arg_names(&args));
+    }
+
+    #[test]
+    fn organization_filter_should_only_apply_with_an_organization() {
+        let (query, args) = ListQuery::new("Customer", "id", ListConfig::default())
+            .filter_organization(".organization.id", None)
+            .filter_organization(".organization.id", Some(Uuid::nil()))
+            .build();
+        assert_eq!(
+            "select <json>Customer { id } filter .organization.id = <uuid>$p0 limit <int64>$p1",
+            query
+        );
+        assert_eq!(vec!["p0", "p1"], arg_names(&args));
+    }
+
+    #[test]
+    fn blank_text_should_be_no_value() {
+        assert_eq!(None, empty_as_none(Some("  ".to_string())));
+        assert_eq!(None, empty_as_none(None));
+        assert_eq!(
+            Some("021 555 1234".to_string()),
+            empty_as_none(Some(" 021 555 1234 ".to_string()))
+        );
+    }
+
+    #[test]
+    fn id_list_should_be_json_without_repeats() {
+        let ids = unique(&[Uuid::from_u128(2), Uuid::from_u128(1), Uuid::from_u128(2)]);
+        assert_eq!(vec![Uuid::from_u128(1), Uuid::from_u128(2)], ids);
+        assert_eq!(
+            r#"["00000000-0000-0000-0000-000000000001","00000000-0000-0000-0000-000000000002"]"#,
+            id_list(&ids)
+        );
+        assert_eq!("[]", id_list(&[]));
+    }
+
+    #[test]
+    fn json_filter_should_bind_key_and_value() {
+        let (query, args) = ListQuery::new("Customer", "id", ListConfig::default())
+            .filter_json(".custom", "seats".to_string(), "float64", "5".to_string())
+            .build();
+        assert_eq!(
+            "select <json>Customer { id } filter <float64>json_get(.custom, <str>$p0) = <float64><str>$p1 limit <int64>$p2",
+            query
+        );
+        assert_eq!(vec!["p0", "p1", "p2"], arg_names(&args));
+    }
+
+    #[test]
+    fn limit_should_be_clamped_to_max() {
+        let config = ListConfig { max_limit: 50 };
+        assert_eq!(50, ListQuery::new("Customer", "id", config).page_size());
+        assert_eq!(
+            50,
+            ListQuery::new("Customer", "id", config)
+                .limit(1000)
+                .page_size()
+        );
+        assert_eq!(
+            1,
+            ListQuery::new("Customer", "id", config)
+                .limit(0)
+                .page_size()
+        );
+    }
+
+    #[test]
+    fn seek_should_only_filter_the_page() {
+        let (query, args) = ListQuery::new("Customer", "id, name", ListConfig::default())
+            .filter_exists(".deleted")
+            .order_by(&Name, SortDirection::Desc)
+            .seek("Acme".to_string(), Uuid::from_u128(1))
+            .limit(10)
+            .build_page();
+        assert!(query.starts_with("with filtered := (select Customer filter exists .deleted)"));
+        assert!(query.contains(
+            "filter (.name < <str><str>$p0 or (.name = <str><str>$p0 and .id < <uuid>$p1)) order by .name desc then .id desc limit <int64>$p2"
+        ));
+        match args {
+            Value::SparseObject(args) => {
+                assert_eq!(
+                    Some(&Value::Int64(11)),
+                    args.pairs().last().and_then(|(_, value)| value)
+                );
+            }
+            _ => panic!("Expected named arguments"),
+        }
+    }
+
+    #[test]
+    fn optional_sort_field_should_order_empty_last() {
+        struct CloseDate;
+        impl SortField for CloseDate {
+            fn path(&self) -> &'static str {
+                ".close_date"
+            }
+            fn cast(&self) -> &'static str {
+                "cal::local_date"
+            }
+            fn optional(&self) -> bool {
+                true
+            }
+        }
+        let (query, _) = ListQuery::new("Opportunity", "id", ListConfig::default())
+            .order_by(&CloseDate, SortDirection::Asc)
+            .seek("2023-05-01".to_string(), Uuid::from_u128(1))
+            .build();
+        assert_eq!(
+            "select <json>Opportunity { id } order by .close_date asc empty last then .id asc limit <int64>$p0",
+            query
+        );
+    }
+}
@@ -0,0 +1,720 @@
+use axum::{
+    extract::{self, Path, State},
+    handler::Handler,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use edgedb_tokio::Transaction;
+use frontend::{
+    AuditAction, AuditEntity, BulkTagRequest, OrganizationId, Permission, SortDirection, Tag, TagId,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    actor::Actor,
+    audit::AuditTrail,
+    auth::SessionClient,
+    errors::{from_json, ApiError},
+    organizations::CurrentOrganization,
+    permissions::allow,
+    query::{id_list, unique, ListConfig, ListQuery, TextField},
+    transaction::transaction,
+    AppState,
+};
+
+const TAG_SHAPE: &str = "id, name, colour";
+
+pub fn tag_routes() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/tags",
+            get(tags).post(create_tag.layer(allow(Permission::Edit))),
+        )
+        .route(
+            "/tags/apply",
+            post(apply_tags.layer(allow(Permission::Edit))),
+        )
+        .route(
+            "/tag/:id",
+            put(update_tag.layer(allow(Permission::ManageSettings)))
+                .delete(delete_tag.layer(allow(Permission::ManageSettings))),
+        )
+}
+
+/// Names are compared without surrounding spaces and colours are stored in lower case
+fn normalised(tag: Tag) -> Tag {
+    Tag {
+        name: tag.name.trim().to_string(),
+        colour: tag.colour.to_lowercase(),
+        ..tag
+    }
+}
+
+/// Every tag of the organisation ordered by name
+async fn tags(
+    SessionClient(db): SessionClient,
+    State(config): State<ListConfig>,
+    CurrentOrganization(organization): CurrentOrganization,
+) -> Result<Response, ApiError> {
+    let (query, args) = ListQuery::new("Tag", TAG_SHAPE, config)
+        .filter_organization(".organization.id", organization)
+        .order_by(&TextField(".name"), SortDirection::Asc)
+        .build();
+    let result: Vec<Tag> = db.query(query.as_str(), &args).await?;
+    Ok((Json(result)).into_response())
+}
+
+async fn create_tag(
+    SessionClient(db): SessionClient,
+    actor: Actor,
+    CurrentOrganization(organization): CurrentOrganization,
+    Json(body): extract::Json<Tag>,
+) -> Result<Response, ApiError> {
+    let body = normalised(body);
+    body.validate()?;
+    let tag: Tag = db
+        .query_required_single(
+            r#"
+            select <json>(
+                insert Tag {
+                    name := <str>$0,
+                    colour := <str>$1,
+                    created_by := <optional str>$2,
+                    modified_by := <optional str>$2,
+                    organization := (select Organization filter .id = <optional uuid>$3)
+                        ?? assert_exists((select Organization order by .created limit 1)),
+                }
+            ) { id, name, colour };"#,
+            &(body.name, body.colour, actor.0, organization),
+        )
+        .await?;
+    Ok((StatusCode::CREATED, Json(tag)).into_response())
+}
+
+/// Renames or recolours the tag everywhere it is used
+async fn update_tag(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<TagId>,
+    actor: Actor,
+    CurrentOrganization(organization): CurrentOrganization,
+    Json(body): extract::Json<Tag>,
+) -> Result<Response, ApiError> {
+    if body.id.ne(&id) {
+        return Err(ApiError::BadRequest(
+            "The tag id does not match the url".to_string(),
+        ));
+    }
+    let body = normalised(body);
+    body.validate()?;
+    let result: Option<Tag> = db
+        .query_single(
+            r#"
+            select <json>(
+                update Tag filter .id = <uuid>$0
+                    and (not exists <optional uuid>$4 or .organization.id ?= <optional uuid>$4)
+                set {
+                    name := <str>$1,
+                    colour := <str>$2,
+                    modified := datetime_current(),
+                    modified_by := <optional str>$3,
+                }
+            ) { id, name, colour };"#,
+            &(id, body.name, body.colour, actor.0, organization),
+        )
+        .await?;
+    let tag = result.ok_or(ApiError::NotFound)?;
+    Ok((Json(tag)).into_response())
+}
+
+/// Deletes the tag, it is taken off every customer and opportunity that has it, recording the change
+/// to each of them in the audit log
+async fn delete_tag(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<TagId>,
+    actor: Actor,
+    CurrentOrganization(organization): CurrentOrganization,
+) -> Result<Response, ApiError> {
+    let result: Option<TagId> = transaction(db, move |mut tx| {
+        let actor = actor.clone();
+        async move { untag_and_delete(&mut tx, id, &actor, organization).await }
+    })
+    .await?;
+    result.ok_or(ApiError::NotFound)?;
+    Ok((StatusCode::OK).into_response())
+}
+
+/// Takes the tag off the records that have it, then deletes it. Returns `None` when the tag can not
+/// be found, tags of other organisations are not found.
+async fn untag_and_delete(
+    tx: &mut Transaction,
+    id: TagId,
+    actor: &Actor,
+    organization: Option<OrganizationId>,
+) -> Result<Option<TagId>, edgedb_tokio::Error> {
+    let found: Option<TagId> = tx
+        .query_single(
+            r#"
+            select (
+                select Tag filter .id = <uuid>$0
+                    and (not exists <optional uuid>$1 or .organization.id ?= <optional uuid>$1)
+            ).id;"#,
+            &(id, organization),
+        )
+        .await?;
+    if found.is_none() {
+        return Ok(None);
+    }
+    let customers: Vec<Uuid> = tx
+        .query(
+            "select (select Customer filter <uuid>$0 in .tags.id).id;",
+            &(id,),
+        )
+        .await?;
+    let json = tx
+        .query_json(
+            r#"
+            select Opportunity {
+                id,
+                customer_id := assert_single(.customer.id)
+            } filter <uuid>$0 in .tags.id and exists .customer;"#,
+            &(id,),
+        )
+        .await?;
+    let opportunities: Vec<TaggedOpportunity> = from_json(&json)?;
+
+    let mut trails = vec![];
+    for customer in customers {
+        trails.push(AuditTrail::start(tx, AuditEntity::Customer, customer, customer, actor).await?);
+    }
+    for opportunity in opportunities {
+        trails.push(
+            AuditTrail::start(
+                tx,
+                AuditEntity::Opportunity,
+                opportunity.id,
+                opportunity.customer_id,
+                actor,
+            )
+            .await?,
+        );
+    }
+    let _: Vec<Uuid> = tx
+        .query(
+            r#"
+            with
+                tag := (select Tag filter .id = <uuid>$0),
+                customers := (
+                    update Customer filter tag in .tags
+                    set {
+                        tags -= tag,
+                        modified := datetime_current(),
+                        modified_by := <optional str>$1,
+                    }
+                ),
+                opportunities := (
+                    update Opportunity filter tag in .tags
+                    set {
+                        tags -= tag,
+                        modified := datetime_current(),
+                        modified_by := <optional str>$1,
+                    }
+                )
+            select customers.id union opportunities.id;"#,
+            &(id, actor.0.clone()),
+        )
+        .await?;
+    let deleted: Option<TagId> = tx
+        .query_single("select (delete Tag filter .id = <uuid>$0).id;", &(id,))
+        .await?;
+    for trail in trails {
+        trail.finish(tx, AuditAction::Updated).await?;
+    }
+    Ok(deleted)
+}
+
+/// Opportunity to tag along with the customer its audit events belong to
+#[derive(Debug, Deserialize)]
+struct TaggedOpportunity {
+    id: Uuid,
+    customer_id: Uuid,
+}
+
+/// Adds or removes the tags on one record, records that already have (or lack) every tag are left alone
+/// so their version does not change
+fn tag_query(object: &str, remove: bool) -> String {
+    let (changed, op) = match remove {
+        true => ("any(chosen in .tags)", "-="),
+        false => ("not all(chosen in .tags)", "+="),
+    };
+    format!(
+        r#"
+        with chosen := (
+            select Tag filter .id in <uuid><str>json_array_unpack(to_json(<str>$1))
+                and (not exists <optional uuid>$3 or .organization.id ?= <optional uuid>$3)
+        )
+        select (
+            update {object} filter .id = <uuid>$0 and {changed}
+            set {{
+                tags {op} chosen,
+                modified := datetime_current(),
+                modified_by := <optional str>$2,
+            }}
+        ).id;"#
+    )
+}
+
+/// Tags every record in the request, recording each change in the audit log.
+/// Returns the error to report when a tag or record can not be found, before anything is changed,
+/// records of other organisations are not found.
+async fn bulk_tag(
+    tx: &mut Transaction,
+    body: &BulkTagRequest,
+    actor: &Actor,
+    organization: Option<OrganizationId>,
+) -> Result<Result<(), ApiError>, edgedb_tokio::Error> {
+    let tags = unique(&body.tags);
+    let customers = unique(&body.customers);
+    let tag_count: i64 = tx
+        .query_required_single(
+            r#"
+            select count(
+                Tag filter .id in <uuid><str>json_array_unpack(to_json(<str>$0))
+                    and (not exists <optional uuid>$1 or .organization.id ?= <optional uuid>$1)
+            );"#,
+            &(id_list(&tags), organization),
+        )
+        .await?;
+    let customer_count: i64 = tx
+        .query_required_single(
+            r#"
+            select count(
+                Customer filter .id in <uuid><str>json_array_unpack(to_json(<str>$0)) and not exists .deleted
+                    and (not exists <optional uuid>$1 or .organization.id ?= <optional uuid>$1)
+            );"#,
+            &(id_list(&customers), organization),
+        )
+        .await?;
+    let json = tx
+        .query_json(
+            r#"
+            select Opportunity {
+                id,
+                customer_id := assert_single(.customer.id)
+            } filter .id in <uuid><str>json_array_unpack(to_json(<str>$0))
+                and exists .customer and not exists .customer.deleted
+                and (not exists <optional uuid>$1 or .organization.id ?= <optional uuid>$1);"#,
+            &(id_list(&unique(&body.opportunities)), organization),
+        )
+        .await?;
+    let opportunities: Vec<TaggedOpportunity> = from_json(&json)?;
+    if tag_count as usize != tags.len()
+        || customer_count as usize != customers.len()
+        || opportunities.len() != unique(&body.opportunities).len()
+    {
+        return Ok(Err(ApiError::NotFound));
+    }
+
+    let tags = id_list(&tags);
+    let customer_query = tag_query("Customer", body.remove);
+    for id in customers {
+        let trail = AuditTrail::start(tx, AuditEntity::Customer, id, id, actor).await?;
+        let _: Vec<Uuid> = tx
+            .query(
+                customer_query.as_str(),
+                &(id, tags.clone(), actor.0.clone(), organization),
+            )
+            .await?;
+        trail.finish(tx, AuditAction::Updated).await?;
+    }
+    let opportunity_query = tag_query("Opportunity", body.remove);
+    for opportunity in opportunities {
+        let trail = AuditTrail::start(
+            tx,
+            AuditEntity::Opportunity,
+            opportunity.id,
+            opportunity.customer_id,
+            actor,
+        )
+        .await?;
+        let _: Vec<Uuid> = tx
+            .query(
+                opportunity_query.as_str(),
+                &(opportunity.id, tags.clone(), actor.0.clone(), organization),
+            )
+            .await?;
+        trail.finish(tx, AuditAction::Updated).await?;
+    }
+    Ok(Ok(()))
+}
+
+/// Adds the tags to, or takes them off, a selection of customers and opportunities in one transaction
+async fn apply_tags(
+    SessionClient(db): SessionClient,
+    actor: Actor,
+    CurrentOrganization(organization): CurrentOrganization,
+    Json(body): extract::Json<BulkTagRequest>,
+) -> Result<Response, ApiError> {
+    body.validate()?;
+    transaction(db, move |mut tx| {
+        let body = body.clone();
+        let actor = actor.clone();
+        async move { bulk_tag(&mut tx, &body, &actor, organization).await }
+    })
+    .await??;
+    Ok((StatusCode::OK).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use edgedb_protocol::value::Value;
+    use edgedb_tokio::Client;
+    use frontend::{Customer, ErrorResponse};
+    use rand::distributions::{Alphanumeric, DistString};
+    use serde::de::DeserializeOwned;
+
+    use super::*;
+    const TEST_EMAIL_DOMAIN: &str = "@test.email.com";
+
+    async fn get_db() -> Client {
+        edgedb_tokio::create_client()
+            .await
+            .expect("Failed to connect to the DB")
+    }
+
+    async fn into_type<T: DeserializeOwned>(response: Response) -> T {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice::<T>(&body).unwrap()
+    }
+
+    fn random_string() -> String {
+        Alphanumeric.sample_string(&mut rand::thread_rng(), 16)
+    }
+
+    async fn add_tag(db: &Client) -> Tag {
+        into_type(
+            create_tag(
+                SessionClient(db.clone()),
+                Actor::default(),
+                CurrentOrganization::default(),
+                Json(Tag {
+                    name: format!(" Tag {} ", random_string()),
+                    colour: "#3E8ED0".to_string(),
+                    ..Default::default()
+                }),
+            )
+            .await
+            .into_response(),
+        )
+        .await
+    }
+
+    async fn add_customer(db: &Client) -> Customer {
+        let random_string = random_string();
+        db.query_required_single(
+            r#"
+            select <json>(insert Customer {
+                name := <str>$0,
+                email := <str>$1,
+            }) { id, name, email, status, created, version := <str>.modified };"#,
+            &(
+                format!("Test {}", random_string),
+                format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+            ),
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn customer_tags(db: &Client, id: Uuid) -> Vec<String> {
+        db.query(
+            "select (select Customer filter .id = <uuid>$0).tags.name;",
+            &(id,),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn tag_query_should_only_update_records_that_change() {
+        let add = tag_query("Customer", false);
+        let remove = tag_query("Opportunity", true);
+        assert!(add.contains("update Customer filter .id = <uuid>$0 and not all(chosen in .tags)"));
+        assert!(add.contains("tags += chosen"));
+        assert!(
+            remove.contains("update Opportunity filter .id = <uuid>$0 and any(chosen in .tags)")
+        );
+        assert!(remove.contains("tags -= chosen"));
+    }
+
+    #[tokio::test]
+    async fn created_tag_should_be_trimmed_and_listed() {
+        let db = get_db().await;
+        let tag = add_tag(&db).await;
+        let response = tags(
+            SessionClient(db.clone()),
+            State(ListConfig::default()),
+            CurrentOrganization::default(),
+        )
+        .await
+        .into_response();
+        let listed: Vec<Tag> = into_type(response).await;
+        let _ = delete_tag(
+            SessionClient(db.clone()),
+            Path(tag.id),
+            Actor::default(),
+            CurrentOrganization::default(),
+        )
+        .await;
+
+        assert!(tag.name.starts_with("Tag "));
+        assert_eq!("#3e8ed0", tag.colour);
+        assert!(listed.contains(&tag));
+    }
+
+    #[tokio::test]
+    async fn duplicate_tag_name_should_conflict() {
+        let db = get_db().await;
+        let tag = add_tag(&db).await;
+        let response = create_tag(
+            SessionClient(db.clone()),
+            Actor::default(),
+            CurrentOrganization::default(),
+            Json(Tag {
+                id: TagId::default(),
+                ..tag.clone()
+            }),
+        )
+        .await
+        .into_response();
+        let _ = delete_tag(
+            SessionClient(db.clone()),
+            Path(tag.id),
+            Actor::default(),
+            CurrentOrganization::default(),
+        )
+        .await;
+
+        assert_eq!(StatusCode::CONFLICT, response.status());
+        let error: ErrorResponse = into_type(response).await;
+        assert!(error.fields.contains_key("name"));
+    }
+
+    #[tokio::test]
+    async fn tag_names_should_only_be_unique_within_an_organization() {
+        let db = get_db().await;
+        let name = format!("Tag {}", random_string());
+        let mut organizations = vec![];
+        let mut created = vec![];
+        for _ in 0..2 {
+            let organization: Uuid = db
+                .query_required_single(
+                    "select (insert Organization { name := <str>$0 }).id;",
+                    &(format!("Organization {}", random_string()),),
+                )
+                .await
+                .unwrap();
+            let response = create_tag(
+                SessionClient(db.clone()),
+                Actor::default(),
+                CurrentOrganization(Some(organization)),
+                Json(Tag {
+                    name: name.clone(),
+                    colour: "#3e8ed0".to_string(),
+                    ..Default::default()
+                }),
+            )
+            .await
+            .into_response();
+            organizations.push(organization);
+            created.push(response.status());
+        }
+        let listed: Vec<Tag> = into_type(
+            tags(
+                SessionClient(db.clone()),
+                State(ListConfig::default()),
+                CurrentOrganization(Some(organizations[0])),
+            )
+            .await
+            .into_response(),
+        )
+        .await;
+        let _: Vec<Uuid> = db
+            .query(
+                r#"
+                with
+                    tags := (delete Tag filter .organization.id in {<uuid>$0, <uuid>$1})
+                select (delete Organization filter .id in {<uuid>$0, <uuid>$1}).id;"#,
+                &(organizations[0], organizations[1]),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(vec![StatusCode::CREATED, StatusCode::CREATED], created);
+        assert_eq!(
+            vec![name],
+            listed.into_iter().map(|t| t.name).collect::<Vec<String>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_colour_should_be_bad_request() {
+        let db = get_db().await;
+        let response = create_tag(
+            SessionClient(db.clone()),
+            Actor::default(),
+            CurrentOrganization::default(),
+            Json(Tag {
+                name: "Invalid colour".to_string(),
+                colour: "blue".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let error: ErrorResponse = into_type(response).await;
+        assert!(error.fields.contains_key("colour"));
+    }
+
+    #[tokio::test]
+    async fn bulk_tags_should_be_added_and_removed() {
+        let db = get_db().await;
+        let tag = add_tag(&db).await;
+        let first = add_customer(&db).await;
+        let second = add_customer(&db).await;
+        let request = BulkTagRequest {
+            tags: vec![tag.id],
+            customers: vec![first.id, second.id],
+            ..Default::default()
+        };
+
+        let added = apply_tags(
+            SessionClient(db.clone()),
+            Actor::default(),
+            CurrentOrganization::default(),
+            Json(request.clone()),
+        )
+        .await
+        .into_response();
+        let tagged = customer_tags(&db, first.id).await;
+        let removed = apply_tags(
+            SessionClient(db.clone()),
+            Actor::default(),
+            CurrentOrganization::default(),
+            Json(BulkTagRequest {
+                customers: vec![first.id],
+                remove: true,
+                ..request
+            }),
+        )
+        .await
+        .into_response();
+        let untagged = customer_tags(&db, first.id).await;
+        let still_tagged = customer_tags(&db, second.id).await;
+        let _ = delete_tag(
+            SessionClient(db.clone()),
+            Path(tag.id),
+            Actor::default(),
+            CurrentOrganization::default(),
+        )
+        .await;
+        let _: Vec<Value> = db
+            .query(
+                "delete Customer filter .id in {<uuid>$0, <uuid>$1};",
+                &(first.id, second.id),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, added.status());
+        assert_eq!(vec![tag.name.clone()], tagged);
+        assert_eq!(StatusCode::OK, removed.status());
+        assert!(untagged.is_empty());
+        assert_eq!(vec![tag.name], still_tagged);
+    }
+
+    #[tokio::test]
+    async fn deleted_tag_should_be_recorded_on_the_records_that_had_it() {
+        let db = get_db().await;
+        let tag = add_tag(&db).await;
+        let customer = add_customer(&db).await;
+        let _ = apply_tags(
+            SessionClient(db.clone()),
+            Actor::default(),
+            CurrentOrganization::default(),
+            Json(BulkTagRequest {
+                tags: vec![tag.id],
+                customers: vec![customer.id],
+                ..Default::default()
+            }),
+        )
+        .await;
+
+        let response = delete_tag(
+            SessionClient(db.clone()),
+            Path(tag.id),
+            Actor(Some("Bob".to_string())),
+            CurrentOrganization::default(),
+        )
+        .await
+        .into_response();
+        let modified_by: Option<String> = db
+            .query_single(
+                "select (select Customer filter .id = <uuid>$0).modified_by;",
+                &(customer.id,),
+            )
+            .await
+            .unwrap();
+        let recorded: i64 = db
+            .query_required_single(
+                r#"
+                select count(
+                    AuditEvent filter .entity_id = <uuid>$0 and .changed_by = 'Bob'
+                        and .action = AuditAction.Updated
+                );"#,
+                &(customer.id,),
+            )
+            .await
+            .unwrap();
+        let tagged = customer_tags(&db, customer.id).await;
+        let _: Vec<Value> = db
+            .query("delete Customer filter .id = <uuid>$0;", &(customer.id,))
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(Some("Bob".to_string()), modified_by);
+        assert_eq!(1, recorded);
+        assert!(tagged.is_empty());
+    }
+
+    #[tokio::test]
+    async fn bulk_tags_with_unknown_tag_should_be_not_found() {
+        let db = get_db().await;
+        let customer = add_customer(&db).await;
+        let response = apply_tags(
+            SessionClient(db.clone()),
+            Actor::default(),
+            CurrentOrganization::default(),
+            Json(BulkTagRequest {
+                tags: vec![Uuid::from_u128(1)],
+                customers: vec![customer.id],
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        let tagged = customer_tags(&db, customer.id).await;
+        let _: Vec<Value> = db
+            .query("delete Customer filter .id = <uuid>$0;", &(customer.id,))
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert!(tagged.is_empty());
+    }
+}
@@ -0,0 +1,669 @@
+use axum::{
+    extract::{self, Path, Query, State},
+    handler::Handler,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, put},
+    Json, Router,
+};
+use chrono::{NaiveDate, Utc};
+use edgedb_protocol::{model::LocalDate, value::Value};
+use frontend::{
+    AuditAction, AuditEntity, CustomerId, Permission, SortDirection, Task, TaskDue, TaskId,
+    TasksQueryParams,
+};
+use validator::Validate;
+
+use crate::{
+    actor::{Actor, ActorId},
+    audit::AuditTrail,
+    auth::SessionClient,
+    errors::ApiError,
+    organizations::CurrentOrganization,
+    permissions::allow,
+    query::{DateField, ListConfig, ListQuery, Op, Param},
+    transaction::transaction,
+    AppState,
+};
+
+const TASK_SHAPE: &str = "id, title, due, assignee: { id, name }, completed, priority, opportunity_id := .opportunity.id, customer_id := .customer.id, customer_name := .customer.name, created";
+
+pub fn task_routes() -> Router<AppState> {
+    Router::new()
+        .route("/tasks", get(tasks))
+        .route(
+            "/customer/:id/tasks",
+            get(customer_tasks).post(create_task.layer(allow(Permission::Edit))),
+        )
+        .route(
+            "/customer/:id/task/:tid",
+            put(update_task.layer(allow(Permission::Edit)))
+                .delete(delete_task.layer(allow(Permission::Edit))),
+        )
+}
+
+/// Today for the caller, falling back to the date in UTC
+fn today(date: &Option<String>) -> Result<LocalDate, ApiError> {
+    let today = match date {
+        Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| ApiError::BadRequest(format!("{} is not a valid date", date)))?,
+        None => Utc::now().date_naive(),
+    };
+    LocalDate::try_from(today)
+        .map_err(|_| ApiError::BadRequest("The date is out of range".to_string()))
+}
+
+/// Tasks across every customer of the organisation that is not in the trash, soonest due first
+async fn tasks(
+    SessionClient(db): SessionClient,
+    State(config): State<ListConfig>,
+    CurrentOrganization(organization): CurrentOrganization,
+    ActorId(user): ActorId,
+    Query(params): extract::Query<TasksQueryParams>,
+) -> Result<Response, ApiError> {
+    params.validate()?;
+    let today = today(&params.today)?;
+    let mut query = ListQuery::new("Task", TASK_SHAPE, config)
+        .filter_missing(".customer.deleted")
+        .filter_organization(".customer.organization.id", organization)
+        .order_by(&DateField(".due"), SortDirection::Asc)
+        .limit(params.limit);
+    query = match params.due {
+        TaskDue::Overdue => query.filter(".due", Op::Lt, Param::Date(today)).filter(
+            ".completed",
+            Op::Eq,
+            Param::Bool(false),
+        ),
+        TaskDue::Today => query.filter(".due", Op::Eq, Param::Date(today)),
+        TaskDue::Upcoming => query.filter(".due", Op::Gt, Param::Date(today)).filter(
+            ".completed",
+            Op::Eq,
+            Param::Bool(false),
+        ),
+    };
+    if params.mine {
+        // without a signed in user nothing is theirs
+        query = query.filter(
+            ".assignee.id",
+            Op::Eq,
+            Param::Uuid(user.unwrap_or_default()),
+        );
+    }
+    let (query, args) = query.build();
+    let result: Vec<Task> = db.query(query.as_str(), &args).await?;
+    Ok((Json(result)).into_response())
+}
+
+async fn customer_tasks(
+    SessionClient(db): SessionClient,
+    State(config): State<ListConfig>,
+    Path(id): extract::Path<CustomerId>,
+) -> Result<Response, ApiError> {
+    let (query, args) = ListQuery::new("Task", TASK_SHAPE, config)
+        .filter(".customer.id", Op::Eq, Param::Uuid(id))
+        .order_by(&DateField(".due"), SortDirection::Asc)
+        .build();
+    let result: Vec<Task> = db.query(query.as_str(), &args).await?;
+    Ok((Json(result)).into_response())
+}
+
+/// Adds a task to the customer, the opportunity is only linked when it belongs to the customer
+async fn create_task(
+    SessionClient(db): SessionClient,
+    Path(id): extract::Path<CustomerId>,
+    actor: Actor,
+    Json(body): extract::Json<Task>,
+) -> Result<Response, ApiError> {
+    body.validate()?;
+    let result: Option<Task> = transaction(db, move |mut tx| {
+        let body = body.clone();
+        let actor = actor.clone();
+        async move {
+            let task: Option<Task> = tx
+                .query_single(
+                    r#"
+                    with
+                        customer := (select Customer filter .id = <uuid>$0 and not exists .deleted),
+                        task := (for c in customer union (
+                            insert Task {
+                                title := <str>$1,
+                                due := <cal::local_date><str>$2,
+                                assignee := (select User filter .id = <optional uuid>$3),
+                                completed := <bool>$4,
+                                priority := <str>$5,
+                                opportunity := (select c.opportunities filter .id = <optional uuid>$6),
+                                created_by := <optional str>$7,
+                                modified_by := <optional str>$7,
+                            }
+                        )),
+                        linked := (update customer set { tasks += task })
+                    select <json>task {
+                        id,
+                        title,
+                        due,
+                        assignee: { id, name },
+                        completed,
+                        priority,
+                        opportunity_id := .opportunity.id,
+                        customer_id := customer.id,
+                        customer_name := customer.name,
+                        created
+                    };"#,
+                    &(
+                        id,
+                        body.title,
+                        body.due,
+                        body.assignee.map(|a| a.id),
+                        body.completed,
+                        body.priority,
+                        body.opportunity_id,
+                        actor.0.clone(),
+                    ),
+                )
+                .await?;
+            if let Some(task) = &task {
+                AuditTrail::new(AuditEntity::Task, task.id, id, &actor)
+                    .finish(&mut tx, AuditAction::Created)
+                    .await?;
+            }
+            Ok(task)
+        }
+    })
+    .await?;
+    let task = result.ok_or(ApiError::NotFound)?;
+    Ok((StatusCode::CREATED, Json(task)).into_response())
+}
+
+async fn update_task(
+    SessionClient(db): SessionClient,
+    Path((id, tid)): extract::Path<(CustomerId, TaskId)>,
+    actor: Actor,
+    Json(body): extract::Json<Task>,
+) -> Result<Response, ApiError> {
+    if body.id.ne(&tid) {
+        return Err(ApiError::BadRequest(
+            "The task id does not match the url".to_string(),
+        ));
+    }
+    body.validate()?;
+    let result: Option<Task> = transaction(db, move |mut tx| {
+        let body = body.clone();
+        let actor = actor.clone();
+        async move {
+            let trail = AuditTrail::start(&mut tx, AuditEntity::Task, tid, id, &actor).await?;
+            let task: Option<Task> = tx
+                .query_single(
+                    r#"
+                    with customer := (select Customer filter .id = <uuid>$0 and not exists .deleted)
+                    select <json>(
+                        update customer.tasks filter .id = <uuid>$1
+                        set {
+                            title := <str>$2,
+                            due := <cal::local_date><str>$3,
+                            assignee := (select User filter .id = <optional uuid>$4),
+                            completed := <bool>$5,
+                            priority := <str>$6,
+                            opportunity := (select customer.opportunities filter .id = <optional uuid>$7),
+                            modified := datetime_current(),
+                            modified_by := <optional str>$8,
+                        }
+                    ) {
+                        id,
+                        title,
+                        due,
+                        assignee: { id, name },
+                        completed,
+                        priority,
+                        opportunity_id := .opportunity.id,
+                        customer_id := customer.id,
+                        customer_name := customer.name,
+                        created
+                    };"#,
+                    &(
+                        id,
+                        body.id,
+                        body.title,
+                        body.due,
+                        body.assignee.map(|a| a.id),
+                        body.completed,
+                        body.priority,
+                        body.opportunity_id,
+                        actor.0.clone(),
+                    ),
+                )
+                .await?;
+            if task.is_some() {
+                trail.finish(&mut tx, AuditAction::Updated).await?;
+            }
+            Ok(task)
+        }
+    })
+    .await?;
+    let task = result.ok_or(ApiError::NotFound)?;
+    Ok((Json(task)).into_response())
+}
+
+async fn delete_task(
+    SessionClient(db): SessionClient,
+    Path((id, tid)): extract::Path<(CustomerId, TaskId)>,
+    actor: Actor,
+) -> Result<Response, ApiError> {
+    let result: Option<Value> = transaction(db, move |mut tx| {
+        let actor = actor.clone();
+        async move {
+            let trail = AuditTrail::start(&mut tx, AuditEntity::Task, tid, id, &actor).await?;
+            let deleted: Option<Value> = tx
+                .query_single(
+                    r#"
+                    delete Task filter .customer.id = <uuid>$0 and .id = <uuid>$1"#,
+                    &(id, tid),
+                )
+                .await?;
+            if deleted.is_some() {
+                trail.finish(&mut tx, AuditAction::Deleted).await?;
+            }
+            Ok(deleted)
+        }
+    })
+    .await?;
+    result.ok_or(ApiError::NotFound)?;
+    Ok((StatusCode::OK).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use edgedb_tokio::Client;
+    use frontend::{Customer, ErrorResponse, Owner, UserId};
+    use rand::distributions::{Alphanumeric, DistString};
+    use serde::de::DeserializeOwned;
+    use uuid::Uuid;
+
+    use super::*;
+    const TEST_EMAIL_DOMAIN: &str = "@test.email.com";
+
+    async fn get_db() -> Client {
+        edgedb_tokio::create_client()
+            .await
+            .expect("Failed to connect to the DB")
+    }
+
+    async fn add_customer(db: &Client) -> Customer {
+        db.query_required_single(
+            r#"
+            select <json>(
+                insert Customer {
+                    name := <str>$0,
+                    email := <str>$1,
+                })
+                {
+                    id,
+                    name,
+                    email,
+                    status,
+                    created
+                };"#,
+            &(
+                "Task test".to_string(),
+                format!(
+                    "{}{}",
+                    Alphanumeric.sample_string(&mut rand::thread_rng(), 16),
+                    TEST_EMAIL_DOMAIN
+                ),
+            ),
+        )
+        .await
+        .expect("Failed to add customer")
+    }
+
+    async fn remove_customer(db: &Client, id: CustomerId) {
+        let _: Vec<Value> = db
+            .query("delete Customer filter .id = <uuid>$0", &(id,))
+            .await
+            .expect("Failed to remove customer");
+    }
+
+    async fn add_user(db: &Client, name: &str) -> UserId {
+        let random_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        db.query_required_single(
+            r#"
+            select (insert User {
+                name := <str>$0,
+                email := <str>$1,
+                password_hash := '',
+            }).id;"#,
+            &(
+                name.to_string(),
+                format!("{}{}", random_string, TEST_EMAIL_DOMAIN),
+            ),
+        )
+        .await
+        .expect("Failed to add user")
+    }
+
+    async fn remove_user(db: &Client, id: UserId) {
+        let _: Option<Value> = db
+            .query_single("delete User filter .id = <uuid>$0", &(id,))
+            .await
+            .unwrap();
+    }
+
+    async fn into_type<T: DeserializeOwned>(response: Response) -> T {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice::<T>(&body).unwrap()
+    }
+
+    async fn add_task(db: &Client, id: CustomerId, task: Task) -> Task {
+        let response = create_task(
+            SessionClient(db.clone()),
+            Path(id),
+            Actor::default(),
+            Json(task),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::CREATED, response.status());
+        into_type(response).await
+    }
+
+    fn task(title: &str, due: &str, assignee: Option<UserId>) -> Task {
+        Task {
+            title: title.to_string(),
+            due: due.to_string(),
+            assignee: assignee.map(|id| Owner {
+                id,
+                ..Default::default()
+            }),
+            priority: "Normal".to_string(),
+            ..Default::default()
+        }
+    }
+
+    async fn due(db: &Client, due: TaskDue, user: UserId) -> Vec<Task> {
+        due_in(db, CurrentOrganization::default(), due, user).await
+    }
+
+    async fn due_in(
+        db: &Client,
+        organization: CurrentOrganization,
+        due: TaskDue,
+        user: UserId,
+    ) -> Vec<Task> {
+        let response = tasks(
+            SessionClient(db.clone()),
+            State(ListConfig::default()),
+            organization,
+            ActorId(Some(user)),
+            Query(TasksQueryParams {
+                due,
+                mine: true,
+                today: Some("2023-05-10".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::OK, response.status());
+        into_type(response).await
+    }
+
+    #[tokio::test]
+    async fn tasks_should_be_grouped_by_due_date() {
+        let db = get_db().await;
+        let customer = add_customer(&db).await;
+        let assignee = add_user(&db, "Task test").await;
+        let someone = add_user(&db, "Task test").await;
+        let overdue = add_task(
+            &db,
+            customer.id,
+            task("Call back", "2023-05-01", Some(assignee)),
+        )
+        .await;
+        let _done = add_task(
+            &db,
+            customer.id,
+            Task {
+                completed: true,
+                ..task("Already done", "2023-05-02", Some(assignee))
+            },
+        )
+        .await;
+        let today = add_task(
+            &db,
+            customer.id,
+            task("Send quote", "2023-05-10", Some(assignee)),
+        )
+        .await;
+        let upcoming = add_task(&db, customer.id, task("Demo", "2023-06-01", Some(assignee))).await;
+        let _other = add_task(
+            &db,
+            customer.id,
+            task("Not mine", "2023-05-10", Some(someone)),
+        )
+        .await;
+
+        let overdue_tasks = due(&db, TaskDue::Overdue, assignee).await;
+        let today_tasks = due(&db, TaskDue::Today, assignee).await;
+        let upcoming_tasks = due(&db, TaskDue::Upcoming, assignee).await;
+
+        remove_customer(&db, customer.id).await;
+        remove_user(&db, assignee).await;
+        remove_user(&db, someone).await;
+        assert_eq!(
+            vec![overdue.id],
+            overdue_tasks.iter().map(|t| t.id).collect::<Vec<TaskId>>()
+        );
+        assert_eq!(
+            vec![today.id],
+            today_tasks.iter().map(|t| t.id).collect::<Vec<TaskId>>()
+        );
+        assert_eq!(
+            vec![upcoming.id],
+            upcoming_tasks.iter().map(|t| t.id).collect::<Vec<TaskId>>()
+        );
+        assert_eq!(Some(customer.name), today_tasks[0].customer_name);
+        assert_eq!(
+            Some("Task test".to_string()),
+            today_tasks[0].assignee.as_ref().map(|a| a.name.clone())
+        );
+    }
+
+    #[tokio::test]
+    async fn my_tasks_should_follow_the_user_not_their_name() {
+        let db = get_db().await;
+        let customer = add_customer(&db).await;
+        let assignee = add_user(&db, "Task test namesake").await;
+        let namesake = add_user(&db, "Task test namesake").await;
+        let added = add_task(
+            &db,
+            customer.id,
+            task("Call back", "2023-05-10", Some(assignee)),
+        )
+        .await;
+        let _: Option<Value> = db
+            .query_single(
+                "update User filter .id = <uuid>$0 set { name := 'Task test renamed' }",
+                &(assignee,),
+            )
+            .await
+            .unwrap();
+
+        let renamed = due(&db, TaskDue::Today, assignee).await;
+        let others = due(&db, TaskDue::Today, namesake).await;
+
+        remove_customer(&db, customer.id).await;
+        remove_user(&db, assignee).await;
+        remove_user(&db, namesake).await;
+        assert_eq!(
+            vec![added.id],
+            renamed.iter().map(|t| t.id).collect::<Vec<TaskId>>()
+        );
+        assert!(others.is_empty());
+    }
+
+    #[tokio::test]
+    async fn tasks_of_other_organizations_should_be_hidden() {
+        let db = get_db().await;
+        let assignee = add_user(&db, "Task test").await;
+        let mut organizations = vec![];
+        let mut added = vec![];
+        for _ in 0..2 {
+            let organization: Uuid = db
+                .query_required_single(
+                    "select (insert Organization { name := <str>$0 }).id;",
+                    &(format!(
+                        "Organization {}",
+                        Alphanumeric.sample_string(&mut rand::thread_rng(), 16)
+                    ),),
+                )
+                .await
+                .unwrap();
+            let customer = add_customer(&db).await;
+            // added before the move so its audit event stays with the first organisation
+            let task = add_task(
+                &db,
+                customer.id,
+                task("Call back", "2023-05-10", Some(assignee)),
+            )
+            .await;
+            db.query::<Uuid, _>(
+                r#"
+                update Customer filter .id = <uuid>$0
+                set { organization := (select Organization filter .id = <uuid>$1) };"#,
+                &(customer.id, organization),
+            )
+            .await
+            .unwrap();
+            organizations.push(organization);
+            added.push((customer.id, task.id));
+        }
+
+        let first = due_in(
+            &db,
+            CurrentOrganization(Some(organizations[0])),
+            TaskDue::Today,
+            assignee,
+        )
+        .await;
+        let unscoped = due(&db, TaskDue::Today, assignee).await;
+
+        for ((customer, _), organization) in added.iter().zip(&organizations) {
+            remove_customer(&db, *customer).await;
+            db.query::<Uuid, _>(
+                "select (delete Organization filter .id = <uuid>$0).id;",
+                &(organization,),
+            )
+            .await
+            .unwrap();
+        }
+        remove_user(&db, assignee).await;
+        assert_eq!(
+            vec![added[0].1],
+            first.iter().map(|t| t.id).collect::<Vec<TaskId>>()
+        );
+        assert_eq!(2, unscoped.len());
+    }
+
+    #[tokio::test]
+    async fn task_changes_should_be_recorded_in_the_customers_log() {
+        let db = get_db().await;
+        let customer = add_customer(&db).await;
+        let added = add_task(&db, customer.id, task("Follow up", "2023-05-01", None)).await;
+        let _ = update_task(
+            SessionClient(db.clone()),
+            Path((customer.id, added.id)),
+            Actor(Some("Alice".to_string())),
+            Json(Task {
+                completed: true,
+                ..added.clone()
+            }),
+        )
+        .await
+        .into_response();
+        let _ = delete_task(
+            SessionClient(db.clone()),
+            Path((customer.id, added.id)),
+            Actor::default(),
+        )
+        .await
+        .into_response();
+
+        let actions: Vec<String> = db
+            .query(
+                r#"
+                select <str>(
+                    select AuditEvent filter .entity = 'task' and .entity_id = <uuid>$0
+                        and .customer_id = <uuid>$1
+                    order by .created
+                ).action;"#,
+                &(added.id, customer.id),
+            )
+            .await
+            .unwrap();
+        remove_customer(&db, customer.id).await;
+        assert_eq!(vec!["Created", "Updated", "Deleted"], actions);
+    }
+
+    #[tokio::test]
+    async fn completing_a_task_should_persist() {
+        let db = get_db().await;
+        let customer = add_customer(&db).await;
+        let added = add_task(&db, customer.id, task("Follow up", "2023-05-01", None)).await;
+
+        let response = update_task(
+            SessionClient(db.clone()),
+            Path((customer.id, added.id)),
+            Actor::default(),
+            Json(Task {
+                completed: true,
+                priority: "High".to_string(),
+                ..added.clone()
+            }),
+        )
+        .await
+        .into_response();
+        let listed: Vec<Task> = into_type(
+            customer_tasks(
+                SessionClient(db.clone()),
+                State(ListConfig::default()),
+                Path(customer.id),
+            )
+            .await
+            .into_response(),
+        )
+        .await;
+        let deleted = delete_task(
+            SessionClient(db.clone()),
+            Path((customer.id, added.id)),
+            Actor::default(),
+        )
+        .await
+        .into_response();
+
+        remove_customer(&db, customer.id).await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert!(listed[0].completed);
+        assert_eq!("High", listed[0].priority);
+        assert_eq!(StatusCode::OK, deleted.status());
+    }
+
+    #[tokio::test]
+    async fn invalid_task_should_fail() {
+        let db = get_db().await;
+        let response = create_task(
+            SessionClient(db.clone()),
+            Path(CustomerId::from_u128(rand::random())),
+            Actor::default(),
+            Json(Task {
+                priority: "Urgent".to_string(),
+                ..task("No", "next tuesday", None)
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let error: ErrorResponse = into_type(response).await;
+        assert!(error.fields.contains_key("title"));
+        assert!(error.fields.contains_key("due"));
+        assert!(error.fields.contains_key("priority"));
+    }
+}
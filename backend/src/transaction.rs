@@ -0,0 +1,22 @@
+use std::future::Future;
+
+use edgedb_tokio::{Client, Transaction};
+use tokio::runtime::Handle;
+
+use crate::errors::ApiError;
+
+/// Runs the body in a transaction, retrying it when the database asks to.
+/// The transaction future of edgedb-tokio is not `Send` so it can not be awaited in a handler,
+/// instead it is driven on a blocking thread and the handler waits for the result.
+pub async fn transaction<T, B, F>(db: Client, body: B) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    B: FnMut(Transaction) -> F + Send + 'static,
+    F: Future<Output = Result<T, edgedb_tokio::Error>>,
+{
+    let handle = Handle::current();
+    match tokio::task::spawn_blocking(move || handle.block_on(db.transaction(body))).await {
+        Ok(result) => Ok(result?),
+        Err(error) => std::panic::resume_unwind(error.into_panic()),
+    }
+}
@@ -0,0 +1,87 @@
+use std::convert::Infallible;
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::IF_MATCH, request::Parts, HeaderValue},
+};
+use edgedb_protocol::query_arg::QueryArgs;
+use edgedb_tokio::Transaction;
+
+use crate::errors::ApiError;
+
+/// Version of the record the client last read, taken from the `If-Match` header.
+/// Without the header, or with `*`, changes are made whatever the current version is.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IfMatch(pub Option<String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for IfMatch {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let version = parts
+            .headers
+            .get(IF_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty() && *value != "*")
+            .map(|value| value.trim_start_matches("W/").trim_matches('"').to_string());
+        Ok(IfMatch(version))
+    }
+}
+
+/// `ETag` header value for a version
+pub fn etag(version: &str) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", version)).unwrap_or(HeaderValue::from_static("\"\""))
+}
+
+/// Error for a conditional change that matched nothing, `exists_query` tells whether the record is
+/// gone or has moved on to a newer version than the client sent
+pub async fn missing_or_stale(
+    tx: &mut Transaction,
+    exists_query: &str,
+    arguments: &impl QueryArgs,
+) -> Result<ApiError, edgedb_tokio::Error> {
+    let exists: bool = tx.query_required_single(exists_query, arguments).await?;
+    Ok(match exists {
+        true => ApiError::PreconditionFailed,
+        false => ApiError::NotFound,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use super::*;
+
+    async fn if_match(request: Request<()>) -> IfMatch {
+        let (mut parts, _) = request.into_parts();
+        IfMatch::from_request_parts(&mut parts, &()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn version_should_be_read_without_quotes() {
+        let strong = Request::builder()
+            .header(IF_MATCH, "\"2023-05-10T01:02:03.123456+00:00\"")
+            .body(())
+            .unwrap();
+        let weak = Request::builder()
+            .header(IF_MATCH, "W/\"abc\"")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            IfMatch(Some("2023-05-10T01:02:03.123456+00:00".to_string())),
+            if_match(strong).await
+        );
+        assert_eq!(IfMatch(Some("abc".to_string())), if_match(weak).await);
+    }
+
+    #[tokio::test]
+    async fn missing_header_or_any_version_should_be_unconditional() {
+        let any = Request::builder().header(IF_MATCH, "*").body(()).unwrap();
+        assert_eq!(IfMatch(None), if_match(any).await);
+        assert_eq!(IfMatch(None), if_match(Request::new(())).await);
+    }
+}
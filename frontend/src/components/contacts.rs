@@ -0,0 +1,221 @@
+use std::rc::Rc;
+
+use crate::{
+    components::{error::ComponentError, progress_bar::Progress},
+    data::*,
+};
+use uuid::Uuid;
+use yew::prelude::*;
+use yew_hooks::{use_async_with_options, UseAsyncHandle, UseAsyncOptions};
+use yewdux::prelude::use_store;
+use yewdux_input::InputDispatch;
+
+use super::validation::{field_message, is_valid, modal_visible, submit_disabled};
+
+#[derive(Properties, PartialEq)]
+pub struct CustomerContactsProps {
+    pub id: CustomerId,
+}
+
+#[function_component(CustomerContactsList)]
+pub fn customer_contacts_list(props: &CustomerContactsProps) -> Html {
+    let id = props.id.clone();
+    let contacts: UseAsyncHandle<Vec<Contact>, MultiError> = use_async_with_options(
+        async move { get_data(format!("/customer/{}/contacts", id)).await },
+        UseAsyncOptions::enable_auto(),
+    );
+    let (selected_contact, dispatch) = use_store::<Contact>();
+    let (user, _) = use_store::<CurrentUser>();
+    let can_edit = user.can(Permission::Edit);
+    let modal_open = use_state(|| false);
+
+    let close_modal = {
+        let open_handle = modal_open.clone();
+        Callback::from(move |_| {
+            open_handle.set(false);
+        })
+    };
+    let server_error = use_state(|| None::<MultiError>);
+    {
+        let server_error = server_error.clone();
+        use_effect_with_deps(
+            move |_| {
+                server_error.set(None);
+                || ()
+            },
+            (*selected_contact).clone(),
+        );
+    }
+    let save = |contact: Rc<Contact>| {
+        let reload_list = contacts.clone();
+        let customer_id = id.clone();
+        let current_modal_state = modal_open.clone();
+        let current_server_error = server_error.clone();
+        dispatch.reduce_mut_future_callback(move |_| {
+            let c = contact.clone();
+            let modal = current_modal_state.clone();
+            let reload = reload_list.clone();
+            let server_error = current_server_error.clone();
+            Box::pin(async move {
+                let result = if c.id.eq(&Uuid::default()) {
+                    post_data(format!("/customer/{}/contacts", customer_id), c).await
+                } else {
+                    put_data(format!("/customer/{}/contact/{}", customer_id, c.id), c).await
+                };
+                match result {
+                    Ok(_) => {
+                        modal.set(false);
+                        reload.run();
+                    }
+                    Err(error) => server_error.set(Some(error)),
+                }
+                ()
+            })
+        })
+    };
+    let delete_contact = |contact_id: ContactId| {
+        let reload_list = contacts.clone();
+        let customer_id = id.clone();
+        dispatch.reduce_mut_future_callback(move |_| {
+            let reload = reload_list.clone();
+            Box::pin(async move {
+                if let Ok(_) =
+                    delete_data(format!("/customer/{}/contact/{}", customer_id, contact_id)).await
+                {
+                    reload.run();
+                }
+                ()
+            })
+        })
+    };
+    let select_contact = |contact: Contact| {
+        let current_modal_state = modal_open.clone();
+        let current_server_error = server_error.clone();
+        dispatch.reduce_mut_callback(move |state| {
+            *state = contact.to_owned();
+            current_server_error.set(None);
+            current_modal_state.set(true);
+        })
+    };
+    let add_contact = select_contact(Contact::default());
+    fn modal_label(id: ContactId) -> String {
+        match id.eq(&Uuid::default()) {
+            true => "Add contact".to_string(),
+            false => "Edit contact".to_string(),
+        }
+    }
+    html! {
+        <>
+        if let Some(contacts) = contacts.data.clone() {
+            <div class={classes!("modal",modal_visible(*modal_open))}>
+                <div class="modal-background"></div>
+                <div class="modal-card">
+                    <header class="modal-card-head">
+                        <p class="modal-card-title">{modal_label(selected_contact.id)}</p>
+                        <button onclick={&close_modal} class="delete" aria-label="close"></button>
+                    </header>
+                        <section class="modal-card-body">
+                            if let Some(error) = (*server_error).clone() {
+                                <div class="notification is-danger is-light">{error.message()}</div>
+                            }
+                            <div class="field">
+                                <label class="label">{"Name"}</label>
+                                <div class="control">
+                                <input value={selected_contact.name.clone()} oninput={dispatch.input_mut(|contact, text| contact.name = text)} class={classes!("input",is_valid("name", selected_contact.as_ref(), &server_error))} type="text" placeholder="Name"/>
+                                </div>
+                                <p class="help is-danger">{field_message("name", selected_contact.as_ref(), &server_error)}</p>
+                            </div>
+                            <div class="field">
+                                <label class="label">{"Email"}</label>
+                                <div class="control">
+                                <input value={selected_contact.email.clone()} oninput={dispatch.input_mut(|contact, text| contact.email = text)} class={classes!("input",is_valid("email", selected_contact.as_ref(), &server_error))} type="email" placeholder="Email"/>
+                                </div>
+                                <p class="help is-danger">{field_message("email", selected_contact.as_ref(), &server_error)}</p>
+                            </div>
+                            <div class="field">
+                                <label class="label">{"Phone"}</label>
+                                <div class="control">
+                                <input value={selected_contact.phone.clone().unwrap_or_default()} oninput={dispatch.input_mut(|contact, text: String| contact.phone = Some(text).filter(|t| !t.is_empty()))} class={classes!("input",is_valid("phone", selected_contact.as_ref(), &server_error))} type="tel" placeholder="Phone"/>
+                                </div>
+                                <p class="help is-danger">{field_message("phone", selected_contact.as_ref(), &server_error)}</p>
+                            </div>
+                            <div class="field">
+                                <label class="label">{"Job title"}</label>
+                                <div class="control">
+                                <input value={selected_contact.job_title.clone().unwrap_or_default()} oninput={dispatch.input_mut(|contact, text: String| contact.job_title = Some(text).filter(|t| !t.is_empty()))} class={classes!("input",is_valid("job_title", selected_contact.as_ref(), &server_error))} type="text" placeholder="Job title"/>
+                                </div>
+                                <p class="help is-danger">{field_message("job_title", selected_contact.as_ref(), &server_error)}</p>
+                            </div>
+                            <div class="field">
+                                <div class="control">
+                                <label class="checkbox">
+                                    <input type="checkbox" checked={selected_contact.is_primary} onclick={dispatch.reduce_mut_callback(|contact| contact.is_primary = !contact.is_primary)}/>
+                                    {" Primary contact"}
+                                </label>
+                                </div>
+                            </div>
+                        </section>
+                    <footer class="modal-card-foot">
+                        <button disabled={submit_disabled(dispatch.get().as_ref())} onclick={&save(dispatch.get())} class="button is-success">{"Save changes"}</button>
+                        <button onclick={&close_modal} class="button">{"Cancel"}</button>
+                    </footer>
+                </div>
+            </div>
+
+            if can_edit {
+                <div class="field is-grouped">
+                    <div class="control">
+                        <button onclick={add_contact} class="button is-link">{"Add contact"}</button>
+                    </div>
+                </div>
+            }
+            <table class="table is-fullwidth">
+                <thead>
+                <tr>
+                    <td>{"Name"}</td>
+                    <td>{"Job title"}</td>
+                    <td>{"Email"}</td>
+                    <td>{"Phone"}</td>
+                    <td>{""}</td>
+                </tr>
+                </thead>
+                <tbody>
+                {
+                    contacts.iter().map(|c| {
+                        html!{
+                        <tr>
+                            <td>
+                                {&c.name}
+                                if c.is_primary {
+                                    <span class="tag is-primary is-light ml-2">{"Primary"}</span>
+                                }
+                            </td>
+                            <td>{c.job_title.clone().unwrap_or_default()}</td>
+                            <td><a href={format!("mailto:{}", c.email)}>{&c.email}</a></td>
+                            <td>{c.phone.clone().unwrap_or_default()}</td>
+                            <td>
+                            if can_edit {
+                                <div class="field is-grouped">
+                                    <div class="control">
+                                        <button onclick={select_contact(c.clone())} class="button is-info"><ion-icon class="" name="pencil"/></button>
+                                        <button onclick={delete_contact(c.id)} class="button is-danger"><ion-icon class="" name="trash"/></button>
+                                    </div>
+                                </div>
+                            }
+                            </td>
+                        </tr>
+                        }
+                    }).collect::<Html>()
+                }
+                </tbody>
+            </table>
+        } else {
+            if contacts.error.is_some() {
+                <ComponentError />
+            }else{
+                <Progress/>
+            }
+        }
+        </>
+    }
+}
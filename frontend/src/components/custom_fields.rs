@@ -0,0 +1,454 @@
+use serde_json::Value;
+use web_sys::HtmlInputElement;
+use yew::{platform::spawn_local, prelude::*};
+use yew_hooks::{use_async_with_options, UseAsyncHandle, UseAsyncOptions};
+use yewdux::prelude::use_store;
+
+use crate::{
+    components::{
+        error::ComponentError,
+        nav_bar::Navbar,
+        progress_bar::Progress,
+        validation::{field_message, is_valid, submit_disabled},
+    },
+    data::*,
+};
+
+#[derive(Properties, PartialEq)]
+pub struct CustomFieldInputsProps {
+    /// Fields of the kind of record being edited
+    pub fields: Vec<CustomField>,
+    pub values: CustomValues,
+    /// Called with every value after one of them changed
+    pub onchange: Callback<CustomValues>,
+    #[prop_or_default]
+    pub server_error: Option<MultiError>,
+}
+
+#[derive(Properties, PartialEq)]
+pub struct CustomFieldValuesProps {
+    pub fields: Vec<CustomField>,
+    pub values: CustomValues,
+}
+
+/// Fields of the kind of record, loaded when the component is first shown
+#[hook]
+pub fn use_custom_fields(
+    entity: CustomFieldEntity,
+) -> UseAsyncHandle<Vec<CustomField>, MultiError> {
+    use_async_with_options(
+        async move { get_data(format!("/custom-fields?entity={}", entity)).await },
+        UseAsyncOptions::enable_auto(),
+    )
+}
+
+/// Value as it is shown in an input, numbers and booleans are edited as text
+fn input_value(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Null) | None => String::new(),
+        Some(other) => other.to_string(),
+    }
+}
+
+/// Value as it is shown on the record
+pub fn display_value(field: &CustomField, value: Option<&Value>) -> String {
+    match (field.kind, value) {
+        (CustomFieldKind::Boolean, Some(Value::Bool(true))) => "Yes".to_string(),
+        (CustomFieldKind::Boolean, Some(Value::Bool(false))) => "No".to_string(),
+        (_, value) => input_value(value),
+    }
+}
+
+/// Client side message for the value, falling back to the message the server returned
+fn value_message(
+    field: &CustomField,
+    values: &CustomValues,
+    server_error: &Option<MultiError>,
+) -> Option<String> {
+    field.check(values.get(&field.key)).err().or_else(|| {
+        server_error
+            .as_ref()
+            .and_then(|error| error.field_message(&format!("custom.{}", field.key)))
+    })
+}
+
+/// An input per custom field of the kind of record, chosen by the type of the field
+#[function_component(CustomFieldInputs)]
+pub fn custom_field_inputs(props: &CustomFieldInputsProps) -> Html {
+    let set = |key: &str| {
+        let key = key.to_string();
+        let values = props.values.clone();
+        let onchange = props.onchange.clone();
+        Callback::from(move |value: Option<Value>| {
+            let mut values = values.clone();
+            match value {
+                Some(value) => values.insert(key.clone(), value),
+                None => values.remove(&key),
+            };
+            onchange.emit(values);
+        })
+    };
+    let input = |field: &CustomField| {
+        let set = set(&field.key);
+        let current = props.values.get(&field.key);
+        let text = input_value(current);
+        let message = value_message(field, &props.values, &props.server_error);
+        let danger = message.as_ref().map(|_| "is-danger");
+        let set_text = {
+            let set = set.clone();
+            Callback::from(move |e: InputEvent| {
+                let text = e.target_unchecked_into::<HtmlInputElement>().value();
+                set.emit((!text.is_empty()).then_some(Value::String(text)))
+            })
+        };
+        let choose = |value: Option<Value>| {
+            let set = set.clone();
+            Callback::from(move |_| set.emit(value.clone()))
+        };
+        let control = match field.kind {
+            CustomFieldKind::Text => html! {
+                <input value={text} oninput={set_text} class={classes!("input", danger)} type="text"/>
+            },
+            CustomFieldKind::Number => html! {
+                <input value={text} oninput={set_text} class={classes!("input", danger)} type="number" step="any"/>
+            },
+            CustomFieldKind::Date => html! {
+                <input value={text} oninput={set_text} class={classes!("input", danger)} type="date"/>
+            },
+            CustomFieldKind::Select => html! {
+                <div class={classes!("select", "is-fullwidth", danger)}>
+                    <select>
+                        <option onclick={choose(None)} selected={text.is_empty()} value="">{""}</option>
+                        {field.options.iter().map(|option| html! {
+                            <option onclick={choose(Some(Value::String(option.clone())))} selected={*option == text} value={option.clone()}>{option}</option>
+                        }).collect::<Html>()}
+                    </select>
+                </div>
+            },
+            CustomFieldKind::Boolean => html! {
+                <div class={classes!("select", "is-fullwidth", danger)}>
+                    <select>
+                        <option onclick={choose(None)} selected={current.is_none()} value="">{""}</option>
+                        <option onclick={choose(Some(Value::Bool(true)))} selected={current == Some(&Value::Bool(true))} value="true">{"Yes"}</option>
+                        <option onclick={choose(Some(Value::Bool(false)))} selected={current == Some(&Value::Bool(false))} value="false">{"No"}</option>
+                    </select>
+                </div>
+            },
+        };
+        html! {
+            <div class="field">
+                <label class="label">
+                    {&field.label}
+                    if field.is_required {
+                        {" *"}
+                    }
+                </label>
+                <div class="control">{control}</div>
+                <p class="help is-danger">{message}</p>
+            </div>
+        }
+    };
+    html! {
+        {props.fields.iter().map(input).collect::<Html>()}
+    }
+}
+
+/// Labels and values of the custom fields the record has a value for
+#[function_component(CustomFieldValues)]
+pub fn custom_field_values(props: &CustomFieldValuesProps) -> Html {
+    let filled: Vec<(String, String)> = props
+        .fields
+        .iter()
+        .filter_map(|field| {
+            props
+                .values
+                .get(&field.key)
+                .map(|value| (field.label.clone(), display_value(field, Some(value))))
+        })
+        .collect();
+    html! {
+        if !filled.is_empty() {
+            <div class="field is-grouped is-grouped-multiline">
+                {filled.into_iter().map(|(label, value)| html! {
+                    <div class="control">
+                        <div class="tags has-addons">
+                            <span class="tag is-dark">{label}</span>
+                            <span class="tag is-light">{value}</span>
+                        </div>
+                    </div>
+                }).collect::<Html>()}
+            </div>
+        }
+    }
+}
+
+/// Lets an admin add custom fields to customers and opportunities and change their labels and rules
+#[function_component(CustomFieldsPage)]
+pub fn custom_fields_page() -> Html {
+    let fields: UseAsyncHandle<Vec<CustomField>, MultiError> = use_async_with_options(
+        async move { get_data("/custom-fields".to_string()).await },
+        UseAsyncOptions::enable_auto(),
+    );
+    let (user, _) = use_store::<CurrentUser>();
+    let can_manage = user.can(Permission::ManageSettings);
+    let field = use_state(CustomField::default);
+    let options_text = use_state(String::new);
+    let error = use_state(|| None::<MultiError>);
+    let editing = !field.id.is_nil();
+
+    let update = |change: fn(&mut CustomField, String)| {
+        let field = field.clone();
+        Callback::from(move |e: InputEvent| {
+            let mut next = (*field).clone();
+            change(
+                &mut next,
+                e.target_unchecked_into::<HtmlInputElement>().value(),
+            );
+            field.set(next);
+        })
+    };
+    let set_options = {
+        let field = field.clone();
+        let options_text = options_text.clone();
+        Callback::from(move |e: InputEvent| {
+            let text = e.target_unchecked_into::<HtmlInputElement>().value();
+            field.set(CustomField {
+                options: text
+                    .split(',')
+                    .map(|o| o.trim().to_string())
+                    .filter(|o| !o.is_empty())
+                    .collect(),
+                ..(*field).clone()
+            });
+            options_text.set(text);
+        })
+    };
+    let choose = |change: CustomField| {
+        let field = field.clone();
+        Callback::from(move |_| field.set(change.clone()))
+    };
+    let toggle_required = {
+        let field = field.clone();
+        Callback::from(move |_| {
+            field.set(CustomField {
+                is_required: !field.is_required,
+                ..(*field).clone()
+            })
+        })
+    };
+    let reset = {
+        let field = field.clone();
+        let options_text = options_text.clone();
+        let error = error.clone();
+        Callback::from(move |_| {
+            field.set(CustomField::default());
+            options_text.set(String::new());
+            error.set(None);
+        })
+    };
+    let edit = |selected: &CustomField| {
+        let selected = selected.clone();
+        let field = field.clone();
+        let options_text = options_text.clone();
+        let error = error.clone();
+        Callback::from(move |_| {
+            options_text.set(selected.options.join(", "));
+            field.set(selected.clone());
+            error.set(None);
+        })
+    };
+    let save = {
+        let field = field.clone();
+        let error = error.clone();
+        let reset = reset.clone();
+        let reload = fields.clone();
+        Callback::from(move |_| {
+            let body = (*field).clone();
+            let error = error.clone();
+            let reset = reset.clone();
+            let reload = reload.clone();
+            spawn_local(async move {
+                let saved = match body.id.is_nil() {
+                    true => post_data("/custom-fields".to_string(), body).await,
+                    false => put_data(format!("/custom-field/{}", body.id), body).await,
+                };
+                match saved {
+                    Ok(_) => {
+                        reset.emit(());
+                        reload.run();
+                    }
+                    Err(e) => error.set(Some(e)),
+                }
+            });
+        })
+    };
+    let delete = |id: CustomFieldId| {
+        let reload = fields.clone();
+        Callback::from(move |_| {
+            let reload = reload.clone();
+            spawn_local(async move {
+                if delete_data(format!("/custom-field/{}", id)).await.is_ok() {
+                    reload.run();
+                }
+            });
+        })
+    };
+    let range_label = match field.kind {
+        CustomFieldKind::Text => Some(("Fewest characters", "Most characters")),
+        CustomFieldKind::Number => Some(("Minimum", "Maximum")),
+        _ => None,
+    };
+    let set_min = update(|field, text| field.min = text.parse().ok());
+    let set_max = update(|field, text| field.max = text.parse().ok());
+    let kinds = [
+        CustomFieldKind::Text,
+        CustomFieldKind::Number,
+        CustomFieldKind::Date,
+        CustomFieldKind::Select,
+        CustomFieldKind::Boolean,
+    ];
+    html! {
+        <>
+        <section class="hero is-primary">
+            <Navbar/>
+            <div class="hero-body">
+                <p class="title">
+                {"Custom fields"}
+                </p>
+            </div>
+        </section>
+        <section class="section">
+            if can_manage {
+                <div class="box">
+                    <h2 class="title is-5">{if editing { "Edit field" } else { "New field" }}</h2>
+                    if let Some(error) = (*error).clone() {
+                        <div class="notification is-danger is-light">{error.message()}</div>
+                    }
+                    <div class="columns">
+                        <div class="column">
+                            <div class="field">
+                                <label class="label">{"Record"}</label>
+                                <div class="control">
+                                <div class="select is-fullwidth">
+                                <select disabled={editing}>
+                                    <option onclick={choose(CustomField { entity: CustomFieldEntity::Customer, ..(*field).clone() })} selected={field.entity == CustomFieldEntity::Customer}>{"Customer"}</option>
+                                    <option onclick={choose(CustomField { entity: CustomFieldEntity::Opportunity, ..(*field).clone() })} selected={field.entity == CustomFieldEntity::Opportunity}>{"Opportunity"}</option>
+                                </select>
+                                </div>
+                                </div>
+                            </div>
+                            <div class="field">
+                                <label class="label">{"Key"}</label>
+                                <div class="control">
+                                <input value={field.key.clone()} oninput={update(|field, text| field.key = text)} disabled={editing} class={classes!("input",is_valid("key", &*field, &error))} type="text" placeholder="arr_band"/>
+                                </div>
+                                <p class="help is-danger">{field_message("key", &*field, &error)}</p>
+                            </div>
+                            <div class="field">
+                                <label class="label">{"Label"}</label>
+                                <div class="control">
+                                <input value={field.label.clone()} oninput={update(|field, text| field.label = text)} class={classes!("input",is_valid("label", &*field, &error))} type="text" placeholder="ARR band"/>
+                                </div>
+                                <p class="help is-danger">{field_message("label", &*field, &error)}</p>
+                            </div>
+                        </div>
+                        <div class="column">
+                            <div class="field">
+                                <label class="label">{"Type"}</label>
+                                <div class="control">
+                                <div class="select is-fullwidth">
+                                <select disabled={editing}>
+                                    {kinds.into_iter().map(|kind| html! {
+                                        <option onclick={choose(CustomField { kind, ..(*field).clone() })} selected={field.kind == kind}>{kind.to_string()}</option>
+                                    }).collect::<Html>()}
+                                </select>
+                                </div>
+                                </div>
+                            </div>
+                            if field.kind == CustomFieldKind::Select {
+                                <div class="field">
+                                    <label class="label">{"Options"}</label>
+                                    <div class="control">
+                                    <input value={(*options_text).clone()} oninput={set_options} class={classes!("input",is_valid("custom_field", &*field, &error))} type="text" placeholder="Small, Medium, Large"/>
+                                    </div>
+                                    <p class="help is-danger">{field_message("custom_field", &*field, &error)}</p>
+                                </div>
+                            }
+                            if let Some((min_label, max_label)) = range_label {
+                                <div class="field is-grouped">
+                                    <div class="control is-expanded">
+                                        <label class="label">{min_label}</label>
+                                        <input value={field.min.map(|m| m.to_string()).unwrap_or_default()} oninput={set_min} class="input" type="number" step="any"/>
+                                    </div>
+                                    <div class="control is-expanded">
+                                        <label class="label">{max_label}</label>
+                                        <input value={field.max.map(|m| m.to_string()).unwrap_or_default()} oninput={set_max} class="input" type="number" step="any"/>
+                                    </div>
+                                </div>
+                                <p class="help is-danger">{field_message("custom_field", &*field, &error)}</p>
+                            }
+                            <div class="field">
+                                <label class="checkbox">
+                                    <input type="checkbox" checked={field.is_required} onclick={toggle_required}/>
+                                    {" Required"}
+                                </label>
+                            </div>
+                        </div>
+                    </div>
+                    <div class="buttons">
+                        <button disabled={submit_disabled(&*field)} onclick={save} class="button is-success">{if editing { "Save field" } else { "Add field" }}</button>
+                        if editing {
+                            <button onclick={reset.reform(|_| ())} class="button">{"Cancel"}</button>
+                        }
+                    </div>
+                </div>
+            }
+            if let Some(fields) = fields.data.clone() {
+                <table class="table is-fullwidth">
+                    <thead>
+                    <tr>
+                        <td>{"Record"}</td>
+                        <td>{"Label"}</td>
+                        <td>{"Key"}</td>
+                        <td>{"Type"}</td>
+                        <td>{"Required"}</td>
+                        <td>{""}</td>
+                    </tr>
+                    </thead>
+                    <tbody>
+                    {fields.iter().map(|f| html! {
+                        <tr>
+                            <td>{f.entity.to_string()}</td>
+                            <td>{&f.label}</td>
+                            <td><code>{&f.key}</code></td>
+                            <td>
+                                {f.kind.to_string()}
+                                if f.kind == CustomFieldKind::Select {
+                                    <p class="help">{f.options.join(", ")}</p>
+                                }
+                            </td>
+                            <td>{if f.is_required { "Yes" } else { "No" }}</td>
+                            <td>
+                                if can_manage {
+                                    <div class="buttons">
+                                        <button onclick={edit(f)} class="button is-info"><ion-icon class="" name="pencil"/></button>
+                                        <button onclick={delete(f.id)} class="button is-danger"><ion-icon class="" name="trash"/></button>
+                                    </div>
+                                }
+                            </td>
+                        </tr>
+                    }).collect::<Html>()}
+                    </tbody>
+                </table>
+            } else {
+                if fields.error.is_some() {
+                    <ComponentError/>
+                } else {
+                    <Progress/>
+                }
+            }
+        </section>
+        </>
+    }
+}
@@ -2,21 +2,33 @@ use std::rc::Rc;
 
 use crate::{
     components::{
+        contacts::CustomerContactsList,
+        custom_fields::{use_custom_fields, CustomFieldInputs, CustomFieldValues},
+        customers::CustomerFormFields,
+        history::CustomerHistory,
         nav_bar::Navbar,
         progress_bar::{PageProgress, Progress},
+        tags::TagEditor,
+        tasks::CustomerTasksList,
+        timeline::CustomerTimeline,
+        users::{use_users, OwnerPicker},
     },
     data::Opportunity,
     data::*,
+    routes::AppRoute,
 };
 use uuid::Uuid;
-use validator::Validate;
-use yew::prelude::*;
-use yew_hooks::{use_async_with_options, UseAsyncHandle, UseAsyncOptions};
+use yew::{platform::spawn_local, prelude::*};
+use yew_hooks::{use_async, use_async_with_options, UseAsyncHandle, UseAsyncOptions};
+use yew_router::prelude::use_navigator;
 
 use yewdux::prelude::use_store;
 use yewdux_input::InputDispatch;
 
-use super::error::ComponentError;
+use super::{
+    error::{ComponentError, StaleRecord},
+    validation::{field_message, is_valid, modal_visible, submit_disabled},
+};
 
 #[derive(Properties, PartialEq)]
 pub struct CustomerDetailProps {
@@ -29,56 +41,11 @@ pub struct EditOpportunityModalProps {
     pub open: bool,
 }
 
-fn is_valid(field: &str, state: &Rc<Opportunity>) -> Option<String> {
-    match validation_message(field, state) {
-        Some(_) => Some("is-danger".to_string()),
-        None => None,
-    }
-}
-
-fn validation_message(field: &str, state: &Rc<Opportunity>) -> Option<String> {
-    match state.validate() {
-        Err(error) => {
-            let field_messages = error
-                .field_errors()
-                .iter()
-                .filter(|(f, _)| f.clone().cmp(&field.clone()).is_eq())
-                .map(|(_, v)| {
-                    format!(
-                        "{}",
-                        v.into_iter()
-                            .filter(|f| f.message.is_some())
-                            .map(|f| f.message.clone().unwrap().to_string())
-                            .collect::<Vec<String>>()
-                            .join(",")
-                    )
-                })
-                .collect::<Vec<String>>();
-            let overall_messages = error
-                .field_errors()
-                .iter()
-                .filter(|(f, _)| f.clone().contains(&"__all__"))
-                .map(|(_, v)| {
-                    format!(
-                        "{}",
-                        v.into_iter()
-                            .filter(|f| f.code.contains(field))
-                            .map(|f| f.message.clone().unwrap().to_string())
-                            .collect::<Vec<String>>()
-                            .join("")
-                    )
-                })
-                .filter(|s| s.len() > 0)
-                .collect::<Vec<String>>();
-            let combined = [field_messages, overall_messages].concat();
-            if combined.len() == 0 {
-                None
-            } else {
-                Some(combined.join(","))
-            }
-        }
-        Ok(_) => None,
-    }
+/// Tabs of the customer page, the history is only fetched when its tab is open
+#[derive(Clone, Copy, PartialEq)]
+enum DetailTab {
+    Overview,
+    History,
 }
 
 #[function_component(CustomerDetail)]
@@ -88,9 +55,193 @@ pub fn customer_detail(props: &CustomerDetailProps) -> Html {
         async move { get_data(format!("/customer/{}", id)).await },
         UseAsyncOptions::enable_auto(),
     );
+    let all_tags: UseAsyncHandle<Vec<Tag>, MultiError> = use_async_with_options(
+        async move { get_data("/tags".to_string()).await },
+        UseAsyncOptions::enable_auto(),
+    );
+    let custom_fields = use_custom_fields(CustomFieldEntity::Customer);
+    let users = use_users();
+    let owner_changed = {
+        let reload_customer = customer.clone();
+        Callback::from(move |_| reload_customer.run())
+    };
+    let tags_changed = {
+        let reload_customer = customer.clone();
+        let reload_tags = all_tags.clone();
+        Callback::from(move |_| {
+            reload_customer.run();
+            reload_tags.run();
+        })
+    };
+    let (_, dispatch) = use_store::<Customer>();
+    let (user, _) = use_store::<CurrentUser>();
+    let modal_open = use_state(|| false);
+    let tab = use_state(|| DetailTab::Overview);
+    let select_tab = |selected: DetailTab| {
+        let tab = tab.clone();
+        Callback::from(move |_| tab.set(selected))
+    };
+    let tab_class = |this: DetailTab| (*tab == this).then(|| "is-active");
+
+    let close_modal = {
+        let open_handle = modal_open.clone();
+        Callback::from(move |_| {
+            open_handle.set(false);
+        })
+    };
+    let customer_error = use_state(|| None::<MultiError>);
+    let edit_customer = |customer: Customer| {
+        let current_modal_state = modal_open.clone();
+        let current_customer_error = customer_error.clone();
+        dispatch.reduce_mut_callback(move |state| {
+            *state = customer.to_owned();
+            current_customer_error.set(None);
+            current_modal_state.set(true);
+        })
+    };
+    let update = |customer_update: Rc<Customer>| {
+        let reload_customer = customer.clone();
+        let current_modal_state = modal_open.clone();
+        let current_customer_error = customer_error.clone();
+        dispatch.reduce_mut_future_callback(move |_| {
+            let c = customer_update.clone();
+            let modal = current_modal_state.clone();
+            let reload = reload_customer.clone();
+            let customer_error = current_customer_error.clone();
+            Box::pin(async move {
+                match put_versioned(format!("/customer/{}", c.id), c.clone(), &c.version).await {
+                    Ok(_) => {
+                        modal.set(false);
+                        reload.run();
+                    }
+                    Err(error) => customer_error.set(Some(error)),
+                }
+                ()
+            })
+        })
+    };
+    let reload_edit = {
+        let reload_customer = customer.clone();
+        let current_customer_error = customer_error.clone();
+        let dispatch = dispatch.clone();
+        Callback::from(move |_| {
+            let reload = reload_customer.clone();
+            let customer_error = current_customer_error.clone();
+            let dispatch = dispatch.clone();
+            spawn_local(async move {
+                if let Ok(fresh) = get_data::<Customer>(format!("/customer/{}", id)).await {
+                    dispatch.set(fresh);
+                    customer_error.set(None);
+                    reload.run();
+                }
+            });
+        })
+    };
+    let delete_open = use_state(|| false);
+    let delete_error = use_state(|| None::<MultiError>);
+    let deletion: UseAsyncHandle<CustomerDeletion, MultiError> =
+        use_async(async move { get_data(format!("/customer/{}/deletion", id)).await });
+    let open_delete = {
+        let open_handle = delete_open.clone();
+        let preview = deletion.clone();
+        Callback::from(move |_| {
+            preview.run();
+            open_handle.set(true);
+        })
+    };
+    let close_delete = {
+        let open_handle = delete_open.clone();
+        let current_delete_error = delete_error.clone();
+        Callback::from(move |_| {
+            current_delete_error.set(None);
+            open_handle.set(false);
+        })
+    };
+    let reload_delete = {
+        let reload_customer = customer.clone();
+        let preview = deletion.clone();
+        let current_delete_error = delete_error.clone();
+        Callback::from(move |_| {
+            current_delete_error.set(None);
+            reload_customer.run();
+            preview.run();
+        })
+    };
+    let navigator = use_navigator();
+    let confirm_delete = {
+        let customer_id = id.clone();
+        let version = customer
+            .data
+            .as_ref()
+            .map(|c| c.version.clone())
+            .unwrap_or_default();
+        let current_delete_error = delete_error.clone();
+        dispatch.reduce_mut_future_callback(move |_| {
+            let navigator = navigator.clone();
+            let version = version.clone();
+            let delete_error = current_delete_error.clone();
+            Box::pin(async move {
+                match delete_versioned(format!("/customer/{}", customer_id), &version).await {
+                    Ok(_) => {
+                        if let Some(navigator) = navigator {
+                            navigator.push(&AppRoute::CustomerList);
+                        }
+                    }
+                    Err(error) => delete_error.set(Some(error)),
+                }
+                ()
+            })
+        })
+    };
     html! {
         <>
         if let Some(customer) = customer.data.clone() {
+            <div class={classes!("modal",modal_visible(*delete_open))}>
+                <div class="modal-background"></div>
+                <div class="modal-card">
+                    <header class="modal-card-head">
+                        <p class="modal-card-title">{"Delete customer"}</p>
+                        <button onclick={&close_delete} class="delete" aria-label="close"></button>
+                    </header>
+                        <section class="modal-card-body">
+                        if (*delete_error).as_ref().map(|e| e.is_stale()).unwrap_or(false) {
+                            <StaleRecord on_reload={&reload_delete}/>
+                        }
+                        if let Some(error) = (*delete_error).clone().filter(|e| !e.is_stale()) {
+                            <div class="notification is-danger is-light">{error.message()}</div>
+                        }
+                        if let Some(preview) = deletion.data.clone() {
+                            <p>{format!("{} and its {} opportunities will be moved to the trash.", customer.name, preview.opportunity_count)}</p>
+                            <p>{"They can be restored from the trash until it is emptied."}</p>
+                        } else {
+                            <Progress/>
+                        }
+                        </section>
+                    <footer class="modal-card-foot">
+                        <button disabled={deletion.data.is_none()} onclick={&confirm_delete} class="button is-danger">{"Move to trash"}</button>
+                        <button onclick={&close_delete} class="button">{"Cancel"}</button>
+                    </footer>
+                </div>
+            </div>
+            <div class={classes!("modal",modal_visible(*modal_open))}>
+                <div class="modal-background"></div>
+                <div class="modal-card">
+                    <header class="modal-card-head">
+                        <p class="modal-card-title">{"Edit customer"}</p>
+                        <button onclick={&close_modal} class="delete" aria-label="close"></button>
+                    </header>
+                        <section class="modal-card-body">
+                            if (*customer_error).as_ref().map(|e| e.is_stale()).unwrap_or(false) {
+                                <StaleRecord on_reload={&reload_edit}/>
+                            }
+                            <CustomerFormFields server_error={(*customer_error).clone().filter(|e| !e.is_stale())}/>
+                        </section>
+                    <footer class="modal-card-foot">
+                        <button disabled={submit_disabled(dispatch.get().as_ref()) || check_custom_values(&custom_fields.data.clone().unwrap_or_default(), &dispatch.get().custom).is_err()} onclick={&update(dispatch.get())} class="button is-success">{"Save changes"}</button>
+                        <button onclick={&close_modal} class="button">{"Cancel"}</button>
+                    </footer>
+                </div>
+            </div>
             <section class="hero is-primary">
             <Navbar/>
                 <div class="hero-body">
@@ -100,11 +251,63 @@ pub fn customer_detail(props: &CustomerDetailProps) -> Html {
                     <p class="sub-title">
                     {&customer.email}
                     </p>
+                    <div class="mt-2">
+                        <span class="mr-2">{"Owner"}</span>
+                        <OwnerPicker
+                            owner={customer.owner.clone()}
+                            users={users.data.clone().unwrap_or_default()}
+                            target={AssignRequest { customers: vec![customer.id], ..AssignRequest::default() }}
+                            onchange={owner_changed}/>
+                    </div>
+                    <div class="mt-3">
+                        <CustomFieldValues fields={custom_fields.data.clone().unwrap_or_default()} values={customer.custom.clone()}/>
+                    </div>
+                    <div class="box mt-3 p-3">
+                        <TagEditor
+                            tags={customer.tags.clone()}
+                            all_tags={all_tags.data.clone().unwrap_or_default()}
+                            target={BulkTagRequest { customers: vec![customer.id], ..BulkTagRequest::default() }}
+                            onchange={tags_changed}/>
+                    </div>
+                    <div class="buttons mt-3">
+                        if user.can(Permission::Edit) {
+                            <button onclick={edit_customer(customer.clone())} class="button is-light"><ion-icon class="" name="pencil"/><span>{"Edit customer"}</span></button>
+                        }
+                        if user.can(Permission::DeleteCustomers) {
+                            <button onclick={open_delete} class="button is-danger"><ion-icon class="" name="trash"/><span>{"Delete customer"}</span></button>
+                        }
+                    </div>
                 </div>
             </section>
-            <section class="section">
-                <CustomerOpportunitiesList id={customer.id}/>
-            </section>
+            <div class="tabs is-boxed mt-3">
+                <ul>
+                    <li class={classes!(tab_class(DetailTab::Overview))}><a onclick={select_tab(DetailTab::Overview)}>{"Overview"}</a></li>
+                    <li class={classes!(tab_class(DetailTab::History))}><a onclick={select_tab(DetailTab::History)}>{"History"}</a></li>
+                </ul>
+            </div>
+            if *tab == DetailTab::History {
+                <section class="section">
+                    <h2 class="title is-4">{"History"}</h2>
+                    <CustomerHistory id={customer.id}/>
+                </section>
+            } else {
+                <section class="section">
+                    <h2 class="title is-4">{"Contacts"}</h2>
+                    <CustomerContactsList id={customer.id}/>
+                </section>
+                <section class="section">
+                    <h2 class="title is-4">{"Opportunities"}</h2>
+                    <CustomerOpportunitiesList id={customer.id}/>
+                </section>
+                <section class="section">
+                    <h2 class="title is-4">{"Tasks"}</h2>
+                    <CustomerTasksList id={customer.id}/>
+                </section>
+                <section class="section">
+                    <h2 class="title is-4">{"Timeline"}</h2>
+                    <CustomerTimeline id={customer.id}/>
+                </section>
+            }
         } else {
             if customer.error.is_some() {
                 <ComponentError/>
@@ -116,14 +319,77 @@ pub fn customer_detail(props: &CustomerDetailProps) -> Html {
     }
 }
 
+fn outcome_tag(outcome: Option<&str>) -> &'static str {
+    match outcome {
+        Some("Won") => "is-success",
+        Some("Lost") => "is-danger",
+        _ => "is-info",
+    }
+}
+
 #[function_component(CustomerOpportunitiesList)]
 pub fn customer_opportunities_list(props: &CustomerDetailProps) -> Html {
     let id = props.id.clone();
-    let opportunities: UseAsyncHandle<Vec<Opportunity>, MultiError> = use_async_with_options(
-        async move { get_data(format!("/customer/{}/opportunities", id)).await },
+    let query = use_state(OpportunitiesQueryParams::default);
+    let opportunities: UseAsyncHandle<Vec<Opportunity>, MultiError> = {
+        let query = (*query).clone();
+        use_async(async move {
+            get_data(format!(
+                "/customer/{}/opportunities{}",
+                id,
+                query.to_query_string()
+            ))
+            .await
+        })
+    };
+    {
+        let opportunities = opportunities.clone();
+        use_effect_with_deps(
+            move |_| {
+                opportunities.run();
+                || ()
+            },
+            (*query).clone(),
+        );
+    }
+    let toggle_close_date_sort = {
+        let query = query.clone();
+        Callback::from(move |_| {
+            query.set(match query.sort {
+                OpportunitySortField::CloseDate => OpportunitiesQueryParams::default(),
+                OpportunitySortField::Created => OpportunitiesQueryParams {
+                    sort: OpportunitySortField::CloseDate,
+                    direction: SortDirection::Asc,
+                },
+            })
+        })
+    };
+    let pipelines: UseAsyncHandle<Vec<Pipeline>, MultiError> = use_async_with_options(
+        async move { get_data("/pipelines".to_string()).await },
         UseAsyncOptions::enable_auto(),
     );
+    let all_tags: UseAsyncHandle<Vec<Tag>, MultiError> = use_async_with_options(
+        async move { get_data("/tags".to_string()).await },
+        UseAsyncOptions::enable_auto(),
+    );
+    let tags_changed = {
+        let reload_list = opportunities.clone();
+        let reload_tags = all_tags.clone();
+        Callback::from(move |_| {
+            reload_list.run();
+            reload_tags.run();
+        })
+    };
+    let custom_fields = use_custom_fields(CustomFieldEntity::Opportunity);
+    let users = use_users();
+    let owner_changed = {
+        let reload_list = opportunities.clone();
+        Callback::from(move |_| reload_list.run())
+    };
     let (selected_opportunity, dispatch) = use_store::<Opportunity>();
+    let (user, _) = use_store::<CurrentUser>();
+    let can_edit = user.can(Permission::Edit);
+    let can_delete = user.can(Permission::DeleteOpportunities);
     let modal_open = use_state(|| false);
 
     let close_modal = {
@@ -132,74 +398,155 @@ pub fn customer_opportunities_list(props: &CustomerDetailProps) -> Html {
             open_handle.set(false);
         })
     };
+    let server_error = use_state(|| None::<MultiError>);
+    {
+        let server_error = server_error.clone();
+        use_effect_with_deps(
+            move |_| {
+                server_error.set(None);
+                || ()
+            },
+            (*selected_opportunity).clone(),
+        );
+    }
     let update = |opportunity: Rc<Opportunity>| {
         let reload_list = opportunities.clone();
         let o: Rc<Opportunity> = opportunity.clone();
         let customer_id = id.clone();
         let current_modal_state = modal_open.clone();
+        let current_server_error = server_error.clone();
         dispatch.reduce_mut_future_callback(move |_| {
             let op = o.clone();
             let modal = current_modal_state.clone();
             let reload = reload_list.clone();
+            let server_error = current_server_error.clone();
             Box::pin(async move {
-                if op.id.eq(&Uuid::default()) {
-                    if let Ok(_) =
-                        post_data(format!("/customer/{}/opportunities", customer_id), op).await
-                    {
-                        modal.set(false);
-                        reload.run();
-                    }
+                let result = if op.id.eq(&Uuid::default()) {
+                    post_data(format!("/customer/{}/opportunities", customer_id), op).await
                 } else {
-                    if let Ok(_) = put_data(
+                    put_versioned(
                         format!("/customer/{}/opportunity/{}", customer_id, op.id),
-                        op,
+                        op.clone(),
+                        &op.version,
                     )
                     .await
-                    {
+                };
+                match result {
+                    Ok(_) => {
                         modal.set(false);
                         reload.run();
                     }
+                    Err(error) => server_error.set(Some(error)),
                 }
                 ()
             })
         })
     };
-    let delete_opportunity = |opportunity_id: OpportunityId| {
+    let reload_edit = {
         let reload_list = opportunities.clone();
-        let selected_opportunity = opportunity_id.clone();
         let customer_id = id.clone();
         let current_modal_state = modal_open.clone();
+        let current_server_error = server_error.clone();
+        let dispatch = dispatch.clone();
+        Callback::from(move |_| {
+            let reload = reload_list.clone();
+            let modal = current_modal_state.clone();
+            let server_error = current_server_error.clone();
+            let dispatch = dispatch.clone();
+            spawn_local(async move {
+                let path = format!("/customer/{}/opportunities", customer_id);
+                if let Ok(list) = get_data::<Vec<Opportunity>>(path).await {
+                    let selected_id = dispatch.get().id;
+                    match list.into_iter().find(|o| o.id == selected_id) {
+                        Some(fresh) => dispatch.set(fresh),
+                        None => modal.set(false),
+                    }
+                    server_error.set(None);
+                    reload.run();
+                }
+            });
+        })
+    };
+    let list_error = use_state(|| None::<MultiError>);
+    let reload_list = {
+        let reload_list = opportunities.clone();
+        let current_list_error = list_error.clone();
+        Callback::from(move |_| {
+            current_list_error.set(None);
+            reload_list.run();
+        })
+    };
+    let delete_opportunity = |opportunity: &Opportunity| {
+        let reload_list = opportunities.clone();
+        let selected_opportunity = opportunity.id.clone();
+        let version = opportunity.version.clone();
+        let customer_id = id.clone();
+        let current_modal_state = modal_open.clone();
+        let current_list_error = list_error.clone();
         dispatch.reduce_mut_future_callback(move |_| {
             let modal = current_modal_state.clone();
             let reload = reload_list.clone();
+            let version = version.clone();
+            let list_error = current_list_error.clone();
             Box::pin(async move {
-                if let Ok(_) = delete_data(format!(
-                    "/customer/{}/opportunity/{}",
-                    customer_id,
-                    selected_opportunity.clone()
-                ))
+                match delete_versioned(
+                    format!(
+                        "/customer/{}/opportunity/{}",
+                        customer_id,
+                        selected_opportunity.clone()
+                    ),
+                    &version,
+                )
                 .await
                 {
-                    modal.set(false);
-                    reload.run();
+                    Ok(_) => {
+                        modal.set(false);
+                        list_error.set(None);
+                        reload.run();
+                    }
+                    Err(error) => list_error.set(Some(error)),
                 }
                 ()
             })
         })
     };
-    let selected_option = |status: OpportunityStatus| {
-        format!("{}", status).eq(&dispatch.get().clone().status.clone())
+    let pipeline_list = pipelines.data.clone().unwrap_or_default();
+    let selected_pipeline =
+        Pipeline::find(&pipeline_list, selected_opportunity.pipeline_id).cloned();
+    let first_stage = |pipeline: Option<&Pipeline>| {
+        pipeline
+            .and_then(|p| p.stages.first())
+            .map(|s| s.name.clone())
+            .unwrap_or_default()
+    };
+    let pipeline_option = |pipeline: Pipeline| {
+        let pipeline_id = pipeline.id;
+        let status = first_stage(Some(&pipeline));
+        html! {
+            <option onclick={dispatch.reduce_mut_callback(move |state| {
+                state.pipeline_id = Some(pipeline_id);
+                state.status = status.clone();
+            })} selected={selected_pipeline.as_ref().map(|p| p.id) == Some(pipeline.id)}>{&pipeline.name}</option>
+        }
+    };
+    let stage_option = |stage: Stage| {
+        let name = stage.name.clone();
+        html! {
+            <option onclick={dispatch.reduce_mut_callback(move |state| state.status = name.clone())} selected={stage.name.eq(&selected_opportunity.status)} value={stage.name.clone()}>{&stage.name}</option>
+        }
     };
     let select_opportunity = |opportunity: Opportunity| {
         let current_modal_state = modal_open.clone();
+        let current_server_error = server_error.clone();
         let new_opportunity = opportunity.clone();
         dispatch.reduce_mut_callback(move |state| {
             *state = new_opportunity.to_owned();
+            current_server_error.set(None);
             current_modal_state.set(true);
         })
     };
     let add_opportunity = select_opportunity(Opportunity {
-        status: format!("{}", OpportunityStatus::New),
+        status: first_stage(Pipeline::find(&pipeline_list, None)),
         ..Opportunity::default()
     });
     fn modal_label(id: OpportunityId) -> String {
@@ -208,19 +555,6 @@ pub fn customer_opportunities_list(props: &CustomerDetailProps) -> Html {
             false => "Edit".to_string(),
         }
     }
-    fn modal_visible(open: bool) -> Option<String> {
-        if open {
-            Some("is-active".to_string())
-        } else {
-            None
-        }
-    }
-    fn submit_disabled(state: &Rc<Opportunity>) -> bool {
-        match state.validate() {
-            Err(_) => true,
-            Ok(_) => false,
-        }
-    }
     html! {
             <>
         if let Some(opportunities) = opportunities.data.clone() {
@@ -232,45 +566,117 @@ pub fn customer_opportunities_list(props: &CustomerDetailProps) -> Html {
                         <button onclick={&close_modal} class="delete" aria-label="close"></button>
                     </header>
                         <section class="modal-card-body">
+                            if (*server_error).as_ref().map(|e| e.is_stale()).unwrap_or(false) {
+                                <StaleRecord on_reload={&reload_edit}/>
+                            }
+                            if let Some(error) = (*server_error).clone().filter(|e| !e.is_stale()) {
+                                <div class="notification is-danger is-light">{error.message()}</div>
+                            }
                             <div class="field">
                                 <label class="label">{"Name"}</label>
                                 <div class="control">
-                                <input value={dispatch.get().name.clone()} oninput={dispatch.input_mut(|selected_opportunity, text| selected_opportunity.name = text)} class={classes!("input",is_valid("name", &selected_opportunity))} type="text" placeholder="Name"/>
+                                <input value={dispatch.get().name.clone()} oninput={dispatch.input_mut(|selected_opportunity, text| selected_opportunity.name = text)} class={classes!("input",is_valid("name", selected_opportunity.as_ref(), &server_error))} type="text" placeholder="Name"/>
                                 </div>
-                                <p class="help is-danger">{validation_message("name", &selected_opportunity)}</p>
+                                <p class="help is-danger">{field_message("name", selected_opportunity.as_ref(), &server_error)}</p>
                             </div>
 
+                            if pipeline_list.len() > 1 {
+                                <div class="field">
+                                    <label class="label">{"Pipeline"}</label>
+                                    <div class="control">
+                                    <div class="select is-fullwidth">
+                                    <select>
+                                        {pipeline_list.iter().cloned().map(pipeline_option).collect::<Html>()}
+                                    </select>
+                                    </div>
+                                    </div>
+                                </div>
+                            }
                             <div class="field">
-                                <label class="label">{"Status"}</label>
+                                <label class="label">{"Stage"}</label>
                                 <div class="control">
                                 <div class="select is-fullwidth">
-                                <select >
-                                    <option onclick={dispatch.reduce_mut_callback(|state| state.status = format!("{}", OpportunityStatus::New))} selected={selected_option(OpportunityStatus::New)} text={format!("{}", OpportunityStatus::New)}>{"New"}</option>
-                                    <option onclick={dispatch.reduce_mut_callback(|state| state.status = format!("{}", OpportunityStatus::ClosedWon))} selected={selected_option(OpportunityStatus::ClosedWon)} value={format!("{}", OpportunityStatus::ClosedWon)}>{"Closed Won"}</option>
-                                    <option onclick={dispatch.reduce_mut_callback(|state| state.status = format!("{}", OpportunityStatus::ClosedLost))} selected={selected_option(OpportunityStatus::ClosedLost)} value={format!("{}", OpportunityStatus::ClosedLost)}>{"Closed Lost"}</option>
+                                <select>
+                                    {selected_pipeline.map(|p| p.stages).unwrap_or_default().into_iter().map(stage_option).collect::<Html>()}
                                 </select>
                                 </div>
                                 </div>
-                                <p class="help is-danger"></p>
+                                <p class="help is-danger">{field_message("status", selected_opportunity.as_ref(), &server_error)}</p>
+                            </div>
+                            <div class="field is-horizontal">
+                                <div class="field-body">
+                                    <div class="field">
+                                        <label class="label">{"Amount"}</label>
+                                        <div class="control">
+                                        <input value={selected_opportunity.amount.clone().unwrap_or_default()} oninput={dispatch.input_mut(|selected_opportunity, text: String| selected_opportunity.amount = Some(text).filter(|t| !t.is_empty()))} class={classes!("input",is_valid("amount", selected_opportunity.as_ref(), &server_error))} type="text" inputmode="decimal" placeholder="1500.00"/>
+                                        </div>
+                                        <p class="help is-danger">{field_message("amount", selected_opportunity.as_ref(), &server_error)}</p>
+                                    </div>
+                                    <div class="field">
+                                        <label class="label">{"Currency"}</label>
+                                        <div class="control">
+                                        <input value={selected_opportunity.currency.clone()} oninput={dispatch.input_mut(|selected_opportunity, text: String| selected_opportunity.currency = text.to_uppercase())} class={classes!("input",is_valid("currency", selected_opportunity.as_ref(), &server_error))} type="text" maxlength="3" placeholder={DEFAULT_CURRENCY}/>
+                                        </div>
+                                        <p class="help is-danger">{field_message("currency", selected_opportunity.as_ref(), &server_error)}</p>
+                                    </div>
+                                </div>
+                            </div>
+                            <div class="field is-horizontal">
+                                <div class="field-body">
+                                    <div class="field">
+                                        <label class="label">{"Expected close date"}</label>
+                                        <div class="control">
+                                        <input value={selected_opportunity.close_date.clone().unwrap_or_default()} oninput={dispatch.input_mut(|selected_opportunity, text: String| selected_opportunity.close_date = Some(text).filter(|t| !t.is_empty()))} class={classes!("input",is_valid("close_date", selected_opportunity.as_ref(), &server_error))} type="date"/>
+                                        </div>
+                                        <p class="help is-danger">{field_message("close_date", selected_opportunity.as_ref(), &server_error)}</p>
+                                    </div>
+                                    <div class="field">
+                                        <label class="label">{"Probability %"}</label>
+                                        <div class="control">
+                                        <input value={selected_opportunity.probability.to_string()} oninput={dispatch.input_mut(|selected_opportunity, text: String| selected_opportunity.probability = text.parse().unwrap_or(-1))} class={classes!("input",is_valid("probability", selected_opportunity.as_ref(), &server_error))} type="number" min="0" max="100"/>
+                                        </div>
+                                        <p class="help is-danger">{field_message("probability", selected_opportunity.as_ref(), &server_error)}</p>
+                                    </div>
+                                </div>
                             </div>
+                            <CustomFieldInputs
+                                fields={custom_fields.data.clone().unwrap_or_default()}
+                                values={selected_opportunity.custom.clone()}
+                                onchange={dispatch.reduce_mut_callback_with(|selected_opportunity, values| selected_opportunity.custom = values)}
+                                server_error={(*server_error).clone()}/>
                         </section>
                     <footer class="modal-card-foot">
-                        <button disabled={submit_disabled(&dispatch.get())} onclick={&update(dispatch.get())} class="button is-success">{"Save changes"}</button>
+                        <button disabled={submit_disabled(dispatch.get().as_ref()) || check_custom_values(&custom_fields.data.clone().unwrap_or_default(), &dispatch.get().custom).is_err()} onclick={&update(dispatch.get())} class="button is-success">{"Save changes"}</button>
                         <button onclick={&close_modal} class="button">{"Cancel"}</button>
                     </footer>
                 </div>
             </div>
 
-            <div class="field is-grouped">
-                <div class="control">
-                    <button onclick={add_opportunity} class="button is-link">{"Add opportunity"}</button>
+            if can_edit {
+                <div class="field is-grouped">
+                    <div class="control">
+                        <button onclick={add_opportunity} class="button is-link">{"Add opportunity"}</button>
+                    </div>
                 </div>
-            </div>
+            }
+            if (*list_error).as_ref().map(|e| e.is_stale()).unwrap_or(false) {
+                <StaleRecord on_reload={&reload_list}/>
+            }
                 <table class="table is-fullwidth">
                 <thead>
                 <tr>
                     <td>{"Name"}</td>
-                    <td>{"Status"}</td>
+                    <td>{"Stage"}</td>
+                    <td>{"Amount"}</td>
+                    <td>{"Probability"}</td>
+                    <td onclick={toggle_close_date_sort}>
+                        {"Close date"}
+                        if query.sort == OpportunitySortField::CloseDate {
+                            <ion-icon class="" name="chevron-up"></ion-icon>
+                        }
+                    </td>
+                    <td>{"Owner"}</td>
+                    <td>{"Tags"}</td>
                     <td>{""}</td>
                 </tr>
                 </thead>
@@ -279,13 +685,37 @@ pub fn customer_opportunities_list(props: &CustomerDetailProps) -> Html {
                     opportunities.iter().map(|o| {
                         html!{
                         <tr>
-                            <td>{&o.name}</td>
-                            <td>{&o.status}</td>
+                            <td>
+                                {&o.name}
+                                <CustomFieldValues fields={custom_fields.data.clone().unwrap_or_default()} values={o.custom.clone()}/>
+                            </td>
+                            <td><span class={classes!("tag", outcome_tag(o.outcome.as_deref()))}>{&o.status}</span></td>
+                            <td>{o.amount.as_ref().map(|amount| format!("{} {}", amount, o.currency)).unwrap_or_default()}</td>
+                            <td>{format!("{}%", o.probability)}</td>
+                            <td>{o.close_date.clone().unwrap_or_default()}</td>
+                            <td>
+                                <OwnerPicker
+                                    owner={o.owner.clone()}
+                                    users={users.data.clone().unwrap_or_default()}
+                                    target={AssignRequest { opportunities: vec![o.id], ..AssignRequest::default() }}
+                                    onchange={owner_changed.clone()}/>
+                            </td>
+                            <td>
+                                <TagEditor
+                                    tags={o.tags.clone()}
+                                    all_tags={all_tags.data.clone().unwrap_or_default()}
+                                    target={BulkTagRequest { opportunities: vec![o.id], ..BulkTagRequest::default() }}
+                                    onchange={tags_changed.clone()}/>
+                            </td>
                             <td>
                             <div class="field is-grouped">
                                 <div class="control">
-                                    <button onclick={select_opportunity(o.clone())}class="button is-info" ><ion-icon class="" name="pencil"/></button>
-                                    <button onclick={delete_opportunity(o.id.clone())}class="button is-danger" ><ion-icon class="" name="trash"/></button>
+                                    if can_edit {
+                                        <button onclick={select_opportunity(o.clone())}class="button is-info" ><ion-icon class="" name="pencil"/></button>
+                                    }
+                                    if can_delete {
+                                        <button onclick={delete_opportunity(o)}class="button is-danger" ><ion-icon class="" name="trash"/></button>
+                                    }
                                 </div>
                                 </div>
                             </td>
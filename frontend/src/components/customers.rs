@@ -1,18 +1,50 @@
+use std::rc::Rc;
+
 use crate::{
-    components::{error::ComponentError, nav_bar::Navbar, progress_bar::Progress},
+    components::{
+        custom_fields::{use_custom_fields, CustomFieldInputs},
+        error::ComponentError,
+        nav_bar::Navbar,
+        progress_bar::Progress,
+        tags::{chip_style, TagChips, TagPicker},
+        users::{use_users, OwnerPicker},
+        validation::{field_message, is_valid, modal_visible, submit_disabled},
+    },
     data::*,
     routes::AppRoute,
 };
 
-use yew::prelude::*;
-use yew_hooks::{use_async_with_options, UseAsyncHandle, UseAsyncOptions};
-use yew_router::prelude::Link;
+use web_sys::HtmlInputElement;
+use yew::{platform::spawn_local, prelude::*};
+use yew_hooks::{use_async, use_async_with_options, UseAsyncHandle, UseAsyncOptions};
+use yew_router::prelude::{use_location, use_navigator, Link};
+use yewdux::prelude::use_store;
+use yewdux_input::InputDispatch;
 
 #[derive(Properties, PartialEq)]
 pub struct CustomerDetailProps {
     pub id: CustomerId,
 }
 
+#[derive(Properties, PartialEq)]
+pub struct CustomerFormFieldsProps {
+    /// Error from the last save so server side field messages show next to the inputs
+    #[prop_or_default]
+    pub server_error: Option<MultiError>,
+}
+
+#[derive(Properties, PartialEq)]
+pub struct CustomerFiltersProps {
+    pub query: CustomersQueryParams,
+    pub onchange: Callback<CustomersQueryParams>,
+    /// Tags that can be filtered on
+    #[prop_or_default]
+    pub all_tags: Vec<Tag>,
+    /// Customer custom fields that can be filtered on
+    #[prop_or_default]
+    pub custom_fields: Vec<CustomField>,
+}
+
 #[derive(Properties, PartialEq)]
 pub struct SortArrowProps {
     pub pagination: CustomersQueryParams,
@@ -21,39 +53,179 @@ pub struct SortArrowProps {
 
 #[function_component(CustomersTable)]
 pub fn customers_table() -> Html {
-    let pagination = use_state(move || CustomersQueryParams {
-        sort: CustomerSortField::Created,
-        direction: SortDirection::Desc,
-        offset: 0,
-        limit: 20,
-    });
+    let location = use_location();
+    let navigator = use_navigator();
+    let pagination = location
+        .and_then(|l| l.query::<CustomersQueryParams>().ok())
+        .unwrap_or_default();
     let query = pagination.clone();
-    let customers: UseAsyncHandle<Vec<Customer>, _> = use_async_with_options(
-        async move {
-            let query_string = format!(
-                "?sort={}&direction={}&offset={}&limit={}",
-                query.sort, query.direction, query.offset, query.limit
-            );
-            get_data(format!("/customers{}", query_string)).await
-        },
-        UseAsyncOptions::enable_auto(),
-    );
+    let customers: UseAsyncHandle<Page<Customer>, _> =
+        use_async(async move { get_data(format!("/customers{}", query.to_query_string())).await });
+    {
+        let customers = customers.clone();
+        use_effect_with_deps(
+            move |_| {
+                customers.run();
+                || ()
+            },
+            pagination.clone(),
+        );
+    }
+    let set_query = {
+        let navigator = navigator.clone();
+        Callback::from(move |query: CustomersQueryParams| {
+            if let Some(navigator) = &navigator {
+                let _ = navigator.push_with_query(&AppRoute::CustomerList, &query);
+            }
+        })
+    };
     let toggle_sort = |sort_by| {
         let current_page = pagination.clone();
-        let customers_query = customers.clone();
+        let set_query = set_query.clone();
         Callback::from(move |_| {
-            current_page.set(CustomersQueryParams {
+            set_query.emit(CustomersQueryParams {
                 sort: sort_by,
-                direction: if current_page.direction == SortDirection::Asc {
-                    SortDirection::Desc
-                } else {
-                    SortDirection::Asc
-                },
-                offset: 0,
-                limit: current_page.limit.clone(),
+                direction: current_page.direction.reverse(),
+                cursor: None,
+                ..current_page.clone()
             });
-            customers_query.update(vec![Customer::default()]);
-            customers_query.run();
+        })
+    };
+    let go_to_page = |cursor: Option<String>| {
+        let current_page = pagination.clone();
+        let set_query = set_query.clone();
+        Callback::from(move |_| {
+            set_query.emit(CustomersQueryParams {
+                cursor: cursor.clone(),
+                ..current_page.clone()
+            });
+        })
+    };
+    let all_tags: UseAsyncHandle<Vec<Tag>, MultiError> = use_async_with_options(
+        async move { get_data("/tags".to_string()).await },
+        UseAsyncOptions::enable_auto(),
+    );
+    let tag_list = all_tags.data.clone().unwrap_or_default();
+    let custom_fields = use_custom_fields(CustomFieldEntity::Customer);
+    // customers ticked in the table, kept across pages so a selection can span them
+    let selected = use_state(Vec::<CustomerId>::new);
+    let bulk_error = use_state(|| None::<MultiError>);
+    let toggle_selected = |id: CustomerId| {
+        let selected = selected.clone();
+        Callback::from(move |_| {
+            let mut ids = (*selected).clone();
+            match ids.iter().position(|s| *s == id) {
+                Some(index) => {
+                    ids.remove(index);
+                }
+                None => ids.push(id),
+            }
+            selected.set(ids);
+        })
+    };
+    let toggle_page = |ids: Vec<CustomerId>| {
+        let selected = selected.clone();
+        Callback::from(move |_| {
+            let mut current = (*selected).clone();
+            match ids.iter().all(|id| current.contains(id)) {
+                true => current.retain(|id| !ids.contains(id)),
+                false => {
+                    let missing: Vec<CustomerId> = ids
+                        .iter()
+                        .filter(|id| !current.contains(id))
+                        .copied()
+                        .collect();
+                    current.extend(missing);
+                }
+            }
+            selected.set(current);
+        })
+    };
+    let bulk_tag = |remove: bool| {
+        let selected = selected.clone();
+        let bulk_error = bulk_error.clone();
+        let reload_list = customers.clone();
+        let reload_tags = all_tags.clone();
+        Callback::from(move |tag: Tag| {
+            let request = BulkTagRequest {
+                tags: vec![tag.id],
+                customers: (*selected).clone(),
+                remove,
+                ..BulkTagRequest::default()
+            };
+            let selected = selected.clone();
+            let bulk_error = bulk_error.clone();
+            let reload_list = reload_list.clone();
+            let reload_tags = reload_tags.clone();
+            spawn_local(async move {
+                match post_data("/tags/apply".to_string(), request).await {
+                    Ok(_) => {
+                        selected.set(vec![]);
+                        bulk_error.set(None);
+                    }
+                    Err(error) => bulk_error.set(Some(error)),
+                }
+                reload_tags.run();
+                reload_list.run();
+            });
+        })
+    };
+    fn page_summary(total: i64, limit: usize) -> String {
+        let pages = (total.max(1) as usize + limit - 1) / limit;
+        format!("{} customers across {} pages", total, pages)
+    }
+    let (_, dispatch) = use_store::<Customer>();
+    let (user, _) = use_store::<CurrentUser>();
+    let can_edit = user.can(Permission::Edit);
+    let can_assign = user.can(Permission::AssignOwners);
+    let users = use_users();
+    let assigned = {
+        let selected = selected.clone();
+        let reload_list = customers.clone();
+        Callback::from(move |_| {
+            selected.set(vec![]);
+            reload_list.run();
+        })
+    };
+    let modal_open = use_state(|| false);
+    let create_error = use_state(|| None::<MultiError>);
+    let open_modal = {
+        let open_handle = modal_open.clone();
+        let current_create_error = create_error.clone();
+        dispatch.reduce_mut_callback(move |state| {
+            *state = Customer {
+                status: format!("{}", CustomerStatus::Lead),
+                ..Customer::default()
+            };
+            current_create_error.set(None);
+            open_handle.set(true);
+        })
+    };
+    let close_modal = {
+        let open_handle = modal_open.clone();
+        Callback::from(move |_| {
+            open_handle.set(false);
+        })
+    };
+    let create = |customer: Rc<Customer>| {
+        let reload_list = customers.clone();
+        let current_modal_state = modal_open.clone();
+        let current_create_error = create_error.clone();
+        dispatch.reduce_mut_future_callback(move |_| {
+            let c = customer.clone();
+            let modal = current_modal_state.clone();
+            let reload = reload_list.clone();
+            let create_error = current_create_error.clone();
+            Box::pin(async move {
+                match post_data("/customers".to_string(), c).await {
+                    Ok(_) => {
+                        modal.set(false);
+                        reload.run();
+                    }
+                    Err(error) => create_error.set(Some(error)),
+                }
+                ()
+            })
         })
     };
     html! {
@@ -67,20 +239,82 @@ pub fn customers_table() -> Html {
             </div>
         </section>
         <section class="section">
+        <div class={classes!("modal",modal_visible(*modal_open))}>
+            <div class="modal-background"></div>
+            <div class="modal-card">
+                <header class="modal-card-head">
+                    <p class="modal-card-title">{"New customer"}</p>
+                    <button onclick={&close_modal} class="delete" aria-label="close"></button>
+                </header>
+                    <section class="modal-card-body">
+                        <CustomerFormFields server_error={(*create_error).clone()}/>
+                    </section>
+                <footer class="modal-card-foot">
+                    <button disabled={submit_disabled(dispatch.get().as_ref()) || check_custom_values(&custom_fields.data.clone().unwrap_or_default(), &dispatch.get().custom).is_err()} onclick={&create(dispatch.get())} class="button is-success">{"Create customer"}</button>
+                    <button onclick={&close_modal} class="button">{"Cancel"}</button>
+                </footer>
+            </div>
+        </div>
+
+        <div class="field is-grouped">
+            if can_edit {
+                <div class="control">
+                    <button onclick={open_modal} class="button is-link">{"New customer"}</button>
+                </div>
+            }
+            <div class="control">
+                <div class="buttons has-addons">
+                    <a href={export_url(ExportFormat::Csv, &pagination)} download="" class="button">{"Download CSV"}</a>
+                    <a href={export_url(ExportFormat::Xlsx, &pagination)} download="" class="button">{"Excel"}</a>
+                    <a href={export_url(ExportFormat::Ndjson, &pagination)} download="" class="button">{"NDJSON"}</a>
+                </div>
+            </div>
+        </div>
+        <CustomerFilters query={pagination.clone()} onchange={set_query.clone()} all_tags={tag_list.clone()} custom_fields={custom_fields.data.clone().unwrap_or_default()}/>
+        if (can_edit || can_assign) && !selected.is_empty() {
+            <div class="notification is-light">
+                <p class="mb-2">{format!("{} customers selected", selected.len())}</p>
+                if can_edit {
+                    <TagPicker all_tags={tag_list.clone()} onadd={bulk_tag(false)} onremove={bulk_tag(true)}/>
+                }
+                if can_assign {
+                    <div class="mt-2">
+                        <OwnerPicker
+                            users={users.data.clone().unwrap_or_default()}
+                            target={AssignRequest { customers: (*selected).clone(), ..AssignRequest::default() }}
+                            prompt="Assign to"
+                            onchange={assigned}/>
+                    </div>
+                }
+                if let Some(error) = (*bulk_error).clone() {
+                    <p class="help is-danger">{error.message()}</p>
+                }
+            </div>
+        }
         if let Some(customers) = customers.data.clone() {
             <table class="table is-fullwidth">
             <thead>
             <tr>
-                <td onclick={toggle_sort(CustomerSortField::Name)}>{"Name"} <SortArrow pagination={*pagination} field={CustomerSortField::Name}/></td>
-                <td onclick={toggle_sort(CustomerSortField::Email)}>{"Email"} <SortArrow pagination={*pagination} field={CustomerSortField::Email}/></td>
-                <td onclick={toggle_sort(CustomerSortField::Status)}>{"Status"}<SortArrow pagination={*pagination} field={CustomerSortField::Status}/></td>
+                <td>
+                    <input type="checkbox" aria-label="select page"
+                        checked={!customers.items.is_empty() && customers.items.iter().all(|c| selected.contains(&c.id))}
+                        onclick={toggle_page(customers.items.iter().map(|c| c.id).collect())}/>
+                </td>
+                <td onclick={toggle_sort(CustomerSortField::Name)}>{"Name"} <SortArrow pagination={pagination.clone()} field={CustomerSortField::Name}/></td>
+                <td onclick={toggle_sort(CustomerSortField::Email)}>{"Email"} <SortArrow pagination={pagination.clone()} field={CustomerSortField::Email}/></td>
+                <td onclick={toggle_sort(CustomerSortField::Status)}>{"Status"}<SortArrow pagination={pagination.clone()} field={CustomerSortField::Status}/></td>
+                <td onclick={toggle_sort(CustomerSortField::Owner)}>{"Owner"}<SortArrow pagination={pagination.clone()} field={CustomerSortField::Owner}/></td>
+                <td>{"Tags"}</td>
             </tr>
             </thead>
                         <tbody>
                         {
-                            customers.into_iter().map(|p|
+                            customers.items.into_iter().map(|p|
                                 html!{
                                 <tr>
+                                    <td>
+                                        <input type="checkbox" aria-label="select customer" checked={selected.contains(&p.id)} onclick={toggle_selected(p.id)}/>
+                                    </td>
                                     <td>
                                         <Link<AppRoute> to={AppRoute::CustomerDetail { id: p.id.clone() }}>
                                             {format!("{}", p.name)}</Link<AppRoute>>
@@ -92,12 +326,23 @@ pub fn customers_table() -> Html {
                                     <td>
                                     {p.status}
                                     </td>
+                                    <td>
+                                    {p.owner.map(|owner| owner.name).unwrap_or_default()}
+                                    </td>
+                                    <td>
+                                        <TagChips tags={p.tags}/>
+                                    </td>
                                 </tr>
                             }).collect::<Html>()
                         }
                         </tbody>
 
             </table>
+            <nav class="pagination" role="navigation" aria-label="pagination">
+                <button disabled={customers.prev_cursor.is_none()} onclick={go_to_page(customers.prev_cursor.clone())} class="pagination-previous">{"Previous"}</button>
+                <button disabled={customers.next_cursor.is_none()} onclick={go_to_page(customers.next_cursor.clone())} class="pagination-next">{"Next page"}</button>
+                <p class="pagination-list">{page_summary(customers.total, pagination.limit)}</p>
+            </nav>
         } else {
             if customers.error.is_some() {
                 <ComponentError/>
@@ -110,6 +355,300 @@ pub fn customers_table() -> Html {
     }
 }
 
+/// Name, email, status and custom field inputs bound to the shared customer store
+#[function_component(CustomerFormFields)]
+pub fn customer_form_fields(props: &CustomerFormFieldsProps) -> Html {
+    let (customer, dispatch) = use_store::<Customer>();
+    let (user, _) = use_store::<CurrentUser>();
+    let fields = use_custom_fields(CustomFieldEntity::Customer);
+    let selected_option =
+        |status: CustomerStatus| format!("{}", status).eq(&dispatch.get().clone().status.clone());
+    html! {
+        <>
+            if let Some(error) = props.server_error.clone() {
+                <div class="notification is-danger is-light">{error.message()}</div>
+            }
+            <div class="field">
+                <label class="label">{"Name"}</label>
+                <div class="control">
+                <input value={dispatch.get().name.clone()} oninput={dispatch.input_mut(|customer, text| customer.name = text)} class={classes!("input",is_valid("name", customer.as_ref(), &props.server_error))} type="text" placeholder="Name"/>
+                </div>
+                <p class="help is-danger">{field_message("name", customer.as_ref(), &props.server_error)}</p>
+            </div>
+
+            <div class="field">
+                <label class="label">{"Email"}</label>
+                <div class="control">
+                <input value={dispatch.get().email.clone()} oninput={dispatch.input_mut(|customer, text| customer.email = text)} class={classes!("input",is_valid("email", customer.as_ref(), &props.server_error))} type="email" placeholder="Email"/>
+                </div>
+                <p class="help is-danger">{field_message("email", customer.as_ref(), &props.server_error)}</p>
+            </div>
+
+            <div class="field">
+                <label class="label">{"Status"}</label>
+                <div class="control">
+                <div class="select is-fullwidth">
+                <select >
+                    <option onclick={dispatch.reduce_mut_callback(|state| state.status = format!("{}", CustomerStatus::Lead))} selected={selected_option(CustomerStatus::Lead)} value={format!("{}", CustomerStatus::Lead)}>{"Lead"}</option>
+                    <option onclick={dispatch.reduce_mut_callback(|state| state.status = format!("{}", CustomerStatus::Active))} selected={selected_option(CustomerStatus::Active)} value={format!("{}", CustomerStatus::Active)}>{"Active"}</option>
+                    // only managers can move a customer to NonActive
+                    if user.can(Permission::DeactivateCustomers) || selected_option(CustomerStatus::NonActive) {
+                        <option onclick={dispatch.reduce_mut_callback(|state| state.status = format!("{}", CustomerStatus::NonActive))} selected={selected_option(CustomerStatus::NonActive)} value={format!("{}", CustomerStatus::NonActive)}>{"Non Active"}</option>
+                    }
+                </select>
+                </div>
+                </div>
+                <p class="help is-danger">{field_message("status", customer.as_ref(), &props.server_error)}</p>
+            </div>
+            <CustomFieldInputs
+                fields={fields.data.clone().unwrap_or_default()}
+                values={customer.custom.clone()}
+                onchange={dispatch.reduce_mut_callback_with(|customer, values| customer.custom = values)}
+                server_error={props.server_error.clone()}/>
+        </>
+    }
+}
+
+/// Status, owner, tag, email domain, created date and custom field filters, changes start again from the first page
+#[function_component(CustomerFilters)]
+pub fn customer_filters(props: &CustomerFiltersProps) -> Html {
+    let filter_key = use_state(String::new);
+    let filter_value = use_state(String::new);
+    let filter_field = props
+        .custom_fields
+        .iter()
+        .find(|f| f.key == *filter_key)
+        .cloned();
+    let add_custom = {
+        let filter_key = filter_key.clone();
+        let filter_value = filter_value.clone();
+        let query = props.query.clone();
+        let onchange = props.onchange.clone();
+        Callback::from(move |_| {
+            let mut custom = query.custom.clone();
+            custom.retain(|f| f.key != *filter_key);
+            custom.push(CustomFilter {
+                key: (*filter_key).clone(),
+                value: filter_value.trim().to_string(),
+            });
+            filter_value.set(String::new());
+            onchange.emit(CustomersQueryParams {
+                custom,
+                cursor: None,
+                ..query.clone()
+            });
+        })
+    };
+    let remove_custom = |key: &str| {
+        let key = key.to_string();
+        let query = props.query.clone();
+        let onchange = props.onchange.clone();
+        Callback::from(move |_| {
+            onchange.emit(CustomersQueryParams {
+                custom: query
+                    .custom
+                    .iter()
+                    .filter(|f| f.key != key)
+                    .cloned()
+                    .collect(),
+                cursor: None,
+                ..query.clone()
+            })
+        })
+    };
+    let custom_chip = |filter: &CustomFilter| {
+        let label = props
+            .custom_fields
+            .iter()
+            .find(|f| f.key == filter.key)
+            .map(|f| f.label.clone())
+            .unwrap_or_else(|| filter.key.clone());
+        html! {
+            <span class="tag is-info is-light">
+                {format!("{}: {}", label, filter.value)}
+                <button onclick={remove_custom(&filter.key)} class="delete is-small" aria-label="remove filter"></button>
+            </span>
+        }
+    };
+    let choose_field = |key: String| {
+        let filter_key = filter_key.clone();
+        Callback::from(move |_| filter_key.set(key.clone()))
+    };
+    let set_filter_value = {
+        let filter_value = filter_value.clone();
+        Callback::from(move |e: InputEvent| {
+            filter_value.set(e.target_unchecked_into::<HtmlInputElement>().value())
+        })
+    };
+    let (value_type, value_hint) = match &filter_field {
+        Some(field) => match field.kind {
+            CustomFieldKind::Number => ("number", String::new()),
+            CustomFieldKind::Date => ("date", String::new()),
+            CustomFieldKind::Boolean => ("text", "true or false".to_string()),
+            CustomFieldKind::Select => ("text", field.options.join(", ")),
+            CustomFieldKind::Text => ("text", String::new()),
+        },
+        None => ("text", String::new()),
+    };
+    let toggle_status = |status: CustomerStatus| {
+        let query = props.query.clone();
+        let onchange = props.onchange.clone();
+        Callback::from(move |_| {
+            let mut statuses = query.status.clone();
+            match statuses.iter().position(|s| *s == status) {
+                Some(index) => {
+                    statuses.remove(index);
+                }
+                None => statuses.push(status),
+            }
+            onchange.emit(CustomersQueryParams {
+                status: statuses,
+                cursor: None,
+                ..query.clone()
+            });
+        })
+    };
+    let toggle_tag = |tag: TagId| {
+        let query = props.query.clone();
+        let onchange = props.onchange.clone();
+        Callback::from(move |_| {
+            let mut tags = query.tags.clone();
+            match tags.iter().position(|t| *t == tag) {
+                Some(index) => {
+                    tags.remove(index);
+                }
+                None => tags.push(tag),
+            }
+            onchange.emit(CustomersQueryParams {
+                tags,
+                cursor: None,
+                ..query.clone()
+            });
+        })
+    };
+    let tag_button = |tag: &Tag| {
+        let style = match props.query.tags.contains(&tag.id) {
+            true => chip_style(&tag.colour),
+            false => format!("border-color: {};", tag.colour),
+        };
+        html! {
+            <button onclick={toggle_tag(tag.id)} class="button is-small is-rounded" style={style}>{&tag.name}</button>
+        }
+    };
+    let set_text = |update: fn(&mut CustomersQueryParams, Option<String>)| {
+        let query = props.query.clone();
+        let onchange = props.onchange.clone();
+        Callback::from(move |e: Event| {
+            let value = e.target_unchecked_into::<HtmlInputElement>().value();
+            let mut next = CustomersQueryParams {
+                cursor: None,
+                ..query.clone()
+            };
+            update(&mut next, Some(value).filter(|v| !v.trim().is_empty()));
+            onchange.emit(next);
+        })
+    };
+    let clear_filters = {
+        let query = props.query.clone();
+        let onchange = props.onchange.clone();
+        Callback::from(move |_| {
+            onchange.emit(CustomersQueryParams {
+                sort: query.sort,
+                direction: query.direction,
+                limit: query.limit,
+                ..CustomersQueryParams::default()
+            });
+        })
+    };
+    let toggle_mine = {
+        let query = props.query.clone();
+        let onchange = props.onchange.clone();
+        Callback::from(move |_| {
+            onchange.emit(CustomersQueryParams {
+                mine: !query.mine,
+                cursor: None,
+                ..query.clone()
+            });
+        })
+    };
+    let status_button = |status: CustomerStatus, label: &str| {
+        let selected = match props.query.status.contains(&status) {
+            true => Some("is-info is-selected"),
+            false => None,
+        };
+        html! {
+            <button onclick={toggle_status(status)} class={classes!("button",selected)}>{label.to_string()}</button>
+        }
+    };
+    html! {
+        <>
+        <div class="columns">
+            <div class="column is-narrow">
+                <label class="label">{"Status"}</label>
+                <div class="buttons has-addons">
+                    {status_button(CustomerStatus::Lead, "Lead")}
+                    {status_button(CustomerStatus::Active, "Active")}
+                    {status_button(CustomerStatus::NonActive, "Non Active")}
+                </div>
+            </div>
+            <div class="column is-narrow">
+                <label class="label">{"Owner"}</label>
+                <button onclick={toggle_mine} class={classes!("button",props.query.mine.then_some("is-info is-selected"))}>{"My customers"}</button>
+            </div>
+            <div class="column">
+                <label class="label">{"Email domain"}</label>
+                <input value={props.query.email_domain.clone().unwrap_or_default()} onchange={set_text(|q, v| q.email_domain = v)} class="input" type="text" placeholder="example.com"/>
+            </div>
+            <div class="column is-narrow">
+                <label class="label">{"Created from"}</label>
+                <input value={props.query.created_from.clone().unwrap_or_default()} onchange={set_text(|q, v| q.created_from = v)} class="input" type="date"/>
+            </div>
+            <div class="column is-narrow">
+                <label class="label">{"Created to"}</label>
+                <input value={props.query.created_to.clone().unwrap_or_default()} onchange={set_text(|q, v| q.created_to = v)} class="input" type="date"/>
+            </div>
+            <div class="column is-narrow is-flex is-align-items-flex-end">
+                <button onclick={clear_filters} class="button">{"Clear filters"}</button>
+            </div>
+        </div>
+        if !props.all_tags.is_empty() {
+            <div class="field">
+                <label class="label">{"Tags"}</label>
+                <div class="buttons">
+                    {props.all_tags.iter().map(tag_button).collect::<Html>()}
+                </div>
+            </div>
+        }
+        if !props.custom_fields.is_empty() {
+            <div class="field">
+                <label class="label">{"Custom fields"}</label>
+                <div class="field has-addons">
+                    <div class="control">
+                        <div class="select">
+                            <select>
+                                <option onclick={choose_field(String::new())} selected={filter_field.is_none()} value="">{"Field"}</option>
+                                {props.custom_fields.iter().map(|f| html! {
+                                    <option onclick={choose_field(f.key.clone())} selected={*filter_key == f.key} value={f.key.clone()}>{&f.label}</option>
+                                }).collect::<Html>()}
+                            </select>
+                        </div>
+                    </div>
+                    <div class="control">
+                        <input value={(*filter_value).clone()} oninput={set_filter_value} class="input" type={value_type} placeholder={value_hint}/>
+                    </div>
+                    <div class="control">
+                        <button disabled={filter_field.is_none() || filter_value.trim().is_empty()} onclick={add_custom} class="button">{"Filter"}</button>
+                    </div>
+                </div>
+                <div class="tags">
+                    {props.query.custom.iter().map(custom_chip).collect::<Html>()}
+                </div>
+            </div>
+        }
+        </>
+    }
+}
+
 #[function_component(SortArrow)]
 pub fn sort_direction(props: &SortArrowProps) -> Html {
     if props.pagination.sort == props.field {
@@ -22,3 +22,20 @@ pub fn component_error() -> Html {
     </>
     }
 }
+
+#[derive(Properties, PartialEq)]
+pub struct StaleRecordProps {
+    pub on_reload: Callback<MouseEvent>,
+}
+
+/// Shown instead of saving over someone else's changes
+#[function_component(StaleRecord)]
+pub fn stale_record(props: &StaleRecordProps) -> Html {
+    html! {
+        <div class="notification is-warning is-light">
+            <p>{"This record changed while you were editing it. Reload it to see the latest version?"}</p>
+            <p class="has-text-grey is-size-7">{"Reloading discards the changes you made here."}</p>
+            <button onclick={&props.on_reload} class="button is-warning is-small mt-2">{"Reload"}</button>
+        </div>
+    }
+}
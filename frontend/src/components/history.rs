@@ -0,0 +1,91 @@
+use chrono::DateTime;
+use yew::prelude::*;
+use yew_hooks::{use_async_with_options, UseAsyncHandle, UseAsyncOptions};
+
+use crate::{
+    components::{error::ComponentError, progress_bar::Progress},
+    data::*,
+};
+
+#[derive(Properties, PartialEq)]
+pub struct HistoryProps {
+    pub id: CustomerId,
+}
+
+fn display_date(date: &str) -> String {
+    match DateTime::parse_from_rfc3339(date) {
+        Ok(date) => date.format("%Y-%m-%d %H:%M").to_string(),
+        Err(_) => date.to_string(),
+    }
+}
+
+fn display_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => "empty".to_string(),
+        serde_json::Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Every recorded change to the customer, its opportunities and its activities, newest first
+#[function_component(CustomerHistory)]
+pub fn customer_history(props: &HistoryProps) -> Html {
+    let params = AuditQueryParams {
+        entity: AuditEntity::Customer,
+        id: props.id,
+        limit: 100,
+    };
+    let events: UseAsyncHandle<Vec<AuditEvent>, MultiError> = use_async_with_options(
+        async move { get_data(format!("/audit{}", params.to_query_string())).await },
+        UseAsyncOptions::enable_auto(),
+    );
+    html! {
+        <>
+        if let Some(event_list) = events.data.clone() {
+            if event_list.is_empty() {
+                <p class="has-text-grey">{"No changes have been recorded for this customer yet."}</p>
+            } else {
+                <table class="table is-fullwidth is-hoverable">
+                    <thead>
+                        <tr>
+                            <th>{"When"}</th>
+                            <th>{"Who"}</th>
+                            <th>{"What"}</th>
+                            <th>{"Changes"}</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                    {
+                        event_list.into_iter().map(|event| {
+                            html!{
+                                <tr>
+                                    <td>{display_date(&event.created)}</td>
+                                    <td>{event.changed_by.clone().unwrap_or_else(|| "Unknown".to_string())}</td>
+                                    <td>{format!("{} {}", event.action, event.entity)}</td>
+                                    <td>
+                                    {
+                                        event.changes.iter().map(|change| html!{
+                                            <p>
+                                                <strong>{&change.field}</strong>
+                                                {format!(": {} → {}", display_value(&change.before), display_value(&change.after))}
+                                            </p>
+                                        }).collect::<Html>()
+                                    }
+                                    </td>
+                                </tr>
+                            }
+                        }).collect::<Html>()
+                    }
+                    </tbody>
+                </table>
+            }
+        } else {
+            if events.error.is_some() {
+                <ComponentError />
+            }else{
+                <Progress/>
+            }
+        }
+        </>
+    }
+}
@@ -0,0 +1,204 @@
+use yew::{platform::spawn_local, prelude::*};
+use yew_router::prelude::{use_navigator, use_route};
+use yewdux::prelude::*;
+use yewdux_input::InputDispatch;
+
+use crate::{
+    components::{
+        progress_bar::Progress,
+        validation::{field_message, is_valid, submit_disabled},
+    },
+    data::*,
+    routes::AppRoute,
+};
+
+#[derive(Properties, PartialEq)]
+pub struct AuthGuardProps {
+    pub children: Children,
+}
+
+/// Asks the api who is signed in once, then keeps everyone without a session on the sign in page
+#[function_component(AuthGuard)]
+pub fn auth_guard(props: &AuthGuardProps) -> Html {
+    let (current, dispatch) = use_store::<CurrentUser>();
+    let route = use_route::<AppRoute>();
+    let navigator = use_navigator();
+    {
+        let dispatch = dispatch.clone();
+        use_effect_with_deps(
+            move |_| {
+                spawn_local(async move {
+                    let user = get_data::<User>("/auth/me".to_string()).await.ok();
+                    dispatch.set(CurrentUser {
+                        user,
+                        checked: true,
+                    });
+                });
+                || ()
+            },
+            (),
+        );
+    }
+    let on_login = route == Some(AppRoute::Login);
+    {
+        let signed_in = current.user.is_some();
+        use_effect_with_deps(
+            move |(checked, signed_in, on_login)| {
+                if let Some(navigator) = navigator {
+                    if *checked && !*signed_in && !*on_login {
+                        navigator.push(&AppRoute::Login);
+                    }
+                    if *signed_in && *on_login {
+                        navigator.push(&AppRoute::CustomerList);
+                    }
+                }
+                || ()
+            },
+            (current.checked, signed_in, on_login),
+        );
+    }
+    match current.checked && (current.user.is_some() || on_login) {
+        true => html! { <>{ for props.children.iter() }</> },
+        false => html! { <Progress/> },
+    }
+}
+
+/// Sign in form, or the form for the first account when nobody has been set up yet
+#[function_component(Login)]
+pub fn login() -> Html {
+    let (credentials, dispatch) = use_store::<Credentials>();
+    let (new_user, new_user_dispatch) = use_store::<NewUser>();
+    let (_, user_dispatch) = use_store::<CurrentUser>();
+    let first_time = use_state(|| false);
+    let server_error = use_state(|| None::<MultiError>);
+
+    let toggle_first_time = {
+        let first_time = first_time.clone();
+        let server_error = server_error.clone();
+        Callback::from(move |_| {
+            server_error.set(None);
+            first_time.set(!*first_time);
+        })
+    };
+    let sign_in = {
+        let credentials = credentials.clone();
+        let server_error = server_error.clone();
+        let user_dispatch = user_dispatch.clone();
+        let dispatch = dispatch.clone();
+        Callback::from(move |_| {
+            let credentials = (*credentials).clone();
+            let server_error = server_error.clone();
+            let user_dispatch = user_dispatch.clone();
+            let dispatch = dispatch.clone();
+            spawn_local(async move {
+                match post_returning::<Credentials, User>("/auth/login".to_string(), credentials)
+                    .await
+                {
+                    Ok(user) => {
+                        // the password is not kept around once it has been used
+                        dispatch.set(Credentials::default());
+                        user_dispatch.set(CurrentUser {
+                            user: Some(user),
+                            checked: true,
+                        });
+                    }
+                    Err(error) => server_error.set(Some(error)),
+                }
+            });
+        })
+    };
+    let set_up = {
+        let new_user = new_user.clone();
+        let server_error = server_error.clone();
+        let new_user_dispatch = new_user_dispatch.clone();
+        Callback::from(move |_| {
+            let new_user = (*new_user).clone();
+            let server_error = server_error.clone();
+            let user_dispatch = user_dispatch.clone();
+            let new_user_dispatch = new_user_dispatch.clone();
+            spawn_local(async move {
+                match post_returning::<NewUser, User>("/auth/setup".to_string(), new_user).await {
+                    Ok(user) => {
+                        new_user_dispatch.set(NewUser::default());
+                        user_dispatch.set(CurrentUser {
+                            user: Some(user),
+                            checked: true,
+                        });
+                    }
+                    Err(error) => server_error.set(Some(error)),
+                }
+            });
+        })
+    };
+    html! {
+        <>
+        <section class="hero is-primary">
+            <div class="hero-body">
+                <p class="title">
+                {"BasicCRM"}
+                </p>
+                <p class="subtitle">
+                {if *first_time { "Create the first account" } else { "Sign in" }}
+                </p>
+            </div>
+        </section>
+        <section class="section">
+            <div class="container">
+            <div class="columns is-centered">
+            <div class="column is-half">
+            if let Some(error) = (*server_error).clone() {
+                <div class="notification is-danger is-light">{error.message()}</div>
+            }
+            if *first_time {
+                <div class="field">
+                    <label class="label">{"Name"}</label>
+                    <div class="control">
+                    <input value={new_user.name.clone()} oninput={new_user_dispatch.input_mut(|user, text| user.name = text)} class={classes!("input",is_valid("name", new_user.as_ref(), &server_error))} type="text" placeholder="Name"/>
+                    </div>
+                    <p class="help is-danger">{field_message("name", new_user.as_ref(), &server_error)}</p>
+                </div>
+                <div class="field">
+                    <label class="label">{"Email"}</label>
+                    <div class="control">
+                    <input value={new_user.email.clone()} oninput={new_user_dispatch.input_mut(|user, text| user.email = text)} class={classes!("input",is_valid("email", new_user.as_ref(), &server_error))} type="email" placeholder="Email"/>
+                    </div>
+                    <p class="help is-danger">{field_message("email", new_user.as_ref(), &server_error)}</p>
+                </div>
+                <div class="field">
+                    <label class="label">{"Password"}</label>
+                    <div class="control">
+                    <input value={new_user.password.clone()} oninput={new_user_dispatch.input_mut(|user, text| user.password = text)} class={classes!("input",is_valid("password", new_user.as_ref(), &server_error))} type="password" placeholder="At least 10 characters"/>
+                    </div>
+                    <p class="help is-danger">{field_message("password", new_user.as_ref(), &server_error)}</p>
+                </div>
+                <div class="buttons">
+                    <button disabled={submit_disabled(new_user.as_ref())} onclick={set_up} class="button is-success">{"Create account"}</button>
+                    <button onclick={toggle_first_time} class="button is-text">{"I already have an account"}</button>
+                </div>
+            } else {
+                <div class="field">
+                    <label class="label">{"Email"}</label>
+                    <div class="control">
+                    <input value={credentials.email.clone()} oninput={dispatch.input_mut(|credentials, text| credentials.email = text)} class={classes!("input",is_valid("email", credentials.as_ref(), &server_error))} type="email" placeholder="Email"/>
+                    </div>
+                    <p class="help is-danger">{field_message("email", credentials.as_ref(), &server_error)}</p>
+                </div>
+                <div class="field">
+                    <label class="label">{"Password"}</label>
+                    <div class="control">
+                    <input value={credentials.password.clone()} oninput={dispatch.input_mut(|credentials, text| credentials.password = text)} class={classes!("input",is_valid("password", credentials.as_ref(), &server_error))} type="password" placeholder="Password"/>
+                    </div>
+                    <p class="help is-danger">{field_message("password", credentials.as_ref(), &server_error)}</p>
+                </div>
+                <div class="buttons">
+                    <button disabled={submit_disabled(credentials.as_ref())} onclick={sign_in} class="button is-success">{"Sign in"}</button>
+                    <button onclick={toggle_first_time} class="button is-text">{"First time here?"}</button>
+                </div>
+            }
+            </div>
+            </div>
+            </div>
+        </section>
+        </>
+    }
+}
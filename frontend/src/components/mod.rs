@@ -1,6 +1,16 @@
+pub mod contacts;
+pub mod custom_fields;
 pub mod customer_detail;
 pub mod customers;
 pub mod error;
+pub mod history;
+pub mod login;
 pub mod nav_bar;
 pub mod not_found;
 pub mod progress_bar;
+pub mod tags;
+pub mod tasks;
+pub mod timeline;
+pub mod trash;
+pub mod users;
+pub mod validation;
@@ -1,7 +1,8 @@
-use yew::prelude::*;
+use yew::{platform::spawn_local, prelude::*};
 use yew_router::prelude::*;
+use yewdux::prelude::*;
 
-use crate::routes::*;
+use crate::{data::*, routes::*};
 #[function_component(Navbar)]
 pub fn nav_bar() -> Html {
     let state = use_state(move || NavState {
@@ -19,6 +20,18 @@ pub fn nav_bar() -> Html {
             })
         })
     };
+    let (current, dispatch) = use_store::<CurrentUser>();
+    let sign_out = Callback::from(move |_| {
+        let dispatch = dispatch.clone();
+        spawn_local(async move {
+            let _ = post_data("/auth/logout".to_string(), ()).await;
+            // signed out either way, the guard sends everyone back to the sign in page
+            dispatch.set(CurrentUser {
+                user: None,
+                checked: true,
+            });
+        });
+    });
     html! {
       <div class="hero-head">
       <nav class="navbar" role="navigation" aria-label="main navigation">
@@ -39,6 +52,18 @@ pub fn nav_bar() -> Html {
         <div id="navbarMenu" class={classes!("navbar-menu",visible)}>
           <div class="navbar-end">
               <Link<AppRoute> classes={"navbar-item"} to={AppRoute::CustomerList}>{ "Home" }</Link<AppRoute>>
+              <Link<AppRoute> classes={"navbar-item"} to={AppRoute::MyTasks}>{ "My tasks" }</Link<AppRoute>>
+              <Link<AppRoute> classes={"navbar-item"} to={AppRoute::Trash}>{ "Trash" }</Link<AppRoute>>
+              if current.can(Permission::ManageSettings) {
+                <Link<AppRoute> classes={"navbar-item"} to={AppRoute::CustomFields}>{ "Custom fields" }</Link<AppRoute>>
+              }
+              if current.can(Permission::ManageUsers) {
+                <Link<AppRoute> classes={"navbar-item"} to={AppRoute::Users}>{ "Users" }</Link<AppRoute>>
+              }
+              if current.user.is_some() {
+                <span class="navbar-item">{format!("{} ({})", current.name(), current.organization())}</span>
+                <a onclick={sign_out} class="navbar-item">{ "Sign out" }</a>
+              }
           </div>
 
         </div>
@@ -0,0 +1,225 @@
+use crate::data::*;
+use web_sys::HtmlInputElement;
+use yew::{platform::spawn_local, prelude::*};
+use yewdux::prelude::use_store;
+
+#[derive(Properties, PartialEq)]
+pub struct TagChipsProps {
+    pub tags: Vec<Tag>,
+    /// Shows a delete button on each chip when set
+    #[prop_or_default]
+    pub onremove: Option<Callback<Tag>>,
+}
+
+#[derive(Properties, PartialEq)]
+pub struct TagPickerProps {
+    /// Every tag, offered as suggestions while typing
+    pub all_tags: Vec<Tag>,
+    /// Called with the chosen tag, a name that is not a tag yet is created first
+    pub onadd: Callback<Tag>,
+    /// Shows a remove button when set, only existing tags can be removed
+    #[prop_or_default]
+    pub onremove: Option<Callback<Tag>>,
+}
+
+#[derive(Properties, PartialEq)]
+pub struct TagEditorProps {
+    /// Tags the record has now
+    pub tags: Vec<Tag>,
+    pub all_tags: Vec<Tag>,
+    /// The record being tagged, its `tags` and `remove` are filled in by the editor
+    pub target: BulkTagRequest,
+    /// Called once tags were added, removed or created so the parent can reload
+    pub onchange: Callback<()>,
+}
+
+/// Black or white, whichever reads better on the colour
+fn text_colour(colour: &str) -> &'static str {
+    let channel = |start: usize| {
+        colour
+            .get(start..start + 2)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .unwrap_or(0) as u32
+    };
+    let brightness = (channel(1) * 299 + channel(3) * 587 + channel(5) * 114) / 1000;
+    match brightness > 150 {
+        true => "#000000",
+        false => "#ffffff",
+    }
+}
+
+/// Inline style colouring a chip or button with the tag colour
+pub fn chip_style(colour: &str) -> String {
+    format!(
+        "background-color: {}; color: {};",
+        colour,
+        text_colour(colour)
+    )
+}
+
+#[function_component(TagChips)]
+pub fn tag_chips(props: &TagChipsProps) -> Html {
+    let chip = |tag: &Tag| {
+        let remove = props.onremove.clone().map(|onremove| {
+            let tag = tag.clone();
+            Callback::from(move |_| onremove.emit(tag.clone()))
+        });
+        html! {
+            <span class="tag" style={chip_style(&tag.colour)}>
+                {&tag.name}
+                if let Some(remove) = remove {
+                    <button onclick={remove} class="delete is-small" aria-label="remove tag"></button>
+                }
+            </span>
+        }
+    };
+    html! {
+        <div class="tags">
+            {props.tags.iter().map(chip).collect::<Html>()}
+        </div>
+    }
+}
+
+/// Input to choose a tag by name, new names are created with the selected colour
+#[function_component(TagPicker)]
+pub fn tag_picker(props: &TagPickerProps) -> Html {
+    let name = use_state(String::new);
+    let colour = use_state(|| TAG_COLOURS[0].to_string());
+    let error = use_state(|| None::<MultiError>);
+    let existing = props
+        .all_tags
+        .iter()
+        .find(|t| t.name.eq_ignore_ascii_case(name.trim()))
+        .cloned();
+    let set_name = {
+        let name = name.clone();
+        Callback::from(move |e: InputEvent| {
+            name.set(e.target_unchecked_into::<HtmlInputElement>().value())
+        })
+    };
+    let add = {
+        let name = name.clone();
+        let colour = colour.clone();
+        let error = error.clone();
+        let existing = existing.clone();
+        let onadd = props.onadd.clone();
+        Callback::from(move |_| {
+            if let Some(tag) = existing.clone() {
+                name.set(String::new());
+                error.set(None);
+                onadd.emit(tag);
+                return;
+            }
+            let tag = Tag {
+                name: name.trim().to_string(),
+                colour: (*colour).clone(),
+                ..Tag::default()
+            };
+            let name = name.clone();
+            let error = error.clone();
+            let onadd = onadd.clone();
+            spawn_local(async move {
+                match post_returning::<Tag, Tag>("/tags".to_string(), tag).await {
+                    Ok(created) => {
+                        name.set(String::new());
+                        error.set(None);
+                        onadd.emit(created);
+                    }
+                    Err(e) => error.set(Some(e)),
+                }
+            });
+        })
+    };
+    let remove = props.onremove.clone().map(|onremove| {
+        let name = name.clone();
+        let existing = existing.clone();
+        Callback::from(move |_| {
+            if let Some(tag) = existing.clone() {
+                name.set(String::new());
+                onremove.emit(tag);
+            }
+        })
+    });
+    let swatch = |option: &'static str| {
+        let colour = colour.clone();
+        let selected = (*colour == option).then_some("is-selected");
+        html! {
+            <button onclick={Callback::from(move |_| colour.set(option.to_string()))} class={classes!("button", "is-small", selected)} style={chip_style(option)} aria-label={option}>
+                if selected.is_some() {
+                    <ion-icon class="" name="checkmark"/>
+                }
+            </button>
+        }
+    };
+    html! {
+        <>
+            <div class="field has-addons">
+                <div class="control">
+                    <input value={(*name).clone()} oninput={set_name} list="tag-names" class="input is-small" type="text" placeholder="Tag"/>
+                    <datalist id="tag-names">
+                        {props.all_tags.iter().map(|t| html! {<option value={t.name.clone()}/>}).collect::<Html>()}
+                    </datalist>
+                </div>
+                <div class="control">
+                    <button disabled={name.trim().is_empty()} onclick={add} class="button is-small is-link">
+                        {if existing.is_some() || name.trim().is_empty() { "Add tag" } else { "Create tag" }}
+                    </button>
+                </div>
+                if let Some(remove) = remove {
+                    <div class="control">
+                        <button disabled={existing.is_none()} onclick={remove} class="button is-small">{"Remove tag"}</button>
+                    </div>
+                }
+            </div>
+            if existing.is_none() && !name.trim().is_empty() {
+                <div class="buttons are-small mb-1">
+                    {TAG_COLOURS.into_iter().map(swatch).collect::<Html>()}
+                </div>
+            }
+            if let Some(error) = (*error).clone() {
+                <p class="help is-danger">{error.field_message("name").or_else(|| error.field_message("colour")).unwrap_or_else(|| error.message())}</p>
+            }
+        </>
+    }
+}
+
+/// Chips of a record's tags with buttons to take them off and a picker to add more
+#[function_component(TagEditor)]
+pub fn tag_editor(props: &TagEditorProps) -> Html {
+    let error = use_state(|| None::<MultiError>);
+    let (user, _) = use_store::<CurrentUser>();
+    let apply = |remove: bool| {
+        let target = props.target.clone();
+        let onchange = props.onchange.clone();
+        let error = error.clone();
+        Callback::from(move |tag: Tag| {
+            let request = BulkTagRequest {
+                tags: vec![tag.id],
+                remove,
+                ..target.clone()
+            };
+            let onchange = onchange.clone();
+            let error = error.clone();
+            spawn_local(async move {
+                match post_data("/tags/apply".to_string(), request).await {
+                    Ok(_) => error.set(None),
+                    Err(e) => error.set(Some(e)),
+                }
+                onchange.emit(());
+            });
+        })
+    };
+    html! {
+        <>
+            if user.can(Permission::Edit) {
+                <TagChips tags={props.tags.clone()} onremove={apply(true)}/>
+                <TagPicker all_tags={props.all_tags.clone()} onadd={apply(false)}/>
+            } else {
+                <TagChips tags={props.tags.clone()}/>
+            }
+            if let Some(error) = (*error).clone() {
+                <p class="help is-danger">{error.message()}</p>
+            }
+        </>
+    }
+}
@@ -0,0 +1,420 @@
+use std::rc::Rc;
+
+use uuid::Uuid;
+use yew::{platform::spawn_local, prelude::*};
+use yew_hooks::{use_async, use_async_with_options, UseAsyncHandle, UseAsyncOptions};
+use yew_router::prelude::Link;
+use yewdux::prelude::*;
+use yewdux_input::InputDispatch;
+
+use crate::{
+    components::{
+        error::ComponentError, nav_bar::Navbar, progress_bar::Progress, users::use_users,
+    },
+    data::*,
+    routes::AppRoute,
+};
+
+use super::validation::{field_message, is_valid, modal_visible, submit_disabled};
+
+fn local_today() -> String {
+    chrono::Local::now().format("%Y-%m-%d").to_string()
+}
+
+fn priority_tag(priority: &str) -> &'static str {
+    match priority {
+        "High" => "is-danger",
+        "Low" => "is-light",
+        _ => "is-info",
+    }
+}
+
+/// Saves the task with its completed flag flipped then runs `reload`
+fn toggle_completed<T: 'static>(
+    task: Task,
+    reload: UseAsyncHandle<T, MultiError>,
+) -> Callback<MouseEvent> {
+    Callback::from(move |_| {
+        let reload = reload.clone();
+        let task = Task {
+            completed: !task.completed,
+            ..task.clone()
+        };
+        spawn_local(async move {
+            let path = format!(
+                "/customer/{}/task/{}",
+                task.customer_id.unwrap_or_default(),
+                task.id
+            );
+            if let Ok(_) = put_data(path, task).await {
+                reload.run();
+            }
+        });
+    })
+}
+
+#[function_component(MyTasks)]
+pub fn my_tasks() -> Html {
+    html! {
+        <>
+        <section class="hero is-primary">
+            <Navbar/>
+            <div class="hero-body">
+                <p class="title">
+                {"My tasks"}
+                </p>
+            </div>
+        </section>
+        <section class="section">
+            <div class="columns">
+                <div class="column">
+                    <TaskGroup title="Overdue" due={TaskDue::Overdue}/>
+                </div>
+                <div class="column">
+                    <TaskGroup title="Today" due={TaskDue::Today}/>
+                </div>
+                <div class="column">
+                    <TaskGroup title="Upcoming" due={TaskDue::Upcoming}/>
+                </div>
+            </div>
+        </section>
+        </>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct TaskGroupProps {
+    pub title: String,
+    pub due: TaskDue,
+}
+
+#[function_component(TaskGroup)]
+pub fn task_group(props: &TaskGroupProps) -> Html {
+    let query = TasksQueryParams {
+        due: props.due,
+        mine: true,
+        today: Some(local_today()),
+        ..Default::default()
+    };
+    let tasks: UseAsyncHandle<Vec<Task>, MultiError> = {
+        let query = query.clone();
+        use_async(async move { get_data(format!("/tasks{}", query.to_query_string())).await })
+    };
+    {
+        let tasks = tasks.clone();
+        use_effect_with_deps(
+            move |_| {
+                tasks.run();
+                || ()
+            },
+            query,
+        );
+    }
+    html! {
+        <div class="box">
+            <h2 class="title is-5">{&props.title}</h2>
+            if let Some(list) = tasks.data.clone() {
+                if list.is_empty() {
+                    <p class="has-text-grey">{"Nothing to do."}</p>
+                }
+                {
+                    list.into_iter().map(|t| {
+                        html!{
+                            <article class="media">
+                                <figure class="media-left">
+                                    <label class="checkbox">
+                                        <input type="checkbox" checked={t.completed} onclick={toggle_completed(t.clone(), tasks.clone())}/>
+                                    </label>
+                                </figure>
+                                <div class="media-content">
+                                    <p class={classes!(t.completed.then_some("has-text-grey"))}>
+                                        <strong>{&t.title}</strong>
+                                        <span class={classes!("tag", "ml-2", priority_tag(&t.priority))}>{&t.priority}</span>
+                                    </p>
+                                    <p>
+                                        <small>
+                                        {&t.due}
+                                        if let Some(customer_id) = t.customer_id {
+                                            {" · "}
+                                            <Link<AppRoute> to={AppRoute::CustomerDetail { id: customer_id }}>{t.customer_name.clone().unwrap_or_default()}</Link<AppRoute>>
+                                        }
+                                        if let Some(assignee) = &t.assignee {
+                                            {format!(" · {}", assignee.name)}
+                                        }
+                                        </small>
+                                    </p>
+                                </div>
+                            </article>
+                        }
+                    }).collect::<Html>()
+                }
+            } else {
+                if tasks.error.is_some() {
+                    <ComponentError />
+                }else{
+                    <Progress/>
+                }
+            }
+        </div>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct CustomerTasksProps {
+    pub id: CustomerId,
+}
+
+#[function_component(CustomerTasksList)]
+pub fn customer_tasks_list(props: &CustomerTasksProps) -> Html {
+    let id = props.id.clone();
+    let tasks: UseAsyncHandle<Vec<Task>, MultiError> = use_async_with_options(
+        async move { get_data(format!("/customer/{}/tasks", id)).await },
+        UseAsyncOptions::enable_auto(),
+    );
+    let opportunities: UseAsyncHandle<Vec<Opportunity>, MultiError> = use_async_with_options(
+        async move { get_data(format!("/customer/{}/opportunities", id)).await },
+        UseAsyncOptions::enable_auto(),
+    );
+    let users = use_users();
+    let (selected_task, dispatch) = use_store::<Task>();
+    let (user, _) = use_store::<CurrentUser>();
+    let can_edit = user.can(Permission::Edit);
+    let modal_open = use_state(|| false);
+
+    let close_modal = {
+        let open_handle = modal_open.clone();
+        Callback::from(move |_| {
+            open_handle.set(false);
+        })
+    };
+    let server_error = use_state(|| None::<MultiError>);
+    {
+        let server_error = server_error.clone();
+        use_effect_with_deps(
+            move |_| {
+                server_error.set(None);
+                || ()
+            },
+            (*selected_task).clone(),
+        );
+    }
+    let save = |task: Rc<Task>| {
+        let reload_list = tasks.clone();
+        let customer_id = id.clone();
+        let current_modal_state = modal_open.clone();
+        let current_server_error = server_error.clone();
+        dispatch.reduce_mut_future_callback(move |_| {
+            let t = task.clone();
+            let modal = current_modal_state.clone();
+            let reload = reload_list.clone();
+            let server_error = current_server_error.clone();
+            Box::pin(async move {
+                let result = if t.id.eq(&Uuid::default()) {
+                    post_data(format!("/customer/{}/tasks", customer_id), t).await
+                } else {
+                    put_data(format!("/customer/{}/task/{}", customer_id, t.id), t).await
+                };
+                match result {
+                    Ok(_) => {
+                        modal.set(false);
+                        reload.run();
+                    }
+                    Err(error) => server_error.set(Some(error)),
+                }
+                ()
+            })
+        })
+    };
+    let delete_task = |task_id: TaskId| {
+        let reload_list = tasks.clone();
+        let customer_id = id.clone();
+        dispatch.reduce_mut_future_callback(move |_| {
+            let reload = reload_list.clone();
+            Box::pin(async move {
+                if let Ok(_) =
+                    delete_data(format!("/customer/{}/task/{}", customer_id, task_id)).await
+                {
+                    reload.run();
+                }
+                ()
+            })
+        })
+    };
+    let select_task = |task: Task| {
+        let current_modal_state = modal_open.clone();
+        let current_server_error = server_error.clone();
+        let reload_opportunities = opportunities.clone();
+        dispatch.reduce_mut_callback(move |state| {
+            *state = task.to_owned();
+            current_server_error.set(None);
+            reload_opportunities.run();
+            current_modal_state.set(true);
+        })
+    };
+    let add_task = select_task(Task {
+        due: local_today(),
+        priority: format!("{}", TaskPriority::Normal),
+        assignee: user.user.as_ref().map(|u| Owner {
+            id: u.id,
+            name: u.name.clone(),
+        }),
+        ..Task::default()
+    });
+    let priority_option = |priority: TaskPriority| {
+        html! {
+            <option onclick={dispatch.reduce_mut_callback(move |state| state.priority = format!("{}", priority))} selected={format!("{}", priority).eq(&selected_task.priority)} value={format!("{}", priority)}>{format!("{}", priority)}</option>
+        }
+    };
+    fn modal_label(id: TaskId) -> String {
+        match id.eq(&Uuid::default()) {
+            true => "Add task".to_string(),
+            false => "Edit task".to_string(),
+        }
+    }
+    html! {
+        <>
+        if let Some(list) = tasks.data.clone() {
+            <div class={classes!("modal",modal_visible(*modal_open))}>
+                <div class="modal-background"></div>
+                <div class="modal-card">
+                    <header class="modal-card-head">
+                        <p class="modal-card-title">{modal_label(selected_task.id)}</p>
+                        <button onclick={&close_modal} class="delete" aria-label="close"></button>
+                    </header>
+                        <section class="modal-card-body">
+                            if let Some(error) = (*server_error).clone() {
+                                <div class="notification is-danger is-light">{error.message()}</div>
+                            }
+                            <div class="field">
+                                <label class="label">{"Title"}</label>
+                                <div class="control">
+                                <input value={selected_task.title.clone()} oninput={dispatch.input_mut(|task, text| task.title = text)} class={classes!("input",is_valid("title", selected_task.as_ref(), &server_error))} type="text" placeholder="Call back about the quote"/>
+                                </div>
+                                <p class="help is-danger">{field_message("title", selected_task.as_ref(), &server_error)}</p>
+                            </div>
+                            <div class="field">
+                                <label class="label">{"Due"}</label>
+                                <div class="control">
+                                <input value={selected_task.due.clone()} oninput={dispatch.input_mut(|task, text| task.due = text)} class={classes!("input",is_valid("due", selected_task.as_ref(), &server_error))} type="date"/>
+                                </div>
+                                <p class="help is-danger">{field_message("due", selected_task.as_ref(), &server_error)}</p>
+                            </div>
+                            <div class="field">
+                                <label class="label">{"Assigned to"}</label>
+                                <div class="control">
+                                <div class="select is-fullwidth">
+                                <select>
+                                    <option onclick={dispatch.reduce_mut_callback(|state| state.assignee = None)} selected={selected_task.assignee.is_none()}>{"Unassigned"}</option>
+                                    {
+                                        users.data.clone().unwrap_or_default().into_iter().map(|u| {
+                                            let assignee = Owner { id: u.id, name: u.name.clone() };
+                                            html!{
+                                                <option onclick={dispatch.reduce_mut_callback(move |state| state.assignee = Some(assignee.clone()))} selected={selected_task.assignee.as_ref().map(|a| a.id) == Some(u.id)}>{&u.name}</option>
+                                            }
+                                        }).collect::<Html>()
+                                    }
+                                </select>
+                                </div>
+                                </div>
+                            </div>
+                            <div class="field">
+                                <label class="label">{"Priority"}</label>
+                                <div class="control">
+                                <div class="select is-fullwidth">
+                                <select>
+                                    {priority_option(TaskPriority::Low)}
+                                    {priority_option(TaskPriority::Normal)}
+                                    {priority_option(TaskPriority::High)}
+                                </select>
+                                </div>
+                                </div>
+                                <p class="help is-danger">{field_message("priority", selected_task.as_ref(), &server_error)}</p>
+                            </div>
+                            <div class="field">
+                                <label class="label">{"Opportunity"}</label>
+                                <div class="control">
+                                <div class="select is-fullwidth">
+                                <select>
+                                    <option onclick={dispatch.reduce_mut_callback(|state| state.opportunity_id = None)} selected={selected_task.opportunity_id.is_none()}>{"None"}</option>
+                                    {
+                                        opportunities.data.clone().unwrap_or_default().into_iter().map(|o| {
+                                            let opportunity_id = o.id;
+                                            html!{
+                                                <option onclick={dispatch.reduce_mut_callback(move |state| state.opportunity_id = Some(opportunity_id))} selected={selected_task.opportunity_id == Some(o.id)}>{&o.name}</option>
+                                            }
+                                        }).collect::<Html>()
+                                    }
+                                </select>
+                                </div>
+                                </div>
+                            </div>
+                            <div class="field">
+                                <div class="control">
+                                <label class="checkbox">
+                                    <input type="checkbox" checked={selected_task.completed} onclick={dispatch.reduce_mut_callback(|task| task.completed = !task.completed)}/>
+                                    {" Completed"}
+                                </label>
+                                </div>
+                            </div>
+                        </section>
+                    <footer class="modal-card-foot">
+                        <button disabled={submit_disabled(dispatch.get().as_ref())} onclick={&save(dispatch.get())} class="button is-success">{"Save changes"}</button>
+                        <button onclick={&close_modal} class="button">{"Cancel"}</button>
+                    </footer>
+                </div>
+            </div>
+
+            if can_edit {
+                <div class="field is-grouped">
+                    <div class="control">
+                        <button onclick={add_task} class="button is-link">{"Add task"}</button>
+                    </div>
+                </div>
+            }
+            <table class="table is-fullwidth">
+                <thead>
+                <tr>
+                    <td>{""}</td>
+                    <td>{"Task"}</td>
+                    <td>{"Due"}</td>
+                    <td>{"Assigned to"}</td>
+                    <td>{"Priority"}</td>
+                    <td>{""}</td>
+                </tr>
+                </thead>
+                <tbody>
+                {
+                    list.into_iter().map(|t| {
+                        html!{
+                        <tr class={classes!(t.completed.then_some("has-text-grey"))}>
+                            <td><input type="checkbox" disabled={!can_edit} checked={t.completed} onclick={toggle_completed(t.clone(), tasks.clone())}/></td>
+                            <td>{&t.title}</td>
+                            <td>{&t.due}</td>
+                            <td>{t.assignee.as_ref().map(|a| a.name.clone()).unwrap_or_default()}</td>
+                            <td><span class={classes!("tag", priority_tag(&t.priority))}>{&t.priority}</span></td>
+                            <td>
+                            if can_edit {
+                                <div class="field is-grouped">
+                                    <div class="control">
+                                        <button onclick={select_task(t.clone())} class="button is-info"><ion-icon class="" name="pencil"/></button>
+                                        <button onclick={delete_task(t.id)} class="button is-danger"><ion-icon class="" name="trash"/></button>
+                                    </div>
+                                </div>
+                            }
+                            </td>
+                        </tr>
+                        }
+                    }).collect::<Html>()
+                }
+                </tbody>
+            </table>
+        } else {
+            if tasks.error.is_some() {
+                <ComponentError />
+            }else{
+                <Progress/>
+            }
+        }
+        </>
+    }
+}
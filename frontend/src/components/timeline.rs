@@ -0,0 +1,310 @@
+use std::rc::Rc;
+
+use chrono::{DateTime, FixedOffset};
+use uuid::Uuid;
+use yew::prelude::*;
+use yew_hooks::{use_async_with_options, UseAsyncHandle, UseAsyncOptions};
+use yewdux::prelude::use_store;
+use yewdux_input::InputDispatch;
+
+use crate::{
+    components::{error::ComponentError, progress_bar::Progress},
+    data::*,
+};
+
+use super::validation::{field_message, is_valid, modal_visible, submit_disabled};
+
+#[derive(Properties, PartialEq)]
+pub struct TimelineProps {
+    pub id: CustomerId,
+}
+
+/// Something that happened to the customer, shown newest first
+#[derive(Clone, PartialEq)]
+enum TimelineEntry {
+    Activity(Activity),
+    StatusChange(StatusChange),
+}
+
+impl TimelineEntry {
+    fn created(&self) -> Option<DateTime<FixedOffset>> {
+        let created = match &self {
+            TimelineEntry::Activity(activity) => &activity.created,
+            TimelineEntry::StatusChange(change) => &change.created,
+        };
+        DateTime::parse_from_rfc3339(created).ok()
+    }
+}
+
+fn merge(activities: Vec<Activity>, changes: Vec<StatusChange>) -> Vec<TimelineEntry> {
+    let mut entries: Vec<TimelineEntry> = activities
+        .into_iter()
+        .map(TimelineEntry::Activity)
+        .chain(changes.into_iter().map(TimelineEntry::StatusChange))
+        .collect();
+    entries.sort_by(|a, b| b.created().cmp(&a.created()));
+    entries
+}
+
+fn display_date(date: &str) -> String {
+    match DateTime::parse_from_rfc3339(date) {
+        Ok(date) => date.format("%Y-%m-%d %H:%M").to_string(),
+        Err(_) => date.to_string(),
+    }
+}
+
+fn status_change_summary(change: &StatusChange) -> String {
+    match &change.previous_status {
+        Some(previous) => format!(
+            "{} moved from {} to {}",
+            change.opportunity_name, previous, change.status
+        ),
+        None => format!("{} created in {}", change.opportunity_name, change.status),
+    }
+}
+
+fn activity_icon(kind: &str) -> &'static str {
+    match kind {
+        "Call" => "call",
+        "Meeting" => "people",
+        "Email" => "mail",
+        _ => "document-text",
+    }
+}
+
+#[function_component(CustomerTimeline)]
+pub fn customer_timeline(props: &TimelineProps) -> Html {
+    let id = props.id.clone();
+    let activities: UseAsyncHandle<Vec<Activity>, MultiError> = use_async_with_options(
+        async move { get_data(format!("/customer/{}/activities", id)).await },
+        UseAsyncOptions::enable_auto(),
+    );
+    let changes: UseAsyncHandle<Vec<StatusChange>, MultiError> = use_async_with_options(
+        async move { get_data(format!("/customer/{}/status-changes", id)).await },
+        UseAsyncOptions::enable_auto(),
+    );
+    let opportunities: UseAsyncHandle<Vec<Opportunity>, MultiError> = use_async_with_options(
+        async move { get_data(format!("/customer/{}/opportunities", id)).await },
+        UseAsyncOptions::enable_auto(),
+    );
+    let (selected_activity, dispatch) = use_store::<Activity>();
+    let (user, _) = use_store::<CurrentUser>();
+    let can_edit = user.can(Permission::Edit);
+    let modal_open = use_state(|| false);
+
+    let close_modal = {
+        let open_handle = modal_open.clone();
+        Callback::from(move |_| {
+            open_handle.set(false);
+        })
+    };
+    let server_error = use_state(|| None::<MultiError>);
+    {
+        let server_error = server_error.clone();
+        use_effect_with_deps(
+            move |_| {
+                server_error.set(None);
+                || ()
+            },
+            (*selected_activity).clone(),
+        );
+    }
+    let save = |activity: Rc<Activity>| {
+        let reload_list = activities.clone();
+        let customer_id = id.clone();
+        let current_modal_state = modal_open.clone();
+        let current_server_error = server_error.clone();
+        dispatch.reduce_mut_future_callback(move |_| {
+            let a = activity.clone();
+            let modal = current_modal_state.clone();
+            let reload = reload_list.clone();
+            let server_error = current_server_error.clone();
+            Box::pin(async move {
+                let result = if a.id.eq(&Uuid::default()) {
+                    post_data(format!("/customer/{}/activities", customer_id), a).await
+                } else {
+                    put_data(format!("/customer/{}/activity/{}", customer_id, a.id), a).await
+                };
+                match result {
+                    Ok(_) => {
+                        modal.set(false);
+                        reload.run();
+                    }
+                    Err(error) => server_error.set(Some(error)),
+                }
+                ()
+            })
+        })
+    };
+    let delete_activity = |activity_id: ActivityId| {
+        let reload_list = activities.clone();
+        let customer_id = id.clone();
+        dispatch.reduce_mut_future_callback(move |_| {
+            let reload = reload_list.clone();
+            Box::pin(async move {
+                if let Ok(_) = delete_data(format!(
+                    "/customer/{}/activity/{}",
+                    customer_id, activity_id
+                ))
+                .await
+                {
+                    reload.run();
+                }
+                ()
+            })
+        })
+    };
+    let select_activity = |activity: Activity| {
+        let current_modal_state = modal_open.clone();
+        let current_server_error = server_error.clone();
+        let reload_opportunities = opportunities.clone();
+        dispatch.reduce_mut_callback(move |state| {
+            *state = activity.to_owned();
+            current_server_error.set(None);
+            reload_opportunities.run();
+            current_modal_state.set(true);
+        })
+    };
+    let add_activity = select_activity(Activity {
+        kind: format!("{}", ActivityKind::Note),
+        ..Activity::default()
+    });
+    let kind_option = |kind: ActivityKind| {
+        html! {
+            <option onclick={dispatch.reduce_mut_callback(move |state| state.kind = format!("{}", kind))} selected={format!("{}", kind).eq(&selected_activity.kind)} value={format!("{}", kind)}>{format!("{}", kind)}</option>
+        }
+    };
+    fn modal_label(id: ActivityId) -> String {
+        match id.eq(&Uuid::default()) {
+            true => "Log activity".to_string(),
+            false => "Edit activity".to_string(),
+        }
+    }
+    html! {
+        <>
+        <div class={classes!("modal",modal_visible(*modal_open))}>
+            <div class="modal-background"></div>
+            <div class="modal-card">
+                <header class="modal-card-head">
+                    <p class="modal-card-title">{modal_label(selected_activity.id)}</p>
+                    <button onclick={&close_modal} class="delete" aria-label="close"></button>
+                </header>
+                    <section class="modal-card-body">
+                        if let Some(error) = (*server_error).clone() {
+                            <div class="notification is-danger is-light">{error.message()}</div>
+                        }
+                        <div class="field">
+                            <label class="label">{"Kind"}</label>
+                            <div class="control">
+                            <div class="select is-fullwidth">
+                            <select>
+                                {kind_option(ActivityKind::Note)}
+                                {kind_option(ActivityKind::Call)}
+                                {kind_option(ActivityKind::Meeting)}
+                                {kind_option(ActivityKind::Email)}
+                            </select>
+                            </div>
+                            </div>
+                            <p class="help is-danger">{field_message("kind", selected_activity.as_ref(), &server_error)}</p>
+                        </div>
+                        <div class="field">
+                            <label class="label">{"Details"}</label>
+                            <div class="control">
+                            <textarea value={selected_activity.body.clone()} oninput={dispatch.input_mut(|activity, text| activity.body = text)} class={classes!("textarea",is_valid("body", selected_activity.as_ref(), &server_error))} placeholder="What happened?"/>
+                            </div>
+                            <p class="help is-danger">{field_message("body", selected_activity.as_ref(), &server_error)}</p>
+                        </div>
+                        <div class="field">
+                            <label class="label">{"Opportunity"}</label>
+                            <div class="control">
+                            <div class="select is-fullwidth">
+                            <select>
+                                <option onclick={dispatch.reduce_mut_callback(|state| state.opportunity_id = None)} selected={selected_activity.opportunity_id.is_none()}>{"None"}</option>
+                                {
+                                    opportunities.data.clone().unwrap_or_default().into_iter().map(|o| {
+                                        let opportunity_id = o.id;
+                                        html!{
+                                            <option onclick={dispatch.reduce_mut_callback(move |state| state.opportunity_id = Some(opportunity_id))} selected={selected_activity.opportunity_id == Some(o.id)}>{&o.name}</option>
+                                        }
+                                    }).collect::<Html>()
+                                }
+                            </select>
+                            </div>
+                            </div>
+                        </div>
+                    </section>
+                <footer class="modal-card-foot">
+                    <button disabled={submit_disabled(dispatch.get().as_ref())} onclick={&save(dispatch.get())} class="button is-success">{"Save changes"}</button>
+                    <button onclick={&close_modal} class="button">{"Cancel"}</button>
+                </footer>
+            </div>
+        </div>
+
+        if can_edit {
+            <div class="field is-grouped">
+                <div class="control">
+                    <button onclick={add_activity} class="button is-link">{"Log activity"}</button>
+                </div>
+            </div>
+        }
+        if let (Some(activity_list), Some(change_list)) = (activities.data.clone(), changes.data.clone()) {
+            if activity_list.is_empty() && change_list.is_empty() {
+                <p class="has-text-grey">{"Nothing has happened with this customer yet."}</p>
+            }
+            {
+                merge(activity_list, change_list).into_iter().map(|entry| {
+                    match entry {
+                        TimelineEntry::Activity(a) => html!{
+                            <article class="media">
+                                <figure class="media-left">
+                                    <span class="icon is-medium"><ion-icon size="large" name={activity_icon(&a.kind)}/></span>
+                                </figure>
+                                <div class="media-content">
+                                    <p>
+                                        <strong>{&a.kind}</strong>
+                                        <small class="has-text-grey ml-2">{display_date(&a.created)}</small>
+                                        if let Some(name) = &a.opportunity_name {
+                                            <span class="tag is-info is-light ml-2">{name}</span>
+                                        }
+                                    </p>
+                                    <p style="white-space: pre-wrap;">{&a.body}</p>
+                                </div>
+                                if can_edit {
+                                    <div class="media-right">
+                                        <div class="buttons">
+                                            <button onclick={select_activity(a.clone())} class="button is-small is-info"><ion-icon class="" name="pencil"/></button>
+                                            <button onclick={delete_activity(a.id)} class="button is-small is-danger"><ion-icon class="" name="trash"/></button>
+                                        </div>
+                                    </div>
+                                }
+                            </article>
+                        },
+                        TimelineEntry::StatusChange(c) => html!{
+                            <article class="media">
+                                <figure class="media-left">
+                                    <span class="icon is-medium"><ion-icon size="large" name="swap-horizontal"/></span>
+                                </figure>
+                                <div class="media-content">
+                                    <p>
+                                        <strong>{status_change_summary(&c)}</strong>
+                                        <small class="has-text-grey ml-2">{display_date(&c.created)}</small>
+                                        if let Some(name) = &c.changed_by {
+                                            <small class="has-text-grey ml-2">{format!("by {}", name)}</small>
+                                        }
+                                    </p>
+                                </div>
+                            </article>
+                        },
+                    }
+                }).collect::<Html>()
+            }
+        } else {
+            if activities.error.is_some() || changes.error.is_some() {
+                <ComponentError />
+            }else{
+                <Progress/>
+            }
+        }
+        </>
+    }
+}
@@ -0,0 +1,122 @@
+use crate::{
+    components::{error::ComponentError, nav_bar::Navbar, progress_bar::Progress},
+    data::*,
+};
+
+use yew::{platform::spawn_local, prelude::*};
+use yew_hooks::{use_async_with_options, UseAsyncHandle, UseAsyncOptions};
+use yewdux::prelude::use_store;
+
+fn display_date(date: &str) -> String {
+    match chrono::DateTime::parse_from_rfc3339(date) {
+        Ok(date) => date.format("%Y-%m-%d %H:%M").to_string(),
+        Err(_) => date.to_string(),
+    }
+}
+
+#[function_component(Trash)]
+pub fn trash() -> Html {
+    let deleted: UseAsyncHandle<Vec<DeletedCustomer>, MultiError> = use_async_with_options(
+        async move { get_data("/customers/trash".to_string()).await },
+        UseAsyncOptions::enable_auto(),
+    );
+    let (user, _) = use_store::<CurrentUser>();
+    let can_delete = user.can(Permission::DeleteCustomers);
+    let restore = |customer_id: CustomerId| {
+        let reload = deleted.clone();
+        Callback::from(move |_| {
+            let reload = reload.clone();
+            spawn_local(async move {
+                if let Ok(_) = post_data(format!("/customer/{}/restore", customer_id), ()).await {
+                    reload.run();
+                }
+            });
+        })
+    };
+    let purge = |customer_id: CustomerId| {
+        let reload = deleted.clone();
+        Callback::from(move |_| {
+            let reload = reload.clone();
+            spawn_local(async move {
+                if let Ok(_) = delete_data(format!("/customers/trash/{}", customer_id)).await {
+                    reload.run();
+                }
+            });
+        })
+    };
+    let empty_trash = {
+        let reload = deleted.clone();
+        Callback::from(move |_| {
+            let reload = reload.clone();
+            spawn_local(async move {
+                if let Ok(_) = delete_data("/customers/trash".to_string()).await {
+                    reload.run();
+                }
+            });
+        })
+    };
+    html! {
+        <>
+        <section class="hero is-primary">
+            <Navbar/>
+            <div class="hero-body">
+                <p class="title">
+                {"Trash"}
+                </p>
+            </div>
+        </section>
+        <section class="section">
+        if let Some(customers) = deleted.data.clone() {
+            if can_delete {
+                <div class="field is-grouped">
+                    <div class="control">
+                        <button disabled={customers.is_empty()} onclick={empty_trash} class="button is-danger">{"Empty trash"}</button>
+                    </div>
+                </div>
+            }
+            <table class="table is-fullwidth">
+            <thead>
+            <tr>
+                <td>{"Name"}</td>
+                <td>{"Email"}</td>
+                <td>{"Opportunities"}</td>
+                <td>{"Deleted"}</td>
+                <td>{""}</td>
+            </tr>
+            </thead>
+            <tbody>
+            {
+                customers.into_iter().map(|c| {
+                    html!{
+                    <tr>
+                        <td>{&c.name}</td>
+                        <td>{&c.email}</td>
+                        <td>{c.opportunity_count}</td>
+                        <td>{display_date(&c.deleted)}</td>
+                        <td>
+                        if can_delete {
+                            <div class="field is-grouped">
+                                <div class="control">
+                                    <button onclick={restore(c.id)} class="button is-info" title="Restore"><ion-icon class="" name="arrow-undo"/></button>
+                                    <button onclick={purge(c.id)} class="button is-danger" title="Delete forever"><ion-icon class="" name="trash"/></button>
+                                </div>
+                            </div>
+                        }
+                        </td>
+                    </tr>
+                    }
+                }).collect::<Html>()
+            }
+            </tbody>
+            </table>
+        } else {
+            if deleted.error.is_some() {
+                <ComponentError/>
+            }else{
+                <Progress/>
+            }
+        }
+        </section>
+        </>
+    }
+}
@@ -0,0 +1,331 @@
+use yew::{platform::spawn_local, prelude::*};
+use yew_hooks::{use_async_with_options, UseAsyncHandle, UseAsyncOptions};
+use yewdux::prelude::*;
+use yewdux_input::InputDispatch;
+
+use crate::{
+    components::{
+        error::ComponentError,
+        nav_bar::Navbar,
+        progress_bar::Progress,
+        validation::{field_message, is_valid, submit_disabled},
+    },
+    data::*,
+};
+
+const ROLES: [Role; 4] = [
+    Role::Admin,
+    Role::SalesManager,
+    Role::SalesRep,
+    Role::ReadOnly,
+];
+
+#[derive(Properties, PartialEq)]
+pub struct OwnerPickerProps {
+    /// Owner the record has now
+    #[prop_or_default]
+    pub owner: Option<Owner>,
+    /// Everyone the records can be given to
+    pub users: Vec<User>,
+    /// The records being reassigned, its `owner` is filled in by the picker
+    pub target: AssignRequest,
+    /// Shown in place of the owner when reassigning a selection of records
+    #[prop_or_default]
+    pub prompt: Option<AttrValue>,
+    /// Called once the owner was changed so the parent can reload
+    pub onchange: Callback<()>,
+}
+
+/// Everyone who can sign in, loaded when the component is first shown
+#[hook]
+pub fn use_users() -> UseAsyncHandle<Vec<User>, MultiError> {
+    use_async_with_options(
+        async move { get_data("/users".to_string()).await },
+        UseAsyncOptions::enable_auto(),
+    )
+}
+
+/// Name of the owner, users allowed to assign owners get a select to give the records to someone else
+#[function_component(OwnerPicker)]
+pub fn owner_picker(props: &OwnerPickerProps) -> Html {
+    let error = use_state(|| None::<MultiError>);
+    let (user, _) = use_store::<CurrentUser>();
+    let assign = |owner: Option<UserId>| {
+        let target = props.target.clone();
+        let onchange = props.onchange.clone();
+        let error = error.clone();
+        Callback::from(move |_| {
+            let request = AssignRequest {
+                owner,
+                ..target.clone()
+            };
+            let onchange = onchange.clone();
+            let error = error.clone();
+            spawn_local(async move {
+                match post_data("/customers/assign".to_string(), request).await {
+                    Ok(_) => error.set(None),
+                    Err(e) => error.set(Some(e)),
+                }
+                onchange.emit(());
+            });
+        })
+    };
+    let current = match &props.prompt {
+        Some(_) => None,
+        None => Some(props.owner.as_ref().map(|owner| owner.id)),
+    };
+    html! {
+        <>
+            if user.can(Permission::AssignOwners) {
+                <div class="select is-small">
+                <select>
+                    if let Some(prompt) = props.prompt.clone() {
+                        <option selected=true disabled=true>{prompt}</option>
+                    }
+                    <option onclick={assign(None)} selected={current == Some(None)}>{"Unassigned"}</option>
+                    {props.users.iter().map(|u| html! {
+                        <option onclick={assign(Some(u.id))} selected={current == Some(Some(u.id))}>{&u.name}</option>
+                    }).collect::<Html>()}
+                </select>
+                </div>
+            } else {
+                {props.owner.as_ref().map(|owner| owner.name.clone()).unwrap_or_else(|| "Unassigned".to_string())}
+            }
+            if let Some(error) = (*error).clone() {
+                <p class="help is-danger">{error.message()}</p>
+            }
+        </>
+    }
+}
+
+/// Sets up another organisation and the account of its first admin, who then adds its other users
+#[function_component(NewOrganizationForm)]
+fn new_organization_form() -> Html {
+    let (organization, dispatch) = use_store::<NewOrganization>();
+    let error = use_state(|| None::<MultiError>);
+    let created = use_state(|| None::<String>);
+
+    let create = {
+        let organization = organization.clone();
+        let dispatch = dispatch.clone();
+        let error = error.clone();
+        let created = created.clone();
+        Callback::from(move |_| {
+            let organization = (*organization).clone();
+            let dispatch = dispatch.clone();
+            let error = error.clone();
+            let created = created.clone();
+            spawn_local(async move {
+                let name = organization.organization.clone();
+                match post_data("/organizations".to_string(), organization).await {
+                    Ok(_) => {
+                        dispatch.set(NewOrganization::default());
+                        error.set(None);
+                        created.set(Some(name));
+                    }
+                    Err(e) => error.set(Some(e)),
+                }
+            });
+        })
+    };
+    html! {
+        <div class="box">
+            <h2 class="title is-5">{"New organisation"}</h2>
+            <p class="block">{"Its customers, opportunities, users, tags, pipelines and custom fields are kept apart from this organisation's. It starts with the default Sales pipeline."}</p>
+            if let Some(error) = (*error).clone() {
+                <div class="notification is-danger is-light">{error.message()}</div>
+            }
+            if let Some(name) = (*created).clone() {
+                <div class="notification is-success is-light">{format!("{} was set up, its admin can sign in now.", name)}</div>
+            }
+            <div class="columns">
+                <div class="column">
+                    <div class="field">
+                        <label class="label">{"Organisation"}</label>
+                        <div class="control">
+                        <input value={organization.organization.clone()} oninput={dispatch.input_mut(|o, text| o.organization = text)} class={classes!("input",is_valid("organization", organization.as_ref(), &error))} type="text" placeholder="Name of the organisation"/>
+                        </div>
+                        <p class="help is-danger">{field_message("organization", organization.as_ref(), &error)}</p>
+                    </div>
+                    <div class="field">
+                        <label class="label">{"Admin name"}</label>
+                        <div class="control">
+                        <input value={organization.name.clone()} oninput={dispatch.input_mut(|o, text| o.name = text)} class={classes!("input",is_valid("name", organization.as_ref(), &error))} type="text" placeholder="Name"/>
+                        </div>
+                        <p class="help is-danger">{field_message("name", organization.as_ref(), &error)}</p>
+                    </div>
+                </div>
+                <div class="column">
+                    <div class="field">
+                        <label class="label">{"Admin email"}</label>
+                        <div class="control">
+                        <input value={organization.email.clone()} oninput={dispatch.input_mut(|o, text| o.email = text)} class={classes!("input",is_valid("email", organization.as_ref(), &error))} type="email" placeholder="Email"/>
+                        </div>
+                        <p class="help is-danger">{field_message("email", organization.as_ref(), &error)}</p>
+                    </div>
+                    <div class="field">
+                        <label class="label">{"Admin password"}</label>
+                        <div class="control">
+                        <input value={organization.password.clone()} oninput={dispatch.input_mut(|o, text| o.password = text)} class={classes!("input",is_valid("password", organization.as_ref(), &error))} type="password" placeholder="At least 10 characters"/>
+                        </div>
+                        <p class="help is-danger">{field_message("password", organization.as_ref(), &error)}</p>
+                    </div>
+                </div>
+            </div>
+            <button disabled={submit_disabled(organization.as_ref())} onclick={create} class="button is-success">{"Add organisation"}</button>
+        </div>
+    }
+}
+
+/// Accounts and their roles, admins add colleagues here
+#[function_component(Users)]
+pub fn users() -> Html {
+    let users = use_users();
+    let (new_user, dispatch) = use_store::<NewUser>();
+    let (current, _) = use_store::<CurrentUser>();
+    let error = use_state(|| None::<MultiError>);
+
+    let create = {
+        let new_user = new_user.clone();
+        let dispatch = dispatch.clone();
+        let error = error.clone();
+        let reload = users.clone();
+        Callback::from(move |_| {
+            let new_user = (*new_user).clone();
+            let dispatch = dispatch.clone();
+            let error = error.clone();
+            let reload = reload.clone();
+            spawn_local(async move {
+                match post_data("/users".to_string(), new_user).await {
+                    Ok(_) => {
+                        dispatch.set(NewUser::default());
+                        error.set(None);
+                        reload.run();
+                    }
+                    Err(e) => error.set(Some(e)),
+                }
+            });
+        })
+    };
+    let set_role = |user: &User, role: Role| {
+        let id = user.id;
+        let error = error.clone();
+        let reload = users.clone();
+        Callback::from(move |_| {
+            let error = error.clone();
+            let reload = reload.clone();
+            spawn_local(async move {
+                match put_data(format!("/user/{}/role", id), role).await {
+                    Ok(_) => error.set(None),
+                    Err(e) => error.set(Some(e)),
+                }
+                reload.run();
+            });
+        })
+    };
+    html! {
+        <>
+        <section class="hero is-primary">
+            <Navbar/>
+            <div class="hero-body">
+                <p class="title">
+                {"Users"}
+                </p>
+            </div>
+        </section>
+        <section class="section">
+            if !current.can(Permission::ManageUsers) {
+                <div class="notification is-warning is-light">{"Only admins can add users and change their roles."}</div>
+            } else {
+                <div class="box">
+                    <h2 class="title is-5">{"New user"}</h2>
+                    if let Some(error) = (*error).clone() {
+                        <div class="notification is-danger is-light">{error.message()}</div>
+                    }
+                    <div class="columns">
+                        <div class="column">
+                            <div class="field">
+                                <label class="label">{"Name"}</label>
+                                <div class="control">
+                                <input value={new_user.name.clone()} oninput={dispatch.input_mut(|user, text| user.name = text)} class={classes!("input",is_valid("name", new_user.as_ref(), &error))} type="text" placeholder="Name"/>
+                                </div>
+                                <p class="help is-danger">{field_message("name", new_user.as_ref(), &error)}</p>
+                            </div>
+                            <div class="field">
+                                <label class="label">{"Email"}</label>
+                                <div class="control">
+                                <input value={new_user.email.clone()} oninput={dispatch.input_mut(|user, text| user.email = text)} class={classes!("input",is_valid("email", new_user.as_ref(), &error))} type="email" placeholder="Email"/>
+                                </div>
+                                <p class="help is-danger">{field_message("email", new_user.as_ref(), &error)}</p>
+                            </div>
+                        </div>
+                        <div class="column">
+                            <div class="field">
+                                <label class="label">{"Password"}</label>
+                                <div class="control">
+                                <input value={new_user.password.clone()} oninput={dispatch.input_mut(|user, text| user.password = text)} class={classes!("input",is_valid("password", new_user.as_ref(), &error))} type="password" placeholder="At least 10 characters"/>
+                                </div>
+                                <p class="help is-danger">{field_message("password", new_user.as_ref(), &error)}</p>
+                            </div>
+                            <div class="field">
+                                <label class="label">{"Role"}</label>
+                                <div class="control">
+                                <div class="select is-fullwidth">
+                                <select>
+                                    {ROLES.into_iter().map(|role| html! {
+                                        <option onclick={dispatch.reduce_mut_callback(move |user| user.role = role)} selected={new_user.role == role}>{role.label()}</option>
+                                    }).collect::<Html>()}
+                                </select>
+                                </div>
+                                </div>
+                            </div>
+                        </div>
+                    </div>
+                    <button disabled={submit_disabled(new_user.as_ref())} onclick={create} class="button is-success">{"Add user"}</button>
+                </div>
+            }
+            if current.can(Permission::ManageOrganizations) {
+                <NewOrganizationForm/>
+            }
+            if let Some(list) = users.data.clone() {
+                <table class="table is-fullwidth">
+                    <thead>
+                    <tr>
+                        <td>{"Name"}</td>
+                        <td>{"Email"}</td>
+                        <td>{"Role"}</td>
+                    </tr>
+                    </thead>
+                    <tbody>
+                    {list.iter().map(|u| html! {
+                        <tr>
+                            <td>{&u.name}</td>
+                            <td>{&u.email}</td>
+                            <td>
+                                if current.can(Permission::ManageUsers) {
+                                    <div class="select is-small">
+                                    <select>
+                                        {ROLES.into_iter().map(|role| html! {
+                                            <option onclick={set_role(u, role)} selected={u.role == role}>{role.label()}</option>
+                                        }).collect::<Html>()}
+                                    </select>
+                                    </div>
+                                } else {
+                                    {u.role.label()}
+                                }
+                            </td>
+                        </tr>
+                    }).collect::<Html>()}
+                    </tbody>
+                </table>
+            } else {
+                if users.error.is_some() {
+                    <ComponentError/>
+                } else {
+                    <Progress/>
+                }
+            }
+        </section>
+        </>
+    }
+}
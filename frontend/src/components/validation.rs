@@ -0,0 +1,90 @@
+use validator::Validate;
+
+use crate::data::MultiError;
+
+/// Bulma class for an input that fails validation here or was rejected by the server
+pub fn is_valid<T: Validate>(
+    field: &str,
+    state: &T,
+    server_error: &Option<MultiError>,
+) -> Option<String> {
+    match field_message(field, state, server_error) {
+        Some(_) => Some("is-danger".to_string()),
+        None => None,
+    }
+}
+
+/// Client side validation message for the field, falling back to any message the server returned
+pub fn field_message<T: Validate>(
+    field: &str,
+    state: &T,
+    server_error: &Option<MultiError>,
+) -> Option<String> {
+    validation_message(field, state).or_else(|| {
+        server_error
+            .as_ref()
+            .and_then(|error| error.field_message(field))
+    })
+}
+
+/// Combined validation messages for a single field, including struct level errors raised against it
+pub fn validation_message<T: Validate>(field: &str, state: &T) -> Option<String> {
+    match state.validate() {
+        Err(error) => {
+            let field_messages = error
+                .field_errors()
+                .iter()
+                .filter(|(f, _)| **f == field)
+                .map(|(_, v)| {
+                    format!(
+                        "{}",
+                        v.into_iter()
+                            .filter(|f| f.message.is_some())
+                            .map(|f| f.message.clone().unwrap().to_string())
+                            .collect::<Vec<String>>()
+                            .join(",")
+                    )
+                })
+                .collect::<Vec<String>>();
+            let overall_messages = error
+                .field_errors()
+                .iter()
+                .filter(|(f, _)| f.contains("__all__"))
+                .map(|(_, v)| {
+                    format!(
+                        "{}",
+                        v.into_iter()
+                            .filter(|f| f.code.contains(field))
+                            .map(|f| f.message.clone().unwrap().to_string())
+                            .collect::<Vec<String>>()
+                            .join("")
+                    )
+                })
+                .filter(|s| s.len() > 0)
+                .collect::<Vec<String>>();
+            let combined = [field_messages, overall_messages].concat();
+            if combined.len() == 0 {
+                None
+            } else {
+                Some(combined.join(","))
+            }
+        }
+        Ok(_) => None,
+    }
+}
+
+/// Disable submit buttons until the form is valid
+pub fn submit_disabled<T: Validate>(state: &T) -> bool {
+    match state.validate() {
+        Err(_) => true,
+        Ok(_) => false,
+    }
+}
+
+pub fn modal_visible(open: bool) -> Option<String> {
+    if open {
+        Some("is-active".to_string())
+    } else {
+        None
+    }
+}
@@ -1,15 +1,30 @@
 use core::fmt;
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
 
 use edgedb_derive::Queryable;
-use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::header::{ACCEPT, CONTENT_TYPE, IF_MATCH};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use validator::{Validate, ValidationError};
 use yew::Properties;
-use yewdux::store::Store;
+use yewdux::{dispatch::Dispatch, store::Store};
 
 pub type CustomerId = Uuid;
 pub type OpportunityId = Uuid;
+pub type ContactId = Uuid;
+pub type ActivityId = Uuid;
+pub type TaskId = Uuid;
+pub type PipelineId = Uuid;
+pub type StageId = Uuid;
+pub type TagId = Uuid;
+pub type CustomFieldId = Uuid;
+pub type UserId = Uuid;
+pub type OrganizationId = Uuid;
+/// Values of the custom fields of a record keyed by field key
+pub type CustomValues = BTreeMap<String, serde_json::Value>;
 
 #[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -18,6 +33,15 @@ pub enum SortDirection {
     Desc,
 }
 
+impl SortDirection {
+    pub fn reverse(&self) -> SortDirection {
+        match &self {
+            SortDirection::Asc => SortDirection::Desc,
+            SortDirection::Desc => SortDirection::Asc,
+        }
+    }
+}
+
 impl fmt::Display for SortDirection {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self {
@@ -34,6 +58,8 @@ pub enum CustomerSortField {
     Email,
     Status,
     Created,
+    /// Name of the owner, customers without one come first when ascending
+    Owner,
 }
 
 impl fmt::Display for CustomerSortField {
@@ -43,71 +69,1465 @@ impl fmt::Display for CustomerSortField {
             CustomerSortField::Email => write!(f, "email"),
             CustomerSortField::Status => write!(f, "status"),
             CustomerSortField::Created => write!(f, "created"),
+            CustomerSortField::Owner => write!(f, "owner"),
         }
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub enum OpportunityStatus {
-    New,
-    ClosedWon,
-    ClosedLost,
+pub enum CustomerStatus {
+    Active,
+    NonActive,
+    Lead,
 }
 
-impl fmt::Display for OpportunityStatus {
+impl fmt::Display for CustomerStatus {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self {
-            OpportunityStatus::New => write!(f, "New"),
-            OpportunityStatus::ClosedWon => write!(f, "ClosedWon"),
-            OpportunityStatus::ClosedLost => write!(f, "ClosedLost"),
+            CustomerStatus::Active => write!(f, "Active"),
+            CustomerStatus::NonActive => write!(f, "NonActive"),
+            CustomerStatus::Lead => write!(f, "Lead"),
+        }
+    }
+}
+
+impl FromStr for CustomerStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Active" => Ok(CustomerStatus::Active),
+            "NonActive" => Ok(CustomerStatus::NonActive),
+            "Lead" => Ok(CustomerStatus::Lead),
+            _ => Err(format!("{} is not a valid customer status", s)),
         }
     }
 }
 
-#[derive(Properties, Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[derive(Properties, Debug, Clone, PartialEq, Deserialize, Serialize, Validate)]
+#[serde(default)]
 pub struct CustomersQueryParams {
     pub sort: CustomerSortField,
     pub direction: SortDirection,
-    pub offset: usize,
+    /// Opaque position from the `next_cursor` or `prev_cursor` of a previous page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    #[validate(range(min = 1, message = "Must return at least one row"))]
     pub limit: usize,
+    /// Only include customers with one of these statuses, comma separated in the query string
+    #[serde(skip_serializing_if = "Vec::is_empty", with = "comma_separated")]
+    pub status: Vec<CustomerStatus>,
+    /// Domain part of the email address e.g. example.com
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_domain: Option<String>,
+    /// Inclusive start date as yyyy-mm-dd
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(custom = "valid_date")]
+    pub created_from: Option<String>,
+    /// Inclusive end date as yyyy-mm-dd
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(custom = "valid_date")]
+    pub created_to: Option<String>,
+    /// Only include customers with at least one of these tags, comma separated in the query string
+    #[serde(skip_serializing_if = "Vec::is_empty", with = "comma_separated")]
+    pub tags: Vec<TagId>,
+    /// Only include customers whose custom fields have these values, as `key:value` comma separated in the query string
+    #[serde(skip_serializing_if = "Vec::is_empty", with = "comma_separated")]
+    pub custom: Vec<CustomFilter>,
+    /// Only include customers owned by the signed in user
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub mine: bool,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable, Validate)]
+impl Default for CustomersQueryParams {
+    fn default() -> Self {
+        CustomersQueryParams {
+            sort: CustomerSortField::Created,
+            direction: SortDirection::Desc,
+            cursor: None,
+            limit: 20,
+            status: vec![],
+            email_domain: None,
+            created_from: None,
+            created_to: None,
+            tags: vec![],
+            custom: vec![],
+            mine: false,
+        }
+    }
+}
+
+impl CustomersQueryParams {
+    pub fn to_query_string(&self) -> String {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        query
+            .append_pair("sort", &self.sort.to_string())
+            .append_pair("direction", &self.direction.to_string())
+            .append_pair("limit", &self.limit.to_string());
+        if let Some(cursor) = &self.cursor {
+            query.append_pair("cursor", cursor);
+        }
+        if !self.status.is_empty() {
+            query.append_pair("status", &comma_separated::join(&self.status));
+        }
+        if let Some(email_domain) = &self.email_domain {
+            query.append_pair("email_domain", email_domain);
+        }
+        if let Some(created_from) = &self.created_from {
+            query.append_pair("created_from", created_from);
+        }
+        if let Some(created_to) = &self.created_to {
+            query.append_pair("created_to", created_to);
+        }
+        if !self.tags.is_empty() {
+            query.append_pair("tags", &comma_separated::join(&self.tags));
+        }
+        if !self.custom.is_empty() {
+            query.append_pair("custom", &comma_separated::join(&self.custom));
+        }
+        if self.mine {
+            query.append_pair("mine", "true");
+        }
+        format!("?{}", query.finish())
+    }
+}
+
+/// One page of a list along with the cursors either side of it
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Number of rows matching the filters across every page
+    pub total: i64,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+fn valid_date(date: &str) -> Result<(), ValidationError> {
+    match chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ValidationError {
+            message: Some("Please enter a date as yyyy-mm-dd".into()),
+            ..ValidationError::new("date")
+        }),
+    }
+}
+
+/// Serializes lists as a single comma separated value so they fit in a query string
+mod comma_separated {
+    use std::{fmt::Display, str::FromStr};
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn join<T: Display>(values: &[T]) -> String {
+        values
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+
+    pub fn serialize<S, T>(values: &[T], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Display,
+    {
+        serializer.serialize_str(&join(values))
+    }
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = String::deserialize(deserializer)?;
+        value
+            .split(',')
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse::<T>().map_err(serde::de::Error::custom))
+            .collect()
+    }
+}
+
+fn valid_customer_status(status: &str) -> Result<(), ValidationError> {
+    match status {
+        "Active" => Ok(()),
+        "NonActive" => Ok(()),
+        "Lead" => Ok(()),
+        _ => Err(ValidationError {
+            message: Some("Please enter a valid status".into()),
+            ..ValidationError::new("status")
+        }),
+    }
+}
+
+#[derive(
+    Properties, Default, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable, Validate, Store,
+)]
 #[edgedb(json)]
 pub struct Customer {
     pub id: CustomerId,
     #[validate(length(min = 3, max = 300, message = "Must be longer than 3 characters"))]
     pub name: String,
-    #[validate(email)]
+    #[validate(email(message = "Please enter a valid email"))]
     pub email: String,
+    #[validate(custom = "valid_customer_status")]
     pub status: String,
     pub created: String,
+    /// Changes whenever the customer is saved, sent back in `If-Match` so other edits are not overwritten
+    #[serde(default)]
+    pub version: String,
+    /// Ordered by name, ignored on create and update, use `BulkTagRequest` to change them
+    #[serde(default)]
+    pub tags: Vec<Tag>,
+    /// Values of the customer custom fields, checked against their definitions by the api
+    #[serde(default)]
+    pub custom: CustomValues,
+    /// Ignored on create and update, use `AssignRequest` to change it
+    #[serde(default)]
+    pub owner: Option<Owner>,
 }
 
-fn valid_opportunity_status(status: &str) -> Result<(), ValidationError> {
-    match status {
-        "New" => Ok(()),
-        "ClosedWon" => Ok(()),
-        "ClosedLost" => Ok(()),
-        _ => Err(ValidationError {
-            message: Some("Please enter a valid status".into()),
-            ..ValidationError::new("status")
-        }),
+/// What is removed along with a customer when it is deleted
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable)]
+#[edgedb(json)]
+pub struct CustomerDeletion {
+    pub id: CustomerId,
+    pub opportunity_count: i64,
+}
+
+/// Customer sitting in the trash waiting to be restored or purged
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable)]
+#[edgedb(json)]
+pub struct DeletedCustomer {
+    pub id: CustomerId,
+    pub name: String,
+    pub email: String,
+    pub deleted: String,
+    pub opportunity_count: i64,
+}
+
+/// Whether an opportunity in the stage is still being worked on, won or lost
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StageOutcome {
+    Open,
+    Won,
+    Lost,
+}
+
+impl fmt::Display for StageOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            StageOutcome::Open => write!(f, "Open"),
+            StageOutcome::Won => write!(f, "Won"),
+            StageOutcome::Lost => write!(f, "Lost"),
+        }
     }
 }
 
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stage {
+    /// Empty for a stage that is being added to the pipeline
+    pub id: StageId,
+    pub name: String,
+    pub outcome: String,
+}
+
+/// Stages need unique names of up to 100 characters and a valid outcome
+fn valid_stages(stages: &[Stage]) -> Result<(), ValidationError> {
+    let invalid = |message: &'static str| ValidationError {
+        message: Some(message.into()),
+        ..ValidationError::new("stages")
+    };
+    if stages.is_empty() {
+        return Err(invalid("Please add at least one stage"));
+    }
+    if stages
+        .iter()
+        .any(|s| s.name.trim().is_empty() || s.name.len() > 100)
+    {
+        return Err(invalid(
+            "Each stage needs a name shorter than 100 characters",
+        ));
+    }
+    if !stages
+        .iter()
+        .all(|s| ["Open", "Won", "Lost"].contains(&s.outcome.as_str()))
+    {
+        return Err(invalid("Each stage needs an outcome of Open, Won or Lost"));
+    }
+    let mut names: Vec<&str> = stages.iter().map(|s| s.name.trim()).collect();
+    names.sort_unstable();
+    names.dedup();
+    match names.len() == stages.len() {
+        true => Ok(()),
+        false => Err(invalid("Stage names must be unique")),
+    }
+}
+
+/// Ordered stages an opportunity moves through.
+/// Opportunities that do not name a pipeline use the default one.
 #[derive(
     Properties, Default, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable, Validate, Store,
 )]
 #[edgedb(json)]
+pub struct Pipeline {
+    pub id: PipelineId,
+    #[validate(length(min = 3, max = 100, message = "Must be longer than 3 characters"))]
+    pub name: String,
+    pub is_default: bool,
+    /// In the order opportunities move through them
+    #[validate(custom = "valid_stages")]
+    pub stages: Vec<Stage>,
+    pub created: String,
+}
+
+impl Pipeline {
+    /// The pipeline with the id, falling back to the default pipeline when there is no id
+    pub fn find(pipelines: &[Pipeline], id: Option<PipelineId>) -> Option<&Pipeline> {
+        match id {
+            Some(id) => pipelines.iter().find(|p| p.id == id),
+            None => pipelines
+                .iter()
+                .find(|p| p.is_default)
+                .or_else(|| pipelines.first()),
+        }
+    }
+}
+
+/// Non negative decimal with at most two decimal places e.g. 1500 or 1499.95
+fn valid_amount(amount: &str) -> Result<(), ValidationError> {
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    let valid = (1..=15).contains(&whole.len())
+        && whole.chars().all(|c| c.is_ascii_digit())
+        && fraction.len() <= 2
+        && fraction.chars().all(|c| c.is_ascii_digit())
+        && !amount.ends_with('.');
+    match valid {
+        true => Ok(()),
+        false => Err(ValidationError {
+            message: Some("Please enter an amount e.g. 1500.00".into()),
+            ..ValidationError::new("amount")
+        }),
+    }
+}
+
+/// Three letter ISO 4217 code e.g. NZD
+fn valid_currency(currency: &str) -> Result<(), ValidationError> {
+    match currency.len() == 3 && currency.chars().all(|c| c.is_ascii_uppercase()) {
+        true => Ok(()),
+        false => Err(ValidationError {
+            message: Some("Please enter a three letter currency code e.g. NZD".into()),
+            ..ValidationError::new("currency")
+        }),
+    }
+}
+
+pub const DEFAULT_CURRENCY: &str = "NZD";
+
+#[derive(
+    Properties, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable, Validate, Store,
+)]
+#[edgedb(json)]
 pub struct Opportunity {
     pub id: OpportunityId,
     #[validate(length(min = 3, max = 300, message = "Must be longer than 3 characters"))]
     pub name: String,
-    #[validate(custom = "valid_opportunity_status")]
+    /// Uses the default pipeline when empty
+    pub pipeline_id: Option<PipelineId>,
+    /// Name of the opportunity's stage, checked against the pipeline by the api
+    #[validate(length(min = 1, max = 100, message = "Please choose a stage"))]
     pub status: String,
+    /// Outcome of the stage, filled in by the api and ignored on create and update
+    #[serde(default)]
+    pub outcome: Option<String>,
+    /// Expected value as a decimal string so no precision is lost on the way to the database
+    #[validate(custom = "valid_amount")]
+    pub amount: Option<String>,
+    #[validate(custom = "valid_currency")]
+    pub currency: String,
+    /// Expected close date as yyyy-mm-dd
+    #[validate(custom = "valid_date")]
+    pub close_date: Option<String>,
+    /// Chance of winning as a percentage
+    #[validate(range(min = 0, max = 100, message = "Must be between 0 and 100"))]
+    pub probability: i16,
     pub created: String,
+    /// Changes whenever the opportunity is saved, sent back in `If-Match` so other edits are not overwritten
+    #[serde(default)]
+    pub version: String,
+    /// Ordered by name, ignored on create and update, use `BulkTagRequest` to change them
+    #[serde(default)]
+    pub tags: Vec<Tag>,
+    /// Values of the opportunity custom fields, checked against their definitions by the api
+    #[serde(default)]
+    pub custom: CustomValues,
+    /// Ignored on create and update, use `AssignRequest` to change it
+    #[serde(default)]
+    pub owner: Option<Owner>,
+}
+
+impl Default for Opportunity {
+    fn default() -> Self {
+        Opportunity {
+            id: OpportunityId::default(),
+            name: String::new(),
+            pipeline_id: None,
+            status: String::new(),
+            outcome: None,
+            amount: None,
+            currency: DEFAULT_CURRENCY.to_string(),
+            close_date: None,
+            probability: 0,
+            created: String::new(),
+            version: String::new(),
+            tags: vec![],
+            custom: CustomValues::new(),
+            owner: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OpportunitySortField {
+    Created,
+    CloseDate,
+}
+
+impl fmt::Display for OpportunitySortField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            OpportunitySortField::Created => write!(f, "created"),
+            OpportunitySortField::CloseDate => write!(f, "closeDate"),
+        }
+    }
+}
+
+#[derive(Properties, Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OpportunitiesQueryParams {
+    pub sort: OpportunitySortField,
+    pub direction: SortDirection,
+}
+
+impl Default for OpportunitiesQueryParams {
+    fn default() -> Self {
+        OpportunitiesQueryParams {
+            sort: OpportunitySortField::Created,
+            direction: SortDirection::Desc,
+        }
+    }
+}
+
+impl OpportunitiesQueryParams {
+    pub fn to_query_string(&self) -> String {
+        format!("?sort={}&direction={}", self.sort, self.direction)
+    }
+}
+
+fn valid_phone(phone: &str) -> Result<(), ValidationError> {
+    let digits = phone.chars().filter(|c| c.is_ascii_digit()).count();
+    let allowed = phone
+        .chars()
+        .all(|c| c.is_ascii_digit() || " +-()".contains(c));
+    match allowed && (6..=20).contains(&digits) {
+        true => Ok(()),
+        false => Err(ValidationError {
+            message: Some("Please enter a valid phone number".into()),
+            ..ValidationError::new("phone")
+        }),
+    }
+}
+
+/// Person at a customer, at most one contact per customer is the primary contact
+#[derive(
+    Properties, Default, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable, Validate, Store,
+)]
+#[edgedb(json)]
+pub struct Contact {
+    pub id: ContactId,
+    #[validate(length(min = 3, max = 300, message = "Must be longer than 3 characters"))]
+    pub name: String,
+    #[validate(email(message = "Please enter a valid email"))]
+    pub email: String,
+    #[validate(custom = "valid_phone")]
+    pub phone: Option<String>,
+    #[validate(length(max = 300, message = "Must be shorter than 300 characters"))]
+    pub job_title: Option<String>,
+    pub is_primary: bool,
+    pub created: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ActivityKind {
+    Call,
+    Meeting,
+    Email,
+    Note,
+}
+
+impl fmt::Display for ActivityKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            ActivityKind::Call => write!(f, "Call"),
+            ActivityKind::Meeting => write!(f, "Meeting"),
+            ActivityKind::Email => write!(f, "Email"),
+            ActivityKind::Note => write!(f, "Note"),
+        }
+    }
+}
+
+fn valid_activity_kind(kind: &str) -> Result<(), ValidationError> {
+    match kind {
+        "Call" => Ok(()),
+        "Meeting" => Ok(()),
+        "Email" => Ok(()),
+        "Note" => Ok(()),
+        _ => Err(ValidationError {
+            message: Some("Please enter a valid kind".into()),
+            ..ValidationError::new("kind")
+        }),
+    }
+}
+
+/// Call, meeting, email or note recorded against a customer and optionally one of its opportunities
+#[derive(
+    Properties, Default, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable, Validate, Store,
+)]
+#[edgedb(json)]
+pub struct Activity {
+    pub id: ActivityId,
+    #[validate(custom = "valid_activity_kind")]
+    pub kind: String,
+    #[validate(length(min = 1, max = 5000, message = "Please enter some details"))]
+    pub body: String,
+    pub opportunity_id: Option<OpportunityId>,
+    /// Filled in by the api, ignored on create and update
+    #[serde(default)]
+    pub opportunity_name: Option<String>,
+    pub created: String,
+}
+
+/// Stage an opportunity was moved to, when and by whom
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable)]
+#[edgedb(json)]
+pub struct StatusChange {
+    pub id: Uuid,
+    pub opportunity_id: OpportunityId,
+    pub opportunity_name: String,
+    pub status: String,
+    /// Empty when the opportunity was created in this stage
+    #[serde(default)]
+    pub previous_status: Option<String>,
+    /// Outcome of the stage the opportunity was moved to
+    #[serde(default)]
+    pub outcome: Option<String>,
+    #[serde(default)]
+    pub changed_by: Option<String>,
+    pub created: String,
+}
+
+/// Total time an opportunity has spent in a stage over every visit
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StageTime {
+    pub status: String,
+    pub seconds: i64,
+}
+
+/// Status changes of one opportunity oldest first with the time it spent in each stage
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable)]
+#[edgedb(json)]
+pub struct StageHistory {
+    pub opportunity_id: OpportunityId,
+    pub changes: Vec<StatusChange>,
+    /// In the order the stages were first reached, filled in by the api
+    #[serde(default)]
+    pub time_in_stage: Vec<StageTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskPriority {
+    Low,
+    Normal,
+    High,
+}
+
+impl fmt::Display for TaskPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            TaskPriority::Low => write!(f, "Low"),
+            TaskPriority::Normal => write!(f, "Normal"),
+            TaskPriority::High => write!(f, "High"),
+        }
+    }
+}
+
+fn valid_task_priority(priority: &str) -> Result<(), ValidationError> {
+    match priority {
+        "Low" => Ok(()),
+        "Normal" => Ok(()),
+        "High" => Ok(()),
+        _ => Err(ValidationError {
+            message: Some("Please enter a valid priority".into()),
+            ..ValidationError::new("priority")
+        }),
+    }
+}
+
+/// Follow up against a customer and optionally one of its opportunities
+#[derive(
+    Properties, Default, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable, Validate, Store,
+)]
+#[edgedb(json)]
+pub struct Task {
+    pub id: TaskId,
+    #[validate(length(min = 3, max = 300, message = "Must be longer than 3 characters"))]
+    pub title: String,
+    /// Due date as yyyy-mm-dd
+    #[validate(custom = "valid_date")]
+    pub due: String,
+    pub assignee: Option<Owner>,
+    pub completed: bool,
+    #[validate(custom = "valid_task_priority")]
+    pub priority: String,
+    pub opportunity_id: Option<OpportunityId>,
+    /// Filled in by the api, ignored on create and update
+    #[serde(default)]
+    pub customer_id: Option<CustomerId>,
+    #[serde(default)]
+    pub customer_name: Option<String>,
+    pub created: String,
+}
+
+/// Which tasks to list relative to today
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskDue {
+    /// Not completed and due before today
+    Overdue,
+    /// Due today whether completed or not
+    Today,
+    /// Not completed and due after today
+    Upcoming,
+}
+
+impl fmt::Display for TaskDue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            TaskDue::Overdue => write!(f, "overdue"),
+            TaskDue::Today => write!(f, "today"),
+            TaskDue::Upcoming => write!(f, "upcoming"),
+        }
+    }
+}
+
+#[derive(Properties, Debug, Clone, PartialEq, Deserialize, Serialize, Validate)]
+#[serde(default)]
+pub struct TasksQueryParams {
+    pub due: TaskDue,
+    /// Only include tasks assigned to the signed in user
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub mine: bool,
+    /// The caller's date as yyyy-mm-dd so today follows their time zone, defaults to the date in UTC
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(custom = "valid_date")]
+    pub today: Option<String>,
+    #[validate(range(min = 1, message = "Must return at least one row"))]
+    pub limit: usize,
+}
+
+impl Default for TasksQueryParams {
+    fn default() -> Self {
+        TasksQueryParams {
+            due: TaskDue::Today,
+            mine: false,
+            today: None,
+            limit: 50,
+        }
+    }
+}
+
+impl TasksQueryParams {
+    pub fn to_query_string(&self) -> String {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        query
+            .append_pair("due", &self.due.to_string())
+            .append_pair("limit", &self.limit.to_string());
+        if self.mine {
+            query.append_pair("mine", "true");
+        }
+        if let Some(today) = &self.today {
+            query.append_pair("today", today);
+        }
+        format!("?{}", query.finish())
+    }
+}
+
+/// Kind of record an audit event is about
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditEntity {
+    Customer,
+    Opportunity,
+    Activity,
+    Contact,
+    Task,
+}
+
+impl fmt::Display for AuditEntity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            AuditEntity::Customer => write!(f, "customer"),
+            AuditEntity::Opportunity => write!(f, "opportunity"),
+            AuditEntity::Activity => write!(f, "activity"),
+            AuditEntity::Contact => write!(f, "contact"),
+            AuditEntity::Task => write!(f, "task"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum AuditAction {
+    Created,
+    Updated,
+    /// Deleted, customers are moved to the trash
+    Deleted,
+    /// Taken back out of the trash
+    Restored,
+    /// Customer permanently deleted from the trash
+    Purged,
+    /// Customer combined with a duplicate, which is removed
+    Merged,
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            AuditAction::Created => write!(f, "Created"),
+            AuditAction::Updated => write!(f, "Updated"),
+            AuditAction::Deleted => write!(f, "Deleted"),
+            AuditAction::Restored => write!(f, "Restored"),
+            AuditAction::Purged => write!(f, "Purged"),
+            AuditAction::Merged => write!(f, "Merged"),
+        }
+    }
+}
+
+/// Value of a field before and after a change, null when the field had no value
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: serde_json::Value,
+    pub after: serde_json::Value,
+}
+
+/// Entry in the append only audit log
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable)]
+#[edgedb(json)]
+pub struct AuditEvent {
+    pub id: Uuid,
+    pub entity: String,
+    pub entity_id: Uuid,
+    pub action: String,
+    pub changes: Vec<FieldChange>,
+    pub changed_by: Option<String>,
+    pub created: String,
+}
+
+#[derive(Properties, Debug, Clone, PartialEq, Deserialize, Serialize, Validate)]
+pub struct AuditQueryParams {
+    /// Asking for a customer also returns the changes to its opportunities, activities, contacts and tasks
+    /// and the log of the customers merged into it
+    pub entity: AuditEntity,
+    pub id: Uuid,
+    #[serde(default = "default_audit_limit")]
+    #[validate(range(min = 1, message = "Must return at least one row"))]
+    pub limit: usize,
+}
+
+fn default_audit_limit() -> usize {
+    50
+}
+
+impl AuditQueryParams {
+    pub fn to_query_string(&self) -> String {
+        format!(
+            "?entity={}&id={}&limit={}",
+            self.entity, self.id, self.limit
+        )
+    }
+}
+
+#[derive(Properties, Debug, Clone, PartialEq, Deserialize, Serialize, Validate)]
+pub struct DuplicatesQueryParams {
+    /// Lowest score, between 0 and 1, of the pairs returned
+    #[serde(default = "default_min_score")]
+    #[validate(range(min = 0.0, max = 1.0, message = "Must be between 0 and 1"))]
+    pub min_score: f64,
+    #[serde(default = "default_duplicates_limit")]
+    #[validate(range(min = 1, message = "Must return at least one row"))]
+    pub limit: usize,
+}
+
+fn default_min_score() -> f64 {
+    0.5
+}
+
+fn default_duplicates_limit() -> usize {
+    50
+}
+
+impl Default for DuplicatesQueryParams {
+    fn default() -> Self {
+        DuplicatesQueryParams {
+            min_score: default_min_score(),
+            limit: default_duplicates_limit(),
+        }
+    }
+}
+
+/// Pair of customers that look like the same company, the reasons explain the score
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DuplicateCandidate {
+    pub customer: Customer,
+    pub duplicate: Customer,
+    /// Between 0 and 1, higher is more likely to be the same company
+    pub score: f64,
+    pub reasons: Vec<String>,
+}
+
+/// Merges the duplicate into the customer in the url, the duplicate is removed
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MergeRequest {
+    pub duplicate_id: CustomerId,
+}
+
+/// Hex colour such as #3e8ed0
+fn valid_colour(colour: &str) -> Result<(), ValidationError> {
+    let hex = colour.strip_prefix('#').unwrap_or_default();
+    match hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => Ok(()),
+        false => Err(ValidationError {
+            message: Some("Please choose a colour e.g. #3e8ed0".into()),
+            ..ValidationError::new("colour")
+        }),
+    }
+}
+
+/// Colours offered when a tag is created
+pub const TAG_COLOURS: [&str; 8] = [
+    "#3e8ed0", "#48c78e", "#ffe08a", "#f14668", "#485fc7", "#00d1b2", "#ff9f43", "#8e44ad",
+];
+
+/// Free-form colour coded label put on customers and opportunities
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable, Validate)]
+#[edgedb(json)]
+pub struct Tag {
+    pub id: TagId,
+    #[validate(length(min = 1, max = 50, message = "Must be between 1 and 50 characters"))]
+    pub name: String,
+    #[validate(custom = "valid_colour")]
+    pub colour: String,
+}
+
+/// Adds the tags to every listed customer and opportunity, or takes them off when `remove` is set
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+pub struct BulkTagRequest {
+    #[validate(length(min = 1, message = "Please choose at least one tag"))]
+    pub tags: Vec<TagId>,
+    #[serde(default)]
+    #[validate(length(max = 500, message = "Please choose at most 500 customers"))]
+    pub customers: Vec<CustomerId>,
+    #[serde(default)]
+    #[validate(length(max = 500, message = "Please choose at most 500 opportunities"))]
+    pub opportunities: Vec<OpportunityId>,
+    #[serde(default)]
+    pub remove: bool,
+}
+
+/// Gives every listed customer and opportunity to the owner, or leaves them without one when it is empty
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+pub struct AssignRequest {
+    pub owner: Option<UserId>,
+    #[serde(default)]
+    #[validate(length(max = 500, message = "Please choose at most 500 customers"))]
+    pub customers: Vec<CustomerId>,
+    #[serde(default)]
+    #[validate(length(max = 500, message = "Please choose at most 500 opportunities"))]
+    pub opportunities: Vec<OpportunityId>,
+}
+
+/// Kind of record a custom field is added to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub enum CustomFieldEntity {
+    #[default]
+    Customer,
+    Opportunity,
+}
+
+impl fmt::Display for CustomFieldEntity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            CustomFieldEntity::Customer => write!(f, "Customer"),
+            CustomFieldEntity::Opportunity => write!(f, "Opportunity"),
+        }
+    }
+}
+
+/// Type of the values a custom field holds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub enum CustomFieldKind {
+    #[default]
+    Text,
+    Number,
+    /// yyyy-mm-dd
+    Date,
+    /// One of the field options
+    Select,
+    Boolean,
+}
+
+impl fmt::Display for CustomFieldKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            CustomFieldKind::Text => write!(f, "Text"),
+            CustomFieldKind::Number => write!(f, "Number"),
+            CustomFieldKind::Date => write!(f, "Date"),
+            CustomFieldKind::Select => write!(f, "Select"),
+            CustomFieldKind::Boolean => write!(f, "Boolean"),
+        }
+    }
+}
+
+/// Lower case letter followed by up to 49 lower case letters, digits or underscores
+fn valid_field_key(key: &str) -> Result<(), ValidationError> {
+    let valid = key.len() <= 50
+        && key.starts_with(|c: char| c.is_ascii_lowercase())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    match valid {
+        true => Ok(()),
+        false => Err(ValidationError {
+            message: Some(
+                "Please use lower case letters, digits and underscores e.g. arr_band".into(),
+            ),
+            ..ValidationError::new("key")
+        }),
+    }
+}
+
+/// Select fields need options and a range needs its minimum below its maximum
+fn valid_custom_field(field: &CustomField) -> Result<(), ValidationError> {
+    let invalid = |message: &'static str| ValidationError {
+        message: Some(message.into()),
+        ..ValidationError::new("custom_field")
+    };
+    if field.kind == CustomFieldKind::Select
+        && (field.options.is_empty() || field.options.iter().any(|o| o.trim().is_empty()))
+    {
+        return Err(invalid("Select fields need at least one option"));
+    }
+    match (field.min, field.max) {
+        (Some(min), Some(max)) if min > max => {
+            Err(invalid("The minimum must not be more than the maximum"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Attribute an admin adds to customers or opportunities, e.g. industry or licence count
+#[derive(
+    Properties, Default, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable, Validate, Store,
+)]
+#[edgedb(json)]
+#[validate(schema(function = "valid_custom_field", skip_on_field_errors = false))]
+pub struct CustomField {
+    pub id: CustomFieldId,
+    /// Can not be changed once the field is created
+    pub entity: CustomFieldEntity,
+    /// Name the values are stored and filtered under, can not be changed once the field is created
+    #[validate(custom = "valid_field_key")]
+    pub key: String,
+    #[validate(length(min = 1, max = 100, message = "Must be between 1 and 100 characters"))]
+    pub label: String,
+    /// Can not be changed once the field is created
+    pub kind: CustomFieldKind,
+    pub is_required: bool,
+    /// Choices of a select field
+    #[serde(default)]
+    pub options: Vec<String>,
+    /// Smallest number, or fewest characters of a text field
+    pub min: Option<f64>,
+    /// Largest number, or most characters of a text field
+    pub max: Option<f64>,
+}
+
+/// A value that is missing, null or only spaces
+fn is_blank(value: Option<&serde_json::Value>) -> bool {
+    match value {
+        None | Some(serde_json::Value::Null) => true,
+        Some(serde_json::Value::String(text)) => text.trim().is_empty(),
+        _ => false,
+    }
+}
+
+impl CustomField {
+    /// The value as it is stored, or the message to show when it is not valid for the field.
+    /// Forms send every value as text so numbers and booleans are also read from strings.
+    pub fn check(
+        &self,
+        value: Option<&serde_json::Value>,
+    ) -> Result<Option<serde_json::Value>, String> {
+        use serde_json::Value;
+        if is_blank(value) {
+            return match self.is_required {
+                true => Err("Please enter a value".to_string()),
+                false => Ok(None),
+            };
+        }
+        let value = value.cloned().unwrap_or_default();
+        let text = match &value {
+            Value::String(text) => Some(text.trim().to_string()),
+            _ => None,
+        };
+        let out_of_range = |number: f64, unit: &str| match (self.min, self.max) {
+            (Some(min), _) if number < min => Some(format!("Must be at least {}{}", min, unit)),
+            (_, Some(max)) if number > max => Some(format!("Must be at most {}{}", max, unit)),
+            _ => None,
+        };
+        match self.kind {
+            CustomFieldKind::Text => {
+                let text = text.ok_or_else(|| "Please enter text".to_string())?;
+                match out_of_range(text.chars().count() as f64, " characters") {
+                    Some(message) => Err(message),
+                    None => Ok(Some(Value::String(text))),
+                }
+            }
+            CustomFieldKind::Number => {
+                let number = match &value {
+                    Value::Number(number) => number.as_f64(),
+                    _ => text.and_then(|t| t.parse::<f64>().ok()),
+                }
+                .filter(|n| n.is_finite())
+                .ok_or_else(|| "Please enter a number".to_string())?;
+                match out_of_range(number, "") {
+                    Some(message) => Err(message),
+                    None => Ok(serde_json::Number::from_f64(number).map(Value::Number)),
+                }
+            }
+            CustomFieldKind::Date => {
+                match text.filter(|t| chrono::NaiveDate::parse_from_str(t, "%Y-%m-%d").is_ok()) {
+                    Some(date) => Ok(Some(Value::String(date))),
+                    None => Err("Please enter a date as yyyy-mm-dd".to_string()),
+                }
+            }
+            CustomFieldKind::Select => match text.filter(|t| self.options.contains(t)) {
+                Some(option) => Ok(Some(Value::String(option))),
+                None => Err(format!("Please choose one of {}", self.options.join(", "))),
+            },
+            CustomFieldKind::Boolean => match (&value, text.as_deref()) {
+                (Value::Bool(flag), _) => Ok(Some(Value::Bool(*flag))),
+                (_, Some("true")) => Ok(Some(Value::Bool(true))),
+                (_, Some("false")) => Ok(Some(Value::Bool(false))),
+                _ => Err("Please choose yes or no".to_string()),
+            },
+        }
+    }
+}
+
+/// Checks the values against the fields, returning the values to store or the messages keyed by `custom.<key>`.
+/// Values of keys that are not one of the fields are dropped.
+pub fn check_custom_values(
+    fields: &[CustomField],
+    values: &CustomValues,
+) -> Result<CustomValues, HashMap<String, Vec<String>>> {
+    let mut checked = CustomValues::new();
+    let mut errors = HashMap::new();
+    for field in fields {
+        match field.check(values.get(&field.key)) {
+            Ok(Some(value)) => {
+                checked.insert(field.key.clone(), value);
+            }
+            Ok(None) => {}
+            Err(message) => {
+                errors.insert(format!("custom.{}", field.key), vec![message]);
+            }
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(checked),
+        false => Err(errors),
+    }
+}
+
+/// Custom field value a list is filtered on, written as `key:value`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomFilter {
+    pub key: String,
+    pub value: String,
+}
+
+impl fmt::Display for CustomFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.key, self.value)
+    }
+}
+
+impl FromStr for CustomFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((key, value)) if !key.is_empty() => Ok(CustomFilter {
+                key: key.to_string(),
+                value: value.to_string(),
+            }),
+            _ => Err(format!("{} is not a custom field filter, use key:value", s)),
+        }
+    }
+}
+
+#[derive(Properties, Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct CustomFieldsQueryParams {
+    /// Only the fields of this kind of record
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity: Option<CustomFieldEntity>,
+}
+
+/// What an import does with a row whose email already belongs to a customer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OnDuplicate {
+    /// Leave the existing customer as it is
+    Skip,
+    /// Overwrite the existing customer with the values in the row
+    Update,
+    /// Report the row as an error
+    #[default]
+    Fail,
+}
+
+impl fmt::Display for OnDuplicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            OnDuplicate::Skip => write!(f, "skip"),
+            OnDuplicate::Update => write!(f, "update"),
+            OnDuplicate::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+/// Options of a customer CSV import, the columns name the CSV header each field is read from
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ImportParams {
+    /// Check the rows and report what would happen without saving anything
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub on_duplicate: OnDuplicate,
+    #[serde(default = "default_name_column")]
+    pub name_column: String,
+    #[serde(default = "default_email_column")]
+    pub email_column: String,
+    /// Rows with an empty status, or files without the column, are imported as Active
+    #[serde(default = "default_status_column")]
+    pub status_column: String,
+}
+
+fn default_name_column() -> String {
+    "name".to_string()
+}
+
+fn default_email_column() -> String {
+    "email".to_string()
+}
+
+fn default_status_column() -> String {
+    "status".to_string()
+}
+
+impl Default for ImportParams {
+    fn default() -> Self {
+        ImportParams {
+            dry_run: false,
+            on_duplicate: OnDuplicate::default(),
+            name_column: default_name_column(),
+            email_column: default_email_column(),
+            status_column: default_status_column(),
+        }
+    }
+}
+
+/// Why a row of an import could not be saved, rows are numbered as lines of the file
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportRowError {
+    pub row: usize,
+    #[serde(flatten)]
+    pub error: ErrorResponse,
+}
+
+/// Outcome of an import, for a dry run the counts are what the import would do.
+/// Nothing is saved when a row fails validation or the duplicate check.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub rows: usize,
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub errors: Vec<ImportRowError>,
+}
+
+/// File format customers and their opportunities are exported in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExportFormat {
+    /// One row per opportunity, customers without opportunities get a row of their own
+    #[default]
+    Csv,
+    /// One customer per line with its opportunities nested
+    Ndjson,
+    /// The same rows as the CSV in an Excel workbook
+    Xlsx,
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            ExportFormat::Csv => write!(f, "csv"),
+            ExportFormat::Ndjson => write!(f, "ndjson"),
+            ExportFormat::Xlsx => write!(f, "xlsx"),
+        }
+    }
+}
+
+/// Export options, the customers are filtered with the `CustomersQueryParams` in the same query string
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ExportParams {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+/// Customer along with every one of its opportunities, a line of the NDJSON export
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable)]
+#[edgedb(json)]
+pub struct CustomerExport {
+    #[serde(flatten)]
+    pub customer: Customer,
+    pub opportunities: Vec<Opportunity>,
+}
+
+/// Download link for every customer matching the filters, in the order of the list
+pub fn export_url(format: ExportFormat, query: &CustomersQueryParams) -> String {
+    let query = CustomersQueryParams {
+        cursor: None,
+        ..query.clone()
+    };
+    format!(
+        "{}/customers/export{}&format={}",
+        get_base_url(),
+        query.to_query_string(),
+        format
+    )
+}
+
+/// What a user is allowed to do, see `Role::can`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub enum Role {
+    Admin,
+    SalesManager,
+    #[default]
+    SalesRep,
+    ReadOnly,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            Role::Admin => write!(f, "Admin"),
+            Role::SalesManager => write!(f, "SalesManager"),
+            Role::SalesRep => write!(f, "SalesRep"),
+            Role::ReadOnly => write!(f, "ReadOnly"),
+        }
+    }
+}
+
+impl Role {
+    /// Name shown to people, `to_string` gives the name in the schema
+    pub fn label(self) -> &'static str {
+        match self {
+            Role::Admin => "Admin",
+            Role::SalesManager => "Sales manager",
+            Role::SalesRep => "Sales rep",
+            Role::ReadOnly => "Read-only",
+        }
+    }
+}
+
+/// Actions that need more than a session, everyone signed in can read
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permission {
+    /// Create and change customers, contacts, opportunities, activities and tasks
+    Edit,
+    /// Change a customer to NonActive
+    DeactivateCustomers,
+    /// Move customers to the trash, restore, purge or merge them
+    DeleteCustomers,
+    DeleteOpportunities,
+    /// Give customers and opportunities to another owner
+    AssignOwners,
+    /// Pipelines, custom fields and tags
+    ManageSettings,
+    ManageUsers,
+    /// Set up another organisation along with its first admin, only for operators whatever their role
+    ManageOrganizations,
+}
+
+impl Role {
+    pub fn can(self, permission: Permission) -> bool {
+        match permission {
+            Permission::Edit => self != Role::ReadOnly,
+            Permission::DeactivateCustomers
+            | Permission::DeleteCustomers
+            | Permission::DeleteOpportunities
+            | Permission::AssignOwners => {
+                matches!(self, Role::Admin | Role::SalesManager)
+            }
+            Permission::ManageSettings | Permission::ManageUsers => self == Role::Admin,
+            Permission::ManageOrganizations => false,
+        }
+    }
+}
+
+/// User a customer or opportunity belongs to
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Owner {
+    pub id: UserId,
+    pub name: String,
+}
+
+/// Business unit sharing the deployment, its users only see its own customers and opportunities
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Organization {
+    pub id: OrganizationId,
+    pub name: String,
+}
+
+/// Someone who can sign in, changes they make are recorded under their name
+#[derive(
+    Properties, Default, Debug, Clone, PartialEq, Serialize, Deserialize, Queryable, Validate,
+)]
+#[edgedb(json)]
+pub struct User {
+    pub id: UserId,
+    #[validate(email(message = "Please enter a valid email address"))]
+    pub email: String,
+    #[validate(length(min = 1, max = 100, message = "Must be between 1 and 100 characters"))]
+    pub name: String,
+    pub role: Role,
+    /// Organisation the user works in, set by the server
+    #[serde(default)]
+    pub organization: Organization,
+    /// Runs the deployment and can set up organisations, set by the server
+    #[serde(default)]
+    pub operator: bool,
+}
+
+impl User {
+    /// Whether the user's role allows the action, setting up organisations is only for operators
+    pub fn can(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::ManageOrganizations => self.operator,
+            _ => self.role.can(permission),
+        }
+    }
+}
+
+/// Email and password sent to sign in
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Validate, Store)]
+pub struct Credentials {
+    #[validate(email(message = "Please enter a valid email address"))]
+    pub email: String,
+    #[validate(length(min = 1, message = "Please enter your password"))]
+    pub password: String,
+}
+
+/// Account to create along with the password it signs in with
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Validate, Store)]
+pub struct NewUser {
+    #[validate(email(message = "Please enter a valid email address"))]
+    pub email: String,
+    #[validate(length(min = 1, max = 100, message = "Must be between 1 and 100 characters"))]
+    pub name: String,
+    #[validate(length(min = 10, max = 128, message = "Must be between 10 and 128 characters"))]
+    pub password: String,
+    /// Ignored for the first account, which is always an admin
+    #[serde(default)]
+    pub role: Role,
+}
+
+/// Organisation to set up along with the account of its first admin
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Validate, Store)]
+pub struct NewOrganization {
+    #[validate(length(min = 1, max = 100, message = "Must be between 1 and 100 characters"))]
+    pub organization: String,
+    #[validate(email(message = "Please enter a valid email address"))]
+    pub email: String,
+    #[validate(length(min = 1, max = 100, message = "Must be between 1 and 100 characters"))]
+    pub name: String,
+    #[validate(length(min = 10, max = 128, message = "Must be between 10 and 128 characters"))]
+    pub password: String,
+}
+
+/// Whoever is signed in, filled in from the session cookie when the app starts
+#[derive(Default, Clone, PartialEq, Store)]
+pub struct CurrentUser {
+    pub user: Option<User>,
+    /// Whether the api has been asked yet, until then the user is not known to be missing
+    pub checked: bool,
+}
+
+impl CurrentUser {
+    /// Name changes are recorded under, empty when nobody is signed in
+    pub fn name(&self) -> String {
+        self.user
+            .as_ref()
+            .map(|u| u.name.clone())
+            .unwrap_or_default()
+    }
+
+    /// Organisation the signed in user belongs to, empty when nobody is signed in
+    pub fn organization(&self) -> String {
+        self.user
+            .as_ref()
+            .map(|u| u.organization.name.clone())
+            .unwrap_or_default()
+    }
+
+    /// Whether the signed in user may perform the action, always false when nobody is signed in
+    pub fn can(&self, permission: Permission) -> bool {
+        self.user
+            .as_ref()
+            .map(|u| u.can(permission))
+            .unwrap_or(false)
+    }
+}
+
+/// Body returned by the api for any unsuccessful request
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    /// Stable machine readable code e.g. not_found, conflict, validation
+    pub code: String,
+    pub message: String,
+    /// Messages for individual fields keyed by field name
+    #[serde(default)]
+    pub fields: HashMap<String, Vec<String>>,
 }
 
 pub async fn get_data<T>(path: String) -> Result<T, MultiError>
@@ -121,14 +1541,11 @@ where
         .header(ACCEPT, "application/json")
         .send()
         .await;
-    match response {
+    match check_response(response).await?.text().await {
         Err(_) => Err(MultiError::RequestError),
-        Ok(response) => match response.text().await {
-            Err(_) => Err(MultiError::RequestError),
-            Ok(text) => match serde_json::from_str::<T>(&text) {
-                Err(_) => Err(MultiError::DeserializeError),
-                Ok(result) => Ok(result),
-            },
+        Ok(text) => match serde_json::from_str::<T>(&text) {
+            Err(_) => Err(MultiError::DeserializeError),
+            Ok(result) => Ok(result),
         },
     }
 }
@@ -145,9 +1562,26 @@ where
         .body(serde_json::to_string(&body).unwrap_or_default())
         .send()
         .await;
-    match response {
+    check_response(response).await.map(|_| true)
+}
+
+/// Like `post_data` but returns the record the api sent back, e.g. a created record with its id
+pub async fn post_returning<T, R>(path: String, body: T) -> Result<R, MultiError>
+where
+    T: serde::ser::Serialize,
+    R: serde::de::DeserializeOwned,
+{
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("{}{}", get_base_url(), path))
+        .header(CONTENT_TYPE, "application/json")
+        .header(ACCEPT, "application/json")
+        .body(serde_json::to_string(&body).unwrap_or_default())
+        .send()
+        .await;
+    match check_response(response).await?.text().await {
         Err(_) => Err(MultiError::RequestError),
-        Ok(_) => Ok(true),
+        Ok(text) => serde_json::from_str::<R>(&text).map_err(|_| MultiError::DeserializeError),
     }
 }
 
@@ -163,10 +1597,7 @@ where
         .body(serde_json::to_string(&body).unwrap_or_default())
         .send()
         .await;
-    match response {
-        Err(_) => Err(MultiError::RequestError),
-        Ok(_) => Ok(true),
-    }
+    check_response(response).await.map(|_| true)
 }
 
 pub async fn delete_data(path: String) -> Result<bool, MultiError> {
@@ -175,9 +1606,76 @@ pub async fn delete_data(path: String) -> Result<bool, MultiError> {
         .delete(format!("{}{}", get_base_url(), path))
         .send()
         .await;
+    check_response(response).await.map(|_| true)
+}
+
+/// Only applies the change when the record is still at `version`, an empty version always applies
+fn with_version(request: reqwest::RequestBuilder, version: &str) -> reqwest::RequestBuilder {
+    match version.is_empty() {
+        true => request,
+        false => request.header(IF_MATCH, format!("\"{}\"", version)),
+    }
+}
+
+/// Like `put_data` but fails with a 412 when someone else saved the record after `version`
+pub async fn put_versioned<T>(path: String, body: T, version: &str) -> Result<bool, MultiError>
+where
+    T: serde::ser::Serialize,
+{
+    let client = reqwest::Client::new();
+    let response = with_version(client.put(format!("{}{}", get_base_url(), path)), version)
+        .header(CONTENT_TYPE, "application/json")
+        .header(ACCEPT, "application/json")
+        .body(serde_json::to_string(&body).unwrap_or_default())
+        .send()
+        .await;
+    check_response(response).await.map(|_| true)
+}
+
+/// Like `delete_data` but fails with a 412 when someone else saved the record after `version`
+pub async fn delete_versioned(path: String, version: &str) -> Result<bool, MultiError> {
+    let client = reqwest::Client::new();
+    let response = with_version(
+        client.delete(format!("{}{}", get_base_url(), path)),
+        version,
+    )
+    .send()
+    .await;
+    check_response(response).await.map(|_| true)
+}
+
+/// Turns any non success status into a `MultiError::ResponseError` with the body the api sent back
+async fn check_response(
+    response: Result<reqwest::Response, reqwest::Error>,
+) -> Result<reqwest::Response, MultiError> {
     match response {
         Err(_) => Err(MultiError::RequestError),
-        Ok(_) => Ok(true),
+        Ok(response) if response.status().is_success() => Ok(response),
+        Ok(response) => {
+            let status = response.status();
+            if status == reqwest::StatusCode::UNAUTHORIZED {
+                // the session ended, the app goes back to the sign in page
+                Dispatch::<CurrentUser>::new().set(CurrentUser {
+                    user: None,
+                    checked: true,
+                });
+            }
+            let error = match response.text().await {
+                Ok(text) => serde_json::from_str::<ErrorResponse>(&text).ok(),
+                Err(_) => None,
+            };
+            Err(MultiError::ResponseError {
+                status: status.as_u16(),
+                error: error.unwrap_or_else(|| ErrorResponse {
+                    code: "unknown".to_string(),
+                    message: status
+                        .canonical_reason()
+                        .unwrap_or("Unexpected response")
+                        .to_string(),
+                    ..Default::default()
+                }),
+            })
+        }
     }
 }
 
@@ -195,5 +1693,36 @@ fn get_base_url() -> String {
 #[derive(Clone, Debug, PartialEq)]
 pub enum MultiError {
     RequestError,
-    DeserializeError, // etc.
+    DeserializeError,
+    /// The api answered with an unsuccessful status code
+    ResponseError {
+        status: u16,
+        error: ErrorResponse,
+    },
+}
+
+impl MultiError {
+    /// Messages the server sent back for a single field
+    pub fn field_message(&self, field: &str) -> Option<String> {
+        match self {
+            MultiError::ResponseError { error, .. } => {
+                error.fields.get(field).map(|messages| messages.join(","))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the record was changed by someone else since it was loaded
+    pub fn is_stale(&self) -> bool {
+        matches!(self, MultiError::ResponseError { status: 412, .. })
+    }
+
+    /// Summary suitable to show above a form
+    pub fn message(&self) -> String {
+        match self {
+            MultiError::RequestError => "Could not reach the server".to_string(),
+            MultiError::DeserializeError => "The server sent an unexpected response".to_string(),
+            MultiError::ResponseError { error, .. } => error.message.clone(),
+        }
+    }
 }
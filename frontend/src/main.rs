@@ -1,3 +1,4 @@
+use components::login::AuthGuard;
 use yew::prelude::*;
 use yew_router::prelude::*;
 
@@ -9,9 +10,11 @@ mod routes;
 fn app() -> Html {
     html! {
         <BrowserRouter>
+            <AuthGuard>
             <main>
             <Switch<routes::AppRoute> render={routes::switch} />
             </main>
+            </AuthGuard>
         </BrowserRouter>
     }
 }
@@ -1,5 +1,8 @@
 use crate::components::customer_detail::CustomerDetail;
-use crate::components::{customers::CustomersTable, not_found::NotFound};
+use crate::components::{
+    custom_fields::CustomFieldsPage, customers::CustomersTable, login::Login, not_found::NotFound,
+    tasks::MyTasks, trash::Trash, users::Users,
+};
 use crate::data::CustomerId;
 use yew::prelude::*;
 use yew_router::prelude::*;
@@ -10,6 +13,16 @@ pub enum AppRoute {
     CustomerList,
     #[at("/customer/:id")]
     CustomerDetail { id: CustomerId },
+    #[at("/tasks")]
+    MyTasks,
+    #[at("/trash")]
+    Trash,
+    #[at("/fields")]
+    CustomFields,
+    #[at("/login")]
+    Login,
+    #[at("/users")]
+    Users,
     #[not_found]
     #[at("/404")]
     NotFound,
@@ -19,6 +32,11 @@ pub fn switch(routes: AppRoute) -> Html {
     match routes {
         AppRoute::CustomerDetail { id } => html! { <CustomerDetail id={id}/> },
         AppRoute::CustomerList => html! { <CustomersTable/> },
+        AppRoute::MyTasks => html! { <MyTasks/> },
+        AppRoute::Trash => html! { <Trash/> },
+        AppRoute::CustomFields => html! { <CustomFieldsPage/> },
+        AppRoute::Login => html! { <Login/> },
+        AppRoute::Users => html! { <Users/> },
         AppRoute::NotFound => html! { <NotFound/> },
     }
 }